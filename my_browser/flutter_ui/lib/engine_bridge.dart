@@ -52,6 +52,8 @@ class EngineBridge {
   static late final ParseUrlViaRustDart _parseUrlViaRust;
   static late final FreeFFILayoutBoxDart _freeFFILayoutBox;
   static late final GetLayoutBoxBatchDart _getLayoutBoxBatch;
+  static late final GetLayoutBoxParentIndexDart _getLayoutBoxParentIndex;
+  static late final LayoutHitTestDart _layoutHitTest;
   
   // New enhanced function pointers
   static late final GetLayoutBoxBatchEnhancedDart _getLayoutBoxBatchEnhanced;
@@ -61,6 +63,7 @@ class EngineBridge {
   // Draw command function pointers
   static late final GetDrawCommandCountDart _getDrawCommandCount;
   static late final GetDrawCommandDart _getDrawCommand;
+  static late final GetDrawCommandBatchDart _getDrawCommandBatch;
   static late final FreeDrawCommandArrayDart _freeDrawCommandArray;
   
   // JavaScript function pointers
@@ -197,6 +200,12 @@ class EngineBridge {
       _getLayoutBoxBatch = _lib!.lookupFunction<GetLayoutBoxBatchC, GetLayoutBoxBatchDart>('get_layout_box_batch');
       logPrint('EngineBridge: _getLayoutBoxBatch function pointer initialized');
       
+      _getLayoutBoxParentIndex = _lib!.lookupFunction<GetLayoutBoxParentIndexC, GetLayoutBoxParentIndexDart>('get_layout_box_parent_index');
+      logPrint('EngineBridge: _getLayoutBoxParentIndex function pointer initialized');
+      
+      _layoutHitTest = _lib!.lookupFunction<LayoutHitTestC, LayoutHitTestDart>('layout_hit_test');
+      logPrint('EngineBridge: _layoutHitTest function pointer initialized');
+      
       _getLayoutBoxBatchEnhanced = _lib!.lookupFunction<GetLayoutBoxBatchEnhancedC, GetLayoutBoxBatchEnhancedDart>('get_layout_box_batch_enhanced');
       logPrint('EngineBridge: _getLayoutBoxBatchEnhanced function pointer initialized');
       
@@ -212,6 +221,9 @@ class EngineBridge {
       _getDrawCommand = _lib!.lookupFunction<GetDrawCommandC, GetDrawCommandDart>('get_draw_command');
       logPrint('EngineBridge: _getDrawCommand function pointer initialized');
       
+      _getDrawCommandBatch = _lib!.lookupFunction<GetDrawCommandBatchC, GetDrawCommandBatchDart>('get_draw_command_batch');
+      logPrint('EngineBridge: _getDrawCommandBatch function pointer initialized');
+      
       _freeDrawCommandArray = _lib!.lookupFunction<FreeDrawCommandArrayC, FreeDrawCommandArrayDart>('free_draw_command_array');
       logPrint('EngineBridge: _freeDrawCommandArray function pointer initialized');
       
@@ -1020,19 +1032,44 @@ class EngineBridge {
     }
   }
 
-  /// Public API: Extracts a list of DrawCommand from a pointer to a draw command array
+  /// Public API: Extracts a list of DrawCommand from a pointer to a draw command array.
+  /// Commands are copied out in batches; the array still owns them.
   static List<DrawCommand> extractDrawCommands(ffi.Pointer<ffi.Void> drawCommandsPtr) {
     if (drawCommandsPtr == nullptr) return [];
     final count = _getDrawCommandCount(drawCommandsPtr);
     final result = <DrawCommand>[];
-    for (var i = 0; i < count; i++) {
-      final cmdPtr = _getDrawCommand(drawCommandsPtr, i);
-      if (cmdPtr == nullptr) continue;
-      result.add(extractSingleDrawCommand(cmdPtr));
+    const int batchSize = 1000;
+    final batchPtr = calloc.allocate<ffi.Pointer<FFIDrawCommand>>(batchSize * ffi.sizeOf<ffi.Pointer<FFIDrawCommand>>());
+    try {
+      var extracted = 0;
+      while (extracted < count) {
+        final n = _getDrawCommandBatch(drawCommandsPtr, extracted, batchSize, batchPtr);
+        if (n <= 0) break;
+        for (var i = 0; i < n; i++) {
+          final cmdPtr = batchPtr[i];
+          if (cmdPtr == nullptr) continue;
+          result.add(extractSingleDrawCommand(cmdPtr));
+        }
+        extracted += n;
+      }
+    } finally {
+      calloc.free(batchPtr);
     }
     return result;
   }
 
+  /// Index of the box's containing box in its layout box array, or -1 for the root
+  static int layoutBoxParentIndex(ffi.Pointer<FFILayoutBox> boxPtr) {
+    if (boxPtr == nullptr) return -1;
+    return _getLayoutBoxParentIndex(boxPtr.cast());
+  }
+
+  /// Index of the topmost box at (x, y) in a layout box array, or -1 when nothing is hit
+  static int layoutHitTest(ffi.Pointer<ffi.Void> boxArrayPtr, double x, double y) {
+    if (boxArrayPtr == nullptr) return -1;
+    return _layoutHitTest(boxArrayPtr, x, y);
+  }
+
   static void _freeDrawCommands(ffi.Pointer<ffi.Void> drawCommandsPtr) {
     if (drawCommandsPtr != nullptr) {
       _freeDrawCommandArray(drawCommandsPtr);
@@ -1394,10 +1431,11 @@ class EngineBridge {
     _domRemoveEventListener(nodeId, typePtr.cast<ffi.Char>(), callbackId);
     calloc.free(typePtr);
   }
-  static void dispatchEvent(int nodeId, String type) {
+  static bool dispatchEvent(int nodeId, String type, {bool bubbles = true}) {
     final typePtr = type.toNativeUtf8();
-    _domDispatchEvent(nodeId, typePtr.cast<ffi.Char>());
+    final handled = _domDispatchEvent(nodeId, typePtr.cast<ffi.Char>(), bubbles);
     calloc.free(typePtr);
+    return handled;
   }
 
   // Public API for refactored top-level functions
@@ -1439,6 +1477,9 @@ class DrawCommand {
   final String font;
   final double size;
   final String src;
+  final bool italic;
+  final double radius;
+  final double alpha;
   final DrawCommandType type;
 
   const DrawCommand.rect({
@@ -1451,6 +1492,9 @@ class DrawCommand {
        font = '',
        size = 0,
        src = '',
+       italic = false,
+       radius = 0,
+       alpha = 1,
        type = DrawCommandType.rect;
 
   const DrawCommand.text({
@@ -1460,9 +1504,12 @@ class DrawCommand {
     required this.font,
     required this.size,
     required this.color,
+    this.italic = false,
   }) : w = 0,
        h = 0,
        src = '',
+       radius = 0,
+       alpha = 1,
        type = DrawCommandType.text;
 
   const DrawCommand.image({
@@ -1475,11 +1522,80 @@ class DrawCommand {
        content = '',
        font = '',
        size = 0,
+       italic = false,
+       radius = 0,
+       alpha = 1,
        type = DrawCommandType.image;
+
+  /// Limits later commands to the rounded rect until the matching [DrawCommand.popClip]
+  const DrawCommand.pushClip({
+    required this.x,
+    required this.y,
+    required this.w,
+    required this.h,
+    this.radius = 0,
+  }) : color = 0,
+       content = '',
+       font = '',
+       size = 0,
+       src = '',
+       italic = false,
+       alpha = 1,
+       type = DrawCommandType.pushClip;
+
+  const DrawCommand.popClip()
+      : x = 0,
+        y = 0,
+        w = 0,
+        h = 0,
+        color = 0,
+        content = '',
+        font = '',
+        size = 0,
+        src = '',
+        italic = false,
+        radius = 0,
+        alpha = 1,
+        type = DrawCommandType.popClip;
+
+  /// Draws later commands offscreen and blends them at [alpha] at the matching
+  /// [DrawCommand.endOpacity]
+  const DrawCommand.beginOpacity({required this.alpha})
+      : x = 0,
+        y = 0,
+        w = 0,
+        h = 0,
+        color = 0,
+        content = '',
+        font = '',
+        size = 0,
+        src = '',
+        italic = false,
+        radius = 0,
+        type = DrawCommandType.beginOpacity;
+
+  const DrawCommand.endOpacity()
+      : x = 0,
+        y = 0,
+        w = 0,
+        h = 0,
+        color = 0,
+        content = '',
+        font = '',
+        size = 0,
+        src = '',
+        italic = false,
+        radius = 0,
+        alpha = 1,
+        type = DrawCommandType.endOpacity;
 }
 
 enum DrawCommandType {
   rect,
   text,
   image,
+  pushClip,
+  popClip,
+  beginOpacity,
+  endOpacity,
 } 
\ No newline at end of file
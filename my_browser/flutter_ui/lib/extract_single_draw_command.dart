@@ -24,6 +24,7 @@ DrawCommand extractSingleDrawCommand(Pointer<FFIDrawCommand> commandPtr) {
   final cmd = commandPtr.ref;
   switch (cmd.command_type) {
     case 0: // rect
+    case 4: // rounded rect, drawn square for now
       return DrawCommand.rect(
         x: cmd.x,
         y: cmd.y,
@@ -39,7 +40,39 @@ DrawCommand extractSingleDrawCommand(Pointer<FFIDrawCommand> commandPtr) {
         font: '', // font not present in FFI struct, set as empty or extend FFI struct
         size: cmd.font_size,
         color: _parseColor(_ffiStringToDart(cmd.color)),
+        italic: cmd.italic,
       );
+    case 5: // shadow, approximated by the offset box without blur
+      return DrawCommand.rect(
+        x: cmd.x + cmd.shadow_offset_x - cmd.shadow_spread,
+        y: cmd.y + cmd.shadow_offset_y - cmd.shadow_spread,
+        w: cmd.width + cmd.shadow_spread * 2,
+        h: cmd.height + cmd.shadow_spread * 2,
+        color: _parseColor(_ffiStringToDart(cmd.color)),
+      );
+    case 6: // linear gradient, approximated by its first color stop
+      final hasStops = cmd.gradient_stops != nullptr && cmd.gradient_stop_count > 0;
+      return DrawCommand.rect(
+        x: cmd.x,
+        y: cmd.y,
+        w: cmd.width,
+        h: cmd.height,
+        color: hasStops ? cmd.gradient_stops[0].color : 0x00000000,
+      );
+    case 7: // clip, a circle arrives as its bounding square with the radius as corner_radius
+      return DrawCommand.pushClip(
+        x: cmd.x,
+        y: cmd.y,
+        w: cmd.width,
+        h: cmd.height,
+        radius: cmd.corner_radius,
+      );
+    case 8: // end of the innermost clip
+      return const DrawCommand.popClip();
+    case 9: // opacity group
+      return DrawCommand.beginOpacity(alpha: cmd.opacity);
+    case 10: // end of the innermost opacity group
+      return const DrawCommand.endOpacity();
     case 3: // image
       return DrawCommand.image(
         x: cmd.x,
@@ -1,17 +1,17 @@
 import 'dart:ffi' as ffi;
 
 final class FFILayoutBox extends ffi.Struct {
-  @ffi.Double()
+  @ffi.Float()
   external double x;
-  @ffi.Double()
+  @ffi.Float()
   external double y;
-  @ffi.Double()
+  @ffi.Float()
   external double width;
-  @ffi.Double()
+  @ffi.Float()
   external double height;
-  @ffi.Double()
+  @ffi.Float()
   external double font_size;
-  @ffi.Double()
+  @ffi.Float()
   external double font_weight;
   external ffi.Pointer<ffi.Char> node_type;
   external ffi.Pointer<ffi.Char> text_content;
@@ -20,35 +20,37 @@ final class FFILayoutBox extends ffi.Struct {
   external ffi.Pointer<ffi.Char> font_family;
   external ffi.Pointer<ffi.Char> border_color;
   external ffi.Pointer<ffi.Char> text_align;
-  @ffi.Double()
+  @ffi.Float()
   external double margin_top;
-  @ffi.Double()
+  @ffi.Float()
   external double margin_right;
-  @ffi.Double()
+  @ffi.Float()
   external double margin_bottom;
-  @ffi.Double()
+  @ffi.Float()
   external double margin_left;
-  @ffi.Double()
+  @ffi.Float()
   external double padding_top;
-  @ffi.Double()
+  @ffi.Float()
   external double padding_right;
-  @ffi.Double()
+  @ffi.Float()
   external double padding_bottom;
-  @ffi.Double()
+  @ffi.Float()
   external double padding_left;
-  @ffi.Double()
+  @ffi.Float()
   external double border_width_top;
-  @ffi.Double()
+  @ffi.Float()
   external double border_width_right;
-  @ffi.Double()
+  @ffi.Float()
   external double border_width_bottom;
-  @ffi.Double()
+  @ffi.Float()
   external double border_width_left;
+  @ffi.Int32()
+  external int parent_index;
 }
 
 final class FFIDrawCommand extends ffi.Struct {
   @ffi.Int32()
-  external int command_type; // 0=rect, 1=text, 2=line, 3=image
+  external int command_type; // 0=rect, 1=text, 2=line, 3=image, 4=rounded_rect, 5=shadow, 6=linear_gradient, 7=clip, 8=end_clip, 9=opacity, 10=end_opacity
   @ffi.Float()
   external double x;
   @ffi.Float()
@@ -63,6 +65,44 @@ final class FFIDrawCommand extends ffi.Struct {
   external double font_size;
   @ffi.Float()
   external double font_weight;
+  @ffi.Bool()
+  external bool italic;
+  @ffi.Float()
+  external double corner_radius;
+  @ffi.Float()
+  external double shadow_offset_x;
+  @ffi.Float()
+  external double shadow_offset_y;
+  @ffi.Float()
+  external double shadow_blur;
+  @ffi.Float()
+  external double shadow_spread;
+  @ffi.Float()
+  external double a;
+  @ffi.Float()
+  external double b;
+  @ffi.Float()
+  external double c;
+  @ffi.Float()
+  external double d;
+  @ffi.Float()
+  external double e;
+  @ffi.Float()
+  external double f;
+  @ffi.Float()
+  external double gradient_angle;
+  external ffi.Pointer<FFIGradientStop> gradient_stops;
+  @ffi.Int32()
+  external int gradient_stop_count;
+  @ffi.Float()
+  external double opacity;
+}
+
+final class FFIGradientStop extends ffi.Struct {
+  @ffi.Uint32()
+  external int color; // 0xAARRGGBB
+  @ffi.Float()
+  external double offset;
 }
 
 final class FFIDrawCommandArray extends ffi.Struct {
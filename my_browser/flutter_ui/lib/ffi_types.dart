@@ -23,6 +23,7 @@ typedef GetLayoutBoxTextContentC = ffi.Pointer<ffi.Char> Function(ffi.Pointer<ff
 typedef GetLayoutBoxBackgroundColorC = ffi.Pointer<ffi.Char> Function(ffi.Pointer<ffi.Void>);
 typedef GetLayoutBoxColorC = ffi.Pointer<ffi.Char> Function(ffi.Pointer<ffi.Void>);
 typedef GetLayoutBoxFontSizeC = ffi.Float Function(ffi.Pointer<ffi.Void>);
+typedef GetLayoutBoxParentIndexC = ffi.Int32 Function(ffi.Pointer<ffi.Void>);
 typedef FreeLayoutBoxArrayC = ffi.Void Function(ffi.Pointer<ffi.Void>);
 typedef FreeLayoutBoxC = ffi.Void Function(ffi.Pointer<ffi.Void>);
 typedef FreeCStringC = ffi.Void Function(ffi.Pointer<ffi.Char>);
@@ -44,6 +45,9 @@ typedef GetLayoutBoxTextContentDart = ffi.Pointer<ffi.Char> Function(ffi.Pointer
 typedef GetLayoutBoxBackgroundColorDart = ffi.Pointer<ffi.Char> Function(ffi.Pointer<ffi.Void>);
 typedef GetLayoutBoxColorDart = ffi.Pointer<ffi.Char> Function(ffi.Pointer<ffi.Void>);
 typedef GetLayoutBoxFontSizeDart = double Function(ffi.Pointer<ffi.Void>);
+typedef GetLayoutBoxParentIndexDart = int Function(ffi.Pointer<ffi.Void>);
+typedef LayoutHitTestC = ffi.Int32 Function(ffi.Pointer<ffi.Void>, ffi.Float, ffi.Float);
+typedef LayoutHitTestDart = int Function(ffi.Pointer<ffi.Void>, double, double);
 typedef FreeLayoutBoxArrayDart = void Function(ffi.Pointer<ffi.Void>);
 typedef FreeLayoutBoxDart = void Function(ffi.Pointer<ffi.Void>);
 typedef FreeCStringDart = void Function(ffi.Pointer<ffi.Char>);
@@ -55,6 +59,8 @@ typedef GetLayoutBoxBatchC = ffi.Int32 Function(ffi.Pointer<ffi.Void>, ffi.Int32
 typedef GetLayoutBoxBatchDart = int Function(ffi.Pointer<ffi.Void>, int, int, ffi.Pointer<ffi.Pointer<FFILayoutBox>>);
 typedef GetLayoutBoxBatchEnhancedC = ffi.Int32 Function(ffi.Pointer<ffi.Void>, ffi.Int32, ffi.Int32, ffi.Pointer<ffi.Pointer<FFILayoutBox>>);
 typedef GetLayoutBoxBatchEnhancedDart = int Function(ffi.Pointer<ffi.Void>, int, int, ffi.Pointer<ffi.Pointer<FFILayoutBox>>);
+typedef GetDrawCommandBatchC = ffi.Int32 Function(ffi.Pointer<ffi.Void>, ffi.Int32, ffi.Int32, ffi.Pointer<ffi.Pointer<FFIDrawCommand>>);
+typedef GetDrawCommandBatchDart = int Function(ffi.Pointer<ffi.Void>, int, int, ffi.Pointer<ffi.Pointer<FFIDrawCommand>>);
 typedef ParseHtmlToDrawCommandsC = ffi.Pointer<ffi.Void> Function(ffi.Pointer<ffi.Char>);
 typedef ParseHtmlToDrawCommandsDart = ffi.Pointer<ffi.Void> Function(ffi.Pointer<ffi.Char>);
 typedef ParseUrlViaRustC = ffi.Pointer<ffi.Void> Function(ffi.Pointer<ffi.Char>);
@@ -128,7 +134,7 @@ typedef DomSetStyleCssTextDart = void Function(int, ffi.Pointer<ffi.Char>);
 // Event Handling API FFI typedefs
 typedef DomAddEventListenerC = ffi.Void Function(ffi.Uint32, ffi.Pointer<ffi.Char>, ffi.Uint32);
 typedef DomRemoveEventListenerC = ffi.Void Function(ffi.Uint32, ffi.Pointer<ffi.Char>, ffi.Uint32);
-typedef DomDispatchEventC = ffi.Void Function(ffi.Uint32, ffi.Pointer<ffi.Char>);
+typedef DomDispatchEventC = ffi.Bool Function(ffi.Uint32, ffi.Pointer<ffi.Char>, ffi.Bool);
 typedef DomAddEventListenerDart = void Function(int, ffi.Pointer<ffi.Char>, int);
 typedef DomRemoveEventListenerDart = void Function(int, ffi.Pointer<ffi.Char>, int);
-typedef DomDispatchEventDart = void Function(int, ffi.Pointer<ffi.Char>); 
\ No newline at end of file
+typedef DomDispatchEventDart = bool Function(int, ffi.Pointer<ffi.Char>, bool); 
\ No newline at end of file
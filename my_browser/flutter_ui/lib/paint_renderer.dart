@@ -158,6 +158,22 @@ class _PaintRendererState extends State<PaintRenderer> {
       case DrawCommandType.image:
         _drawImage(canvas, command);
         break;
+      case DrawCommandType.pushClip:
+        canvas.save();
+        canvas.clipRRect(RRect.fromRectAndRadius(
+          Rect.fromLTWH(command.x, command.y, command.w, command.h),
+          Radius.circular(command.radius),
+        ));
+        break;
+      case DrawCommandType.popClip:
+        canvas.restore();
+        break;
+      case DrawCommandType.beginOpacity:
+        canvas.saveLayer(null, Paint()..color = Color.fromRGBO(0, 0, 0, command.alpha));
+        break;
+      case DrawCommandType.endOpacity:
+        canvas.restore();
+        break;
     }
   }
 
@@ -177,6 +193,7 @@ class _PaintRendererState extends State<PaintRenderer> {
       color: Color(command.color),
       fontSize: command.size,
       fontFamily: command.font.isNotEmpty ? command.font : null,
+      fontStyle: command.italic ? FontStyle.italic : FontStyle.normal,
     );
     
     final textSpan = TextSpan(
@@ -0,0 +1,68 @@
+// Cooperative cancellation for parse/layout/fetch
+// Hosts keep a clone of the token and set it; the pipeline polls it at chunk and subtree boundaries
+
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::Notify;
+
+use crate::error::RiftError;
+
+/// Shared flag a host sets to abort an in-progress render
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<TokenState>);
+
+#[derive(Debug, Default)]
+struct TokenState {
+    cancelled: AtomicBool,
+    // Wakes fetches waiting in `run`
+    notify: Notify,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request cancellation; every clone of this token observes it
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::SeqCst);
+        self.0.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once cancellation has been requested
+    pub async fn cancelled(&self) {
+        loop {
+            // Registered before the check, so a `cancel` in between still wakes it
+            let notified = self.0.notify.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Drive `future` until it completes or the token is cancelled, e.g. a fetch from a
+    /// server that never answers
+    pub async fn run<F: Future>(&self, future: F) -> Result<F::Output, RiftError> {
+        tokio::select! {
+            biased;
+            _ = self.cancelled() => Err(RiftError::Cancelled),
+            output = future => Ok(output),
+        }
+    }
+
+    /// `Err(RiftError::Cancelled)` once cancellation has been requested
+    pub fn check(&self) -> Result<(), RiftError> {
+        if self.is_cancelled() {
+            Err(RiftError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
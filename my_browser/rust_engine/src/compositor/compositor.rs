@@ -1,6 +1,8 @@
 // compositor/compositor.rs
 
+use crate::dom::node::LayoutBox;
 use crate::paint::display_list::{DrawCommand, DisplayList};
+use crate::paint::painter::{is_ancestor_or_self, Painter};
 
 pub struct Compositor;
 
@@ -9,9 +11,115 @@ impl Compositor {
         Compositor
     }
 
-    // For now, just flatten the display list (no real compositing yet)
-    pub fn composite(&self, display_list: DisplayList) -> DisplayList {
-        // TODO: Implement real compositing (z-index, layers, etc.)
+    /// Paint layout boxes in stacking order, compositing each box with `opacity` below 1
+    /// together with its subtree: their commands sit between BeginOpacity and EndOpacity
+    /// so the host can render the group offscreen and blend it once
+    pub fn composite(&self, layout_boxes: &[LayoutBox]) -> DisplayList {
+        Painter::paint_groups(layout_boxes, true)
+    }
+
+    /// Paint layout boxes, promoting `will-change: transform|opacity` (and animated) boxes
+    /// to their own layer so repainting the rest of the page doesn't redraw them.
+    /// Each promoted box and the boxes inside it are wrapped in BeginLayer/EndLayer.
+    pub fn composite_layers(&self, layout_boxes: &[LayoutBox]) -> DisplayList {
+        let mut display_list = Vec::new();
+        let borders = Painter::border_segments(layout_boxes);
+        let text_descendants = Painter::text_descendants(layout_boxes);
+        let mut next_layer_id = 1; // 0 is the root layer
+        let mut index = 0;
+
+        while index < layout_boxes.len() {
+            let b = &layout_boxes[index];
+            if !Self::needs_own_layer(b) {
+                Painter::paint_box(layout_boxes, index, &borders, &text_descendants, &mut display_list);
+                index += 1;
+                continue;
+            }
+
+            // Descendants follow their ancestor in tree order, wherever they're drawn
+            let end = index + 1 + (index + 1..layout_boxes.len())
+                .take_while(|&inner| is_ancestor_or_self(layout_boxes, index, inner))
+                .count();
+
+            let id = next_layer_id;
+            next_layer_id += 1;
+            display_list.push(DrawCommand::BeginLayer { id, x: b.x, y: b.y, w: b.width, h: b.height });
+            for inner in index..end {
+                Painter::paint_box(layout_boxes, inner, &borders, &text_descendants, &mut display_list);
+            }
+            display_list.push(DrawCommand::EndLayer { id });
+            index = end;
+        }
+
         display_list
     }
-} 
\ No newline at end of file
+
+    /// Whether a box's `will-change` hint warrants a separate compositor layer
+    pub fn needs_own_layer(b: &LayoutBox) -> bool {
+        b.will_change
+            .split(',')
+            .any(|hint| matches!(hint.trim(), "transform" | "opacity" | "animation"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(x: f32, width: f32, will_change: &str) -> LayoutBox {
+        let mut b = LayoutBox::new();
+        b.x = x;
+        b.width = width;
+        b.height = 50.0;
+        b.background_color = "#ff0000".to_string();
+        b.will_change = will_change.to_string();
+        b
+    }
+
+    #[test]
+    fn test_will_change_box_gets_own_layer() {
+        let boxes = vec![filled(0.0, 100.0, ""), filled(100.0, 100.0, "transform"), filled(200.0, 100.0, "auto")];
+        let display_list = Compositor::new().composite_layers(&boxes);
+
+        assert_eq!(display_list.len(), 5);
+        assert!(matches!(display_list[0], DrawCommand::Rect { x, .. } if x == 0.0));
+        assert!(matches!(display_list[1], DrawCommand::BeginLayer { id: 1, x, .. } if x == 100.0));
+        assert!(matches!(display_list[2], DrawCommand::Rect { x, .. } if x == 100.0));
+        assert!(matches!(display_list[3], DrawCommand::EndLayer { id: 1 }));
+        assert!(matches!(display_list[4], DrawCommand::Rect { x, .. } if x == 200.0));
+    }
+
+    #[test]
+    fn test_layer_holds_descendants_not_overlapping_siblings() {
+        let mut overflowing = filled(50.0, 300.0, "");
+        overflowing.parent_index = Some(0);
+        let boxes = vec![filled(0.0, 100.0, "transform"), overflowing, filled(10.0, 20.0, "")];
+        let display_list = Compositor::new().composite_layers(&boxes);
+
+        let end = display_list.iter().position(|c| matches!(c, DrawCommand::EndLayer { .. })).unwrap();
+        let sibling = display_list.iter().position(|c| matches!(c, DrawCommand::Rect { x, .. } if *x == 10.0)).unwrap();
+        assert_eq!(end, 3, "the overflowing child is inside the layer");
+        assert!(sibling > end, "a sibling inside the layer's rect is not");
+    }
+
+    #[test]
+    fn test_opacity_group_brackets_subtree() {
+        let mut parser = crate::parser::html::HTMLParser::new(r#"<body><div style="opacity:0.5"><p style="background-color:red">a</p><section style="background-color:blue;opacity:0.5">b</section></div><footer style="background-color:green">c</footer></body>"#.to_string()).with_reuse();
+        let dom = parser.parse();
+        let boxes = crate::layout::layout::LayoutEngine::new(800.0, 600.0).layout(&dom, parser.arena().unwrap());
+        let display_list = Compositor::new().composite(&boxes);
+
+        let position = |matches: &dyn Fn(&DrawCommand) -> bool| display_list.iter().position(matches).unwrap();
+        let begin = position(&|c| matches!(c, DrawCommand::BeginOpacity { alpha } if *alpha == 0.5));
+        let end = display_list.iter().rposition(|c| matches!(c, DrawCommand::EndOpacity)).unwrap();
+        let red = position(&|c| matches!(c, DrawCommand::Rect { color: 0xffff0000, .. }));
+        let blue = position(&|c| matches!(c, DrawCommand::Rect { color: 0xff0000ff, .. }));
+        let green = position(&|c| matches!(c, DrawCommand::Rect { color: 0xff008000, .. }));
+        assert!(begin < red && red < end && begin < blue && blue < end, "both children are in the group");
+        // The group is a stacking context, so it paints after in-flow siblings
+        assert!(green < begin, "the following sibling is outside the group");
+        // The nested group sits inside the outer one
+        let groups: Vec<&DrawCommand> = display_list.iter().filter(|c| matches!(c, DrawCommand::BeginOpacity { .. } | DrawCommand::EndOpacity)).collect();
+        assert!(matches!(groups.as_slice(), [DrawCommand::BeginOpacity { .. }, DrawCommand::BeginOpacity { .. }, DrawCommand::EndOpacity, DrawCommand::EndOpacity]));
+    }
+}
@@ -0,0 +1,41 @@
+// Event propagation shared by host-side and script-side dispatch
+
+use crate::dom::node::DOMArena;
+
+/// The nodes an event dispatched at `target` visits, in order: the target itself, then, for a
+/// bubbling event, each ancestor up to the root. The path is fixed when dispatch starts, so
+/// listeners that move nodes don't change where the event goes.
+pub fn event_path(arena: &DOMArena, target: &str, bubbles: bool) -> Vec<String> {
+    let mut path = Vec::new();
+    let mut current = Some(target.to_string());
+    while let Some(id) = current {
+        let Some(node) = arena.get_node(&id) else { break };
+        current = if bubbles { node.lock().unwrap().parent.clone() } else { None };
+        path.push(id);
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::html::HTMLParser;
+
+    #[test]
+    fn test_event_path_runs_from_target_to_root() {
+        let mut parser = HTMLParser::new(r#"<body><form><input id="field"></form></body>"#.to_string()).with_reuse();
+        let dom = parser.parse();
+        let arena = parser.arena().unwrap();
+        let field = arena.nodes.values().find_map(|node| {
+            let node = node.lock().unwrap();
+            (node.attributes.get("id").map(String::as_str) == Some("field")).then(|| node.id.clone())
+        }).unwrap();
+
+        assert_eq!(event_path(arena, &field, false), vec![field.clone()]);
+        let path = event_path(arena, &field, true);
+        assert_eq!(path.first(), Some(&field));
+        assert_eq!(path.last(), Some(&dom.id));
+        let parent = arena.get_node(&field).unwrap().lock().unwrap().parent.clone();
+        assert_eq!(path.get(1), parent.as_ref());
+    }
+}
@@ -0,0 +1,97 @@
+// Form queries: lookups by `name` and the controls owned by a <form>
+
+use std::collections::HashMap;
+
+use crate::dom::node::{DOMArena, NodeType};
+
+/// Tags listed in `HTMLFormElement.elements`
+const LISTED_ELEMENTS: [&str; 7] = ["button", "fieldset", "input", "object", "output", "select", "textarea"];
+
+/// Ids of elements under `root_id` (inclusive) whose `name` attribute equals `name`, in tree order
+pub fn elements_by_name(root_id: &str, name: &str, arena: &DOMArena) -> Vec<String> {
+    let mut found = Vec::new();
+    walk(root_id, arena, &mut |id, tag, attributes| {
+        if tag.is_some() && attributes.get("name").map(String::as_str) == Some(name) {
+            found.push(id.to_string());
+        }
+    });
+    found
+}
+
+/// Ids of the form controls inside the `<form>` `form_id`, mirroring `HTMLFormElement.elements`.
+/// `<input type="image">` is excluded, as in browsers.
+pub fn form_elements(form_id: &str, arena: &DOMArena) -> Vec<String> {
+    let mut found = Vec::new();
+    walk(form_id, arena, &mut |id, tag, attributes| {
+        let Some(tag) = tag else { return };
+        let is_image_input = tag == "input"
+            && attributes.get("type").is_some_and(|t| t.eq_ignore_ascii_case("image"));
+        if id != form_id && LISTED_ELEMENTS.contains(&tag.as_str()) && !is_image_input {
+            found.push(id.to_string());
+        }
+    });
+    found
+}
+
+/// Pre-order walk passing each node's id, lowercased tag (None for non-elements) and attributes
+fn walk(
+    id: &str,
+    arena: &DOMArena,
+    visit: &mut dyn FnMut(&str, Option<String>, &HashMap<String, String>),
+) {
+    let Some(node) = arena.get_node(id) else { return };
+    let children = {
+        let node = node.lock().unwrap();
+        let tag = match &node.node_type {
+            NodeType::Element(tag) => Some(tag.to_lowercase()),
+            _ => None,
+        };
+        visit(&node.id, tag, &node.attributes);
+        node.children.clone()
+    };
+    for child_id in &children {
+        walk(child_id, arena, visit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::html::HTMLParser;
+
+    fn tag_and_name(arena: &DOMArena, id: &str) -> (String, String) {
+        let node = arena.get_node(id).unwrap();
+        let node = node.lock().unwrap();
+        let tag = match &node.node_type {
+            NodeType::Element(tag) => tag.clone(),
+            _ => String::new(),
+        };
+        (tag, node.attributes.get("name").cloned().unwrap_or_default())
+    }
+
+    #[test]
+    fn test_elements_by_name_and_form_elements() {
+        let html = r#"<body>
+            <form id="signup">
+                <input name="color" value="red"><input name="color" value="blue">
+                <div><select name="size"></select><textarea name="notes"></textarea></div>
+                <input type="image" name="submit">
+            </form>
+            <input name="outside">
+        </body>"#;
+        let mut parser = HTMLParser::new(html.to_string()).with_reuse();
+        let dom = parser.parse();
+        let arena = parser.arena().unwrap();
+
+        let colors = elements_by_name(&dom.id, "color", arena);
+        assert_eq!(colors.len(), 2);
+        assert!(colors.iter().all(|id| tag_and_name(arena, id) == ("input".to_string(), "color".to_string())));
+
+        let form_id = arena.nodes.values().find_map(|node| {
+            let node = node.lock().unwrap();
+            (node.attributes.get("id").map(String::as_str) == Some("signup")).then(|| node.id.clone())
+        }).unwrap();
+        let names: Vec<String> = form_elements(&form_id, arena).iter().map(|id| tag_and_name(arena, id).1).collect();
+        assert_eq!(names, vec!["color", "color", "size", "notes"]);
+    }
+}
@@ -0,0 +1,148 @@
+// Link extraction for crawlers and link-following hosts
+// Walks the DOM collecting <a>/<area>/<link> hrefs with their resolved target and link relations
+
+use crate::dom::node::{DOMArena, DOMNode, NodeType};
+
+/// A hyperlink found in the document
+#[derive(Debug, Clone, PartialEq)]
+pub struct Link {
+    pub href: String,
+    pub text: String,
+    /// Effective browsing context: the link's own `target`, else `<base target>`, else `_self`
+    pub target: String,
+    /// Lowercased `rel` tokens, e.g. `nofollow`, `canonical`, `alternate`
+    pub rel: Vec<String>,
+    /// Language of the linked resource (`hreflang`), empty if unspecified
+    pub hreflang: String,
+    /// Advisory MIME type of the linked resource (`type`), empty if unspecified
+    pub mime_type: String,
+}
+
+impl Link {
+    pub fn has_rel(&self, rel: &str) -> bool {
+        self.rel.iter().any(|r| r.eq_ignore_ascii_case(rel))
+    }
+
+    /// Crawlers should not follow or pass ranking through `rel="nofollow"` links
+    pub fn is_nofollow(&self) -> bool {
+        self.has_rel("nofollow")
+    }
+}
+
+/// Collect every link under `root` in document order
+pub fn extract_links(root: &DOMNode, arena: &DOMArena) -> Vec<Link> {
+    let base_target = find_base_target(root, arena);
+    let mut links = Vec::new();
+    collect_links(root, arena, base_target.as_deref(), &mut links);
+    links
+}
+
+/// Links marked `rel="nofollow"`
+pub fn rel_nofollow_links(links: &[Link]) -> Vec<&Link> {
+    links.iter().filter(|link| link.is_nofollow()).collect()
+}
+
+/// Links carrying the given `rel` token, e.g. `canonical` or `alternate`
+pub fn links_with_rel<'a>(links: &'a [Link], rel: &str) -> Vec<&'a Link> {
+    links.iter().filter(|link| link.has_rel(rel)).collect()
+}
+
+/// The `target` of the first `<base>` element that has one
+fn find_base_target(node: &DOMNode, arena: &DOMArena) -> Option<String> {
+    if let NodeType::Element(tag) = &node.node_type {
+        if tag.eq_ignore_ascii_case("base") {
+            if let Some(target) = node.attributes.get("target").filter(|t| !t.trim().is_empty()) {
+                return Some(target.trim().to_string());
+            }
+        }
+    }
+    node.children.iter()
+        .filter_map(|child_id| arena.get_node(child_id))
+        .find_map(|child| find_base_target(&child.lock().unwrap(), arena))
+}
+
+fn collect_links(node: &DOMNode, arena: &DOMArena, base_target: Option<&str>, links: &mut Vec<Link>) {
+    if let NodeType::Element(tag) = &node.node_type {
+        if tag.eq_ignore_ascii_case("a") || tag.eq_ignore_ascii_case("area") || tag.eq_ignore_ascii_case("link") {
+            if let Some(href) = node.attributes.get("href") {
+                let target = node.attributes.get("target")
+                    .map(|t| t.trim())
+                    .filter(|t| !t.is_empty())
+                    .or(base_target)
+                    .unwrap_or("_self");
+                links.push(Link {
+                    href: href.trim().to_string(),
+                    text: text_content(node, arena).split_whitespace().collect::<Vec<_>>().join(" "),
+                    target: target.to_string(),
+                    rel: node.attributes.get("rel")
+                        .map(|rel| rel.split_whitespace().map(|r| r.to_lowercase()).collect())
+                        .unwrap_or_default(),
+                    hreflang: attribute(node, "hreflang"),
+                    mime_type: attribute(node, "type"),
+                });
+            }
+        }
+    }
+    for child_id in &node.children {
+        if let Some(child) = arena.get_node(child_id) {
+            collect_links(&child.lock().unwrap(), arena, base_target, links);
+        }
+    }
+}
+
+fn attribute(node: &DOMNode, name: &str) -> String {
+    node.attributes.get(name).map(|value| value.trim().to_string()).unwrap_or_default()
+}
+
+fn text_content(node: &DOMNode, arena: &DOMArena) -> String {
+    match node.node_type {
+        NodeType::Text => node.text_content.clone(),
+        _ => node.children.iter()
+            .filter_map(|child_id| arena.get_node(child_id))
+            .map(|child| text_content(&child.lock().unwrap(), arena))
+            .collect::<Vec<_>>()
+            .join(" "),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::html::HTMLParser;
+
+    #[test]
+    fn test_links_inherit_base_target() {
+        let html = r#"<html><head><base target="_blank"></head><body>
+            <a href="/docs">Docs</a>
+            <a href="/home" target="_top">Home</a>
+        </body></html>"#;
+        let mut parser = HTMLParser::new(html.to_string()).with_reuse();
+        let dom = parser.parse();
+        let links = extract_links(&dom, parser.arena().unwrap());
+
+        assert_eq!(links.len(), 2);
+        assert_eq!((links[0].href.as_str(), links[0].text.as_str(), links[0].target.as_str()), ("/docs", "Docs", "_blank"));
+        assert_eq!(links[1].target, "_top");
+    }
+
+    #[test]
+    fn test_links_capture_rel_and_hreflang() {
+        let html = r#"<html><head><link rel="alternate" hreflang="fr" type="text/html" href="/fr/"></head><body>
+            <a href="/ads" rel="Sponsored NoFollow">Ad</a>
+            <a href="/about">About</a>
+        </body></html>"#;
+        let mut parser = HTMLParser::new(html.to_string()).with_reuse();
+        let dom = parser.parse();
+        let links = extract_links(&dom, parser.arena().unwrap());
+
+        assert_eq!(links.len(), 3);
+        assert_eq!(links[0].rel, vec!["alternate".to_string()]);
+        assert_eq!((links[0].hreflang.as_str(), links[0].mime_type.as_str()), ("fr", "text/html"));
+        assert_eq!(links_with_rel(&links, "alternate")[0].href, "/fr/");
+
+        let nofollow = rel_nofollow_links(&links);
+        assert_eq!(nofollow.len(), 1);
+        assert_eq!(nofollow[0].href, "/ads");
+        assert!(!links[2].is_nofollow());
+    }
+}
@@ -1 +1,4 @@
-pub mod node; 
\ No newline at end of file
+pub mod node; 
+pub mod links;
+pub mod forms;
+pub mod events;
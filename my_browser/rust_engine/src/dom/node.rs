@@ -1,8 +1,10 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::os::raw::c_char;
 use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::io::Write;
+use serde::{Deserialize, Serialize};
+use crate::style::selector::{matches_selector, split_selector_list};
 
 pub(crate) static NODE_ID_COUNTER: AtomicU32 = AtomicU32::new(1);
 
@@ -36,9 +38,11 @@ pub struct FFILayoutBox {
     pub border_width_right: f32,
     pub border_width_bottom: f32,
     pub border_width_left: f32,
+    // Array index of the containing box, -1 for the root
+    pub parent_index: i32,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LayoutBox {
     pub x: f32,
     pub y: f32,
@@ -47,6 +51,7 @@ pub struct LayoutBox {
     pub node_type: String,
     pub text_content: String,
     pub background_color: String,
+    pub background_image: String,
     pub color: String,
     pub font_size: f32,
     pub font_family: String,
@@ -55,6 +60,7 @@ pub struct LayoutBox {
     pub padding: BoxValues,
     pub margin: BoxValues,
     pub font_weight: f32,
+    pub font_style: String,
     pub text_align: String,
     // Flexbox properties
     pub flex_direction: String,
@@ -75,6 +81,34 @@ pub struct LayoutBox {
     pub text_overflow: String,
     // Theme support
     pub color_scheme: String,
+    // Table borders
+    pub border_collapse: String,
+    pub border_spacing: f32,
+    // Hit-testing
+    pub pointer_events: String,
+    // Source DOM node
+    pub node_id: String,
+    pub overflow: String,
+    // Compositing
+    pub will_change: String,
+    // Positioning
+    pub position: String,
+    pub z_index: ZIndex,
+    pub opacity: f32,
+    pub transform: String,
+    pub transform_origin: String,
+    pub inset: Inset,
+    // Index of the nearest ancestor's box, assigned after layout
+    pub parent_index: Option<usize>,
+    // Placeholder for iframe/object/embed content the host draws itself
+    pub embedded: bool,
+    // `clip-path`, e.g. `inset(10px)` or `circle(50%)`
+    pub clip_path: String,
+    // Hidden boxes keep their space but aren't painted
+    pub visibility: String,
+    // Decoration, resolved into draw commands by the paint stage
+    pub border_radius: String,
+    pub box_shadow: String,
 }
 
 #[derive(Debug, Clone)]
@@ -87,6 +121,8 @@ pub struct DOMNode {
     pub attributes: HashMap<String, String>,
     pub styles: StyleMap,
     pub event_listeners: HashMap<String, Vec<u32>>,
+    /// Interactive states the host has set, e.g. `hover`, `active`, `focus`
+    pub pseudo_states: HashSet<String>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -167,6 +203,14 @@ pub struct StyleMap {
     pub word_wrap: String,
     pub white_space: String,
     pub text_overflow: String,
+    pub content: String,
+    pub counter_reset: String,
+    pub counter_increment: String,
+    pub border_top_width: String,
+    pub border_right_width: String,
+    pub border_bottom_width: String,
+    pub border_left_width: String,
+    pub clip_path: String,
     pub overflow: String,
     pub overflow_x: String,
     pub overflow_y: String,
@@ -207,9 +251,15 @@ pub struct StyleMap {
     pub animation: String,
     pub box_shadow: String,
     pub text_shadow: String,
+    pub border_collapse: String,
+    pub border_spacing: String,
+    pub will_change: String,
+    pub line_clamp: String,
+    // Properties without a field above, e.g. custom `--*` properties, keyed by name
+    pub extra: HashMap<String, String>,
 }
 
-#[derive(Debug, Clone, Default, PartialEq)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct BoxValues {
     pub top: f32,
     pub right: f32,
@@ -217,6 +267,51 @@ pub struct BoxValues {
     pub left: f32,
 }
 
+/// Resolved `top`/`right`/`bottom`/`left` offsets in px; `None` is `auto`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Inset {
+    pub top: Option<f32>,
+    pub right: Option<f32>,
+    pub bottom: Option<f32>,
+    pub left: Option<f32>,
+}
+
+impl Inset {
+    pub fn from_styles(styles: &StyleMap) -> Self {
+        let offset = |value: &str| value.trim().trim_end_matches("px").parse().ok();
+        Inset {
+            top: offset(&styles.top),
+            right: offset(&styles.right),
+            bottom: offset(&styles.bottom),
+            left: offset(&styles.left),
+        }
+    }
+}
+
+/// Computed `z-index`: `auto` paints with the parent's stacking context,
+/// an integer on a positioned box starts a new one
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ZIndex {
+    #[default]
+    Auto,
+    Index(i32),
+}
+
+impl ZIndex {
+    /// Parse a `z-index` value; anything that isn't an integer is `auto`
+    pub fn parse(value: &str) -> Self {
+        value.trim().parse().map(ZIndex::Index).unwrap_or(ZIndex::Auto)
+    }
+
+    /// Stack level used for ordering, with `auto` treated as 0
+    pub fn level(&self) -> i32 {
+        match self {
+            ZIndex::Auto => 0,
+            ZIndex::Index(z) => *z,
+        }
+    }
+}
+
 impl Default for StyleMap {
     fn default() -> Self {
         Self {
@@ -240,7 +335,7 @@ impl Default for StyleMap {
             align_content: "stretch".to_string(),
             flex_grow: "0".to_string(),
             flex_shrink: "1".to_string(),
-            flex_basis: "0%".to_string(),
+            flex_basis: "auto".to_string(),
             order: "0".to_string(),
             grid_template_columns: "auto".to_string(),
             grid_template_rows: "auto".to_string(),
@@ -252,6 +347,14 @@ impl Default for StyleMap {
             word_wrap: "normal".to_string(),
             white_space: "normal".to_string(),
             text_overflow: "clip".to_string(),
+            content: "none".to_string(),
+            counter_reset: "none".to_string(),
+            counter_increment: "none".to_string(),
+            border_top_width: "0".to_string(),
+            border_right_width: "0".to_string(),
+            border_bottom_width: "0".to_string(),
+            border_left_width: "0".to_string(),
+            clip_path: "none".to_string(),
             color_scheme: "light".to_string(),
             position: "static".to_string(),
             top: "auto".to_string(),
@@ -314,13 +417,183 @@ impl Default for StyleMap {
             animation: "none".to_string(),
             box_shadow: "none".to_string(),
             text_shadow: "none".to_string(),
+            border_collapse: "separate".to_string(),
+            border_spacing: "0".to_string(),
+            will_change: "auto".to_string(),
+            line_clamp: "none".to_string(),
+            extra: HashMap::new(),
+        }
+    }
+}
+
+/// Split on whitespace, keeping functions such as `rgb(0, 0, 255)` whole
+pub(crate) fn split_outside_parens(value: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let (mut depth, mut start) = (0usize, None);
+    for (i, c) in value.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            c if c.is_whitespace() && depth == 0 => {
+                if let Some(begin) = start.take() {
+                    tokens.push(&value[begin..i]);
+                }
+                continue;
+            }
+            _ => {}
+        }
+        start.get_or_insert(i);
+    }
+    tokens.extend(start.map(|begin| &value[begin..]));
+    tokens
+}
+
+/// Split at the commas that aren't inside `(...)` or `[...]`, trimming each part; used for
+/// selector lists, gradient arguments and layered values such as `box-shadow`
+pub(crate) fn split_top_level_commas(value: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut depth, mut start) = (0usize, 0);
+    for (i, c) in value.char_indices() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                parts.push(value[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
         }
     }
+    parts.push(value[start..].trim());
+    parts
+}
+
+/// A `thin`/`medium`/`thick` border width as browsers resolve it; other values pass through
+pub fn border_width_length(value: &str) -> String {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "thin" => "1px".to_string(),
+        "medium" => "3px".to_string(),
+        "thick" => "5px".to_string(),
+        _ => value.to_string(),
+    }
+}
+
+/// Split a 1-4 value box shorthand into top, right, bottom, left
+pub(crate) fn box_sides(value: &str) -> Option<[String; 4]> {
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    let [top, right, bottom, left] = match parts.as_slice() {
+        [all] => [all, all, all, all],
+        [vertical, horizontal] => [vertical, horizontal, vertical, horizontal],
+        [top, horizontal, bottom] => [top, horizontal, bottom, horizontal],
+        [top, right, bottom, left] => [top, right, bottom, left],
+        _ => return None,
+    };
+    Some([top.to_string(), right.to_string(), bottom.to_string(), left.to_string()])
 }
 
 impl StyleMap {
+    /// Expand the `inset` shorthand (1-4 values, clockwise from top like `margin`)
+    pub fn set_inset(&mut self, value: &str) {
+        let Some([top, right, bottom, left]) = box_sides(value) else { return };
+        self.top = top;
+        self.right = right;
+        self.bottom = bottom;
+        self.left = left;
+    }
+
+    /// Set `margin` and expand it into the four `margin-*` longhands
+    pub fn set_margin(&mut self, value: &str) {
+        self.margin = value.to_string();
+        if let Some([top, right, bottom, left]) = box_sides(value) {
+            self.margin_top = top;
+            self.margin_right = right;
+            self.margin_bottom = bottom;
+            self.margin_left = left;
+        }
+    }
+
+    /// Set `padding` and expand it into the four `padding-*` longhands
+    pub fn set_padding(&mut self, value: &str) {
+        self.padding = value.to_string();
+        if let Some([top, right, bottom, left]) = box_sides(value) {
+            self.padding_top = top;
+            self.padding_right = right;
+            self.padding_bottom = bottom;
+            self.padding_left = left;
+        }
+    }
+
+    /// Set `border-width` and expand it into the four `border-*-width` longhands
+    pub fn set_border_width(&mut self, value: &str) {
+        self.border_width = value.to_string();
+        if let Some([top, right, bottom, left]) = box_sides(value) {
+            self.border_top_width = border_width_length(&top);
+            self.border_right_width = border_width_length(&right);
+            self.border_bottom_width = border_width_length(&bottom);
+            self.border_left_width = border_width_length(&left);
+        }
+    }
+
+    /// Expand `border: <width> <style> <color>` (any order, each optional) into
+    /// `border-width`/`-style`/`-color`, the per-side widths and the `border-<side>` shorthands.
+    /// Omitted parts reset to their initial values, as in CSS.
+    pub fn set_border(&mut self, value: &str) {
+        const STYLES: [&str; 10] = ["none", "hidden", "dotted", "dashed", "solid", "double", "groove", "ridge", "inset", "outset"];
+        let mut width = "medium".to_string();
+        let mut style = "none".to_string();
+        let mut color = Vec::new();
+        for token in split_outside_parens(value) {
+            let lower = token.to_lowercase();
+            if matches!(lower.as_str(), "thin" | "medium" | "thick") || lower.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+                width = token.to_string();
+            } else if STYLES.contains(&lower.as_str()) {
+                style = lower;
+            } else {
+                color.push(token);
+            }
+        }
+        let width = border_width_length(&width);
+
+        self.border = value.to_string();
+        self.border_top = value.to_string();
+        self.border_right = value.to_string();
+        self.border_bottom = value.to_string();
+        self.border_left = value.to_string();
+        self.border_style = style;
+        self.border_color = if color.is_empty() { "currentcolor".to_string() } else { color.join(" ") };
+        self.set_border_width(&width);
+    }
+
+    /// Set `flex` and expand it into `flex-grow`/`-shrink`/`-basis`. A lone number is the
+    /// grow factor with a `0%` basis, as in CSS.
+    pub fn set_flex(&mut self, value: &str) {
+        self.flex = value.to_string();
+        let (grow, shrink, basis) = match value.trim().to_lowercase().as_str() {
+            "none" => ("0".to_string(), "0".to_string(), "auto".to_string()),
+            "auto" => ("1".to_string(), "1".to_string(), "auto".to_string()),
+            "initial" => ("0".to_string(), "1".to_string(), "auto".to_string()),
+            _ => {
+                let mut factors = Vec::new();
+                let mut basis = None;
+                for token in value.split_whitespace() {
+                    if factors.len() < 2 && token.parse::<f32>().is_ok() {
+                        factors.push(token.to_string());
+                    } else {
+                        basis = Some(token.to_string());
+                    }
+                }
+                let Some(grow) = factors.first().cloned() else { return };
+                (grow, factors.get(1).cloned().unwrap_or_else(|| "1".to_string()), basis.unwrap_or_else(|| "0%".to_string()))
+            }
+        };
+        self.flex_grow = grow;
+        self.flex_shrink = shrink;
+        self.flex_basis = basis;
+    }
+
     pub fn set_property(&mut self, property: &str, value: &str) {
         match property {
+            "inset" => self.set_inset(value),
             "display" => self.display = value.to_string(),
             "width" => self.width = value.to_string(),
             "height" => self.height = value.to_string(),
@@ -328,10 +601,10 @@ impl StyleMap {
             "color" => self.color = value.to_string(),
             "font-size" => self.font_size = value.to_string(),
             "font-family" => self.font_family = value.to_string(),
-            "border-width" => self.border_width = value.to_string(),
+            "border-width" => self.set_border_width(value),
             "border-color" => self.border_color = value.to_string(),
-            "padding" => self.padding = value.to_string(),
-            "margin" => self.margin = value.to_string(),
+            "padding" => self.set_padding(value),
+            "margin" => self.set_margin(value),
             "font-weight" => self.font_weight = value.to_string(),
             "text-align" => self.text_align = value.to_string(),
             "position" => self.position = value.to_string(),
@@ -352,7 +625,7 @@ impl StyleMap {
             "letter-spacing" => self.letter_spacing = value.to_string(),
             "word-spacing" => self.word_spacing = value.to_string(),
             "border-style" => self.border_style = value.to_string(),
-            "border" => self.border = value.to_string(),
+            "border" => self.set_border(value),
             "border-radius" => self.border_radius = value.to_string(),
             "padding-top" => self.padding_top = value.to_string(),
             "padding-right" => self.padding_right = value.to_string(),
@@ -381,6 +654,14 @@ impl StyleMap {
             "word-wrap" => self.word_wrap = value.to_string(),
             "white-space" => self.white_space = value.to_string(),
             "text-overflow" => self.text_overflow = value.to_string(),
+            "content" => self.content = value.to_string(),
+            "counter-reset" => self.counter_reset = value.to_string(),
+            "counter-increment" => self.counter_increment = value.to_string(),
+            "border-top-width" => self.border_top_width = border_width_length(value),
+            "border-right-width" => self.border_right_width = border_width_length(value),
+            "border-bottom-width" => self.border_bottom_width = border_width_length(value),
+            "border-left-width" => self.border_left_width = border_width_length(value),
+            "clip-path" => self.clip_path = value.to_string(),
             "overflow" => self.overflow = value.to_string(),
             "overflow-x" => self.overflow_x = value.to_string(),
             "overflow-y" => self.overflow_y = value.to_string(),
@@ -408,16 +689,21 @@ impl StyleMap {
             "outline-width" => self.outline_width = value.to_string(),
             "outline-color" => self.outline_color = value.to_string(),
             "outline-style" => self.outline_style = value.to_string(),
-            "flex" => self.flex = value.to_string(),
+            "flex" => self.set_flex(value),
             "grid" => self.grid = value.to_string(),
             "transition" => self.transition = value.to_string(),
             "animation" => self.animation = value.to_string(),
             "box-shadow" => self.box_shadow = value.to_string(),
             "text-shadow" => self.text_shadow = value.to_string(),
+            "border-collapse" => self.border_collapse = value.to_string(),
+            "border-spacing" => self.border_spacing = value.to_string(),
+            "will-change" => self.will_change = value.to_string(),
+            "line-clamp" | "-webkit-line-clamp" => self.line_clamp = value.to_string(),
+            _ if value.is_empty() => {
+                self.extra.remove(property);
+            }
             _ => {
-                // For unknown properties, we could store them in a generic map
-                // For now, just ignore them
-                println!("[CSS] Unknown property: {} = {}", property, value);
+                self.extra.insert(property.to_string(), value.to_string());
             }
         }
     }
@@ -483,6 +769,14 @@ impl StyleMap {
         if !other.word_wrap.is_empty() { self.word_wrap = other.word_wrap.clone(); }
         if !other.white_space.is_empty() { self.white_space = other.white_space.clone(); }
         if !other.text_overflow.is_empty() { self.text_overflow = other.text_overflow.clone(); }
+        if !other.content.is_empty() { self.content = other.content.clone(); }
+        if !other.counter_reset.is_empty() { self.counter_reset = other.counter_reset.clone(); }
+        if !other.counter_increment.is_empty() { self.counter_increment = other.counter_increment.clone(); }
+        if !other.border_top_width.is_empty() { self.border_top_width = other.border_top_width.clone(); }
+        if !other.border_right_width.is_empty() { self.border_right_width = other.border_right_width.clone(); }
+        if !other.border_bottom_width.is_empty() { self.border_bottom_width = other.border_bottom_width.clone(); }
+        if !other.border_left_width.is_empty() { self.border_left_width = other.border_left_width.clone(); }
+        if !other.clip_path.is_empty() { self.clip_path = other.clip_path.clone(); }
         if !other.overflow.is_empty() { self.overflow = other.overflow.clone(); }
         if !other.overflow_x.is_empty() { self.overflow_x = other.overflow_x.clone(); }
         if !other.overflow_y.is_empty() { self.overflow_y = other.overflow_y.clone(); }
@@ -517,6 +811,13 @@ impl StyleMap {
         if !other.animation.is_empty() { self.animation = other.animation.clone(); }
         if !other.box_shadow.is_empty() { self.box_shadow = other.box_shadow.clone(); }
         if !other.text_shadow.is_empty() { self.text_shadow = other.text_shadow.clone(); }
+        if !other.border_collapse.is_empty() { self.border_collapse = other.border_collapse.clone(); }
+        if !other.border_spacing.is_empty() { self.border_spacing = other.border_spacing.clone(); }
+        if !other.will_change.is_empty() { self.will_change = other.will_change.clone(); }
+        if !other.line_clamp.is_empty() { self.line_clamp = other.line_clamp.clone(); }
+        for (property, value) in other.extra.iter().filter(|(_, value)| !value.is_empty()) {
+            self.extra.insert(property.clone(), value.clone());
+        }
     }
 
     pub fn get_property(&self, property: &str) -> Option<&str> {
@@ -581,6 +882,14 @@ impl StyleMap {
             "word-wrap" => Some(&self.word_wrap),
             "white-space" => Some(&self.white_space),
             "text-overflow" => Some(&self.text_overflow),
+            "content" => Some(&self.content),
+            "counter-reset" => Some(&self.counter_reset),
+            "counter-increment" => Some(&self.counter_increment),
+            "border-top-width" => Some(&self.border_top_width),
+            "border-right-width" => Some(&self.border_right_width),
+            "border-bottom-width" => Some(&self.border_bottom_width),
+            "border-left-width" => Some(&self.border_left_width),
+            "clip-path" => Some(&self.clip_path),
             "overflow" => Some(&self.overflow),
             "overflow-x" => Some(&self.overflow_x),
             "overflow-y" => Some(&self.overflow_y),
@@ -614,7 +923,11 @@ impl StyleMap {
             "animation" => Some(&self.animation),
             "box-shadow" => Some(&self.box_shadow),
             "text-shadow" => Some(&self.text_shadow),
-            _ => None,
+            "border-collapse" => Some(&self.border_collapse),
+            "border-spacing" => Some(&self.border_spacing),
+            "will-change" => Some(&self.will_change),
+            "line-clamp" => Some(&self.line_clamp),
+            _ => self.extra.get(property).map(String::as_str),
         }
     }
 
@@ -683,6 +996,14 @@ impl StyleMap {
         self.word_wrap.clear();
         self.white_space.clear();
         self.text_overflow.clear();
+        self.content.clear();
+        self.counter_reset.clear();
+        self.counter_increment.clear();
+        self.border_top_width.clear();
+        self.border_right_width.clear();
+        self.border_bottom_width.clear();
+        self.border_left_width.clear();
+        self.clip_path.clear();
         self.overflow.clear();
         self.overflow_x.clear();
         self.overflow_y.clear();
@@ -716,6 +1037,11 @@ impl StyleMap {
         self.animation.clear();
         self.box_shadow.clear();
         self.text_shadow.clear();
+        self.border_collapse.clear();
+        self.border_spacing.clear();
+        self.will_change.clear();
+        self.line_clamp.clear();
+        self.extra.clear();
     }
 }
 
@@ -729,6 +1055,7 @@ impl LayoutBox {
             node_type: String::new(),
             text_content: String::new(),
             background_color: "transparent".to_string(),
+            background_image: "none".to_string(),
             color: "black".to_string(),
             font_size: 16.0,
             font_family: "Arial".to_string(),
@@ -753,6 +1080,25 @@ impl LayoutBox {
             white_space: String::new(),
             text_overflow: String::new(),
             color_scheme: String::new(),
+            border_collapse: String::new(),
+            border_spacing: 0.0,
+            pointer_events: String::new(),
+            node_id: String::new(),
+            overflow: String::new(),
+            will_change: String::new(),
+            position: String::new(),
+            z_index: ZIndex::Auto,
+            opacity: 1.0,
+            transform: String::new(),
+            transform_origin: "50% 50%".to_string(),
+            parent_index: None,
+            embedded: false,
+            inset: Inset::default(),
+            font_style: "normal".to_string(),
+            clip_path: "none".to_string(),
+            visibility: "visible".to_string(),
+            border_radius: "0".to_string(),
+            box_shadow: "none".to_string(),
         }
     }
 
@@ -813,6 +1159,7 @@ impl LayoutBox {
             border_width_right: self.border_width.right,
             border_width_bottom: self.border_width.bottom,
             border_width_left: self.border_width.left,
+            parent_index: self.parent_index.map_or(-1, |index| index as i32),
         }
     }
 }
@@ -829,6 +1176,7 @@ impl DOMNode {
             attributes: HashMap::new(),
             styles: StyleMap::default(),
             event_listeners: HashMap::new(),
+            pseudo_states: HashSet::new(),
         }
     }
 
@@ -901,18 +1249,84 @@ impl DOMNode {
         None
     }
 
+    /// Collect the descendants of this node (not the node itself) matching any selector in the
+    /// comma-separated `selector` list, in document order and each node once
     pub fn find_elements_by_selector_arena<'a>(&'a self, selector: &str, results: &mut Vec<Arc<Mutex<DOMNode>>>, arena: &'a DOMArena) {
-        // Example: only tag selector for now
-        if let NodeType::Element(ref t) = self.node_type {
-            if t == selector {
-                if let Some(node) = arena.get_node(&self.id) {
-                    results.push(node);
+        let selectors = split_selector_list(selector);
+        if !selectors.is_empty() {
+            self.collect_matching_descendants(&selectors, results, arena);
+        }
+    }
+
+    fn collect_matching_descendants(&self, selectors: &[&str], results: &mut Vec<Arc<Mutex<DOMNode>>>, arena: &DOMArena) {
+        for child_id in &self.children {
+            if let Some(child) = arena.get_node(child_id) {
+                // Clone: matching the child's descendants locks their ancestors, this child included
+                let child = child.lock().unwrap().clone();
+                if selectors.iter().any(|selector| matches_selector(&child, selector, arena)) {
+                    if let Some(node) = arena.get_node(&child.id) {
+                        results.push(node);
+                    }
                 }
+                child.collect_matching_descendants(selectors, results, arena);
             }
         }
+    }
+
+    /// Whether this element matches any selector in the comma-separated `selector` list
+    pub fn matches(&self, selector: &str, arena: &DOMArena) -> bool {
+        split_selector_list(selector).iter().any(|selector| matches_selector(self, selector, arena))
+    }
+
+    /// This node or its nearest ancestor matching `selector`
+    pub fn closest(&self, selector: &str, arena: &DOMArena) -> Option<Arc<Mutex<DOMNode>>> {
+        if self.matches(selector, arena) {
+            return arena.get_node(&self.id);
+        }
+        let mut current = self.parent.clone();
+        while let Some(id) = current {
+            let node = arena.get_node(&id)?;
+            // Clone: matching locks the ancestors of the candidate
+            let ancestor = node.lock().unwrap().clone();
+            if ancestor.matches(selector, arena) {
+                return Some(node);
+            }
+            current = ancestor.parent;
+        }
+        None
+    }
+
+    /// Descendant elements having every class in the whitespace-separated `names`, in tree order
+    pub fn get_elements_by_class_name(&self, names: &str, arena: &DOMArena) -> Vec<Arc<Mutex<DOMNode>>> {
+        let wanted: Vec<&str> = names.split_whitespace().collect();
+        let mut results = Vec::new();
+        if !wanted.is_empty() {
+            self.collect_descendants(&|node: &DOMNode| {
+                let classes: Vec<&str> = node.attributes.get("class").map(|c| c.split_whitespace().collect()).unwrap_or_default();
+                matches!(node.node_type, NodeType::Element(_)) && wanted.iter().all(|name| classes.contains(name))
+            }, &mut results, arena);
+        }
+        results
+    }
+
+    /// Descendant elements with tag `tag` (case-insensitive, `*` for all), in tree order
+    pub fn get_elements_by_tag_name(&self, tag: &str, arena: &DOMArena) -> Vec<Arc<Mutex<DOMNode>>> {
+        let mut results = Vec::new();
+        self.collect_descendants(&|node: &DOMNode| match &node.node_type {
+            NodeType::Element(t) => tag == "*" || t.eq_ignore_ascii_case(tag),
+            _ => false,
+        }, &mut results, arena);
+        results
+    }
+
+    fn collect_descendants(&self, predicate: &dyn Fn(&DOMNode) -> bool, results: &mut Vec<Arc<Mutex<DOMNode>>>, arena: &DOMArena) {
         for child_id in &self.children {
             if let Some(child) = arena.get_node(child_id) {
-                child.lock().unwrap().find_elements_by_selector_arena(selector, results, arena);
+                let matched = predicate(&child.lock().unwrap());
+                if matched {
+                    results.push(child.clone());
+                }
+                child.lock().unwrap().collect_descendants(predicate, results, arena);
             }
         }
     }
@@ -954,7 +1368,7 @@ impl DOMNode {
     }
 
     /// Helper method to find element by ID (immutable reference)
-    fn find_element_by_id_ref(&self, id: &str, arena: &DOMArena) -> Option<String> {
+    pub(crate) fn find_element_by_id_ref(&self, id: &str, arena: &DOMArena) -> Option<String> {
         if let Some(node_id) = self.attributes.get("id") {
             if node_id == id {
                 return Some(self.id.clone());
@@ -1026,6 +1440,44 @@ impl DOMArena {
     pub fn remove_node(&mut self, id: &str) -> Option<Arc<Mutex<DOMNode>>> {
         self.nodes.remove(id)
     }
+
+    /// Drop all nodes but keep the allocated capacity for reuse
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+    }
+
+    /// Copy every node so the arena can later be rolled back with `restore`
+    pub fn snapshot(&self) -> ArenaSnapshot {
+        let nodes = self.nodes.iter()
+            .map(|(id, node)| (id.clone(), node.lock().unwrap().clone()))
+            .collect();
+        ArenaSnapshot { nodes }
+    }
+
+    /// Return the arena to the state captured by `snapshot`.
+    /// Nodes get fresh `Arc`s, so handles obtained before the restore can't alter the restored tree.
+    pub fn restore(&mut self, snapshot: &ArenaSnapshot) {
+        self.nodes.clear();
+        for (id, node) in &snapshot.nodes {
+            self.nodes.insert(id.clone(), Arc::new(Mutex::new(node.clone())));
+        }
+    }
+}
+
+/// Point-in-time copy of a `DOMArena`, including node ids and parent/child links
+#[derive(Debug, Clone)]
+pub struct ArenaSnapshot {
+    nodes: HashMap<String, DOMNode>,
+}
+
+impl ArenaSnapshot {
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
 }
 
 // Deep clone utility for DOMNode
@@ -1045,6 +1497,98 @@ impl DOMNode {
             }
         }).collect();
         clone.event_listeners = HashMap::new();
+        clone.pseudo_states = HashSet::new();
         clone
     }
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn element(arena: &mut DOMArena, tag: &str, parent: Option<&str>) -> String {
+        let mut node = DOMNode::new(NodeType::Element(tag.to_string()));
+        node.parent = parent.map(str::to_string);
+        let id = node.id.clone();
+        arena.add_node(node);
+        if let Some(parent) = parent {
+            arena.get_node(parent).unwrap().lock().unwrap().children.push(id.clone());
+        }
+        id
+    }
+
+    fn describe(arena: &DOMArena) -> Vec<(String, Option<String>, Vec<String>, Vec<(String, String)>)> {
+        let mut nodes: Vec<_> = arena.nodes.values().map(|node| {
+            let node = node.lock().unwrap();
+            let mut attributes: Vec<_> = node.attributes.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+            attributes.sort();
+            (node.id.clone(), node.parent.clone(), node.children.clone(), attributes)
+        }).collect();
+        nodes.sort();
+        nodes
+    }
+
+    #[test]
+    fn test_unknown_properties_round_trip() {
+        let mut styles = StyleMap::default();
+        styles.set_property("--x", "1");
+        styles.set_property("scroll-snap-type", "x mandatory");
+        assert_eq!(styles.get_property("--x"), Some("1"));
+        assert_eq!(styles.get_property("scroll-snap-type"), Some("x mandatory"));
+
+        styles.remove_property("--x");
+        assert_eq!(styles.get_property("--x"), None);
+        styles.clear();
+        assert_eq!(styles.get_property("scroll-snap-type"), None);
+    }
+
+    #[test]
+    fn test_snapshot_restore_rolls_back_mutations() {
+        let mut arena = DOMArena::new();
+        let root = element(&mut arena, "div", None);
+        let child = element(&mut arena, "p", Some(&root));
+        arena.get_node(&child).unwrap().lock().unwrap().attributes.insert("class".to_string(), "intro".to_string());
+
+        let before = describe(&arena);
+        let snapshot = arena.snapshot();
+        let stale = arena.get_node(&child).unwrap();
+
+        element(&mut arena, "span", Some(&child));
+        arena.get_node(&child).unwrap().lock().unwrap().attributes.insert("class".to_string(), "changed".to_string());
+        arena.get_node(&root).unwrap().lock().unwrap().children.clear();
+        arena.remove_node(&child);
+
+        arena.restore(&snapshot);
+        assert_eq!(describe(&arena), before);
+
+        // Handles from before the restore no longer reach into the arena
+        stale.lock().unwrap().attributes.insert("id".to_string(), "leak".to_string());
+        assert_eq!(describe(&arena), before);
+    }
+
+    #[test]
+    fn test_box_shorthands_expand_to_longhands() {
+        let sides = |s: &StyleMap| [s.padding_top.clone(), s.padding_right.clone(), s.padding_bottom.clone(), s.padding_left.clone()];
+        let mut styles = StyleMap::default();
+        styles.set_property("padding", "5px");
+        assert_eq!(sides(&styles), ["5px", "5px", "5px", "5px"]);
+        styles.set_property("padding", "5px 10px");
+        assert_eq!(sides(&styles), ["5px", "10px", "5px", "10px"]);
+        styles.set_property("padding", "1px 2px 3px");
+        assert_eq!(sides(&styles), ["1px", "2px", "3px", "2px"]);
+        styles.set_property("padding", "1px 2px 3px 4px");
+        assert_eq!(sides(&styles), ["1px", "2px", "3px", "4px"]);
+        assert_eq!(styles.get_property("padding-left"), Some("4px"));
+
+        styles.set_property("margin", "0 auto");
+        assert_eq!((styles.margin_top.as_str(), styles.margin_right.as_str()), ("0", "auto"));
+
+        styles.set_property("border", "solid 2px rgb(0, 0, 255)");
+        assert_eq!((styles.border_style.as_str(), styles.border_color.as_str()), ("solid", "rgb(0, 0, 255)"));
+        assert_eq!((styles.border_width.as_str(), styles.border_left_width.as_str()), ("2px", "2px"));
+        styles.set_property("border-width", "thin 4px");
+        assert_eq!((styles.border_top_width.as_str(), styles.border_right_width.as_str()), ("1px", "4px"));
+        styles.set_property("border-left-width", "thick");
+        assert_eq!(styles.border_left_width, "5px");
+    }
+}
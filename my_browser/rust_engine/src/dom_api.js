@@ -3,18 +3,118 @@
 
 // Global document object with full DOM API
 window = {};
+
+// --- Rust DOM bindings: these ops read and write the engine's DOM arena ---
+window._createElement = (tagName) => Deno.core.ops.op_create_element(String(tagName));
+window._setAttribute = (id, name, value) => Deno.core.ops.op_set_attribute(id, String(name), String(value));
+window._getElementById = (id) => Deno.core.ops.op_get_element_by_id(String(id));
+window._setTextContent = (id, content) => Deno.core.ops.op_set_text_content(id, String(content));
+window._appendChild = (parentId, childId) => Deno.core.ops.op_append_child(parentId, childId);
+window._getAttribute = (id, name) => Deno.core.ops.op_get_attribute(id, String(name));
+window._getTextContent = (id) => Deno.core.ops.op_get_text_content(id);
+window._getTagName = (id) => Deno.core.ops.op_get_tag_name(id);
+window._getStyle = (id, property) => Deno.core.ops.op_get_style(id, property);
+window._setStyle = (id, property, value) => Deno.core.ops.op_set_style(id, property, String(value));
+
+// Timer callbacks by id; the engine schedules them and calls _fireTimer when one is due
+window._timerCallbacks = {};
+window._setTimer = function(callback, delay, args, repeat) {
+    const id = Deno.core.ops.op_set_timeout(Number(delay) || 0, repeat);
+    window._timerCallbacks[id] = { callback: () => callback(...args), repeat };
+    return id;
+};
+window._setTimeout = (callback, delay, args) => window._setTimer(callback, delay, args || [], false);
+window._setInterval = (callback, delay, args) => window._setTimer(callback, delay, args || [], true);
+window._clearTimeout = window._clearInterval = function(id) {
+    delete window._timerCallbacks[id];
+    Deno.core.ops.op_clear_timeout(Number(id) || 0);
+};
+window._fireTimer = function(id) {
+    const timer = window._timerCallbacks[id];
+    if (!timer) return;
+    if (!timer.repeat) delete window._timerCallbacks[id];
+    timer.callback();
+};
+
+// Animation frame callbacks by id; the engine passes each frame's ids to _runAnimationFrame
+window._frameCallbacks = {};
+window.requestAnimationFrame = requestAnimationFrame = function(callback) {
+    const id = Deno.core.ops.op_request_animation_frame();
+    window._frameCallbacks[id] = callback;
+    return id;
+};
+window.cancelAnimationFrame = cancelAnimationFrame = function(id) {
+    delete window._frameCallbacks[id];
+    Deno.core.ops.op_cancel_animation_frame(Number(id) || 0);
+};
+window._runAnimationFrame = function(ids, timestamp) {
+    for (const id of ids) {
+        const callback = window._frameCallbacks[id];
+        delete window._frameCallbacks[id];
+        if (callback) callback(timestamp);
+    }
+};
+
+// `backgroundColor` -> `background-color`; hyphenated names pass through
+function _cssPropertyName(name) {
+    return String(name).replace(/[A-Z]/g, (letter) => '-' + letter.toLowerCase());
+}
+
+// element.style: reads and writes go straight to the node's style in the arena
+function _styleProxy(nodeId) {
+    return new Proxy({}, {
+        get: (target, name) => typeof name === 'string' ? window._getStyle(nodeId, _cssPropertyName(name)) : undefined,
+        set: (target, name, value) => {
+            window._setStyle(nodeId, _cssPropertyName(name), value);
+            return true;
+        }
+    });
+}
+
+// A script-side handle on an existing arena node
+function _wrapElement(nodeId) {
+    const element = {
+        id: nodeId,
+        style: _styleProxy(nodeId),
+        get tagName() {
+            return window._getTagName(this.id);
+        },
+        get textContent() {
+            return window._getTextContent(this.id);
+        },
+        set textContent(value) {
+            window._setTextContent(this.id, value);
+        },
+        setAttribute: function(name, value) {
+            window._setAttribute(this.id, name, value);
+        },
+        getAttribute: function(name) {
+            return window._getAttribute(this.id, name);
+        },
+        appendChild: function(child) {
+            window._appendChild(this.id, child.id);
+            return child;
+        }
+    };
+    Object.setPrototypeOf(element, Element.prototype);
+    patchEventAPI(element);
+    return element;
+}
+
 document = {
     createElement: function(tagName) {
         console.log('Creating element:', tagName);
         const elementId = window._createElement(tagName);
         return {
             id: elementId,
-            tagName: tagName,
             attributes: {},
-            style: {},
+            style: _styleProxy(elementId),
             children: [],
             
             // --- DOM property accessors ---
+            get tagName() {
+                return window._getTagName(this.id);
+            },
             get textContent() {
                 return window._getTextContent(this.id);
             },
@@ -33,15 +133,6 @@ document = {
             set outerHTML(value) {
                 window._setOuterHtml(this.id, value);
             },
-            get id() {
-                return window._getId(this.id);
-            },
-            set id(value) {
-                window._setId(this.id, value);
-            },
-            get tagName() {
-                return window._getTagName(this.id);
-            },
             get nodeType() {
                 return window._getNodeType(this.id);
             },
@@ -109,9 +200,8 @@ document = {
     },
     
     getElementById: function(id) {
-        console.log('Getting element by ID:', id);
-        const element = window._getElementById(id);
-        return element;
+        const nodeId = window._getElementById(id);
+        return nodeId == null ? null : _wrapElement(nodeId);
     },
     
     querySelector: function(selector) {
@@ -148,39 +238,40 @@ document = {
 };
 
 // Console API
+// Writes straight to the engine's stdout/stderr; calling console.log here would recurse
+function _printConsole(prefix, args, isError) {
+    Deno.core.print(prefix + ' ' + args.map(String).join(' ') + '\n', isError);
+}
+
 console = {
     log: function(...args) {
-        console.log('JS Console:', ...args);
+        _printConsole('JS Console:', args, false);
     },
     error: function(...args) {
-        console.log('JS Error:', ...args);
+        _printConsole('JS Error:', args, true);
     },
     warn: function(...args) {
-        console.log('JS Warn:', ...args);
+        _printConsole('JS Warn:', args, true);
     },
     info: function(...args) {
-        console.log('JS Info:', ...args);
+        _printConsole('JS Info:', args, false);
     }
 };
 
 // Timer APIs
-setTimeout = function(callback, delay) {
-    console.log('Setting timeout:', delay);
-    return window._setTimeout(callback, delay);
+setTimeout = function(callback, delay, ...args) {
+    return window._setTimeout(callback, delay, args);
 };
 
-setInterval = function(callback, delay) {
-    console.log('Setting interval:', delay);
-    return window._setInterval(callback, delay);
+setInterval = function(callback, delay, ...args) {
+    return window._setInterval(callback, delay, args);
 };
 
 clearTimeout = function(id) {
-    console.log('Clearing timeout:', id);
     window._clearTimeout(id);
 };
 
 clearInterval = function(id) {
-    console.log('Clearing interval:', id);
     window._clearInterval(id);
 };
 
@@ -207,13 +298,14 @@ Event = function(type, options) {
     this.bubbles = options && options.bubbles || false;
     this.cancelable = options && options.cancelable || false;
     this.defaultPrevented = false;
+    this.propagationStopped = false;
     
     this.preventDefault = function() {
         this.defaultPrevented = true;
     };
     
     this.stopPropagation = function() {
-        this.bubbles = false;
+        this.propagationStopped = true;
     };
 };
 
@@ -223,12 +315,15 @@ CustomEvent = function(type, options) {
     this.detail = options && options.detail || null;
 };
 
-// XMLHttpRequest for AJAX
+// XMLHttpRequest for AJAX, on top of fetch; requests always complete asynchronously
 XMLHttpRequest = function() {
     this.readyState = 0;
     this.status = 0;
+    this.statusText = '';
     this.responseText = '';
     this.onreadystatechange = null;
+    this.onload = null;
+    this.onerror = null;
     
     this.open = function(method, url, async) {
         this.method = method;
@@ -239,50 +334,41 @@ XMLHttpRequest = function() {
     };
     
     this.send = function(data) {
-        this.readyState = 4;
-        this.status = 200;
-        this.responseText = '{"success": true}';
-        if (this.onreadystatechange) this.onreadystatechange();
+        fetch(this.url, { method: this.method, body: data }).then((response) => {
+            this.status = response.status;
+            this.statusText = response.statusText;
+            return response.text();
+        }).then((text) => {
+            this.responseText = text;
+            this.readyState = 4;
+            if (this.onreadystatechange) this.onreadystatechange();
+            if (this.onload) this.onload();
+        }, () => {
+            this.readyState = 4;
+            if (this.onreadystatechange) this.onreadystatechange();
+            if (this.onerror) this.onerror();
+        });
     };
 };
 
-// Fetch API
+// Fetch API, performed by the engine; the promise settles once the engine's event loop runs
 fetch = function(url, options) {
-    return new Promise((resolve, reject) => {
-        const xhr = new XMLHttpRequest();
-        xhr.onreadystatechange = function() {
-            if (xhr.readyState === 4) {
-                if (xhr.status === 200) {
-                    resolve({
-                        ok: true,
-                        status: xhr.status,
-                        text: () => Promise.resolve(xhr.responseText),
-                        json: () => Promise.resolve(JSON.parse(xhr.responseText))
-                    });
-                } else {
-                    reject(new Error('Request failed'));
-                }
-            }
+    const method = (options && options.method) || 'GET';
+    const body = options && options.body != null ? String(options.body) : null;
+    return Deno.core.ops.op_fetch(String(url), String(method), body).then((result) => {
+        if ('error' in result) throw new TypeError('Failed to fetch: ' + result.error);
+        const { status, statusText, body: text } = result.response;
+        return {
+            ok: status >= 200 && status < 300,
+            status,
+            statusText,
+            url: String(url),
+            text: () => Promise.resolve(text),
+            json: () => Promise.resolve(text).then((text) => JSON.parse(text))
         };
-        xhr.open(options?.method || 'GET', url, true);
-        xhr.send(options?.body);
     });
 };
 
-// JSON API
-JSON = {
-    parse: function(text) {
-        try {
-            return eval('(' + text + ')');
-        } catch (e) {
-            throw new Error('Invalid JSON');
-        }
-    },
-    stringify: function(obj) {
-        return JSON.stringify(obj);
-    }
-};
-
 // Math and other global objects
 Math = Math || {};
 Date = Date || function() { return new Date(); };
@@ -462,13 +548,28 @@ document.createElement = function(tagName) {
 // Patch document itself for event listeners
 patchEventAPI(document);
 
-// Called from Dart/Flutter when an event occurs
+// Called from the engine when an event occurs
 window._invokeEventCallback = function(nodeId, type, callbackId) {
-    // Find the element by nodeId (you may need a global registry of elements)
-    // For now, just call the handler
     const handler = window._eventCallbackRegistry[callbackId];
     if (handler) {
-        handler({ type, target: { id: nodeId } });
+        const event = new Event(type);
+        event.target = event.currentTarget = { id: nodeId };
+        handler.call(event.target, event);
+    }
+};
+
+// Called from the engine with the listeners of the target and, for a bubbling event,
+// of each ancestor: path is [[nodeId, [callbackId, ...]], ...], target first
+window._runEventPath = function(targetId, type, bubbles, path) {
+    const event = new Event(type, { bubbles });
+    event.target = { id: targetId };
+    for (const [nodeId, callbackIds] of path) {
+        event.currentTarget = { id: nodeId };
+        for (const callbackId of callbackIds) {
+            const handler = window._eventCallbackRegistry[callbackId];
+            if (handler) handler.call(event.currentTarget, event);
+        }
+        if (event.propagationStopped) break;
     }
 };
 
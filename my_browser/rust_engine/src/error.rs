@@ -0,0 +1,20 @@
+// Error type for the rendering pipeline
+
+use std::fmt;
+
+/// Errors returned by the engine's rendering entry points
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RiftError {
+    /// The host cancelled the operation through its `CancellationToken`
+    Cancelled,
+}
+
+impl fmt::Display for RiftError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RiftError::Cancelled => write!(f, "operation cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for RiftError {}
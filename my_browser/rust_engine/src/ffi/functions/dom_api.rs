@@ -6,16 +6,59 @@ use std::ffi::{c_char, CString};
 use std::ptr;
 use once_cell::sync::Lazy;
 use std::sync::Mutex;
-use crate::ffi::{safe_c_string_to_rust, safe_rust_string_to_c};
+use crate::ffi::{safe_c_string_to_rust, safe_rust_string_to_c, get_global_arena};
+use crate::dom::events::event_path;
+use crate::dom::forms::{elements_by_name, form_elements};
+use crate::parser::css::inline_declarations;
+use crate::parser::html::{is_void_element, HTMLParser};
 
-static ARENA: Lazy<Mutex<DOMArena>> = Lazy::new(|| Mutex::new(DOMArena::new()));
+/// What the host must redo after a style toggle, drained with `dom_take_invalidation`
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Invalidation {
+    /// Geometry changed (`display`): lay out again, then repaint
+    Relayout = 1,
+    /// Only pixels changed (`visibility`): repaint the existing layout
+    Repaint = 2,
+}
+
+static INVALIDATIONS: Lazy<Mutex<Vec<(u32, Invalidation)>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+/// Event passed to the host's handler. Strings are only valid for the duration of the call;
+/// `value` is set for input events, `key`/`modifiers` for keyboard events.
+#[repr(C)]
+pub struct DomEvent {
+    pub event_type: *const c_char,
+    pub target: u32,
+    /// Node whose listener is running; an ancestor of `target` while the event bubbles
+    pub current_target: u32,
+    pub value: *const c_char,
+    pub key: *const c_char,
+    pub modifiers: u32,
+}
+
+/// Modifier bits for `dom_dispatch_key_event`
+pub const MODIFIER_SHIFT: u32 = 1;
+pub const MODIFIER_CTRL: u32 = 2;
+pub const MODIFIER_ALT: u32 = 4;
+pub const MODIFIER_META: u32 = 8;
+
+/// Host function invoked with each listener's `callback_id` when an event reaches it
+pub type EventHandler = extern "C" fn(callback_id: u32, event: *const DomEvent);
+
+static EVENT_HANDLER: Lazy<Mutex<Option<EventHandler>>> = Lazy::new(|| Mutex::new(None));
+
+thread_local! {
+    /// Set by `dom_stop_propagation` from inside the handler; dispatch runs on the caller's thread
+    static PROPAGATION_STOPPED: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
 
 // --- DOM FFI function implementations ---
 // (Full implementations restored from the old monolithic mod.rs)
 
 #[no_mangle]
 pub extern "C" fn dom_get_parent_node(node_id: u32) -> u32 {
-    let arena = ARENA.lock().unwrap();
+    let arena = get_global_arena();
     let id = id_to_string(node_id);
     if let Some(node) = arena.get_node(&id) {
         if let Some(parent_id) = &node.lock().unwrap().parent {
@@ -33,7 +76,7 @@ fn id_to_string(id: u32) -> String {
 
 #[no_mangle]
 pub extern "C" fn dom_get_child_nodes(node_id: u32, out_buf: *mut u32, max_len: usize) -> usize {
-    let arena = ARENA.lock().unwrap();
+    let arena = get_global_arena();
     let id = id_to_string(node_id);
     if let Some(node) = arena.get_node(&id) {
         let children = &node.lock().unwrap().children;
@@ -51,9 +94,41 @@ pub extern "C" fn dom_get_child_nodes(node_id: u32, out_buf: *mut u32, max_len:
     0
 }
 
+/// Ids of the element children of `id`, skipping text nodes
+fn child_element_ids(arena: &DOMArena, id: &str) -> Option<Vec<String>> {
+    let children = arena.get_node(id)?.lock().unwrap().children.clone();
+    Some(children.into_iter()
+        .filter(|child_id| arena.get_node(child_id).is_some_and(|child| matches!(child.lock().unwrap().node_type, NodeType::Element(_))))
+        .collect())
+}
+
+#[no_mangle]
+pub extern "C" fn dom_get_child_element_count(node_id: u32) -> u32 {
+    let arena = get_global_arena();
+    match child_element_ids(&arena, &id_to_string(node_id)) {
+        Some(ids) => ids.len() as u32,
+        None => {
+            eprintln!("dom_get_child_element_count: node not found for id {}", node_id);
+            0
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn dom_get_child_elements(node_id: u32, out_buf: *mut u32, max_len: usize) -> usize {
+    let arena = get_global_arena();
+    match child_element_ids(&arena, &id_to_string(node_id)) {
+        Some(ids) => write_ids(&ids, out_buf, max_len),
+        None => {
+            eprintln!("dom_get_child_elements: node not found for id {}", node_id);
+            0
+        }
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn dom_get_first_child(node_id: u32) -> u32 {
-    let arena = ARENA.lock().unwrap();
+    let arena = get_global_arena();
     let id = id_to_string(node_id);
     if let Some(node) = arena.get_node(&id) {
         if let Some(first) = node.lock().unwrap().children.first() {
@@ -67,7 +142,7 @@ pub extern "C" fn dom_get_first_child(node_id: u32) -> u32 {
 
 #[no_mangle]
 pub extern "C" fn dom_get_last_child(node_id: u32) -> u32 {
-    let arena = ARENA.lock().unwrap();
+    let arena = get_global_arena();
     let id = id_to_string(node_id);
     if let Some(node) = arena.get_node(&id) {
         if let Some(last) = node.lock().unwrap().children.last() {
@@ -81,7 +156,7 @@ pub extern "C" fn dom_get_last_child(node_id: u32) -> u32 {
 
 #[no_mangle]
 pub extern "C" fn dom_get_next_sibling(node_id: u32) -> u32 {
-    let arena = ARENA.lock().unwrap();
+    let arena = get_global_arena();
     let id = id_to_string(node_id);
     if let Some(node) = arena.get_node(&id) {
         if let Some(parent_id) = &node.lock().unwrap().parent {
@@ -104,7 +179,7 @@ pub extern "C" fn dom_get_next_sibling(node_id: u32) -> u32 {
 
 #[no_mangle]
 pub extern "C" fn dom_get_previous_sibling(node_id: u32) -> u32 {
-    let arena = ARENA.lock().unwrap();
+    let arena = get_global_arena();
     let id = id_to_string(node_id);
     if let Some(node) = arena.get_node(&id) {
         if let Some(parent_id) = &node.lock().unwrap().parent {
@@ -125,9 +200,45 @@ pub extern "C" fn dom_get_previous_sibling(node_id: u32) -> u32 {
     0
 }
 
+/// The next sibling that is an element, like `nextElementSibling`; 0 when there is none
+#[no_mangle]
+pub extern "C" fn dom_get_next_element_sibling(node_id: u32) -> u32 {
+    element_sibling(node_id, true)
+}
+
+/// The previous sibling that is an element, like `previousElementSibling`; 0 when there is none
+#[no_mangle]
+pub extern "C" fn dom_get_previous_element_sibling(node_id: u32) -> u32 {
+    element_sibling(node_id, false)
+}
+
+/// Walk the siblings of `node_id` in one direction, skipping text nodes
+fn element_sibling(node_id: u32, forward: bool) -> u32 {
+    let arena = get_global_arena();
+    let id = id_to_string(node_id);
+    let Some(node) = arena.get_node(&id) else {
+        eprintln!("element_sibling: node not found for id {}", node_id);
+        return 0;
+    };
+    let Some(parent_id) = node.lock().unwrap().parent.clone() else { return 0 };
+    let Some(parent) = arena.get_node(&parent_id) else {
+        eprintln!("element_sibling: parent not found for node id {}", node_id);
+        return 0;
+    };
+    let siblings = parent.lock().unwrap().children.clone();
+    let Some(pos) = siblings.iter().position(|cid| cid == &id) else { return 0 };
+    let is_element = |cid: &&String| arena.get_node(cid).is_some_and(|n| matches!(n.lock().unwrap().node_type, NodeType::Element(_)));
+    let found = if forward {
+        siblings[pos + 1..].iter().find(is_element)
+    } else {
+        siblings[..pos].iter().rev().find(is_element)
+    };
+    found.and_then(|cid| cid.parse().ok()).unwrap_or(0)
+}
+
 #[no_mangle]
 pub extern "C" fn dom_insert_before(parent_id: u32, new_node_id: u32, reference_node_id: u32) {
-    let mut arena = ARENA.lock().unwrap();
+    let mut arena = get_global_arena();
     let parent_id_str = id_to_string(parent_id);
     let new_node_id_str = id_to_string(new_node_id);
     let reference_node_id_str = id_to_string(reference_node_id);
@@ -150,7 +261,7 @@ pub extern "C" fn dom_insert_before(parent_id: u32, new_node_id: u32, reference_
 
 #[no_mangle]
 pub extern "C" fn dom_replace_child(parent_id: u32, new_node_id: u32, old_node_id: u32) {
-    let mut arena = ARENA.lock().unwrap();
+    let mut arena = get_global_arena();
     let parent_id_str = id_to_string(parent_id);
     let new_node_id_str = id_to_string(new_node_id);
     let old_node_id_str = id_to_string(old_node_id);
@@ -178,7 +289,7 @@ pub extern "C" fn dom_replace_child(parent_id: u32, new_node_id: u32, old_node_i
 
 #[no_mangle]
 pub extern "C" fn dom_clone_node(node_id: u32, deep: bool) -> u32 {
-    let mut arena = ARENA.lock().unwrap();
+    let mut arena = get_global_arena();
     let id = id_to_string(node_id);
     if let Some(node) = arena.get_node(&id) {
         let node = node.lock().unwrap();
@@ -205,7 +316,7 @@ pub extern "C" fn dom_clone_node(node_id: u32, deep: bool) -> u32 {
 
 #[no_mangle]
 pub extern "C" fn dom_remove_node(node_id: u32) {
-    let mut arena = ARENA.lock().unwrap();
+    let mut arena = get_global_arena();
     let id = id_to_string(node_id);
     if let Some(node) = arena.get_node(&id) {
         let parent_id_opt = node.lock().unwrap().parent.clone();
@@ -224,7 +335,7 @@ pub extern "C" fn dom_remove_node(node_id: u32) {
 
 #[no_mangle]
 pub extern "C" fn dom_contains_node(parent_id: u32, child_id: u32) -> bool {
-    let arena = ARENA.lock().unwrap();
+    let arena = get_global_arena();
     let parent_id_str = id_to_string(parent_id);
     let child_id_str = id_to_string(child_id);
     fn contains(arena: &DOMArena, parent_id: &str, child_id: &str) -> bool {
@@ -251,9 +362,155 @@ pub extern "C" fn dom_contains_node(parent_id: u32, child_id: u32) -> bool {
     contains(&arena, &parent_id_str, &child_id_str)
 }
 
+/// Elements under `root_id` with a matching `name` attribute, like `document.getElementsByName`.
+/// Writes up to `max_len` ids into `out_buf` and returns how many were written.
+#[no_mangle]
+pub extern "C" fn dom_get_elements_by_name(root_id: u32, name: *const c_char, out_buf: *mut u32, max_len: usize) -> usize {
+    let name = match safe_c_string_to_rust(name) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("dom_get_elements_by_name: name conversion failed: {}", e);
+            return 0;
+        }
+    };
+    let arena = get_global_arena();
+    let ids = elements_by_name(&id_to_string(root_id), &name, &arena);
+    write_ids(&ids, out_buf, max_len)
+}
+
+/// Form controls inside the `<form>` `form_id`, like `HTMLFormElement.elements`.
+/// Writes up to `max_len` ids into `out_buf` and returns how many were written.
+#[no_mangle]
+pub extern "C" fn dom_get_form_elements(form_id: u32, out_buf: *mut u32, max_len: usize) -> usize {
+    let arena = get_global_arena();
+    let ids = form_elements(&id_to_string(form_id), &arena);
+    write_ids(&ids, out_buf, max_len)
+}
+
+/// Descendants of `node_id` having all the given classes, like `getElementsByClassName`.
+/// Writes up to `max_len` ids into `out_buf` and returns how many were written.
+#[no_mangle]
+pub extern "C" fn dom_get_elements_by_class_name(node_id: u32, name: *const c_char, out_buf: *mut u32, max_len: usize) -> usize {
+    let name = match safe_c_string_to_rust(name) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("dom_get_elements_by_class_name: name conversion failed: {}", e);
+            return 0;
+        }
+    };
+    let arena = get_global_arena();
+    let Some(node) = arena.get_node(&id_to_string(node_id)) else {
+        eprintln!("dom_get_elements_by_class_name: node not found for id {}", node_id);
+        return 0;
+    };
+    let matches = node.lock().unwrap().get_elements_by_class_name(&name, &arena);
+    write_ids(&node_ids(&matches), out_buf, max_len)
+}
+
+/// Descendants of `node_id` with the given tag (`*` for all), like `getElementsByTagName`.
+/// Writes up to `max_len` ids into `out_buf` and returns how many were written.
+#[no_mangle]
+pub extern "C" fn dom_get_elements_by_tag_name(node_id: u32, tag: *const c_char, out_buf: *mut u32, max_len: usize) -> usize {
+    let tag = match safe_c_string_to_rust(tag) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("dom_get_elements_by_tag_name: tag conversion failed: {}", e);
+            return 0;
+        }
+    };
+    let arena = get_global_arena();
+    let Some(node) = arena.get_node(&id_to_string(node_id)) else {
+        eprintln!("dom_get_elements_by_tag_name: node not found for id {}", node_id);
+        return 0;
+    };
+    let matches = node.lock().unwrap().get_elements_by_tag_name(&tag, &arena);
+    write_ids(&node_ids(&matches), out_buf, max_len)
+}
+
+/// Elements in the subtree of `node_id` matching a selector list, like `querySelectorAll`:
+/// document order, each element once. Writes up to `max_len` ids into `out_buf` and returns
+/// how many were written.
+#[no_mangle]
+pub extern "C" fn dom_query_selector_all(node_id: u32, selector: *const c_char, out_buf: *mut u32, max_len: usize) -> usize {
+    let selector = match safe_c_string_to_rust(selector) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("dom_query_selector_all: selector conversion failed: {}", e);
+            return 0;
+        }
+    };
+    let arena = get_global_arena();
+    let Some(node) = arena.get_node(&id_to_string(node_id)) else {
+        eprintln!("dom_query_selector_all: node not found for id {}", node_id);
+        return 0;
+    };
+    // Matching locks ancestors, so the root must not stay locked while its subtree is searched
+    let root = node.lock().unwrap().clone();
+    let matches = root.query_selector_all(&selector, &arena);
+    write_ids(&node_ids(&matches), out_buf, max_len)
+}
+
+/// Whether `node_id` matches the selector list, like `Element.matches`
+#[no_mangle]
+pub extern "C" fn dom_matches(node_id: u32, selector: *const c_char) -> bool {
+    let selector = match safe_c_string_to_rust(selector) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("dom_matches: selector conversion failed: {}", e);
+            return false;
+        }
+    };
+    let arena = get_global_arena();
+    let Some(node) = arena.get_node(&id_to_string(node_id)) else {
+        eprintln!("dom_matches: node not found for id {}", node_id);
+        return false;
+    };
+    let node = node.lock().unwrap().clone();
+    node.matches(&selector, &arena)
+}
+
+/// `node_id` or its nearest ancestor matching the selector list, like `Element.closest`;
+/// 0 when none matches
+#[no_mangle]
+pub extern "C" fn dom_closest(node_id: u32, selector: *const c_char) -> u32 {
+    let selector = match safe_c_string_to_rust(selector) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("dom_closest: selector conversion failed: {}", e);
+            return 0;
+        }
+    };
+    let arena = get_global_arena();
+    let Some(node) = arena.get_node(&id_to_string(node_id)) else {
+        eprintln!("dom_closest: node not found for id {}", node_id);
+        return 0;
+    };
+    let node = node.lock().unwrap().clone();
+    let found = node.closest(&selector, &arena);
+    found.and_then(|found| found.lock().unwrap().id.parse().ok()).unwrap_or(0)
+}
+
+fn node_ids(nodes: &[std::sync::Arc<Mutex<DOMNode>>]) -> Vec<String> {
+    nodes.iter().map(|node| node.lock().unwrap().id.clone()).collect()
+}
+
+fn write_ids(ids: &[String], out_buf: *mut u32, max_len: usize) -> usize {
+    if out_buf.is_null() {
+        return 0;
+    }
+    let count = ids.len().min(max_len);
+    unsafe {
+        for (i, id) in ids.iter().take(count).enumerate() {
+            *out_buf.add(i) = id.parse().unwrap_or(0);
+        }
+    }
+    count
+}
+
+/// The attribute's value, or null when the node or attribute is missing; release it with `free_c_string`
 #[no_mangle]
 pub extern "C" fn dom_get_attribute(node_id: u32, name: *const c_char) -> *mut c_char {
-    let arena = ARENA.lock().unwrap();
+    let arena = get_global_arena();
     let id = id_to_string(node_id);
     let name = match safe_c_string_to_rust(name) {
         Ok(s) => s,
@@ -264,7 +521,7 @@ pub extern "C" fn dom_get_attribute(node_id: u32, name: *const c_char) -> *mut c
     };
     if let Some(node) = arena.get_node(&id) {
         if let Some(val) = node.lock().unwrap().attributes.get(&name) {
-            return CString::new(val.as_str()).unwrap().into_raw();
+            return safe_rust_string_to_c(val);
         }
     } else {
         eprintln!("dom_get_attribute: node not found for id {}", node_id);
@@ -274,7 +531,7 @@ pub extern "C" fn dom_get_attribute(node_id: u32, name: *const c_char) -> *mut c
 
 #[no_mangle]
 pub extern "C" fn dom_set_attribute(node_id: u32, name: *const c_char, value: *const c_char) {
-    let mut arena = ARENA.lock().unwrap();
+    let mut arena = get_global_arena();
     let id = id_to_string(node_id);
     let name = match safe_c_string_to_rust(name) {
         Ok(s) => s,
@@ -299,7 +556,7 @@ pub extern "C" fn dom_set_attribute(node_id: u32, name: *const c_char, value: *c
 
 #[no_mangle]
 pub extern "C" fn dom_remove_attribute(node_id: u32, name: *const c_char) {
-    let mut arena = ARENA.lock().unwrap();
+    let mut arena = get_global_arena();
     let id = id_to_string(node_id);
     let name = match safe_c_string_to_rust(name) {
         Ok(s) => s,
@@ -317,7 +574,7 @@ pub extern "C" fn dom_remove_attribute(node_id: u32, name: *const c_char) {
 
 #[no_mangle]
 pub extern "C" fn dom_has_attribute(node_id: u32, name: *const c_char) -> bool {
-    let arena = ARENA.lock().unwrap();
+    let arena = get_global_arena();
     let id = id_to_string(node_id);
     let name = match safe_c_string_to_rust(name) {
         Ok(s) => s,
@@ -327,7 +584,7 @@ pub extern "C" fn dom_has_attribute(node_id: u32, name: *const c_char) -> bool {
         }
     };
     if let Some(node) = arena.get_node(&id) {
-        node.lock().unwrap().attributes.contains_key(&name)
+        node.lock().unwrap().attributes.contains_key(&name.to_lowercase())
     } else {
         eprintln!("dom_has_attribute: node not found for id {}", node_id);
         false
@@ -336,7 +593,7 @@ pub extern "C" fn dom_has_attribute(node_id: u32, name: *const c_char) -> bool {
 
 #[no_mangle]
 pub extern "C" fn dom_class_list_add(node_id: u32, class_name: *const c_char) {
-    let mut arena = ARENA.lock().unwrap();
+    let mut arena = get_global_arena();
     let id = id_to_string(node_id);
     let class_name = match safe_c_string_to_rust(class_name) {
         Ok(s) => s,
@@ -361,7 +618,7 @@ pub extern "C" fn dom_class_list_add(node_id: u32, class_name: *const c_char) {
 
 #[no_mangle]
 pub extern "C" fn dom_class_list_remove(node_id: u32, class_name: *const c_char) {
-    let mut arena = ARENA.lock().unwrap();
+    let mut arena = get_global_arena();
     let id = id_to_string(node_id);
     let class_name = match safe_c_string_to_rust(class_name) {
         Ok(s) => s,
@@ -386,7 +643,7 @@ pub extern "C" fn dom_class_list_remove(node_id: u32, class_name: *const c_char)
 
 #[no_mangle]
 pub extern "C" fn dom_class_list_toggle(node_id: u32, class_name: *const c_char) {
-    let mut arena = ARENA.lock().unwrap();
+    let mut arena = get_global_arena();
     let id = id_to_string(node_id);
     let class_name = match safe_c_string_to_rust(class_name) {
         Ok(s) => s,
@@ -413,7 +670,7 @@ pub extern "C" fn dom_class_list_toggle(node_id: u32, class_name: *const c_char)
 
 #[no_mangle]
 pub extern "C" fn dom_class_list_contains(node_id: u32, class_name: *const c_char) -> bool {
-    let arena = ARENA.lock().unwrap();
+    let arena = get_global_arena();
     let id = id_to_string(node_id);
     let class_name = match safe_c_string_to_rust(class_name) {
         Ok(s) => s,
@@ -432,9 +689,10 @@ pub extern "C" fn dom_class_list_contains(node_id: u32, class_name: *const c_cha
     false
 }
 
+/// The node's `textContent`, or null for an unknown node; release it with `free_c_string`
 #[no_mangle]
 pub extern "C" fn dom_get_text_content(node_id: u32) -> *mut c_char {
-    let arena = ARENA.lock().unwrap();
+    let arena = get_global_arena();
     let id = id_to_string(node_id);
     fn get_text(node: &DOMNode, arena: &DOMArena) -> String {
         match &node.node_type {
@@ -447,7 +705,7 @@ pub extern "C" fn dom_get_text_content(node_id: u32) -> *mut c_char {
     }
     if let Some(node) = arena.get_node(&id) {
         let text = get_text(&node.lock().unwrap(), &arena);
-        CString::new(text).unwrap().into_raw()
+        safe_rust_string_to_c(&text)
     } else {
         eprintln!("dom_get_text_content: node not found for id {}", node_id);
         ptr::null_mut()
@@ -456,7 +714,7 @@ pub extern "C" fn dom_get_text_content(node_id: u32) -> *mut c_char {
 
 #[no_mangle]
 pub extern "C" fn dom_set_text_content(node_id: u32, value: *const c_char) {
-    let mut arena = ARENA.lock().unwrap();
+    let mut arena = get_global_arena();
     let id = id_to_string(node_id);
     let value = match safe_c_string_to_rust(value) {
         Ok(s) => s,
@@ -485,13 +743,14 @@ pub extern "C" fn dom_set_text_content(node_id: u32, value: *const c_char) {
     }
 }
 
+/// The node's `id` attribute, or null for an unknown node; release it with `free_c_string`
 #[no_mangle]
 pub extern "C" fn dom_get_id(node_id: u32) -> *mut c_char {
-    let arena = ARENA.lock().unwrap();
+    let arena = get_global_arena();
     let id = id_to_string(node_id);
     if let Some(node) = arena.get_node(&id) {
         let id_val = node.lock().unwrap().attributes.get("id").cloned().unwrap_or_default();
-        CString::new(id_val).unwrap().into_raw()
+        safe_rust_string_to_c(&id_val)
     } else {
         eprintln!("dom_get_id: node not found for id {}", node_id);
         ptr::null_mut()
@@ -500,7 +759,7 @@ pub extern "C" fn dom_get_id(node_id: u32) -> *mut c_char {
 
 #[no_mangle]
 pub extern "C" fn dom_set_id(node_id: u32, value: *const c_char) {
-    let mut arena = ARENA.lock().unwrap();
+    let mut arena = get_global_arena();
     let id = id_to_string(node_id);
     let value = match safe_c_string_to_rust(value) {
         Ok(s) => s,
@@ -516,16 +775,17 @@ pub extern "C" fn dom_set_id(node_id: u32, value: *const c_char) {
     }
 }
 
+/// The element's tag name, or null for an unknown node; release it with `free_c_string`
 #[no_mangle]
 pub extern "C" fn dom_get_tag_name(node_id: u32) -> *mut c_char {
-    let arena = ARENA.lock().unwrap();
+    let arena = get_global_arena();
     let id = id_to_string(node_id);
     if let Some(node) = arena.get_node(&id) {
         let tag = match &node.lock().unwrap().node_type {
             NodeType::Element(t) => t.clone(),
             _ => String::new(),
         };
-        CString::new(tag).unwrap().into_raw()
+        safe_rust_string_to_c(&tag)
     } else {
         eprintln!("dom_get_tag_name: node not found for id {}", node_id);
         ptr::null_mut()
@@ -534,7 +794,7 @@ pub extern "C" fn dom_get_tag_name(node_id: u32) -> *mut c_char {
 
 #[no_mangle]
 pub extern "C" fn dom_get_node_type(node_id: u32) -> u32 {
-    let arena = ARENA.lock().unwrap();
+    let arena = get_global_arena();
     let id = id_to_string(node_id);
     if let Some(node) = arena.get_node(&id) {
         match node.lock().unwrap().node_type {
@@ -560,7 +820,9 @@ fn serialize_html(node: &DOMNode, arena: &DOMArena, outer: bool) -> String {
                 .filter_map(|cid| arena.get_node(cid))
                 .map(|c| serialize_html(&c.lock().unwrap(), arena, true))
                 .collect::<Vec<_>>().join("");
-            if outer {
+            if outer && is_void_element(tag) {
+                format!("<{}>", open)
+            } else if outer {
                 format!("<{}>{}</{}>", open, children_html, tag)
             } else {
                 children_html
@@ -573,26 +835,28 @@ fn serialize_html(node: &DOMNode, arena: &DOMArena, outer: bool) -> String {
     }
 }
 
+/// The node's children serialized as HTML, or null for an unknown node; release it with `free_c_string`
 #[no_mangle]
 pub extern "C" fn dom_get_inner_html(node_id: u32) -> *mut c_char {
-    let arena = ARENA.lock().unwrap();
+    let arena = get_global_arena();
     let id = id_to_string(node_id);
     if let Some(node) = arena.get_node(&id) {
         let html = serialize_html(&node.lock().unwrap(), &arena, false);
-        CString::new(html).unwrap().into_raw()
+        safe_rust_string_to_c(&html)
     } else {
         eprintln!("dom_get_inner_html: node not found for id {}", node_id);
         ptr::null_mut()
     }
 }
 
+/// The node serialized as HTML, or null for an unknown node; release it with `free_c_string`
 #[no_mangle]
 pub extern "C" fn dom_get_outer_html(node_id: u32) -> *mut c_char {
-    let arena = ARENA.lock().unwrap();
+    let arena = get_global_arena();
     let id = id_to_string(node_id);
     if let Some(node) = arena.get_node(&id) {
         let html = serialize_html(&node.lock().unwrap(), &arena, true);
-        CString::new(html).unwrap().into_raw()
+        safe_rust_string_to_c(&html)
     } else {
         eprintln!("dom_get_outer_html: node not found for id {}", node_id);
         ptr::null_mut()
@@ -601,7 +865,7 @@ pub extern "C" fn dom_get_outer_html(node_id: u32) -> *mut c_char {
 
 #[no_mangle]
 pub extern "C" fn dom_set_inner_html(node_id: u32, value: *const c_char) {
-    let mut arena = ARENA.lock().unwrap();
+    let mut arena = get_global_arena();
     let id = id_to_string(node_id);
     let value = match safe_c_string_to_rust(value) {
         Ok(s) => s,
@@ -611,23 +875,100 @@ pub extern "C" fn dom_set_inner_html(node_id: u32, value: *const c_char) {
         }
     };
     if let Some(node) = arena.get_node(&id) {
-        let mut node = node.lock().unwrap();
-        node.children.clear();
-        let mut text_node = DOMNode::new(NodeType::Text);
-        text_node.text_content = value;
-        let new_id = NODE_ID_COUNTER.fetch_add(1, std::sync::atomic::Ordering::SeqCst).to_string();
-        text_node.id = new_id.clone();
-        text_node.parent = Some(id.clone());
-        arena.add_node(text_node);
-        node.children.push(new_id);
+        let old_children = std::mem::take(&mut node.lock().unwrap().children);
+        for child_id in &old_children {
+            remove_subtree(&mut arena, child_id);
+        }
+        // Build the fragment straight into this node; the builder locks it to append each child
+        let mut target = node.lock().unwrap().clone();
+        let mut parser = HTMLParser::new(value);
+        let tokens = parser.tokenize_streaming();
+        parser.build_dom_enhanced(&tokens, &mut target, &mut arena);
     } else {
         eprintln!("dom_set_inner_html: node not found for id {}", node_id);
     }
 }
 
+/// Parse `html` and insert the nodes relative to `node_id`: before it (`beforebegin`), as its
+/// first or last children (`afterbegin`/`beforeend`) or after it (`afterend`). Returns false,
+/// inserting nothing, if the position doesn't apply to the node, e.g. `afterbegin` on a text node.
+#[no_mangle]
+pub extern "C" fn dom_insert_adjacent_html(node_id: u32, position: *const c_char, html: *const c_char) -> bool {
+    let (position, html) = match (safe_c_string_to_rust(position), safe_c_string_to_rust(html)) {
+        (Ok(position), Ok(html)) => (position.to_lowercase(), html),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("dom_insert_adjacent_html: argument conversion failed: {}", e);
+            return false;
+        }
+    };
+    let mut arena = get_global_arena();
+    let id = id_to_string(node_id);
+    let target = match arena.get_node(&id) {
+        Some(node) => node.lock().unwrap().clone(),
+        None => {
+            eprintln!("dom_insert_adjacent_html: node not found for id {}", node_id);
+            return false;
+        }
+    };
+    let sibling_index = |arena: &DOMArena, parent_id: &str| {
+        arena.get_node(parent_id).and_then(|parent| parent.lock().unwrap().children.iter().position(|cid| *cid == id))
+    };
+    let (parent_id, index) = match (position.as_str(), target.parent.as_deref()) {
+        ("afterbegin" | "beforeend", _) if !matches!(target.node_type, NodeType::Element(_)) => {
+            eprintln!("dom_insert_adjacent_html: node {} can't have children", node_id);
+            return false;
+        }
+        ("afterbegin", _) => (id.clone(), 0),
+        ("beforeend", _) => (id.clone(), target.children.len()),
+        ("beforebegin", Some(parent_id)) => match sibling_index(&arena, parent_id) {
+            Some(index) => (parent_id.to_string(), index),
+            None => return false,
+        },
+        ("afterend", Some(parent_id)) => match sibling_index(&arena, parent_id) {
+            Some(index) => (parent_id.to_string(), index + 1),
+            None => return false,
+        },
+        ("beforebegin" | "afterend", None) => {
+            eprintln!("dom_insert_adjacent_html: node {} has no parent", node_id);
+            return false;
+        }
+        _ => {
+            eprintln!("dom_insert_adjacent_html: invalid position {:?}", position);
+            return false;
+        }
+    };
+
+    // Build the fragment under a scratch container, then move its children into place
+    let mut container = DOMNode::create_element("template");
+    arena.add_node(container.clone());
+    let mut parser = HTMLParser::new(html);
+    let tokens = parser.tokenize_streaming();
+    parser.build_dom_enhanced(&tokens, &mut container, &mut arena);
+    let new_ids = arena.remove_node(&container.id).map(|node| node.lock().unwrap().children.clone()).unwrap_or_default();
+    for new_id in &new_ids {
+        if let Some(new_node) = arena.get_node(new_id) {
+            new_node.lock().unwrap().parent = Some(parent_id.clone());
+        }
+    }
+    if let Some(parent) = arena.get_node(&parent_id) {
+        parent.lock().unwrap().children.splice(index..index, new_ids);
+    }
+    true
+}
+
+/// Drop a node and all its descendants from the arena
+fn remove_subtree(arena: &mut DOMArena, id: &str) {
+    if let Some(node) = arena.remove_node(id) {
+        let children = node.lock().unwrap().children.clone();
+        for child_id in &children {
+            remove_subtree(arena, child_id);
+        }
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn dom_set_outer_html(node_id: u32, value: *const c_char) {
-    let mut arena = ARENA.lock().unwrap();
+    let mut arena = get_global_arena();
     let id = id_to_string(node_id);
     let value = match safe_c_string_to_rust(value) {
         Ok(s) => s,
@@ -648,9 +989,10 @@ pub extern "C" fn dom_set_outer_html(node_id: u32, value: *const c_char) {
     }
 }
 
+/// One of the node's style properties, empty when unset; release it with `free_c_string`
 #[no_mangle]
 pub extern "C" fn dom_get_style(node_id: u32, name: *const c_char) -> *mut c_char {
-    let arena = ARENA.lock().unwrap();
+    let arena = get_global_arena();
     let id = id_to_string(node_id);
     let node = match arena.get_node(&id) {
         Some(n) => n,
@@ -667,7 +1009,7 @@ pub extern "C" fn dom_get_style(node_id: u32, name: *const c_char) -> *mut c_cha
 
 #[no_mangle]
 pub extern "C" fn dom_set_style(node_id: u32, name: *const c_char, value: *const c_char) {
-    let mut arena = ARENA.lock().unwrap();
+    let mut arena = get_global_arena();
     let id = id_to_string(node_id);
     let node = match arena.get_node(&id) {
         Some(n) => n,
@@ -687,7 +1029,7 @@ pub extern "C" fn dom_set_style(node_id: u32, name: *const c_char, value: *const
 
 #[no_mangle]
 pub extern "C" fn dom_remove_style(node_id: u32, name: *const c_char) {
-    let mut arena = ARENA.lock().unwrap();
+    let mut arena = get_global_arena();
     let id = id_to_string(node_id);
     let node = match arena.get_node(&id) {
         Some(n) => n,
@@ -700,9 +1042,110 @@ pub extern "C" fn dom_remove_style(node_id: u32, name: *const c_char) {
     node.lock().unwrap().styles.remove_property(&name_str);
 }
 
+/// Show or hide a node with `visibility`, keeping its box. Queues a repaint if the value changed.
+#[no_mangle]
+pub extern "C" fn dom_set_visible(node_id: u32, visible: bool) -> bool {
+    let value = if visible { "visible" } else { "hidden" };
+    toggle_style(node_id, "visibility", value, Invalidation::Repaint)
+}
+
+/// Set a node's `display` (e.g. `none` to hide it and its subtree). Queues a relayout if the value changed.
+#[no_mangle]
+pub extern "C" fn dom_set_display(node_id: u32, value: *const c_char) -> bool {
+    let value = match safe_c_string_to_rust(value) {
+        Ok(s) => s.trim().to_lowercase(),
+        Err(e) => {
+            eprintln!("dom_set_display: value conversion failed: {}", e);
+            return false;
+        }
+    };
+    toggle_style(node_id, "display", &value, Invalidation::Relayout)
+}
+
+/// Turn an interactive state such as `hover`, `active` or `focus` (with or without the leading
+/// `:`) on or off for a node, so selectors like `.btn:hover` match it. Queues a relayout if it changed.
+#[no_mangle]
+pub extern "C" fn dom_set_pseudo_state(node_id: u32, name: *const c_char, active: bool) -> bool {
+    let name = match safe_c_string_to_rust(name) {
+        Ok(s) => s.trim().trim_start_matches(':').to_lowercase(),
+        Err(e) => {
+            eprintln!("dom_set_pseudo_state: name conversion failed: {}", e);
+            return false;
+        }
+    };
+    let arena = get_global_arena();
+    let Some(node) = arena.get_node(&id_to_string(node_id)) else {
+        eprintln!("dom_set_pseudo_state: node not found for id {}", node_id);
+        return false;
+    };
+    let mut node = node.lock().unwrap();
+    let changed = if active { node.pseudo_states.insert(name) } else { node.pseudo_states.remove(&name) };
+    if changed {
+        INVALIDATIONS.lock().unwrap().push((node_id, Invalidation::Relayout));
+    }
+    changed
+}
+
+/// Pop the oldest pending invalidation, writing its node id to `out_node_id`.
+/// Returns the `Invalidation` code, or 0 when nothing is pending.
+#[no_mangle]
+pub extern "C" fn dom_take_invalidation(out_node_id: *mut u32) -> i32 {
+    let mut pending = INVALIDATIONS.lock().unwrap();
+    if pending.is_empty() {
+        return 0;
+    }
+    let (node_id, invalidation) = pending.remove(0);
+    if !out_node_id.is_null() {
+        unsafe { *out_node_id = node_id };
+    }
+    invalidation as i32
+}
+
+/// Set `property` both on the computed styles and as an inline declaration, so the change
+/// survives the next restyle, and queue `invalidation` when it actually changed anything
+fn toggle_style(node_id: u32, property: &str, value: &str, invalidation: Invalidation) -> bool {
+    let arena = get_global_arena();
+    let Some(node) = arena.get_node(&id_to_string(node_id)) else {
+        eprintln!("dom_set_{}: node not found for id {}", property, node_id);
+        return false;
+    };
+    let mut node = node.lock().unwrap();
+    let unchanged = node.styles.get_property(property) == Some(value)
+        && node.attributes.get("style").is_some_and(|style| inline_declaration(style, property).as_deref() == Some(value));
+    if unchanged {
+        return false;
+    }
+    node.styles.set_property(property, value);
+    let style = node.attributes.get("style").map(String::as_str).unwrap_or("");
+    let style = with_inline_declaration(style, property, value);
+    node.attributes.insert("style".to_string(), style);
+    INVALIDATIONS.lock().unwrap().push((node_id, invalidation));
+    true
+}
+
+/// The value of the last `property` declaration in a `style` attribute
+fn inline_declaration(style: &str, property: &str) -> Option<String> {
+    inline_declarations(style).into_iter()
+        .rfind(|(name, _, _)| name.eq_ignore_ascii_case(property))
+        .map(|(_, value, _)| value)
+}
+
+/// `style` with any `property` declarations replaced by `property: value`
+fn with_inline_declaration(style: &str, property: &str, value: &str) -> String {
+    let mut declarations: Vec<String> = style.split(';')
+        .map(str::trim)
+        .filter(|declaration| !declaration.is_empty())
+        .filter(|declaration| !declaration.split_once(':').is_some_and(|(name, _)| name.trim().eq_ignore_ascii_case(property)))
+        .map(str::to_string)
+        .collect();
+    declarations.push(format!("{}: {}", property, value));
+    declarations.join("; ")
+}
+
+/// The node's styles as a `style` attribute value; release it with `free_c_string`
 #[no_mangle]
 pub extern "C" fn dom_get_style_css_text(node_id: u32) -> *mut c_char {
-    let arena = ARENA.lock().unwrap();
+    let arena = get_global_arena();
     let id = id_to_string(node_id);
     let node = match arena.get_node(&id) {
         Some(n) => n,
@@ -813,12 +1256,17 @@ pub extern "C" fn dom_get_style_css_text(node_id: u32) -> *mut c_char {
     push_prop!("animation", &styles.animation);
     push_prop!("box-shadow", &styles.box_shadow);
     push_prop!("text-shadow", &styles.text_shadow);
+    let mut extra: Vec<_> = styles.extra.iter().collect();
+    extra.sort();
+    for (property, value) in extra {
+        push_prop!(property, value);
+    }
     safe_rust_string_to_c(&css_text)
 }
 
 #[no_mangle]
 pub extern "C" fn dom_set_style_css_text(node_id: u32, css_text: *const c_char) {
-    let mut arena = ARENA.lock().unwrap();
+    let mut arena = get_global_arena();
     let id = id_to_string(node_id);
     let node = match arena.get_node(&id) {
         Some(n) => n,
@@ -839,7 +1287,7 @@ pub extern "C" fn dom_set_style_css_text(node_id: u32, css_text: *const c_char)
 
 #[no_mangle]
 pub extern "C" fn dom_add_event_listener(node_id: u32, event_type: *const c_char, callback_id: u32) {
-    let mut arena = ARENA.lock().unwrap();
+    let mut arena = get_global_arena();
     let id = id_to_string(node_id);
     let event_type = match safe_c_string_to_rust(event_type) {
         Ok(s) => s,
@@ -858,7 +1306,7 @@ pub extern "C" fn dom_add_event_listener(node_id: u32, event_type: *const c_char
 
 #[no_mangle]
 pub extern "C" fn dom_remove_event_listener(node_id: u32, event_type: *const c_char) {
-    let mut arena = ARENA.lock().unwrap();
+    let mut arena = get_global_arena();
     let id = id_to_string(node_id);
     let event_type = match safe_c_string_to_rust(event_type) {
         Ok(s) => s,
@@ -875,9 +1323,95 @@ pub extern "C" fn dom_remove_event_listener(node_id: u32, event_type: *const c_c
     }
 }
 
+/// Register the function that runs listener callbacks; pass None to stop receiving events
+#[no_mangle]
+pub extern "C" fn dom_set_event_handler(handler: Option<EventHandler>) {
+    *EVENT_HANDLER.lock().unwrap() = handler;
+}
+
+/// Set an `<input>`/`<textarea>`'s value as if the user typed it, then fire a bubbling `input`
+#[no_mangle]
+pub extern "C" fn dom_dispatch_input_event(node_id: u32, value: *const c_char) -> bool {
+    let value = match safe_c_string_to_rust(value) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("dom_dispatch_input_event: value conversion failed: {}", e);
+            return false;
+        }
+    };
+    {
+        let arena = get_global_arena();
+        let Some(node) = arena.get_node(&id_to_string(node_id)) else {
+            eprintln!("dom_dispatch_input_event: node not found for id {}", node_id);
+            return false;
+        };
+        node.lock().unwrap().attributes.insert("value".to_string(), value.clone());
+    }
+    let value = CString::new(value).unwrap_or_default();
+    let event_type = CString::new("input").unwrap();
+    propagate(DomEvent {
+        event_type: event_type.as_ptr(),
+        target: node_id,
+        current_target: node_id,
+        value: value.as_ptr(),
+        key: ptr::null(),
+        modifiers: 0,
+    }, true)
+}
+
+/// Fire bubbling `keydown` then `keyup` for `key` (e.g. `Enter`, `a`) with `MODIFIER_*` bits set
+#[no_mangle]
+pub extern "C" fn dom_dispatch_key_event(node_id: u32, key: *const c_char, modifiers: u32) -> bool {
+    let key = match safe_c_string_to_rust(key) {
+        Ok(s) => CString::new(s).unwrap_or_default(),
+        Err(e) => {
+            eprintln!("dom_dispatch_key_event: key conversion failed: {}", e);
+            return false;
+        }
+    };
+    let mut handled = false;
+    for event_type in ["keydown", "keyup"] {
+        let event_type = CString::new(event_type).unwrap();
+        handled |= propagate(DomEvent {
+            event_type: event_type.as_ptr(),
+            target: node_id,
+            current_target: node_id,
+            value: ptr::null(),
+            key: key.as_ptr(),
+            modifiers,
+        }, true);
+    }
+    handled
+}
+
+/// Run the target's listeners for `event` through the host handler. Listener ids are
+/// collected first so the handler can call back into the DOM API without deadlocking.
+/// Returns whether any listener was registered for the event.
+fn fire_event(node_id: u32, event: &DomEvent) -> bool {
+    let event_type = unsafe { std::ffi::CStr::from_ptr(event.event_type) }.to_string_lossy().into_owned();
+    let callbacks = {
+        let arena = get_global_arena();
+        let Some(node) = arena.get_node(&id_to_string(node_id)) else { return false };
+        let node = node.lock().unwrap();
+        match node.event_listeners.get(&event_type) {
+            Some(callbacks) => callbacks.clone(),
+            None => return false,
+        }
+    };
+    if let Some(handler) = *EVENT_HANDLER.lock().unwrap() {
+        for callback_id in &callbacks {
+            handler(*callback_id, event);
+        }
+    }
+    !callbacks.is_empty()
+}
+
+/// Fire `event_type` at `node_id`. With `bubbles` set, the event then reaches each ancestor's
+/// listeners in turn until the root or a handler calls `dom_stop_propagation`. Listeners the
+/// page's scripts registered run in its script runtime first, then the host's.
 #[no_mangle]
-pub extern "C" fn dom_dispatch_event(node_id: u32, event_type: *const c_char) -> bool {
-    let arena = ARENA.lock().unwrap();
+pub extern "C" fn dom_dispatch_event(node_id: u32, event_type: *const c_char, bubbles: bool) -> bool {
+    let arena = get_global_arena();
     let id = id_to_string(node_id);
     let event_type = match safe_c_string_to_rust(event_type) {
         Ok(s) => s,
@@ -886,19 +1420,514 @@ pub extern "C" fn dom_dispatch_event(node_id: u32, event_type: *const c_char) ->
             return false;
         }
     };
-    if let Some(node) = arena.get_node(&id) {
+    if arena.get_node(&id).is_none() {
+        eprintln!("dom_dispatch_event: node not found for id {}", node_id);
+        return false;
+    }
+    if is_pointer_event(&event_type) && has_pointer_events_none(&arena, &id) {
+        // pointer-events:none elements never receive pointer events
+        return false;
+    }
+    drop(arena);
+    let event_type = CString::new(event_type).unwrap_or_default();
+    propagate(DomEvent {
+        event_type: event_type.as_ptr(),
+        target: node_id,
+        current_target: node_id,
+        value: ptr::null(),
+        key: ptr::null(),
+        modifiers: 0,
+    }, bubbles)
+}
+
+/// Run `event` at its target and, with `bubbles` set, at each ancestor in turn until the root
+/// or a handler calls `dom_stop_propagation`. Listeners the page's own scripts added run in
+/// its script runtime first. Returns whether any listener was registered for the event.
+fn propagate(mut event: DomEvent, bubbles: bool) -> bool {
+    let event_type = unsafe { std::ffi::CStr::from_ptr(event.event_type) }.to_string_lossy().into_owned();
+    let mut handled = super::js_api::dispatch_to_page_scripts(&id_to_string(event.target), &event_type, bubbles);
+    let path = event_path(&get_global_arena(), &id_to_string(event.target), bubbles);
+    PROPAGATION_STOPPED.with(|stopped| stopped.set(false));
+    for node_id in path {
+        event.current_target = node_id.parse().unwrap_or(0);
+        handled |= fire_event(event.current_target, &event);
+        if PROPAGATION_STOPPED.with(|stopped| stopped.get()) {
+            break;
+        }
+    }
+    handled
+}
+
+/// Stop the event being dispatched from reaching further ancestors; call from the event handler
+#[no_mangle]
+pub extern "C" fn dom_stop_propagation() {
+    PROPAGATION_STOPPED.with(|stopped| stopped.set(true));
+}
+
+fn is_pointer_event(event_type: &str) -> bool {
+    matches!(event_type, "click" | "dblclick" | "contextmenu" | "wheel")
+        || event_type.starts_with("mouse")
+        || event_type.starts_with("pointer")
+        || event_type.starts_with("touch")
+}
+
+/// `pointer-events` inherits, so the nearest node that sets it decides. A stylesheet `auto`
+/// reads the same as the default, so only an inline `auto` stops the walk.
+fn has_pointer_events_none(arena: &DOMArena, node_id: &str) -> bool {
+    let mut current = Some(node_id.to_string());
+    while let Some(id) = current {
+        let Some(node) = arena.get_node(&id) else { break };
         let node = node.lock().unwrap();
-        if let Some(callback) = node.event_listeners.get(&event_type) {
-            // In a real implementation, this would execute the callback
-            // For now, we just return true to indicate the event was handled
-            true
-        } else {
-            false
+        let inline = node.attributes.get("style").and_then(|style| inline_declaration(style, "pointer-events"));
+        match inline.as_deref().unwrap_or(&node.styles.pointer_events) {
+            "none" => return true,
+            "auto" if inline.is_some() => return false,
+            _ => {}
         }
-    } else {
-        eprintln!("dom_dispatch_event: node not found for id {}", node_id);
-        false
+        current = node.parent.clone();
     }
+    false
 }
 
-// ... (Insert all pub extern "C" fn dom_get_*, dom_set_*, dom_insert_*, dom_remove_*, dom_class_list_*, dom_add_event_listener, dom_remove_event_listener, dom_dispatch_event, id_to_string, serialize_html, get_text, etc. here) ... 
\ No newline at end of file
+// ... (Insert all pub extern "C" fn dom_get_*, dom_set_*, dom_insert_*, dom_remove_*, dom_class_list_*, dom_add_event_listener, dom_remove_event_listener, dom_dispatch_event, id_to_string, serialize_html, get_text, etc. here) ... 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add_clickable(style: Option<&str>) -> u32 {
+        let mut node = DOMNode::create_element("div");
+        if let Some(style) = style {
+            node.attributes.insert("style".to_string(), style.to_string());
+        }
+        node.event_listeners.insert("click".to_string(), vec![1]);
+        let id = node.id.parse().unwrap();
+        get_global_arena().add_node(node);
+        id
+    }
+
+    #[test]
+    fn test_get_elements_by_name_and_form_elements() {
+        let element = |tag: &str, name: &str, parent: Option<u32>| -> u32 {
+            let mut node = DOMNode::create_element(tag);
+            node.attributes.insert("name".to_string(), name.to_string());
+            node.parent = parent.map(id_to_string);
+            let id = node.id.clone();
+            let mut arena = get_global_arena();
+            arena.add_node(node);
+            if let Some(parent) = parent {
+                arena.get_node(&id_to_string(parent)).unwrap().lock().unwrap().children.push(id.clone());
+            }
+            id.parse().unwrap()
+        };
+        let root = element("body", "", None);
+        let form = element("form", "", Some(root));
+        let first = element("input", "tag", Some(form));
+        let second = element("input", "tag", Some(form));
+        let notes = element("textarea", "notes", Some(form));
+        element("input", "tag", Some(root));
+
+        let name = CString::new("tag").unwrap();
+        let mut out = [0u32; 8];
+        let count = dom_get_elements_by_name(form, name.as_ptr(), out.as_mut_ptr(), out.len());
+        assert_eq!(&out[..count], &[first, second]);
+        assert_eq!(dom_get_elements_by_name(root, name.as_ptr(), out.as_mut_ptr(), out.len()), 3);
+
+        let count = dom_get_form_elements(form, out.as_mut_ptr(), out.len());
+        assert_eq!(&out[..count], &[first, second, notes]);
+    }
+
+    #[test]
+    fn test_hover_rule_applies_only_while_state_is_set() {
+        let mut body = DOMNode::create_element("body");
+        let mut button = DOMNode::create_element("div");
+        button.parent = Some(body.id.clone());
+        button.attributes.insert("class".to_string(), "btn".to_string());
+        body.children.push(button.id.clone());
+        let button_id: u32 = button.id.parse().unwrap();
+        {
+            let mut arena = get_global_arena();
+            arena.add_node(body.clone());
+            arena.add_node(button);
+        }
+        let background = || {
+            let engine = crate::layout::layout::LayoutEngine::new(800.0, 600.0)
+                .with_stylesheet(crate::parser::css::parse_css(".btn:hover { background-color: blue }"));
+            let boxes = engine.layout(&body, &get_global_arena());
+            boxes.into_iter().find(|b| b.node_id == id_to_string(button_id)).unwrap().background_color
+        };
+        let hover = CString::new(":hover").unwrap();
+        assert_ne!(background(), "blue");
+
+        assert!(dom_set_pseudo_state(button_id, hover.as_ptr(), true));
+        assert!(!dom_set_pseudo_state(button_id, hover.as_ptr(), true), "already hovered");
+        assert_eq!(background(), "blue");
+
+        assert!(dom_set_pseudo_state(button_id, hover.as_ptr(), false));
+        assert_ne!(background(), "blue");
+    }
+
+    #[test]
+    fn test_display_and_visibility_toggles_queue_invalidations() {
+        let mut body = DOMNode::create_element("body");
+        let mut panel = DOMNode::create_element("div");
+        panel.parent = Some(body.id.clone());
+        panel.attributes.insert("style".to_string(), "width: 300px; display: none".to_string());
+        body.children.push(panel.id.clone());
+        let panel_id: u32 = panel.id.parse().unwrap();
+        {
+            let mut arena = get_global_arena();
+            arena.add_node(body.clone());
+            arena.add_node(panel);
+        }
+        let panel_boxes = || {
+            let boxes = crate::layout::layout::LayoutEngine::new(800.0, 600.0).layout(&body, &get_global_arena());
+            boxes.into_iter().filter(|b| b.node_id == id_to_string(panel_id)).collect::<Vec<_>>()
+        };
+        let drain = || {
+            let mut node_id = 0;
+            let mut taken = Vec::new();
+            loop {
+                match dom_take_invalidation(&mut node_id) {
+                    0 => return taken,
+                    kind if node_id == panel_id => taken.push(kind),
+                    _ => {}
+                }
+            }
+        };
+        assert!(panel_boxes().is_empty());
+
+        let block = CString::new("block").unwrap();
+        assert!(dom_set_display(panel_id, block.as_ptr()));
+        assert!(!dom_set_display(panel_id, block.as_ptr()));
+        assert_eq!(drain(), vec![Invalidation::Relayout as i32]);
+        let shown = panel_boxes();
+        assert_eq!(shown.len(), 1);
+        assert_eq!(shown[0].width, 300.0);
+
+        assert!(dom_set_visible(panel_id, false));
+        assert_eq!(drain(), vec![Invalidation::Repaint as i32]);
+        let visibility = CString::new("visibility").unwrap();
+        let style = dom_get_style(panel_id, visibility.as_ptr());
+        assert_eq!(safe_c_string_to_rust(style).unwrap(), "hidden");
+        crate::ffi::functions::free_c_string(style);
+        assert_eq!(panel_boxes().len(), 1);
+    }
+
+    /// Every event the host handler saw, with its target: (target, listener id, type, value, key, modifiers)
+    static RECEIVED: Mutex<Vec<(u32, u32, String, String, String, u32)>> = Mutex::new(Vec::new());
+
+    extern "C" fn record_event(callback_id: u32, event: *const DomEvent) {
+        let event = unsafe { &*event };
+        let text = |p: *const c_char| if p.is_null() { String::new() } else { unsafe { std::ffi::CStr::from_ptr(p) }.to_string_lossy().into_owned() };
+        // Listeners may read the DOM while handling the event
+        let live_value = dom_get_attribute(event.target, CString::new("value").unwrap().as_ptr());
+        crate::ffi::functions::free_c_string(live_value);
+        RECEIVED.lock().unwrap().push((event.target, callback_id, text(event.event_type), text(event.value), text(event.key), event.modifiers));
+        if callback_id == STOPPING_CALLBACK {
+            dom_stop_propagation();
+        }
+    }
+
+    /// The handler is global, so each test reads back only the events dispatched at its own nodes
+    fn received_at(target: u32) -> Vec<(u32, String, String, String, u32)> {
+        RECEIVED.lock().unwrap().iter()
+            .filter(|event| event.0 == target)
+            .map(|(_, callback_id, event_type, value, key, modifiers)| (*callback_id, event_type.clone(), value.clone(), key.clone(), *modifiers))
+            .collect()
+    }
+
+    /// Listener id `record_event` stops propagation for
+    const STOPPING_CALLBACK: u32 = 7102;
+
+    #[test]
+    fn test_dispatch_bubbles_to_ancestors_until_stopped() {
+        let mut parent = DOMNode::create_element("form");
+        let mut child = DOMNode::create_element("input");
+        child.attributes.insert("value".to_string(), String::new());
+        child.parent = Some(parent.id.clone());
+        parent.children.push(child.id.clone());
+        parent.event_listeners.insert("click".to_string(), vec![7101]);
+        parent.event_listeners.insert("submit".to_string(), vec![7103]);
+        child.event_listeners.insert("submit".to_string(), vec![STOPPING_CALLBACK]);
+        let child_id: u32 = child.id.parse().unwrap();
+        get_global_arena().add_node(parent);
+        get_global_arena().add_node(child);
+        dom_set_event_handler(Some(record_event));
+
+        let (click, submit) = (CString::new("click").unwrap(), CString::new("submit").unwrap());
+        assert!(dom_dispatch_event(child_id, click.as_ptr(), true));
+        assert!(!dom_dispatch_event(child_id, click.as_ptr(), false));
+        assert!(dom_dispatch_event(child_id, submit.as_ptr(), true));
+        let received: Vec<u32> = received_at(child_id).into_iter().map(|event| event.0).collect();
+        assert_eq!(received, vec![7101, STOPPING_CALLBACK]);
+    }
+
+    #[test]
+    fn test_input_and_key_events_reach_listeners() {
+        let mut form = DOMNode::create_element("form");
+        let mut input = DOMNode::create_element("input");
+        input.attributes.insert("value".to_string(), "old".to_string());
+        input.parent = Some(form.id.clone());
+        form.children.push(input.id.clone());
+        input.event_listeners.insert("input".to_string(), vec![7001]);
+        input.event_listeners.insert("keydown".to_string(), vec![7002]);
+        form.event_listeners.insert("input".to_string(), vec![7004]);
+        form.event_listeners.insert("keyup".to_string(), vec![7005]);
+        let input_id: u32 = input.id.parse().unwrap();
+        get_global_arena().add_node(form);
+        get_global_arena().add_node(input);
+        dom_set_event_handler(Some(record_event));
+
+        let typed = CString::new("hello").unwrap();
+        assert!(dom_dispatch_input_event(input_id, typed.as_ptr()));
+        let value = get_global_arena().get_node(&id_to_string(input_id)).unwrap().lock().unwrap().attributes["value"].clone();
+        assert_eq!(value, "hello");
+
+        let key = CString::new("Enter").unwrap();
+        assert!(dom_dispatch_key_event(input_id, key.as_ptr(), MODIFIER_SHIFT | MODIFIER_CTRL));
+        assert_eq!(received_at(input_id), vec![
+            (7001, "input".to_string(), "hello".to_string(), String::new(), 0),
+            (7004, "input".to_string(), "hello".to_string(), String::new(), 0),
+            (7002, "keydown".to_string(), String::new(), "Enter".to_string(), 3),
+            (7005, "keyup".to_string(), String::new(), "Enter".to_string(), 3),
+        ]);
+    }
+
+    #[test]
+    fn test_dispatch_skips_pointer_events_none() {
+        let click = CString::new("click").unwrap();
+        let overlay = add_clickable(Some("pointer-events: none"));
+        let button = add_clickable(None);
+
+        assert!(!dom_dispatch_event(overlay, click.as_ptr(), false));
+        assert!(dom_dispatch_event(button, click.as_ptr(), false));
+    }
+
+    #[test]
+    fn test_dispatch_skips_children_of_pointer_events_none() {
+        let click = CString::new("click").unwrap();
+        let overlay = add_clickable(Some("pointer-events: none"));
+        let inside = add_clickable(None);
+        let reenabled = add_clickable(Some("pointer-events: auto"));
+        for child in [inside, reenabled] {
+            dom_insert_before(overlay, child, 0);
+        }
+
+        assert!(!dom_dispatch_event(inside, click.as_ptr(), true));
+        assert!(dom_dispatch_event(reenabled, click.as_ptr(), false));
+    }
+
+    #[test]
+    fn test_void_elements_serialize_without_end_tag() {
+        let mut image = DOMNode::create_element("img");
+        image.attributes.insert("src".to_string(), "x".to_string());
+        let id: u32 = image.id.parse().unwrap();
+        get_global_arena().add_node(image);
+        let html = dom_get_outer_html(id);
+        assert_eq!(safe_c_string_to_rust(html).unwrap(), "<img src=\"x\">");
+        crate::ffi::functions::free_c_string(html);
+    }
+
+    #[test]
+    fn test_set_inner_html_parses_markup_into_children() {
+        let mut node = DOMNode::create_element("div");
+        let old_text = DOMNode::create_text_node("old");
+        let old_id = old_text.id.clone();
+        node.children.push(old_id.clone());
+        let id: u32 = node.id.parse().unwrap();
+        get_global_arena().add_node(node);
+        get_global_arena().add_node(old_text);
+
+        let markup = CString::new("<span class=\"x\">y</span>").unwrap();
+        dom_set_inner_html(id, markup.as_ptr());
+
+        let arena = get_global_arena();
+        assert!(arena.get_node(&old_id).is_none());
+        let children = arena.get_node(&id_to_string(id)).unwrap().lock().unwrap().children.clone();
+        assert_eq!(children.len(), 1);
+        let span = arena.get_node(&children[0]).unwrap().lock().unwrap().clone();
+        assert_eq!(span.node_type, NodeType::Element("span".to_string()));
+        assert_eq!(span.attributes["class"], "x");
+        assert_eq!(span.parent, Some(id_to_string(id)));
+        assert_eq!(span.children.len(), 1);
+        let text = arena.get_node(&span.children[0]).unwrap().lock().unwrap().clone();
+        assert_eq!((text.node_type, text.text_content.as_str()), (NodeType::Text, "y"));
+        assert_eq!(text.parent, Some(span.id.clone()));
+    }
+
+    #[test]
+    fn test_child_element_accessors_skip_text() {
+        let list = parse_and_find("<ul id=\"mixed-children\">one<li>a</li>two<li>b</li><li>c</li>three</ul>", "mixed-children");
+        assert_eq!(dom_get_child_element_count(list), 3);
+
+        let mut buf = [0u32; 8];
+        let count = dom_get_child_elements(list, buf.as_mut_ptr(), buf.len());
+        let arena = get_global_arena();
+        let types: Vec<NodeType> = buf[..count].iter().map(|&id| arena.get_node(&id_to_string(id)).unwrap().lock().unwrap().node_type.clone()).collect();
+        drop(arena);
+        assert_eq!(types, vec![NodeType::Element("li".to_string()); 3]);
+        assert_eq!(dom_get_child_elements(list, buf.as_mut_ptr(), 2), 2);
+    }
+
+    #[test]
+    fn test_insert_adjacent_html_positions() {
+        let mut parent = DOMNode::create_element("div");
+        let mut target = DOMNode::create_element("p");
+        let (mut before, mut after) = (DOMNode::create_element("header"), DOMNode::create_element("footer"));
+        let mut text = DOMNode::create_text_node("inside");
+        text.parent = Some(target.id.clone());
+        for child in [&mut before, &mut target, &mut after] {
+            child.parent = Some(parent.id.clone());
+        }
+        target.children.push(text.id.clone());
+        parent.children = vec![before.id.clone(), target.id.clone(), after.id.clone()];
+        let (parent_id, target_id) = (parent.id.clone(), target.id.clone());
+        let text_id: u32 = text.id.parse().unwrap();
+        for node in [parent, target, before, after, text] {
+            get_global_arena().add_node(node);
+        }
+
+        for (position, tag) in [("beforebegin", "a"), ("afterbegin", "b"), ("beforeend", "i"), ("afterend", "u")] {
+            let (position, markup) = (CString::new(position).unwrap(), CString::new(format!("<{0}>{0}</{0}>", tag)).unwrap());
+            assert!(dom_insert_adjacent_html(target_id.parse().unwrap(), position.as_ptr(), markup.as_ptr()));
+        }
+        for position in ["afterbegin", "beforeend"] {
+            let (position, markup) = (CString::new(position).unwrap(), CString::new("<s>s</s>").unwrap());
+            assert!(!dom_insert_adjacent_html(text_id, position.as_ptr(), markup.as_ptr()), "text nodes take no children");
+        }
+
+        let arena = get_global_arena();
+        let children = |id: &str| -> Vec<(String, Option<String>)> {
+            arena.get_node(id).unwrap().lock().unwrap().children.iter()
+                .map(|cid| arena.get_node(cid).unwrap().lock().unwrap().clone())
+                .map(|child| match child.node_type {
+                    NodeType::Element(tag) => (tag, child.parent),
+                    _ => (child.text_content, child.parent),
+                })
+                .collect()
+        };
+        let under = |parent: &str, names: &[&str]| -> Vec<(String, Option<String>)> {
+            names.iter().map(|name| (name.to_string(), Some(parent.to_string()))).collect()
+        };
+        assert_eq!(children(&parent_id), under(&parent_id, &["header", "a", "p", "u", "footer"]));
+        assert_eq!(children(&target_id), under(&target_id, &["b", "inside", "i"]));
+    }
+
+    #[test]
+    fn test_parsed_document_is_visible_to_dom_functions() {
+        let _parse = crate::ffi::TOP_LEVEL_PARSE.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let html = CString::new(r#"<body><a id="shared-arena-link" href="/index.html">Home</a></body>"#).unwrap();
+        let boxes = crate::ffi::functions::parse_html(html.as_ptr());
+        assert!(!boxes.is_null());
+        crate::ffi::functions::free_layout_box_array(boxes);
+
+        let link_id: u32 = get_global_arena().nodes.values()
+            .map(|node| node.lock().unwrap().clone())
+            .find(|node| node.attributes.get("id").map(String::as_str) == Some("shared-arena-link"))
+            .unwrap()
+            .id.parse().unwrap();
+        let name = CString::new("href").unwrap();
+        let href = dom_get_attribute(link_id, name.as_ptr());
+        assert_eq!(safe_c_string_to_rust(href).unwrap(), "/index.html");
+        crate::ffi::functions::free_c_string(href);
+    }
+
+    /// Parse `html` into the shared arena and return the id of the element with `id` attribute `key`
+    fn parse_and_find(html: &str, key: &str) -> u32 {
+        let mut arena = get_global_arena();
+        HTMLParser::new(html.to_string()).parse_into(&mut arena);
+        let node = arena.nodes.values()
+            .map(|node| node.lock().unwrap().clone())
+            .find(|node| node.attributes.get("id").map(String::as_str) == Some(key))
+            .unwrap();
+        node.id.parse().unwrap()
+    }
+
+    fn attribute_values(ids: &[u32], name: &str) -> Vec<String> {
+        let arena = get_global_arena();
+        ids.iter().map(|id| arena.get_node(&id_to_string(*id)).unwrap().lock().unwrap().attributes[name].clone()).collect()
+    }
+
+    #[test]
+    fn test_get_elements_by_class_and_tag_name() {
+        let root = parse_and_find(r#"<div id="by-class-root">
+            <p class="note big" title="1">a</p><p class="notes" title="2">b</p>
+            <section><span class=" big  note " title="3">c</span><p title="4">d</p></section>
+        </div>"#, "by-class-root");
+        let mut out = [0u32; 8];
+        let name = CString::new("note").unwrap();
+        let count = dom_get_elements_by_class_name(root, name.as_ptr(), out.as_mut_ptr(), out.len());
+        assert_eq!(attribute_values(&out[..count], "title"), ["1", "3"]);
+        let both = CString::new("big note").unwrap();
+        assert_eq!(dom_get_elements_by_class_name(root, both.as_ptr(), out.as_mut_ptr(), out.len()), 2);
+
+        let tag = CString::new("P").unwrap();
+        let count = dom_get_elements_by_tag_name(root, tag.as_ptr(), out.as_mut_ptr(), out.len());
+        assert_eq!(attribute_values(&out[..count], "title"), ["1", "2", "4"]);
+        let all = CString::new("*").unwrap();
+        assert_eq!(dom_get_elements_by_tag_name(root, all.as_ptr(), out.as_mut_ptr(), 2), 2);
+    }
+
+    #[test]
+    fn test_query_selector_all_takes_a_selector_list() {
+        let root = parse_and_find(r#"<div id="qsa-root"><p class="a" title="1">x</p>
+            <section><p id="b" class="a" title="2">y</p><span title="3" data-k="a,b">z</span></section></div>"#, "qsa-root");
+        let mut out = [0u32; 8];
+        let selector = CString::new(".a, #b").unwrap();
+        let count = dom_query_selector_all(root, selector.as_ptr(), out.as_mut_ptr(), out.len());
+        assert_eq!(attribute_values(&out[..count], "title"), ["1", "2"]);
+
+        let selector = CString::new("section > [data-k=\"a,b\"], div p#b").unwrap();
+        let count = dom_query_selector_all(root, selector.as_ptr(), out.as_mut_ptr(), out.len());
+        assert_eq!(attribute_values(&out[..count], "title"), ["2", "3"]);
+
+        // The element searched from is never one of its own results
+        let selector = CString::new("div, #b").unwrap();
+        let count = dom_query_selector_all(root, selector.as_ptr(), out.as_mut_ptr(), out.len());
+        assert_eq!(attribute_values(&out[..count], "title"), ["2"]);
+    }
+
+    #[test]
+    fn test_element_siblings_skip_whitespace_text() {
+        // <ul> <li>a</li> <li>b</li> </ul>, keeping the whitespace between the items
+        let mut list = DOMNode::create_element("ul");
+        let mut children = Vec::new();
+        for item in [None, Some("a"), None, Some("b"), None] {
+            let mut child = match item {
+                Some(text) => {
+                    let mut li = DOMNode::create_element("li");
+                    li.text_content = text.to_string();
+                    li
+                }
+                None => DOMNode::create_text_node(" "),
+            };
+            child.parent = Some(list.id.clone());
+            list.children.push(child.id.clone());
+            children.push(child.id.parse::<u32>().unwrap());
+            get_global_arena().add_node(child);
+        }
+        get_global_arena().add_node(list);
+        let (first, second) = (children[1], children[3]);
+
+        assert_eq!(dom_get_next_sibling(first), children[2]);
+        assert_eq!(dom_get_next_element_sibling(first), second);
+        assert_eq!(dom_get_previous_element_sibling(second), first);
+        assert_eq!(dom_get_next_element_sibling(second), 0);
+        assert_eq!(dom_get_previous_element_sibling(first), 0);
+    }
+
+    #[test]
+    fn test_matches_and_closest() {
+        let button = parse_and_find(r#"<ul class="menu" id="closest-menu"><li class="item"><button id="closest-button" class="go">Go</button></li></ul>"#, "closest-button");
+        let selector = |s: &str| CString::new(s).unwrap();
+
+        assert!(dom_matches(button, selector("ul button.go").as_ptr()));
+        assert!(dom_matches(button, selector("a, .go").as_ptr()));
+        assert!(!dom_matches(button, selector("li").as_ptr()));
+
+        assert_eq!(dom_closest(button, selector("button").as_ptr()), button);
+        let menu = dom_closest(button, selector("ul.menu").as_ptr());
+        assert_eq!(attribute_values(&[menu], "id"), ["closest-menu"]);
+        assert_eq!(dom_closest(button, selector(".missing").as_ptr()), 0);
+    }
+}
@@ -1,19 +1,18 @@
 // Draw command FFI functions for the browser engine
 // Extracted from functions.rs for modularization
 
-use crate::paint::painter::Painter;
-use crate::ffi::{DrawCommandArray, FFIPerformanceTracker, safe_rust_string_to_c, safe_c_string_to_rust, DrawCommand as FFIDrawCommand};
+use crate::paint::clip::{clip_shape, ClipShape};
+use crate::paint::decoration::{box_shadow, corner_radius};
+use crate::paint::painter::{is_ancestor_or_self, is_slanted, BorderSegment, Painter};
+use crate::paint::stacking::paint_order;
+use crate::paint::transform::{accumulated_transforms, Transform};
+use crate::ffi::{DrawCommandArray, FFIPerformanceTracker, GradientStop, release_c_string, safe_rust_string_to_c, safe_c_string_to_rust, DrawCommand as FFIDrawCommand, layout_engine_for, get_global_arena, parse_document};
 use crate::dom::node::LayoutBox;
 use crate::parser::html::HTMLParser;
-use crate::layout::layout::LayoutEngine;
-use std::ffi::{c_char, CString};
+use crate::style::color::Color;
+use crate::style::gradient::Gradient;
+use std::ffi::c_char;
 use std::ptr;
-use std::sync::Mutex;
-use once_cell::sync::Lazy;
-
-static ARENA: Lazy<Mutex<crate::dom::node::DOMArena>> = Lazy::new(|| {
-    Mutex::new(crate::dom::node::DOMArena::new())
-});
 
 #[no_mangle]
 pub extern "C" fn parse_html_to_draw_commands(input_ptr: *const c_char) -> *mut DrawCommandArray {
@@ -30,15 +29,15 @@ pub extern "C" fn parse_html_to_draw_commands(input_ptr: *const c_char) -> *mut
     tracker.record_stage("input_conversion", input_start.elapsed());
     let result = std::panic::catch_unwind(|| {
         let parse_start = std::time::Instant::now();
+        let mut arena = get_global_arena();
         let mut parser = HTMLParser::new(input_string);
-        let dom = parser.parse();
+        let dom = parse_document(&mut parser, &mut arena);
         let parse_duration = parse_start.elapsed();
         let css_start = std::time::Instant::now();
         let stylesheet = parser.get_stylesheet();
         let css_duration = css_start.elapsed();
         let layout_start = std::time::Instant::now();
-        let mut layout_engine = LayoutEngine::new(800.0, 600.0).with_stylesheet(stylesheet);
-        let arena = ARENA.lock().unwrap();
+        let layout_engine = layout_engine_for(stylesheet);
         let layout_boxes = layout_engine.layout(&dom, &*arena);
         let layout_duration = layout_start.elapsed();
         let draw_start = std::time::Instant::now();
@@ -69,35 +68,145 @@ pub extern "C" fn parse_html_to_draw_commands(input_ptr: *const c_char) -> *mut
 
 fn layout_boxes_to_draw_commands(layout_boxes: &[LayoutBox]) -> Vec<FFIDrawCommand> {
     let mut commands = Vec::new();
-    for layout_box in layout_boxes {
-        let rect_command = FFIDrawCommand {
-            command_type: 0,
+    let borders = Painter::border_segments(layout_boxes);
+    let text_descendants = Painter::text_descendants(layout_boxes);
+    let transforms = accumulated_transforms(layout_boxes);
+    // Open opacity groups and clips as (owning box, closing command), innermost last
+    let mut open: Vec<(usize, FFIDrawCommand)> = Vec::new();
+    // Stacking order, so positioned and z-indexed boxes paint over what they overlap
+    for index in paint_order(layout_boxes) {
+        while let Some((owner, _)) = open.last() {
+            if is_ancestor_or_self(layout_boxes, *owner, index) {
+                break;
+            }
+            commands.extend(open.pop().map(|(_, close)| close));
+        }
+        let layout_box = &layout_boxes[index];
+        let first_command = commands.len();
+        // The group encloses the clip, so opacity applies to the clipped result
+        if layout_box.opacity < 1.0 {
+            commands.push(FFIDrawCommand { command_type: 9, opacity: layout_box.opacity.max(0.0), ..Default::default() });
+            open.push((index, FFIDrawCommand { command_type: 10, ..Default::default() }));
+        }
+        // `clip-path` limits the box and its descendants until the matching end_clip
+        if let Some(shape) = clip_shape(layout_box) {
+            commands.push(clip_command(shape));
+            open.push((index, FFIDrawCommand { command_type: 8, ..Default::default() }));
+        }
+        box_commands(layout_boxes, index, &borders, &text_descendants, &mut commands);
+        // Everything drawn for the box moves with its transform
+        let Transform { a, b, c, d, e, f } = transforms[index];
+        for command in &mut commands[first_command..] {
+            (command.a, command.b, command.c, command.d, command.e, command.f) = (a, b, c, d, e, f);
+        }
+    }
+    commands.extend(open.into_iter().rev().map(|(_, close)| close));
+    commands
+}
+
+/// Emit the draw commands for a single box; `borders` and `text_descendants` come from the
+/// `Painter` functions of the same names
+fn box_commands(layout_boxes: &[LayoutBox], index: usize, borders: &[BorderSegment], text_descendants: &[bool], commands: &mut Vec<FFIDrawCommand>) {
+    let layout_box = &layout_boxes[index];
+    // `visibility: hidden` keeps the box's space but draws nothing
+    if layout_box.visibility == "hidden" {
+        return;
+    }
+    let corner_radius = corner_radius(layout_box);
+    // The shadow sits behind the box, with the box's corners
+    if let Some(shadow) = box_shadow(layout_box) {
+        commands.push(FFIDrawCommand {
+            command_type: 5,
+            x: layout_box.x,
+            y: layout_box.y,
+            width: layout_box.width,
+            height: layout_box.height,
+            color: safe_rust_string_to_c(&Color::parse_or(&shadow.color, Color::BLACK).to_argb_hex()),
+            corner_radius,
+            shadow_offset_x: shadow.offset_x,
+            shadow_offset_y: shadow.offset_y,
+            shadow_blur: shadow.blur,
+            shadow_spread: shadow.spread,
+            ..Default::default()
+        });
+    }
+    let background_command = match Gradient::parse(&layout_box.background_image) {
+        // A gradient is drawn instead of the flat background rect
+        Some(gradient) => {
+            let stops: Box<[GradientStop]> = gradient.stops.iter()
+                .map(|(color, offset)| GradientStop { color: color.to_argb(), offset: *offset })
+                .collect();
+            FFIDrawCommand {
+                command_type: 6,
+                x: layout_box.x,
+                y: layout_box.y,
+                width: layout_box.width,
+                height: layout_box.height,
+                corner_radius,
+                gradient_angle: gradient.angle,
+                gradient_stop_count: stops.len() as i32,
+                gradient_stops: Box::into_raw(stops) as *mut GradientStop,
+                ..Default::default()
+            }
+        }
+        None => FFIDrawCommand {
+            command_type: if corner_radius > 0.0 { 4 } else { 0 },
             x: layout_box.x,
             y: layout_box.y,
             width: layout_box.width,
             height: layout_box.height,
-            color: safe_rust_string_to_c(""),
+            color: safe_rust_string_to_c(&Color::parse_or(&layout_box.background_color, Color::TRANSPARENT).to_argb_hex()),
+            text: ptr::null_mut(),
+            font_size: 0.0,
+            font_weight: 0.0,
+            italic: false,
+            corner_radius,
+            ..Default::default()
+        },
+    };
+    commands.push(background_command);
+    for segment in borders.iter().filter(|segment| segment.owner == index) {
+        commands.push(FFIDrawCommand {
+            command_type: 0,
+            x: segment.x,
+            y: segment.y,
+            width: segment.width,
+            height: segment.height,
+            color: safe_rust_string_to_c(&Color::parse_or(&segment.color, Color::BLACK).to_argb_hex()),
             text: ptr::null_mut(),
             font_size: 0.0,
             font_weight: 0.0,
+            italic: false,
+            ..Default::default()
+        });
+    }
+    // An element's text is drawn by its text boxes when it has any
+    if !layout_box.text_content.is_empty() && (layout_box.node_type == "text" || !text_descendants[index]) {
+        let text_command = FFIDrawCommand {
+            command_type: 1,
+            x: layout_box.x + 2.0,
+            y: layout_box.y + layout_box.font_size + 2.0,
+            width: layout_box.width - 4.0,
+            height: layout_box.font_size,
+            color: safe_rust_string_to_c(&Color::parse_or(&layout_box.color, Color::BLACK).to_argb_hex()),
+            text: safe_rust_string_to_c(&layout_box.text_content),
+            font_size: layout_box.font_size,
+            font_weight: layout_box.font_weight,
+            italic: is_slanted(&layout_box.font_style),
+            ..Default::default()
         };
-        commands.push(rect_command);
-        if !layout_box.text_content.is_empty() {
-            let text_command = FFIDrawCommand {
-                command_type: 1,
-                x: layout_box.x + 2.0,
-                y: layout_box.y + layout_box.font_size + 2.0,
-                width: layout_box.width - 4.0,
-                height: layout_box.font_size,
-                color: safe_rust_string_to_c(""),
-                text: safe_rust_string_to_c(&layout_box.text_content),
-                font_size: layout_box.font_size,
-                font_weight: layout_box.font_weight,
-            };
-            commands.push(text_command);
-        }
+        commands.push(text_command);
     }
-    commands
+}
+
+/// The clip command for `shape`; a circle is sent as its bounding square with the radius
+/// as `corner_radius`
+fn clip_command(shape: ClipShape) -> FFIDrawCommand {
+    let (x, y, width, height, corner_radius) = match shape {
+        ClipShape::Rect { x, y, w, h } => (x, y, w, h, 0.0),
+        ClipShape::Circle { cx, cy, r } => (cx - r, cy - r, r * 2.0, r * 2.0, r),
+    };
+    FFIDrawCommand { command_type: 7, x, y, width, height, corner_radius, ..Default::default() }
 }
 
 #[no_mangle]
@@ -133,6 +242,40 @@ pub extern "C" fn get_draw_command(cmd_array_ptr: *mut DrawCommandArray, index:
     }
 }
 
+/// Copy up to `count` command pointers starting at `start` into `out_ptr`, like
+/// `get_layout_box_batch_enhanced`. Returns how many were written.
+#[no_mangle]
+pub extern "C" fn get_draw_command_batch(
+    cmd_array_ptr: *mut DrawCommandArray,
+    start: i32,
+    count: i32,
+    out_ptr: *mut *mut FFIDrawCommand,
+) -> i32 {
+    let result = std::panic::catch_unwind(|| {
+        if cmd_array_ptr.is_null() || out_ptr.is_null() || start < 0 || count <= 0 {
+            println!("[FFI] get_draw_command_batch: invalid arguments");
+            return 0;
+        }
+        let cmd_array = unsafe { &*cmd_array_ptr };
+        let len = cmd_array.commands.len() as i32;
+        let end = start.saturating_add(count).min(len);
+        let actual_count = (end - start).max(0);
+        for i in 0..actual_count {
+            unsafe {
+                *out_ptr.offset(i as isize) = cmd_array.commands[(start + i) as usize];
+            }
+        }
+        actual_count
+    });
+    match result {
+        Ok(n) => n,
+        Err(_) => {
+            eprintln!("[FFI] get_draw_command_batch: panic caught!");
+            0
+        }
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn free_draw_command_array(cmd_array_ptr: *mut DrawCommandArray) {
     if !cmd_array_ptr.is_null() {
@@ -141,14 +284,266 @@ pub extern "C" fn free_draw_command_array(cmd_array_ptr: *mut DrawCommandArray)
             for cmd_ptr in cmd_array.commands {
                 if !cmd_ptr.is_null() {
                     let cmd = Box::from_raw(cmd_ptr);
-                    if !cmd.color.is_null() {
-                        let _ = CString::from_raw(cmd.color);
-                    }
-                    if !cmd.text.is_null() {
-                        let _ = CString::from_raw(cmd.text);
+                    release_c_string(cmd.color);
+                    release_c_string(cmd.text);
+                    if !cmd.gradient_stops.is_null() {
+                        let stops = ptr::slice_from_raw_parts_mut(cmd.gradient_stops, cmd.gradient_stop_count as usize);
+                        let _ = Box::from_raw(stops);
                     }
                 }
             }
         }
     }
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::layout::LayoutEngine;
+
+    fn rect_command(x: f32) -> FFIDrawCommand {
+        FFIDrawCommand {
+            command_type: 0,
+            x,
+            y: 0.0,
+            width: 1.0,
+            height: 1.0,
+            color: ptr::null_mut(),
+            text: ptr::null_mut(),
+            font_size: 0.0,
+            font_weight: 0.0,
+            italic: false,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_draw_command_batches_cover_array_once() {
+        let array = Box::into_raw(Box::new(DrawCommandArray::new((0..35).map(|i| rect_command(i as f32)).collect())));
+        assert_eq!(get_draw_command_count(array), 35);
+
+        let mut seen = Vec::new();
+        let mut out = [ptr::null_mut(); 10];
+        let mut start = 0;
+        loop {
+            let written = get_draw_command_batch(array, start, 10, out.as_mut_ptr());
+            if written == 0 {
+                break;
+            }
+            seen.extend(out[..written as usize].iter().map(|&cmd| unsafe { (*cmd).x }));
+            start += written;
+        }
+        assert_eq!(seen, (0..35).map(|i| i as f32).collect::<Vec<_>>());
+
+        assert_eq!(get_draw_command_batch(array, 40, 10, out.as_mut_ptr()), 0);
+        assert_eq!(get_draw_command_batch(array, -1, 10, out.as_mut_ptr()), 0);
+        assert_eq!(get_draw_command_batch(array, 0, 10, ptr::null_mut()), 0);
+        free_draw_command_array(array);
+    }
+
+    #[test]
+    fn test_border_radius_and_box_shadow_commands() {
+        let mut parser = HTMLParser::new(r#"<body><div style="width:100px;height:50px;border-radius:8px;box-shadow:2px 4px 6px rgba(0, 0, 0, 0.5)">Card</div></body>"#.to_string()).with_reuse();
+        let dom = parser.parse();
+        let boxes = LayoutEngine::new(800.0, 600.0).layout(&dom, parser.arena().unwrap());
+        let commands = layout_boxes_to_draw_commands(&boxes);
+
+        let rounded: Vec<&FFIDrawCommand> = commands.iter().filter(|cmd| cmd.command_type == 4).collect();
+        assert_eq!(rounded.len(), 1);
+        assert_eq!((rounded[0].corner_radius, rounded[0].width), (8.0, 100.0));
+        let shadow = commands.iter().position(|cmd| cmd.command_type == 5).unwrap();
+        let cmd = &commands[shadow];
+        assert_eq!((cmd.shadow_offset_x, cmd.shadow_offset_y, cmd.shadow_blur, cmd.shadow_spread, cmd.corner_radius), (2.0, 4.0, 6.0, 0.0, 8.0));
+        assert_eq!(unsafe { std::ffi::CStr::from_ptr(cmd.color) }.to_str().unwrap(), "#80000000");
+        assert_eq!(commands[shadow + 1].command_type, 4, "shadow is drawn right before its box");
+        assert!(commands.iter().filter(|cmd| cmd.command_type != 4 && cmd.command_type != 5).all(|cmd| cmd.corner_radius == 0.0));
+    }
+
+    #[test]
+    fn test_transform_matrix_on_commands() {
+        let matrix_for = |style: &str| {
+            let html = format!(r#"<body><div style="width:100px;height:50px;background-color:red;{}"></div></body>"#, style);
+            let mut parser = HTMLParser::new(html).with_reuse();
+            let dom = parser.parse();
+            let boxes = LayoutEngine::new(800.0, 600.0).layout(&dom, parser.arena().unwrap());
+            let div = boxes.iter().position(|b| b.node_type == "div").unwrap();
+            let commands = layout_boxes_to_draw_commands(&boxes);
+            let cmd = commands.iter().find(|cmd| cmd.command_type == 0 && cmd.width == 100.0).unwrap();
+            let matrix = Transform { a: cmd.a, b: cmd.b, c: cmd.c, d: cmd.d, e: cmd.e, f: cmd.f };
+            let (x, y) = (boxes[div].x, boxes[div].y);
+            (matrix, matrix.apply(x, y), matrix.apply(x + 100.0, y + 50.0), (x, y))
+        };
+
+        let (identity, ..) = matrix_for("");
+        assert_eq!(identity, Transform::IDENTITY);
+
+        let (translated, top_left, _, (x, y)) = matrix_for("transform:translate(10px, 20px)");
+        assert_eq!((translated.e, translated.f), (10.0, 20.0));
+        assert_eq!(top_left, (x + 10.0, y + 20.0));
+
+        let (scaled, top_left, bottom_right, _) = matrix_for("transform:scale(2)");
+        assert_eq!((scaled.a, scaled.d), (2.0, 2.0));
+        assert_eq!((bottom_right.0 - top_left.0, bottom_right.1 - top_left.1), (200.0, 100.0));
+
+        // Left to right: translate, then scale from the translated origin
+        let (composed, top_left, _, (x, y)) = matrix_for("transform:translate(10px, 0) scale(2);transform-origin:0 0");
+        assert_eq!(composed.a, 2.0);
+        assert_eq!(top_left, (x + 10.0, y));
+    }
+
+    #[test]
+    fn test_gradient_replaces_background_rect() {
+        let mut parser = HTMLParser::new(r#"<body><div style="width:100px;height:50px;background:linear-gradient(90deg, red 0%, blue 100%)"></div></body>"#.to_string()).with_reuse();
+        let dom = parser.parse();
+        let boxes = LayoutEngine::new(800.0, 600.0).layout(&dom, parser.arena().unwrap());
+        let commands = layout_boxes_to_draw_commands(&boxes);
+
+        let gradient = commands.iter().find(|cmd| cmd.command_type == 6).unwrap();
+        assert_eq!((gradient.width, gradient.gradient_angle), (100.0, 90.0));
+        let stops = unsafe { std::slice::from_raw_parts(gradient.gradient_stops, gradient.gradient_stop_count as usize) };
+        assert_eq!(stops, [GradientStop { color: 0xffff0000, offset: 0.0 }, GradientStop { color: 0xff0000ff, offset: 1.0 }]);
+        assert!(!commands.iter().any(|cmd| cmd.command_type == 0 && cmd.width == 100.0 && cmd.height == 50.0));
+        free_draw_command_array(Box::into_raw(Box::new(DrawCommandArray::new(commands))));
+    }
+
+    #[test]
+    fn test_gradient_is_found_among_other_background_shorthand_tokens() {
+        let mut parser = HTMLParser::new(r#"<body><div style="width:100px;height:50px;background:#fff linear-gradient(to right, red, blue) no-repeat"></div></body>"#.to_string()).with_reuse();
+        let dom = parser.parse();
+        let boxes = LayoutEngine::new(800.0, 600.0).layout(&dom, parser.arena().unwrap());
+        let commands = layout_boxes_to_draw_commands(&boxes);
+
+        let gradient = commands.iter().find(|cmd| cmd.command_type == 6).unwrap();
+        assert_eq!((gradient.width, gradient.gradient_angle, gradient.gradient_stop_count), (100.0, 90.0, 2));
+        free_draw_command_array(Box::into_raw(Box::new(DrawCommandArray::new(commands))));
+    }
+
+    #[test]
+    fn test_colors_cross_as_argb_like_gradient_stops() {
+        let mut parser = HTMLParser::new(r#"<body><div style="width:100px;height:50px;background-color:rgba(255, 128, 0, 0.5)"></div></body>"#.to_string()).with_reuse();
+        let dom = parser.parse();
+        let boxes = LayoutEngine::new(800.0, 600.0).layout(&dom, parser.arena().unwrap());
+        let commands = layout_boxes_to_draw_commands(&boxes);
+
+        let rect = commands.iter().find(|cmd| cmd.command_type == 0 && cmd.width == 100.0).unwrap();
+        let hex = unsafe { std::ffi::CStr::from_ptr(rect.color) }.to_str().unwrap();
+        // The host reads 8 hex digits as AARRGGBB, the packing gradient stops use
+        let packed = u32::from_str_radix(hex.trim_start_matches('#'), 16).unwrap();
+        assert_eq!(packed, Color::parse("rgba(255, 128, 0, 0.5)").unwrap().to_argb());
+        assert_eq!(packed, 0x80ff8000);
+        free_draw_command_array(Box::into_raw(Box::new(DrawCommandArray::new(commands))));
+    }
+
+    #[test]
+    fn test_commands_follow_z_index() {
+        let html = r#"<body><div style="position:relative;z-index:10;background-color:red">top</div><div style="position:relative;z-index:1;background-color:blue">below</div><p style="background-color:green">flow</p></body>"#;
+        let mut parser = HTMLParser::new(html.to_string()).with_reuse();
+        let dom = parser.parse();
+        let boxes = LayoutEngine::new(800.0, 600.0).layout(&dom, parser.arena().unwrap());
+        let commands = layout_boxes_to_draw_commands(&boxes);
+
+        let position_of = |color: &str| commands.iter()
+            .position(|cmd| !cmd.color.is_null() && unsafe { std::ffi::CStr::from_ptr(cmd.color) }.to_str() == Ok(color))
+            .unwrap();
+        let (red, blue, green) = (position_of("#ffff0000"), position_of("#ff0000ff"), position_of("#ff008000"));
+        assert!(green < blue && blue < red, "in-flow content, then z-index 1, then z-index 10");
+    }
+
+    #[test]
+    fn test_clip_path_brackets_subtree_with_clip_commands() {
+        let html = r#"<body><div style="width:100px;height:100px;clip-path:inset(10px);background-color:red"><p style="background-color:blue">inside</p></div><p style="background-color:green">after</p><div style="width:40px;height:40px;clip-path:circle(50%)"></div></body>"#;
+        let mut parser = HTMLParser::new(html.to_string()).with_reuse();
+        let dom = parser.parse();
+        let boxes = LayoutEngine::new(800.0, 600.0).layout(&dom, parser.arena().unwrap());
+        let commands = layout_boxes_to_draw_commands(&boxes);
+
+        let position_of = |color: &str| commands.iter()
+            .position(|cmd| !cmd.color.is_null() && unsafe { std::ffi::CStr::from_ptr(cmd.color) }.to_str() == Ok(color))
+            .unwrap();
+        let clips: Vec<usize> = commands.iter().enumerate().filter(|(_, cmd)| cmd.command_type == 7).map(|(i, _)| i).collect();
+        let ends: Vec<usize> = commands.iter().enumerate().filter(|(_, cmd)| cmd.command_type == 8).map(|(i, _)| i).collect();
+        assert_eq!((clips.len(), ends.len()), (2, 2));
+        let (red, blue, green) = (position_of("#ffff0000"), position_of("#ff0000ff"), position_of("#ff008000"));
+        assert!(clips[0] < red && red < blue && blue < ends[0] && ends[0] < green, "the clip covers the div and its child only");
+
+        let div = boxes.iter().find(|b| b.clip_path.starts_with("inset")).unwrap();
+        let inset = &commands[clips[0]];
+        assert_eq!((inset.x, inset.y, inset.width, inset.height, inset.corner_radius), (div.x + 10.0, div.y + 10.0, 80.0, 80.0, 0.0));
+        let circle_div = boxes.iter().find(|b| b.clip_path.starts_with("circle")).unwrap();
+        let circle = &commands[clips[1]];
+        assert_eq!((circle.x, circle.y, circle.width, circle.corner_radius), (circle_div.x, circle_div.y, 40.0, 20.0));
+        free_draw_command_array(Box::into_raw(Box::new(DrawCommandArray::new(commands))));
+    }
+
+    #[test]
+    fn test_opacity_groups_enclose_subtree_and_clip() {
+        let html = r#"<body><div style="opacity:0.5;clip-path:inset(5px);background-color:red"><p style="background-color:blue">faded</p></div></body>"#;
+        let mut parser = HTMLParser::new(html.to_string()).with_reuse();
+        let dom = parser.parse();
+        let boxes = LayoutEngine::new(800.0, 600.0).layout(&dom, parser.arena().unwrap());
+        let commands = layout_boxes_to_draw_commands(&boxes);
+
+        let position_of = |color: &str| commands.iter()
+            .position(|cmd| !cmd.color.is_null() && unsafe { std::ffi::CStr::from_ptr(cmd.color) }.to_str() == Ok(color))
+            .unwrap();
+        let type_position = |command_type: i32| commands.iter().position(|cmd| cmd.command_type == command_type).unwrap();
+        let (begin, clip, end_clip, end) = (type_position(9), type_position(7), type_position(8), type_position(10));
+        let (red, blue) = (position_of("#ffff0000"), position_of("#ff0000ff"));
+        assert!(begin < clip && clip < red && red < blue && blue < end_clip && end_clip < end);
+        assert!(commands[end + 1..].iter().all(|cmd| cmd.command_type != 1), "the child's text is inside the group");
+        assert_eq!(commands[begin].opacity, 0.5);
+        free_draw_command_array(Box::into_raw(Box::new(DrawCommandArray::new(commands))));
+    }
+
+    #[test]
+    fn test_hidden_boxes_draw_nothing() {
+        let html = r#"<body><div style="visibility:hidden;background-color:red;box-shadow:2px 2px red">secret<p style="visibility:visible;background-color:blue">shown</p></div></body>"#;
+        let mut parser = HTMLParser::new(html.to_string()).with_reuse();
+        let dom = parser.parse();
+        let boxes = LayoutEngine::new(800.0, 600.0).layout(&dom, parser.arena().unwrap());
+        let commands = layout_boxes_to_draw_commands(&boxes);
+
+        let string = |ptr: *mut c_char| if ptr.is_null() { String::new() } else { unsafe { std::ffi::CStr::from_ptr(ptr) }.to_string_lossy().into_owned() };
+        let colors: Vec<String> = commands.iter().map(|cmd| string(cmd.color)).collect();
+        let texts: Vec<String> = commands.iter().map(|cmd| string(cmd.text)).filter(|text| !text.is_empty()).collect();
+        assert!(!colors.contains(&"#ffff0000".to_string()), "neither the hidden box nor its shadow is drawn");
+        assert!(colors.contains(&"#ff0000ff".to_string()), "a visible child of a hidden box still draws");
+        assert!(!texts.contains(&"secret".to_string()), "{:?}", texts);
+        free_draw_command_array(Box::into_raw(Box::new(DrawCommandArray::new(commands))));
+    }
+
+    #[test]
+    fn test_text_is_drawn_once_by_its_text_box() {
+        let html = r#"<body><div><p>first</p><span>second</span></div><button>label</button></body>"#;
+        let mut parser = HTMLParser::new(html.to_string()).with_reuse();
+        let dom = parser.parse();
+        let boxes = LayoutEngine::new(800.0, 600.0).layout(&dom, parser.arena().unwrap());
+        let commands = layout_boxes_to_draw_commands(&boxes);
+
+        let mut texts: Vec<String> = commands.iter()
+            .filter(|cmd| cmd.command_type == 1)
+            .map(|cmd| unsafe { std::ffi::CStr::from_ptr(cmd.text) }.to_string_lossy().trim().to_string())
+            .collect();
+        texts.sort();
+        assert_eq!(texts, ["first", "label", "second"], "ancestors don't repeat their descendants' text");
+        free_draw_command_array(Box::into_raw(Box::new(DrawCommandArray::new(commands))));
+    }
+
+    #[test]
+    fn test_box_shadow_color_goes_before_or_after_the_lengths() {
+        let html = r#"<body><div style="box-shadow:red 2px 4px">a</div><div style="box-shadow:2px 4px 3px blue">b</div><div style="box-shadow:2px red 4px">c</div></body>"#;
+        let mut parser = HTMLParser::new(html.to_string()).with_reuse();
+        let dom = parser.parse();
+        let boxes = LayoutEngine::new(800.0, 600.0).layout(&dom, parser.arena().unwrap());
+
+        let shadows: Vec<Option<(f32, f32, f32, String)>> = boxes.iter()
+            .filter(|b| b.node_type == "div")
+            .map(|b| box_shadow(b).map(|shadow| (shadow.offset_x, shadow.offset_y, shadow.blur, shadow.color)))
+            .collect();
+        assert_eq!(shadows, vec![
+            Some((2.0, 4.0, 0.0, "red".to_string())),
+            Some((2.0, 4.0, 3.0, "blue".to_string())),
+            None,
+        ], "the color may lead or trail, but the lengths are one run");
+    }
+}
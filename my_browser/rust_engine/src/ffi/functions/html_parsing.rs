@@ -1,17 +1,12 @@
 use std::ffi::c_char;
 use std::ptr;
-use crate::ffi::{LayoutBoxArray, FFIPerformanceTracker, safe_c_string_to_rust, process_html_streaming};
+use crate::ffi::{LayoutBoxArray, FFIPerformanceTracker, safe_c_string_to_rust, safe_rust_string_to_c, process_html_streaming, viewport, layout_engine_for, loader, get_global_arena, parse_document};
+use crate::ffi::{clear_last_error, GLOBAL_STYLE_CACHE, last_error, panic_message, set_last_error, FFIErrorKind};
 use crate::parser::html::HTMLParser;
-use crate::parser::css::parse_css;
-use crate::layout::layout::LayoutEngine;
-use crate::paint::painter::Painter;
+use crate::parser::css::{parse_css, Stylesheet};
 use crate::compositor::compositor::Compositor;
-use crate::dom::node::DOMArena;
 use crate::VeloxEngine;
-use std::sync::Mutex;
-use once_cell::sync::Lazy;
-
-static ARENA: Lazy<Mutex<DOMArena>> = Lazy::new(|| Mutex::new(DOMArena::new()));
+use crate::loader::{resolve_url, ResourceLoader};
 
 // HTML parsing with JavaScript execution
 #[no_mangle]
@@ -32,22 +27,21 @@ pub extern "C" fn parse_html_with_javascript(html_ptr: *const c_char) -> *mut La
             println!("[PERF] WARNING: Large input detected ({}bytes)", input_string.len());
         }
         let parse_start = std::time::Instant::now();
+        let mut arena = get_global_arena();
         let mut parser = HTMLParser::new(input_string);
-        let dom = parser.parse();
+        let dom = parse_document(&mut parser, &mut arena);
         let parse_duration = parse_start.elapsed();
         println!("[FFI] DOM parsed with {} nodes", dom.children.len());
         let css_start = std::time::Instant::now();
         let stylesheet = parser.get_stylesheet();
         let css_duration = css_start.elapsed();
         let layout_start = std::time::Instant::now();
-        let mut layout_engine = LayoutEngine::new(800.0, 600.0).with_stylesheet(stylesheet);
-        let arena = ARENA.lock().unwrap();
+        let layout_engine = layout_engine_for(stylesheet);
         let layout_boxes = layout_engine.layout(&dom, &*arena);
         let layout_duration = layout_start.elapsed();
         let paint_start = std::time::Instant::now();
-        let display_list = Painter::from_layout_boxes(&layout_boxes);
         let compositor = Compositor::new();
-        let _composited_list = compositor.composite(display_list);
+        let _composited_list = compositor.composite(&layout_boxes);
         let paint_duration = paint_start.elapsed();
         println!("[FFI] Generated {} layout boxes", layout_boxes.len());
         let conversion_start = std::time::Instant::now();
@@ -77,11 +71,12 @@ pub extern "C" fn parse_html_with_javascript(html_ptr: *const c_char) -> *mut La
 pub extern "C" fn parse_html(input_ptr: *const c_char) -> *mut LayoutBoxArray {
     let mut tracker = FFIPerformanceTracker::new();
     println!("[FFI] parse_html called");
+    clear_last_error();
     let input_start = std::time::Instant::now();
     let input_string = match safe_c_string_to_rust(input_ptr) {
         Ok(s) => s,
         Err(e) => {
-            eprintln!("[FFI] Input conversion failed: {}", e);
+            set_last_error(FFIErrorKind::Input, &e);
             return ptr::null_mut();
         }
     };
@@ -91,22 +86,21 @@ pub extern "C" fn parse_html(input_ptr: *const c_char) -> *mut LayoutBoxArray {
             println!("[PERF] WARNING: Large input detected ({}bytes)", input_string.len());
         }
         let parse_start = std::time::Instant::now();
+        let mut arena = get_global_arena();
         let mut parser = HTMLParser::new(input_string);
-        let dom = parser.parse();
+        let dom = parse_document(&mut parser, &mut arena);
         let parse_duration = parse_start.elapsed();
         println!("[FFI] DOM parsed with {} nodes", dom.children.len());
         let css_start = std::time::Instant::now();
         let stylesheet = parser.get_stylesheet();
         let css_duration = css_start.elapsed();
         let layout_start = std::time::Instant::now();
-        let mut layout_engine = LayoutEngine::new(800.0, 600.0).with_stylesheet(stylesheet);
-        let arena = ARENA.lock().unwrap();
+        let layout_engine = layout_engine_for(stylesheet);
         let layout_boxes = layout_engine.layout(&dom, &*arena);
         let layout_duration = layout_start.elapsed();
         let paint_start = std::time::Instant::now();
-        let display_list = Painter::from_layout_boxes(&layout_boxes);
         let compositor = Compositor::new();
-        let _composited_list = compositor.composite(display_list);
+        let _composited_list = compositor.composite(&layout_boxes);
         let paint_duration = paint_start.elapsed();
         println!("[FFI] Generated {} layout boxes", layout_boxes.len());
         let conversion_start = std::time::Instant::now();
@@ -124,8 +118,8 @@ pub extern "C" fn parse_html(input_ptr: *const c_char) -> *mut LayoutBoxArray {
             tracker.log_performance();
             Box::into_raw(Box::new(layout_array))
         }
-        Err(_) => {
-            eprintln!("[FFI] parse_html: panic caught!");
+        Err(payload) => {
+            set_last_error(FFIErrorKind::Panic, &format!("parse_html: {}", panic_message(&*payload)));
             ptr::null_mut()
         }
     }
@@ -136,18 +130,19 @@ pub extern "C" fn parse_html(input_ptr: *const c_char) -> *mut LayoutBoxArray {
 pub extern "C" fn parse_html_with_css(html_ptr: *const c_char, css_ptr: *const c_char) -> *mut LayoutBoxArray {
     let mut tracker = FFIPerformanceTracker::new();
     println!("[FFI] parse_html_with_css called");
+    clear_last_error();
     let input_start = std::time::Instant::now();
     let html_string = match safe_c_string_to_rust(html_ptr) {
         Ok(s) => s,
         Err(e) => {
-            eprintln!("[FFI] HTML input conversion failed: {}", e);
+            set_last_error(FFIErrorKind::Input, &format!("HTML: {}", e));
             return ptr::null_mut();
         }
     };
     let css_string = match safe_c_string_to_rust(css_ptr) {
         Ok(s) => s,
         Err(e) => {
-            eprintln!("[FFI] CSS input conversion failed: {}", e);
+            set_last_error(FFIErrorKind::Input, &format!("CSS: {}", e));
             return ptr::null_mut();
         }
     };
@@ -157,26 +152,24 @@ pub extern "C" fn parse_html_with_css(html_ptr: *const c_char, css_ptr: *const c
             println!("[PERF] WARNING: Large HTML input detected ({}bytes)", html_string.len());
         }
         let parse_start = std::time::Instant::now();
+        let mut arena = get_global_arena();
         let mut parser = HTMLParser::new(html_string);
-        let dom = parser.parse();
+        let dom = parse_document(&mut parser, &mut arena);
         let parse_duration = parse_start.elapsed();
         println!("[FFI] DOM parsed with {} nodes", dom.children.len());
         let css_start = std::time::Instant::now();
         let mut stylesheet = parser.get_stylesheet();
         if !css_string.is_empty() {
-            let additional_css = parse_css(&css_string);
-            stylesheet.rules.extend(additional_css.rules);
+            stylesheet.append(parse_css(&css_string));
         }
         let css_duration = css_start.elapsed();
         let layout_start = std::time::Instant::now();
-        let mut layout_engine = LayoutEngine::new(800.0, 600.0).with_stylesheet(stylesheet);
-        let arena = ARENA.lock().unwrap();
+        let layout_engine = layout_engine_for(stylesheet);
         let layout_boxes = layout_engine.layout(&dom, &*arena);
         let layout_duration = layout_start.elapsed();
         let paint_start = std::time::Instant::now();
-        let display_list = Painter::from_layout_boxes(&layout_boxes);
         let compositor = Compositor::new();
-        let _composited_list = compositor.composite(display_list);
+        let _composited_list = compositor.composite(&layout_boxes);
         let paint_duration = paint_start.elapsed();
         println!("[FFI] Generated {} layout boxes", layout_boxes.len());
         let conversion_start = std::time::Instant::now();
@@ -194,8 +187,8 @@ pub extern "C" fn parse_html_with_css(html_ptr: *const c_char, css_ptr: *const c
             tracker.log_performance();
             Box::into_raw(Box::new(layout_array))
         }
-        Err(_) => {
-            eprintln!("[FFI] parse_html_with_css: panic caught!");
+        Err(payload) => {
+            set_last_error(FFIErrorKind::Panic, &format!("parse_html_with_css: {}", panic_message(&*payload)));
             ptr::null_mut()
         }
     }
@@ -214,7 +207,9 @@ pub extern "C" fn parse_html_with_css_and_images(input_ptr: *const c_char) -> *m
         }
     };
     let result = std::panic::catch_unwind(|| {
-        let mut engine = VeloxEngine::new(800.0, 600.0);
+        let (width, height) = viewport();
+        let mut engine = VeloxEngine::new(width, height);
+        engine.layout_engine = engine.layout_engine.with_style_cache(GLOBAL_STYLE_CACHE.clone());
         let runtime = tokio::runtime::Runtime::new().unwrap();
         let layout_boxes = runtime.block_on(async {
             engine.render_html_with_js(&input_string).await
@@ -226,11 +221,11 @@ pub extern "C" fn parse_html_with_css_and_images(input_ptr: *const c_char) -> *m
             }
             Err(e) => {
                 eprintln!("[FFI] JavaScript rendering failed: {}", e);
+                let mut arena = get_global_arena();
                 let mut parser = HTMLParser::new(input_string);
-                let dom = parser.parse();
+                let dom = parse_document(&mut parser, &mut arena);
                 let stylesheet = parser.get_stylesheet();
-                let layout_engine = LayoutEngine::new(800.0, 600.0).with_stylesheet(stylesheet);
-                let arena = ARENA.lock().unwrap();
+                let layout_engine = layout_engine_for(stylesheet);
                 let boxes = layout_engine.layout(&dom, &*arena);
                 LayoutBoxArray::new(boxes)
             }
@@ -259,14 +254,19 @@ pub extern "C" fn parse_url_via_rust(url_ptr: *const std::os::raw::c_char) -> *m
 pub extern "C" fn parse_url_via_rust_enhanced(url_ptr: *const c_char) -> *mut LayoutBoxArray {
     let mut tracker = FFIPerformanceTracker::new();
     println!("[FFI] parse_url_via_rust_enhanced called");
+    clear_last_error();
     let url_start = std::time::Instant::now();
     let url = match safe_c_string_to_rust(url_ptr) {
         Ok(s) => s,
         Err(e) => {
-            eprintln!("[FFI] URL conversion failed: {}", e);
+            set_last_error(FFIErrorKind::Input, &e);
             return ptr::null_mut();
         }
     };
+    if let Err(e) = reqwest::Url::parse(&url) {
+        set_last_error(FFIErrorKind::Parse, &format!("invalid URL {}: {}", url, e));
+        return ptr::null_mut();
+    }
     tracker.record_stage("url_conversion", url_start.elapsed());
     println!("[FFI] Processing URL: {}", url);
     
@@ -277,22 +277,24 @@ pub extern "C" fn parse_url_via_rust_enhanced(url_ptr: *const c_char) -> *mut La
                 Ok((tokens, css_rules)) => {
                     println!("[FFI] Streamed {} tokens and {} CSS rules", tokens.len(), css_rules.len());
                     let mut parser = HTMLParser::new(format!("<html><head></head><body></body></html>"));
-                    let mut dom = parser.parse();
+                    // Don't hold the shared arena while @imports are fetched
+                    let dom = parse_document(&mut parser, &mut get_global_arena());
                     let mut stylesheet = parser.get_stylesheet();
                     
-                    // Apply CSS rules
+                    // Apply CSS rules, each sheet's @imports ahead of its own rules
+                    let loader = loader();
                     for css in css_rules {
-                        let additional_css = parse_css(&css);
-                        stylesheet.rules.extend(additional_css.rules);
+                        stylesheet.append(parse_css_with_imports(&css, &url, &*loader).await);
                     }
                     
-                    let layout_engine = LayoutEngine::new(800.0, 600.0).with_stylesheet(stylesheet);
-                    let arena = ARENA.lock().unwrap();
+                    let layout_engine = layout_engine_for(stylesheet);
+                    let arena = get_global_arena();
                     let boxes = layout_engine.layout(&dom, &*arena);
                     Ok(boxes)
                 }
                 Err(e) => {
-                    eprintln!("[FFI] Streaming failed: {}", e);
+                    // The host still gets the fallback page below, with the reason on record
+                    set_last_error(FFIErrorKind::Network, &format!("{}: {}", url, e));
                     Err(e)
                 }
             }
@@ -305,11 +307,11 @@ pub extern "C" fn parse_url_via_rust_enhanced(url_ptr: *const c_char) -> *mut La
             }
             Err(_) => {
                 // Fallback to simple HTML parsing
+                let mut arena = get_global_arena();
                 let mut parser = HTMLParser::new(format!("<html><body><p>Failed to load: {}</p></body></html>", url));
-                let dom = parser.parse();
+                let dom = parse_document(&mut parser, &mut arena);
                 let stylesheet = parser.get_stylesheet();
-                let layout_engine = LayoutEngine::new(800.0, 600.0).with_stylesheet(stylesheet);
-                let arena = ARENA.lock().unwrap();
+                let layout_engine = layout_engine_for(stylesheet);
                 let boxes = layout_engine.layout(&dom, &*arena);
                 LayoutBoxArray::new(boxes)
             }
@@ -321,9 +323,109 @@ pub extern "C" fn parse_url_via_rust_enhanced(url_ptr: *const c_char) -> *mut La
             tracker.log_performance();
             Box::into_raw(Box::new(layout_array))
         }
-        Err(_) => {
-            eprintln!("[FFI] parse_url_via_rust_enhanced: panic caught!");
+        Err(payload) => {
+            set_last_error(FFIErrorKind::Panic, &format!("parse_url_via_rust_enhanced: {}", panic_message(&*payload)));
             ptr::null_mut()
         }
     }
-} 
\ No newline at end of file
+}
+
+/// Parse `css` from the document at `base_url`, fetching its @imports through `loader`
+async fn parse_css_with_imports(css: &str, base_url: &str, loader: &dyn ResourceLoader) -> Stylesheet {
+    let mut stylesheet = parse_css(css);
+    for import in stylesheet.imports.clone().iter().rev() {
+        let import_url = resolve_url(base_url, import);
+        match loader.fetch(&import_url).await {
+            Ok(bytes) => stylesheet.prepend_imported(parse_css(&String::from_utf8_lossy(&bytes))),
+            Err(e) => eprintln!("[FFI] @import {} failed: {}", import_url, e),
+        }
+    }
+    stylesheet
+}
+
+/// Why the last `parse_html`, `parse_html_with_css` or `parse_url_via_rust_enhanced` call on
+/// this thread failed, as `<category>: <message>` with category `input`, `parse`, `network`
+/// or `panic`; null when it succeeded. Release the string with `free_c_string`.
+#[no_mangle]
+pub extern "C" fn get_last_error() -> *mut c_char {
+    match last_error() {
+        Some(message) => safe_rust_string_to_c(&message),
+        None => ptr::null_mut(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ffi::functions::{free_c_string, free_layout_box_array};
+    use crate::ffi::{TOP_LEVEL_PARSE, GLOBAL_DOM_ARENA};
+    use std::sync::PoisonError;
+
+    fn take_last_error() -> Option<String> {
+        let error = get_last_error();
+        let message = safe_c_string_to_rust(error).ok();
+        free_c_string(error);
+        message
+    }
+
+    #[test]
+    fn test_invalid_utf8_sets_last_error() {
+        let _parse = TOP_LEVEL_PARSE.lock().unwrap_or_else(PoisonError::into_inner);
+        let invalid = b"<p>\xff\xfe</p>\0";
+        assert!(parse_html(invalid.as_ptr() as *const c_char).is_null());
+        let message = take_last_error().unwrap();
+        assert!(message.starts_with("input: Invalid UTF-8"), "{}", message);
+
+        let url = b"not a url\0";
+        assert!(parse_url_via_rust_enhanced(url.as_ptr() as *const c_char).is_null());
+        assert!(take_last_error().unwrap().starts_with("parse: invalid URL"));
+
+        let valid = b"<p>ok</p>\0";
+        let boxes = parse_html(valid.as_ptr() as *const c_char);
+        assert!(!boxes.is_null());
+        assert_eq!(take_last_error(), None, "a successful call clears the error");
+        free_layout_box_array(boxes);
+    }
+
+    #[test]
+    fn test_top_level_parse_replaces_previous_document() {
+        let _parse = TOP_LEVEL_PARSE.lock().unwrap_or_else(PoisonError::into_inner);
+        let find = |key: &str| get_global_arena().nodes.values()
+            .find(|node| node.lock().unwrap().attributes.get("id").map(String::as_str) == Some(key))
+            .map(|node| node.lock().unwrap().id.clone());
+        free_layout_box_array(parse_html(b"<p id=\"old-document\">old</p>\0".as_ptr() as *const c_char));
+        let old = find("old-document").unwrap();
+        free_layout_box_array(parse_html(b"<p id=\"new-document\">new</p>\0".as_ptr() as *const c_char));
+        assert!(get_global_arena().get_node(&old).is_none(), "the old document's nodes are dropped");
+        assert!(find("new-document").is_some());
+
+        // A panic while the arena is locked doesn't take the arena down with it
+        let _ = std::thread::spawn(|| {
+            let _arena = get_global_arena();
+            panic!("render failed");
+        }).join();
+        assert!(find("new-document").is_some());
+        assert!(!GLOBAL_DOM_ARENA.is_poisoned());
+    }
+
+    struct ImportLoader;
+
+    impl ResourceLoader for ImportLoader {
+        fn fetch<'a>(&'a self, url: &'a str) -> futures::future::BoxFuture<'a, crate::loader::LoadResult> {
+            Box::pin(async move {
+                match url {
+                    "app://site/css/base.css" => Ok(bytes::Bytes::from_static(b"p { color: #111111; }")),
+                    _ => Err(format!("no such file: {}", url).into()),
+                }
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_imports_are_fetched_through_the_loader() {
+        let css = "@import url(base.css); @import url(missing.css); div { color: #222222; }";
+        let stylesheet = parse_css_with_imports(css, "app://site/css/page.css", &ImportLoader).await;
+        let selectors = stylesheet.rules.iter().map(|rule| rule.selector.as_str()).collect::<Vec<_>>();
+        assert_eq!(selectors, ["p", "div"], "the import's rules come first; a failed import is skipped");
+    }
+}
@@ -1,10 +1,59 @@
 // JavaScript execution FFI functions for the browser engine
 // Extracted from functions.rs for modularization
 
+use std::cell::RefCell;
 use std::ffi::c_char;
-use crate::VeloxEngine;
-use crate::ffi::{FFIPerformanceTracker, safe_c_string_to_rust};
+use crate::javascript::ScriptManager;
+use crate::ffi::{FFIPerformanceTracker, GLOBAL_DOM_ARENA, document_root, safe_c_string_to_rust};
 
+thread_local! {
+    // Scripts of the document in GLOBAL_DOM_ARENA, with that document's root id. The runtime
+    // isn't Send, so it lives on the host thread that makes the FFI calls.
+    static PAGE_SCRIPTS: RefCell<Option<(String, ScriptManager)>> = const { RefCell::new(None) };
+}
+
+/// Run `f` with the current document's script manager, starting a new one when a parse has
+/// replaced the document since. None when no document is loaded or the runtime fails to start.
+fn with_page_scripts<R>(f: impl FnOnce(&mut ScriptManager) -> R) -> Option<R> {
+    let root = document_root()?;
+    PAGE_SCRIPTS.with(|scripts| {
+        let mut scripts = scripts.borrow_mut();
+        if scripts.as_ref().map(|(scripts_root, _)| scripts_root) != Some(&root) {
+            let mut manager = match ScriptManager::new(GLOBAL_DOM_ARENA.clone(), root.clone()) {
+                Ok(manager) => manager,
+                Err(e) => {
+                    eprintln!("[FFI] Failed to start the page's JavaScript runtime: {}", e);
+                    return None;
+                }
+            };
+            if let Err(e) = manager.initialize() {
+                eprintln!("[FFI] Failed to initialize the page's JavaScript runtime: {}", e);
+                return None;
+            }
+            *scripts = Some((root, manager));
+        }
+        scripts.as_mut().map(|(_, manager)| f(manager))
+    })
+}
+
+/// Run the page's script listeners for an event the host dispatched; see
+/// `ScriptManager::dispatch_event`. Pages that never ran a script have no listeners to run,
+/// so no runtime is started for them.
+pub(crate) fn dispatch_to_page_scripts(node_id: &str, event_type: &str, bubbles: bool) -> bool {
+    let Some(root) = document_root() else { return false };
+    let started = PAGE_SCRIPTS.with(|scripts| {
+        scripts.borrow().as_ref().is_some_and(|(scripts_root, _)| *scripts_root == root)
+    });
+    if !started {
+        return false;
+    }
+    with_page_scripts(|scripts| scripts.dispatch_event(node_id, event_type, bubbles))
+        .and_then(|result| result.map_err(|e| eprintln!("[FFI] Page event listener failed: {}", e)).ok())
+        .unwrap_or(false)
+}
+
+/// Run a script against the loaded document, in the same runtime as the page's earlier
+/// scripts so globals and listeners they registered carry over
 #[no_mangle]
 pub extern "C" fn execute_javascript(script_ptr: *const c_char, script_name_ptr: *const c_char) -> i32 {
     let mut tracker = FFIPerformanceTracker::new();
@@ -26,19 +75,22 @@ pub extern "C" fn execute_javascript(script_ptr: *const c_char, script_name_ptr:
     };
     tracker.record_stage("input_conversion", input_start.elapsed());
     let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-        let mut engine = VeloxEngine::new(800.0, 600.0);
         let js_start = std::time::Instant::now();
-        let execution_result = engine.execute_script(&script_content, &script_name);
+        let execution_result = with_page_scripts(|scripts| scripts.execute_script(&script_content, &script_name));
         let _js_duration = js_start.elapsed();
         match execution_result {
-            Ok(_) => {
+            Some(Ok(_)) => {
                 println!("[FFI] JavaScript executed successfully: {}", script_name);
                 0
             }
-            Err(e) => {
+            Some(Err(e)) => {
                 eprintln!("[FFI] JavaScript execution failed: {}", e);
                 -1
             }
+            None => {
+                eprintln!("[FFI] execute_javascript: no document loaded");
+                -1
+            }
         }
     }));
     match result {
@@ -48,4 +100,40 @@ pub extern "C" fn execute_javascript(script_ptr: *const c_char, script_name_ptr:
             -1
         }
     }
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+    use crate::ffi::functions::{dom_dispatch_event, free_layout_box_array, parse_html};
+    use crate::ffi::get_global_arena;
+
+    #[test]
+    fn test_host_dispatch_runs_page_script_listeners() {
+        let _parse = crate::ffi::TOP_LEVEL_PARSE.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let html = CString::new(r#"<body><div id="outer"><button id="page-button">Go</button></div></body>"#).unwrap();
+        free_layout_box_array(parse_html(html.as_ptr()));
+        let script = CString::new(r#"
+            document.getElementById("outer").addEventListener("click", function(e) {
+                document.getElementById("outer").setAttribute("data-clicked", "yes");
+            });
+        "#).unwrap();
+        let name = CString::new("listeners.js").unwrap();
+        assert_eq!(execute_javascript(script.as_ptr(), name.as_ptr()), 0);
+
+        let button_id: u32 = get_global_arena().nodes.values()
+            .map(|node| node.lock().unwrap().clone())
+            .find(|node| node.attributes.get("id").map(String::as_str) == Some("page-button"))
+            .unwrap()
+            .id.parse().unwrap();
+        let click = CString::new("click").unwrap();
+        assert!(dom_dispatch_event(button_id, click.as_ptr(), true));
+        let clicked = get_global_arena().nodes.values()
+            .map(|node| node.lock().unwrap().clone())
+            .find(|node| node.attributes.get("id").map(String::as_str) == Some("outer"))
+            .unwrap()
+            .attributes.get("data-clicked").cloned();
+        assert_eq!(clicked.as_deref(), Some("yes"));
+    }
+}
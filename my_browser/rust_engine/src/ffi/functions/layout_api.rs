@@ -2,7 +2,8 @@
 // Extracted from functions.rs for modularization
 
 use crate::dom::node::{FFILayoutBox, LayoutBox};
-use crate::ffi::LayoutBoxArray;
+use crate::ffi::{LayoutBoxArray, GLOBAL_VIEWPORT};
+use crate::layout::layout::LayoutEngine;
 use std::ffi::c_char;
 use std::ptr;
 
@@ -133,4 +134,85 @@ pub extern "C" fn get_layout_box_height(box_ptr: *mut FFILayoutBox) -> f32 {
         Ok(val) => val,
         Err(_) => 0.0
     }
-} 
\ No newline at end of file
+}
+
+/// Index of the box's containing box in its `LayoutBoxArray`, or -1 for the root
+#[no_mangle]
+pub extern "C" fn get_layout_box_parent_index(box_ptr: *mut FFILayoutBox) -> i32 {
+    let result = std::panic::catch_unwind(|| {
+        if box_ptr.is_null() { return -1; }
+        let layout_box = unsafe { &*box_ptr };
+        layout_box.parent_index
+    });
+    result.unwrap_or(-1)
+}
+
+/// Index of the topmost box at (`x`, `y`) in the array, or -1 when nothing is hit
+#[no_mangle]
+pub extern "C" fn layout_hit_test(box_array_ptr: *mut LayoutBoxArray, x: f32, y: f32) -> i32 {
+    let result = std::panic::catch_unwind(|| {
+        if box_array_ptr.is_null() {
+            return -1;
+        }
+        let box_array = unsafe { &*box_array_ptr };
+        LayoutEngine::hit_test(&box_array.layout_boxes, x, y).map_or(-1, |index| index as i32)
+    });
+    result.unwrap_or(-1)
+}
+
+/// Resize the viewport later FFI renders lay out against. Returns false, leaving the
+/// viewport unchanged, unless both dimensions are positive and finite.
+#[no_mangle]
+pub extern "C" fn set_viewport(width: f32, height: f32) -> bool {
+    if !(width.is_finite() && height.is_finite() && width > 0.0 && height > 0.0) {
+        eprintln!("[FFI] set_viewport: invalid size {}x{}", width, height);
+        return false;
+    }
+    match GLOBAL_VIEWPORT.lock() {
+        Ok(mut viewport) => {
+            *viewport = (width, height);
+            println!("[FFI] Viewport set to {}x{}", width, height);
+            true
+        }
+        Err(_) => false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ffi::functions::free_layout_box_array;
+    use crate::parser::html::HTMLParser;
+
+    #[test]
+    fn test_child_box_reports_parent_index() {
+        let mut parser = HTMLParser::new("<body><div><p>Hi</p></div></body>".to_string()).with_reuse();
+        let dom = parser.parse();
+        let boxes = LayoutEngine::new(800.0, 600.0).layout(&dom, parser.arena().unwrap());
+        let tags: Vec<String> = boxes.iter().map(|b| b.node_type.clone()).collect();
+        let array = Box::into_raw(Box::new(LayoutBoxArray::new(boxes)));
+
+        let index_of = |tag: &str| tags.iter().position(|t| t == tag).unwrap() as i32;
+        let parent_of = |index: i32| get_layout_box_parent_index(get_layout_box(array, index));
+        assert_eq!(parent_of(index_of("p")), index_of("div"));
+        assert_eq!(parent_of(index_of("div")), index_of("body"));
+        assert_eq!(parent_of(0), -1);
+        assert_eq!(get_layout_box_parent_index(ptr::null_mut()), -1);
+        free_layout_box_array(array);
+    }
+
+    #[test]
+    fn test_layout_hit_test_over_ffi() {
+        let mut parser = HTMLParser::new(r#"<body><div style="width:300px;height:300px"><p style="width:100px;height:40px">Hi</p></div></body>"#.to_string()).with_reuse();
+        let dom = parser.parse();
+        let boxes = LayoutEngine::new(800.0, 600.0).layout(&dom, parser.arena().unwrap());
+        let p = boxes.iter().position(|b| b.node_type == "p").unwrap();
+        let (x, y) = (boxes[p].x + 5.0, boxes[p].y + 5.0);
+        let array = Box::into_raw(Box::new(LayoutBoxArray::new(boxes)));
+
+        assert_eq!(layout_hit_test(array, x, y), p as i32, "the paragraph, over the div");
+        assert_eq!(layout_hit_test(array, 5000.0, 5000.0), -1);
+        assert_eq!(layout_hit_test(ptr::null_mut(), x, y), -1);
+        free_layout_box_array(array);
+    }
+}
@@ -2,8 +2,8 @@
 // Extracted from functions.rs for modularization
 
 use crate::dom::node::FFILayoutBox;
-use crate::ffi::{LayoutBoxArray, DrawCommandArray};
-use std::ffi::{c_char, CString};
+use crate::ffi::{outstanding_cstrings, release_c_string, LayoutBoxArray, DrawCommandArray};
+use std::ffi::c_char;
 
 #[no_mangle]
 pub extern "C" fn free_layout_box_array(box_array_ptr: *mut LayoutBoxArray) {
@@ -28,11 +28,42 @@ pub extern "C" fn free_ffi_layout_box(box_ptr: *mut FFILayoutBox) {
     }
 }
 
+/// Free any string an FFI getter returned (`dom_get_attribute`, `dom_get_inner_html`,
+/// `dom_get_style`, ...). This is the only way to release them; null is ignored.
 #[no_mangle]
 pub extern "C" fn free_c_string(s: *mut c_char) {
-    if !s.is_null() {
-        unsafe {
-            let _ = CString::from_raw(s);
+    release_c_string(s);
+}
+
+/// Strings returned to the host on this thread and not yet passed to `free_c_string`.
+/// Debug builds only, for leak checks in tests; always 0 in release builds.
+#[no_mangle]
+pub extern "C" fn ffi_outstanding_cstrings() -> i64 {
+    outstanding_cstrings()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dom::node::DOMNode;
+    use crate::ffi::functions::{dom_get_outer_html, dom_get_tag_name};
+    use crate::ffi::{safe_rust_string_to_c, get_global_arena};
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn test_freeing_returned_strings_balances_counter() {
+        let node = DOMNode::create_element("span");
+        let id: u32 = node.id.parse().unwrap();
+        get_global_arena().add_node(node);
+
+        let mut strings: Vec<*mut c_char> = (0..8).map(|i| safe_rust_string_to_c(&format!("s{}", i))).collect();
+        strings.push(dom_get_tag_name(id));
+        strings.push(dom_get_outer_html(id));
+        assert_eq!(ffi_outstanding_cstrings(), 10);
+        for s in strings {
+            free_c_string(s);
         }
+        free_c_string(std::ptr::null_mut());
+        assert_eq!(ffi_outstanding_cstrings(), 0);
     }
 } 
\ No newline at end of file
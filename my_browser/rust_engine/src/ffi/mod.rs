@@ -7,15 +7,18 @@ use std::ptr;
 use tokio::runtime::Runtime;
 use reqwest::Client as AsyncClient;
 use futures::StreamExt;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
 use lazy_static::lazy_static;
 
 use crate::dom::node::{DOMNode, LayoutBox, FFILayoutBox, NodeType, StyleMap, BoxValues, DOMArena};
-use crate::parser::html::{HTMLParser, StreamingHTMLParser};
-use crate::parser::css::{parse_css, Stylesheet};
+use crate::parser::html::{charset_from_content_type, HTMLParser, StreamingHTMLParser};
+use crate::parser::css::{cascade_declarations, parse_css, substitute_variables, CssRule, Stylesheet, VariableMap};
+use crate::layout::cache::StyleCache;
 use crate::layout::layout::LayoutEngine;
 use crate::paint::painter::Painter;
 use crate::compositor::compositor::Compositor;
+use crate::loader::{HttpLoader, ResourceLoader};
+use crate::style::selector::matches_selector;
 
 // Include the functions module
 pub mod functions;
@@ -26,19 +29,85 @@ pub struct LayoutBoxArray {
     pub boxes: Vec<*mut FFILayoutBox>,
     pub total_count: i32,
     pub batch_size: i32,
+    // The boxes as laid out, for queries that need more than the FFI fields (e.g. hit-testing)
+    pub layout_boxes: Vec<LayoutBox>,
+}
+
+/// One color stop of a gradient draw command
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GradientStop {
+    pub color: u32, // 0xAARRGGBB
+    pub offset: f32, // 0..1 along the gradient line
 }
 
 #[repr(C)]
 pub struct DrawCommand {
-    pub command_type: i32, // 0=rect, 1=text, 2=line, 3=image
+    // 0=rect, 1=text, 2=line, 3=image, 4=rounded_rect, 5=shadow, 6=linear_gradient,
+    // 7=clip (x/y/width/height with corner_radius, until the matching 8=end_clip),
+    // 9=opacity (commands up to the matching 10=end_opacity are blended at `opacity`)
+    pub command_type: i32,
     pub x: f32,
     pub y: f32,
     pub width: f32,
     pub height: f32,
-    pub color: *mut c_char,
+    pub color: *mut c_char, // #aarrggbb, the same packing as gradient stops
     pub text: *mut c_char,
     pub font_size: f32,
     pub font_weight: f32,
+    pub italic: bool,
+    pub corner_radius: f32,
+    // Shadow of the box at x/y/width/height; color is the shadow color
+    pub shadow_offset_x: f32,
+    pub shadow_offset_y: f32,
+    pub shadow_blur: f32,
+    pub shadow_spread: f32,
+    // Affine transform from the box's (and its ancestors') `transform`, identity by default
+    pub a: f32,
+    pub b: f32,
+    pub c: f32,
+    pub d: f32,
+    pub e: f32,
+    pub f: f32,
+    // Linear gradient filling x/y/width/height: angle in degrees (CSS convention) and an
+    // owned array of `gradient_stop_count` stops
+    pub gradient_angle: f32,
+    pub gradient_stops: *mut GradientStop,
+    pub gradient_stop_count: i32,
+    // Group alpha of an opacity command
+    pub opacity: f32,
+}
+
+impl Default for DrawCommand {
+    fn default() -> Self {
+        DrawCommand {
+            command_type: 0,
+            x: 0.0,
+            y: 0.0,
+            width: 0.0,
+            height: 0.0,
+            color: ptr::null_mut(),
+            text: ptr::null_mut(),
+            font_size: 0.0,
+            font_weight: 0.0,
+            italic: false,
+            corner_radius: 0.0,
+            shadow_offset_x: 0.0,
+            shadow_offset_y: 0.0,
+            shadow_blur: 0.0,
+            shadow_spread: 0.0,
+            a: 1.0,
+            b: 0.0,
+            c: 0.0,
+            d: 1.0,
+            e: 0.0,
+            f: 0.0,
+            gradient_angle: 0.0,
+            gradient_stops: ptr::null_mut(),
+            gradient_stop_count: 0,
+            opacity: 1.0,
+        }
+    }
 }
 
 #[repr(C)]
@@ -82,7 +151,7 @@ impl FFIPerformanceTracker {
 
 impl LayoutBoxArray {
     pub fn new(boxes: Vec<LayoutBox>) -> Self {
-        let ffi_boxes: Vec<*mut FFILayoutBox> = boxes.into_iter()
+        let ffi_boxes: Vec<*mut FFILayoutBox> = boxes.iter()
             .map(|b| Box::into_raw(Box::new(b.to_ffi())))
             .collect();
         let total_count = ffi_boxes.len() as i32;
@@ -90,6 +159,7 @@ impl LayoutBoxArray {
             boxes: ffi_boxes, 
             total_count,
             batch_size: 100, // Default batch size
+            layout_boxes: boxes,
         }
     }
 
@@ -143,44 +213,99 @@ pub fn safe_c_string_to_rust(c_ptr: *const c_char) -> Result<String, String> {
     }
 }
 
+/// The category of failure recorded for `get_last_error`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FFIErrorKind {
+    /// A null or malformed argument, e.g. a string that isn't UTF-8
+    Input,
+    /// Input that was readable but couldn't be parsed, e.g. a malformed URL
+    Parse,
+    /// A fetch failed
+    Network,
+    /// The call panicked and was caught at the FFI boundary
+    Panic,
+}
+
+impl FFIErrorKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FFIErrorKind::Input => "input",
+            FFIErrorKind::Parse => "parse",
+            FFIErrorKind::Network => "network",
+            FFIErrorKind::Panic => "panic",
+        }
+    }
+}
+
+thread_local! {
+    /// Why the last parse call on this thread failed, as `<category>: <message>`
+    static LAST_ERROR: std::cell::RefCell<Option<String>> = const { std::cell::RefCell::new(None) };
+}
+
+/// Record why the current FFI call failed, replacing any earlier error on this thread
+pub fn set_last_error(kind: FFIErrorKind, message: &str) {
+    eprintln!("[FFI] {} error: {}", kind.as_str(), message);
+    LAST_ERROR.with(|error| *error.borrow_mut() = Some(format!("{}: {}", kind.as_str(), message)));
+}
+
+/// Forget the previous call's error; each parse call starts with a clean slate
+pub fn clear_last_error() {
+    LAST_ERROR.with(|error| *error.borrow_mut() = None);
+}
+
+pub fn last_error() -> Option<String> {
+    LAST_ERROR.with(|error| error.borrow().clone())
+}
+
+/// The message a caught panic was raised with
+pub fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    payload.downcast_ref::<&str>().map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+thread_local! {
+    /// C strings handed out on this thread and not yet freed; only counted in debug builds
+    static OUTSTANDING_CSTRINGS: std::cell::Cell<i64> = const { std::cell::Cell::new(0) };
+}
+
+/// The allocation path for every string returned across the FFI. The host owns the result
+/// and must release it with `free_c_string`; null when `s` contains a NUL byte.
 pub fn safe_rust_string_to_c(s: &str) -> *mut c_char {
     match CString::new(s) {
-        Ok(c_string) => c_string.into_raw(),
+        Ok(c_string) => {
+            track_cstrings(1);
+            c_string.into_raw()
+        }
         Err(_) => ptr::null_mut(),
     }
 }
 
-// Enhanced selector matching for CSS
-pub fn matches_selector(node: &DOMNode, selector: &str) -> bool {
-    match &node.node_type {
-        NodeType::Element(tag_name) => {
-            if selector == tag_name {
-                return true;
-            }
-            
-            if selector.starts_with('.') {
-                let class_name = &selector[1..];
-                if let Some(classes) = node.attributes.get("class") {
-                    return classes.split_whitespace().any(|c| c == class_name);
-                }
-            }
-            
-            if selector.starts_with('#') {
-                let id_name = &selector[1..];
-                if let Some(id) = node.attributes.get("id") {
-                    return id == id_name;
-                }
-            }
-            
-            false
-        }
-        _ => false,
+/// Free a string from `safe_rust_string_to_c`; null is ignored
+pub(crate) fn release_c_string(s: *mut c_char) {
+    if s.is_null() {
+        return;
+    }
+    unsafe {
+        drop(CString::from_raw(s));
+    }
+    track_cstrings(-1);
+}
+
+/// Strings allocated minus strings freed on the calling thread; always 0 in release builds
+pub fn outstanding_cstrings() -> i64 {
+    OUTSTANDING_CSTRINGS.with(|count| count.get())
+}
+
+fn track_cstrings(delta: i64) {
+    if cfg!(debug_assertions) {
+        OUTSTANDING_CSTRINGS.with(|count| count.set(count.get() + delta));
     }
 }
 
 // Apply CSS stylesheet to DOM
 pub fn apply_stylesheet_to_dom(dom: &mut DOMNode, stylesheet: &Stylesheet, arena: &mut DOMArena) {
-    fn recurse(node: &mut DOMNode, stylesheet: &Stylesheet, arena: &mut DOMArena) {
+    fn recurse(node: &mut DOMNode, stylesheet: &Stylesheet, variables: &VariableMap, arena: &mut DOMArena) {
         if let NodeType::Element(_) = &node.node_type {
             let mut style_map = std::collections::HashMap::new();
             let tag = match &node.node_type {
@@ -190,13 +315,21 @@ pub fn apply_stylesheet_to_dom(dom: &mut DOMNode, stylesheet: &Stylesheet, arena
             let class_attr = node.attributes.get("class").cloned().unwrap_or_default();
             let id_attr = node.attributes.get("id").cloned().unwrap_or_default();
             
-            for rule in &stylesheet.rules {
-                let sel = rule.selector.trim();
-                if matches_selector(node, sel) {
-                    println!("[CSS MATCH] selector='{}' -> <{} class='{}' id='{}'>", sel, tag, class_attr, id_attr);
-                    for (k, v) in &rule.declarations {
-                        style_map.insert(k.clone(), v.clone());
-                    }
+            // Media-dependent rules are left to layout, which knows the viewport
+            let matching: Vec<&CssRule> = stylesheet.cascade_order()
+                .filter(|rule| rule.media.is_none())
+                .filter(|rule| matches_selector(node, rule.selector.trim(), arena))
+                .inspect(|rule| println!("[CSS MATCH] selector='{}' -> <{} class='{}' id='{}'>", rule.selector.trim(), tag, class_attr, id_attr))
+                .collect();
+            for (k, v) in cascade_declarations(&matching) {
+                if !v.contains("var(") {
+                    style_map.insert(k.clone(), v.clone());
+                    continue;
+                }
+                let in_scope = variables.get(&node.id).cloned().unwrap_or_default();
+                match substitute_variables(v, &in_scope) {
+                    Some(value) => { style_map.insert(k.clone(), value); }
+                    None => println!("[CSS] Dropping {}: {} (undefined variable)", k, v),
                 }
             }
             // Convert HashMap to StyleMap
@@ -211,30 +344,37 @@ pub fn apply_stylesheet_to_dom(dom: &mut DOMNode, stylesheet: &Stylesheet, arena
         }
         for child_id in &node.children {
             if let Some(child_node) = arena.get_node(child_id) {
-                let mut child = child_node.lock().unwrap();
-                recurse(&mut child, stylesheet, arena);
+                // Style a copy so no lock is held while selectors walk the ancestors
+                let mut child = child_node.lock().unwrap().clone();
+                recurse(&mut child, stylesheet, variables, arena);
+                child_node.lock().unwrap().styles = child.styles;
             }
         }
     }
-    recurse(dom, stylesheet, arena);
+    let variables = stylesheet.resolve_variables(arena);
+    recurse(dom, stylesheet, &variables, arena);
 }
 
 // Async HTML processing with streaming
 pub async fn process_html_streaming(url: &str) -> Result<(Vec<crate::parser::html::Token>, Vec<String>), Box<dyn std::error::Error>> {
     let client = AsyncClient::new();
     let response = client.get(url).send().await?;
-    let mut stream = response.bytes_stream();
     let mut parser = StreamingHTMLParser::new();
+    let charset = response.headers().get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(charset_from_content_type);
+    if let Some(charset) = charset {
+        parser.set_encoding(charset);
+    }
+    let mut stream = response.bytes_stream();
     let mut all_tokens = Vec::new();
     
     while let Some(chunk) = stream.next().await {
         let bytes = chunk?;
-        if let Ok(chunk_str) = String::from_utf8(bytes.to_vec()) {
-            // Process chunk and collect tokens
-            let new_tokens = parser.process_chunk(&chunk_str);
-            all_tokens.extend(new_tokens);
-        }
+        // Process chunk and collect tokens
+        all_tokens.extend(parser.feed_chunk(&bytes));
     }
+    all_tokens.extend(parser.finish());
     
     // Also get any remaining tokens from the parser
     all_tokens.extend(parser.get_tokens().to_vec());
@@ -259,8 +399,114 @@ pub use self::functions::{
 
 lazy_static! {
     pub static ref GLOBAL_DOM_ARENA: Arc<Mutex<DOMArena>> = Arc::new(Mutex::new(DOMArena::new()));
+    // Width and height every FFI render lays out against; changed with `set_viewport`
+    pub static ref GLOBAL_VIEWPORT: Mutex<(f32, f32)> = Mutex::new((800.0, 600.0));
+    // Loader FFI renders fetch subresources through; replaced with `set_loader`
+    static ref GLOBAL_LOADER: Mutex<Arc<dyn ResourceLoader>> = Mutex::new(Arc::new(HttpLoader::new()));
+    // Root of the document the last top-level parse put in GLOBAL_DOM_ARENA
+    static ref GLOBAL_DOCUMENT: Mutex<Option<String>> = Mutex::new(None);
+    // Matched styles kept across FFI renders, so relayouts of the same document reuse them
+    static ref GLOBAL_STYLE_CACHE: Arc<StyleCache> = Arc::new(StyleCache::default());
+}
+
+/// Held by tests that parse top-level documents and then read them back, so another test's
+/// parse doesn't replace the document in between
+#[cfg(test)]
+pub(crate) static TOP_LEVEL_PARSE: Mutex<()> = Mutex::new(());
+
+/// The viewport FFI renders use, 800x600 until the host calls `set_viewport`
+pub fn viewport() -> (f32, f32) {
+    *GLOBAL_VIEWPORT.lock().unwrap()
+}
+
+/// A layout engine for an FFI render at the current viewport, sharing the style cache every
+/// FFI render uses
+pub fn layout_engine_for(stylesheet: Stylesheet) -> LayoutEngine {
+    let (width, height) = viewport();
+    LayoutEngine::new(width, height).with_style_cache(GLOBAL_STYLE_CACHE.clone()).with_stylesheet(stylesheet)
+}
+
+/// The loader FFI renders fetch subresources through, HTTP until an embedder calls `set_loader`
+pub fn loader() -> Arc<dyn ResourceLoader> {
+    GLOBAL_LOADER.lock().unwrap_or_else(PoisonError::into_inner).clone()
+}
+
+/// Serve later FFI renders' subresources from `loader` instead of the network
+pub fn set_loader(loader: Arc<dyn ResourceLoader>) {
+    *GLOBAL_LOADER.lock().unwrap_or_else(PoisonError::into_inner) = loader;
 }
 
-pub fn get_global_arena() -> std::sync::MutexGuard<'static, DOMArena> {
-    GLOBAL_DOM_ARENA.lock().unwrap()
-} 
\ No newline at end of file
+/// Root id of the document the last top-level `parse_document` produced, if any
+pub fn document_root() -> Option<String> {
+    GLOBAL_DOCUMENT.lock().unwrap_or_else(PoisonError::into_inner).clone()
+}
+
+/// Lock the global arena. A panic in an earlier render poisons the lock while the arena itself
+/// is still usable, so the poison is cleared rather than failing every call after it.
+pub fn get_global_arena() -> MutexGuard<'static, DOMArena> {
+    GLOBAL_DOM_ARENA.lock().unwrap_or_else(|poisoned| {
+        GLOBAL_DOM_ARENA.clear_poison();
+        poisoned.into_inner()
+    })
+}
+
+/// Parse a top-level document into the global arena in place of the previous one. The old
+/// document's nodes are removed, so the arena doesn't grow with every parse and its ids stop
+/// resolving.
+pub fn parse_document(parser: &mut HTMLParser, arena: &mut DOMArena) -> DOMNode {
+    let mut document = GLOBAL_DOCUMENT.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(previous) = document.take() {
+        let mut pending = vec![previous];
+        while let Some(id) = pending.pop() {
+            if let Some(node) = arena.remove_node(&id) {
+                pending.extend(node.lock().unwrap().children.iter().cloned());
+            }
+        }
+    }
+    let dom = parser.parse_into(arena);
+    *document = Some(dom.id.clone());
+    dom
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn styled_color(html: &str, css: &str, class: &str) -> String {
+        let mut parser = HTMLParser::new(html.to_string()).with_reuse();
+        let mut dom = parser.parse();
+        let arena = parser.arena_mut().unwrap();
+        apply_stylesheet_to_dom(&mut dom, &parse_css(css), arena);
+        let node = arena.nodes.values()
+            .find(|node| node.lock().unwrap().attributes.get("class").map(String::as_str) == Some(class))
+            .unwrap();
+        let color = node.lock().unwrap().styles.color.clone();
+        color
+    }
+
+    #[test]
+    fn test_child_and_descendant_combinators() {
+        let direct = r#"<body><div class="card"><span class="title">Hi</span></div></body>"#;
+        let nested = r#"<body><div class="card"><section><p><span class="title">Hi</span></p></section></div></body>"#;
+
+        assert_eq!(styled_color(direct, ".card > .title { color: red; }", "title"), "red");
+        assert_eq!(styled_color(nested, ".card .title { color: red; }", "title"), "red");
+        assert_eq!(styled_color(nested, "div section > p span { color: red; }", "title"), "red");
+        assert_ne!(styled_color(nested, ".card > .title { color: red; }", "title"), "red");
+        assert_ne!(styled_color(direct, "nav .title { color: red; }", "title"), "red");
+    }
+
+    #[test]
+    fn test_attribute_selectors() {
+        let html = r#"<body><input class="field" type="text" data-active=""><a class="link" href="https://example.com/docs.pdf">Docs</a></body>"#;
+
+        assert_eq!(styled_color(html, "[data-active] { color: red; }", "field"), "red");
+        assert_eq!(styled_color(html, r#"input[type="text"] { color: red; }"#, "field"), "red");
+        assert_ne!(styled_color(html, r#"input[type="submit"] { color: red; }"#, "field"), "red");
+        assert_eq!(styled_color(html, r#"a[href^="https"] { color: red; }"#, "link"), "red");
+        assert_eq!(styled_color(html, r#".link[href$=".pdf"] { color: red; }"#, "link"), "red");
+        assert_eq!(styled_color(html, r#"body a[href*="example.com"] { color: red; }"#, "link"), "red");
+        assert_ne!(styled_color(html, r#"a[href^="http:"] { color: red; }"#, "link"), "red");
+        assert_ne!(styled_color(html, r#"div[href] { color: red; }"#, "link"), "red");
+    }
+}
@@ -1,13 +1,17 @@
-use deno_core::{JsRuntime, RuntimeOptions, OpState, Extension, op2};
+use deno_core::{JsRuntime, RuntimeOptions, OpState, Extension, PollEventLoopOptions, op2};
 use deno_core::error::AnyError;
 use deno_core::v8::{self, HandleScope, Local, Object, String as V8String, Function, Value, Array};
 use deno_core::serde_v8;
 use deno_core::serde_json::Value as JsonValue;
 
+use crate::dom::events::event_path;
 use crate::dom::node::{DOMNode, NodeType, StyleMap, DOMArena};
+use crate::loader::ResourceLoader;
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use std::cell::RefCell;
+use std::time::{Duration, Instant};
+use serde::Serialize;
 
 /// DOM mutation event types
 #[derive(Debug, Clone)]
@@ -23,6 +27,336 @@ pub enum DomMutationEvent {
 /// Event listener for DOM mutations
 pub type DomMutationListener = Box<dyn Fn(DomMutationEvent) + Send + Sync>;
 
+/// Native function exposed to page scripts through `register_global_fn`
+pub type HostFn = Box<dyn Fn(Vec<JsonValue>) -> Result<JsonValue, AnyError>>;
+
+/// Host functions registered on a runtime, kept in the op state and looked up by name
+#[derive(Default)]
+struct HostFunctions(HashMap<String, HostFn>);
+
+/// Result of a host call; errors are rethrown on the JS side
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum HostCallResult {
+    Value(JsonValue),
+    Error(String),
+}
+
+#[op2]
+#[serde]
+fn op_host_call(state: &mut OpState, #[string] name: String, #[serde] args: Vec<JsonValue>) -> HostCallResult {
+    let Some(host_fn) = state.borrow::<HostFunctions>().0.get(&name) else {
+        return HostCallResult::Error(format!("{} is not a registered host function", name));
+    };
+    match host_fn(args) {
+        Ok(value) => HostCallResult::Value(value),
+        Err(e) => HostCallResult::Error(e.to_string()),
+    }
+}
+
+/// The page's arena and mutation queue, shared with the runtime through the op state
+struct DomBindings {
+    arena: Arc<Mutex<DOMArena>>,
+    event_queue: Arc<Mutex<Vec<DomMutationEvent>>>,
+}
+
+impl DomBindings {
+    fn node(&self, id: &str) -> Option<Arc<Mutex<DOMNode>>> {
+        self.arena.lock().unwrap().get_node(id)
+    }
+
+    fn queue(&self, event: DomMutationEvent) {
+        self.event_queue.lock().unwrap().push(event);
+    }
+}
+
+/// Create a detached element in the arena and return its node id
+#[op2]
+#[string]
+fn op_create_element(state: &mut OpState, #[string] tag_name: String) -> String {
+    let bindings = state.borrow::<DomBindings>();
+    let node = DOMNode::create_element(&tag_name);
+    let id = node.id.clone();
+    bindings.arena.lock().unwrap().add_node(node);
+    bindings.queue(DomMutationEvent::ElementCreated { id: id.clone(), tag_name });
+    id
+}
+
+#[op2(fast)]
+fn op_set_attribute(state: &mut OpState, #[string] id: String, #[string] name: String, #[string] value: String) -> bool {
+    let bindings = state.borrow::<DomBindings>();
+    let Some(node) = bindings.node(&id) else { return false };
+    node.lock().unwrap().attributes.insert(name.clone(), value.clone());
+    bindings.queue(DomMutationEvent::AttributeChanged { id, name, value });
+    bindings.queue(DomMutationEvent::LayoutRecalculationNeeded);
+    true
+}
+
+/// The node id of the element whose `id` attribute is `element_id`, or null
+#[op2]
+#[string]
+fn op_get_element_by_id(state: &mut OpState, #[string] element_id: String) -> Option<String> {
+    let arena = state.borrow::<DomBindings>().arena.lock().unwrap();
+    arena.nodes.values().find_map(|node| {
+        let node = node.lock().unwrap();
+        (node.attributes.get("id") == Some(&element_id)).then(|| node.id.clone())
+    })
+}
+
+#[op2(fast)]
+fn op_set_text_content(state: &mut OpState, #[string] id: String, #[string] content: String) -> bool {
+    let bindings = state.borrow::<DomBindings>();
+    let Some(node) = bindings.node(&id) else { return false };
+    node.lock().unwrap().text_content = content.clone();
+    bindings.queue(DomMutationEvent::TextContentChanged { id, content });
+    bindings.queue(DomMutationEvent::LayoutRecalculationNeeded);
+    true
+}
+
+/// Move `child_id` to the end of `parent_id`'s children, detaching it from any previous parent
+#[op2(fast)]
+fn op_append_child(state: &mut OpState, #[string] parent_id: String, #[string] child_id: String) -> bool {
+    let bindings = state.borrow::<DomBindings>();
+    let mut arena = bindings.arena.lock().unwrap();
+    let (Some(parent), Some(child)) = (arena.get_node(&parent_id), arena.get_node(&child_id)) else {
+        return false;
+    };
+    // Appending the parent or one of its ancestors under it would make a cycle
+    let mut ancestor = Some(parent_id.clone());
+    while let Some(id) = ancestor {
+        if id == child_id {
+            return false;
+        }
+        ancestor = arena.get_node(&id).and_then(|node| node.lock().unwrap().parent.clone());
+    }
+    let previous_parent = child.lock().unwrap().parent.clone();
+    if let Some(previous) = previous_parent.and_then(|id| arena.get_node(&id)) {
+        previous.lock().unwrap().remove_child(&child_id, &mut arena);
+    }
+    parent.lock().unwrap().append_child(child_id, &mut arena);
+    drop(arena);
+    bindings.queue(DomMutationEvent::LayoutRecalculationNeeded);
+    true
+}
+
+#[op2]
+#[string]
+fn op_get_attribute(state: &mut OpState, #[string] id: String, #[string] name: String) -> Option<String> {
+    let node = state.borrow::<DomBindings>().node(&id)?;
+    let value = node.lock().unwrap().attributes.get(&name).cloned();
+    value
+}
+
+#[op2]
+#[string]
+fn op_get_text_content(state: &mut OpState, #[string] id: String) -> String {
+    let Some(node) = state.borrow::<DomBindings>().node(&id) else { return String::new() };
+    let content = node.lock().unwrap().text_content.clone();
+    content
+}
+
+/// The element's tag name, uppercased as `Element.tagName` reports it; empty for other nodes
+#[op2]
+#[string]
+fn op_get_tag_name(state: &mut OpState, #[string] id: String) -> String {
+    let Some(node) = state.borrow::<DomBindings>().node(&id) else { return String::new() };
+    let tag_name = match &node.lock().unwrap().node_type {
+        NodeType::Element(tag) => tag.to_uppercase(),
+        _ => String::new(),
+    };
+    tag_name
+}
+
+/// A property of the node's style map, as read by `dom_get_style`; empty when unset
+#[op2]
+#[string]
+fn op_get_style(state: &mut OpState, #[string] id: String, #[string] property: String) -> String {
+    let Some(node) = state.borrow::<DomBindings>().node(&id) else { return String::new() };
+    let value = node.lock().unwrap().styles.get_property(&property).unwrap_or("").to_string();
+    value
+}
+
+/// Set a style property the way `dom_set_style` does, and mirror it into the `style`
+/// attribute so the next layout sees it as an inline declaration
+#[op2(fast)]
+fn op_set_style(state: &mut OpState, #[string] id: String, #[string] property: String, #[string] value: String) -> bool {
+    let bindings = state.borrow::<DomBindings>();
+    let Some(node) = bindings.node(&id) else { return false };
+    {
+        let mut node = node.lock().unwrap();
+        node.styles.set_property(&property, &value);
+        let inline = node.attributes.get("style").map(String::as_str).unwrap_or("");
+        let inline = with_declaration(inline, &property, &value);
+        node.attributes.insert("style".to_string(), inline);
+    }
+    bindings.queue(DomMutationEvent::StyleChanged { id, property, value });
+    bindings.queue(DomMutationEvent::LayoutRecalculationNeeded);
+    true
+}
+
+/// A pending `setTimeout`/`setInterval`; its callback is kept in the JS-side registry under `id`
+struct Timer {
+    id: u32,
+    due: Instant,
+    interval: Option<Duration>,
+}
+
+/// Timers scheduled by scripts, fired by `run_event_loop`
+#[derive(Default)]
+struct TimerQueue {
+    next_id: u32,
+    timers: Vec<Timer>,
+}
+
+impl TimerQueue {
+    fn schedule(&mut self, delay: Duration, repeat: bool) -> u32 {
+        self.next_id += 1;
+        self.timers.push(Timer {
+            id: self.next_id,
+            due: Instant::now() + delay,
+            interval: repeat.then_some(delay),
+        });
+        self.next_id
+    }
+
+    fn clear(&mut self, id: u32) {
+        self.timers.retain(|timer| timer.id != id);
+    }
+
+    /// Ids of the timers due at `now`, earliest first. One-shot timers are dropped and
+    /// intervals rescheduled from `now`, so each fires at most once per call.
+    fn take_due(&mut self, now: Instant) -> Vec<u32> {
+        let mut due: Vec<(Instant, u32)> = self.timers.iter()
+            .filter(|timer| timer.due <= now)
+            .map(|timer| (timer.due, timer.id))
+            .collect();
+        due.sort();
+        self.timers.retain_mut(|timer| {
+            if timer.due > now {
+                return true;
+            }
+            match timer.interval {
+                Some(interval) => {
+                    timer.due = now + interval;
+                    true
+                }
+                None => false,
+            }
+        });
+        due.into_iter().map(|(_, id)| id).collect()
+    }
+}
+
+/// Longest timer delay browsers honor, 2^31-1 ms (about 24.8 days)
+const MAX_TIMER_DELAY_MS: f64 = 2_147_483_647.0;
+
+#[op2(fast)]
+fn op_set_timeout(state: &mut OpState, delay_ms: f64, repeat: bool) -> u32 {
+    // Negative and NaN delays mean "as soon as possible", as in browsers; huge and infinite
+    // ones are clamped so the due time can't overflow
+    let delay = Duration::from_secs_f64(delay_ms.clamp(0.0, MAX_TIMER_DELAY_MS) / 1000.0);
+    state.borrow_mut::<TimerQueue>().schedule(delay, repeat)
+}
+
+#[op2(fast)]
+fn op_clear_timeout(state: &mut OpState, id: u32) {
+    state.borrow_mut::<TimerQueue>().clear(id);
+}
+
+/// Outcome of a script's `fetch`; an error rejects the JS promise
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum FetchResult {
+    Response(FetchResponse),
+    Error(String),
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FetchResponse {
+    status: u16,
+    status_text: String,
+    body: String,
+}
+
+/// A script's `fetch` that hasn't completed by then rejects with a timeout error
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[op2(async)]
+#[serde]
+async fn op_fetch(#[string] url: String, #[string] method: String, #[string] body: Option<String>) -> FetchResult {
+    match fetch(&url, &method, body).await {
+        Ok(response) => FetchResult::Response(response),
+        Err(e) => {
+            eprintln!("[JS] fetch {} {} failed: {}", method, url, e);
+            FetchResult::Error(e.to_string())
+        }
+    }
+}
+
+async fn fetch(url: &str, method: &str, body: Option<String>) -> Result<FetchResponse, AnyError> {
+    let method = reqwest::Method::from_bytes(method.to_ascii_uppercase().as_bytes())?;
+    let client = reqwest::Client::builder().timeout(FETCH_TIMEOUT).build()?;
+    let mut request = client.request(method, url);
+    if let Some(body) = body {
+        request = request.body(body);
+    }
+    let response = request.send().await?;
+    let status = response.status();
+    Ok(FetchResponse {
+        status: status.as_u16(),
+        status_text: status.canonical_reason().unwrap_or("").to_string(),
+        body: response.text().await?,
+    })
+}
+
+/// `requestAnimationFrame` callbacks waiting for the next frame, by id in the JS-side registry
+#[derive(Default)]
+struct AnimationFrames {
+    next_id: u32,
+    pending: Vec<u32>,
+}
+
+#[op2(fast)]
+fn op_request_animation_frame(state: &mut OpState) -> u32 {
+    let frames = state.borrow_mut::<AnimationFrames>();
+    frames.next_id += 1;
+    frames.pending.push(frames.next_id);
+    frames.next_id
+}
+
+#[op2(fast)]
+fn op_cancel_animation_frame(state: &mut OpState, id: u32) {
+    state.borrow_mut::<AnimationFrames>().pending.retain(|&pending| pending != id);
+}
+
+deno_core::extension!(
+    rift_host,
+    ops = [
+        op_host_call,
+        op_create_element,
+        op_set_attribute,
+        op_get_attribute,
+        op_get_element_by_id,
+        op_set_text_content,
+        op_get_text_content,
+        op_get_tag_name,
+        op_append_child,
+        op_get_style,
+        op_set_style,
+        op_set_timeout,
+        op_clear_timeout,
+        op_request_animation_frame,
+        op_cancel_animation_frame,
+        op_fetch,
+    ],
+    state = |state| {
+        state.put(HostFunctions::default());
+        state.put(TimerQueue::default());
+        state.put(AnimationFrames::default());
+    },
+);
+
 /// JavaScript runtime with full DOM integration
 pub struct JavaScriptRuntime {
     runtime: JsRuntime,
@@ -41,19 +375,96 @@ impl JavaScriptRuntime {
         let event_queue = Arc::new(Mutex::new(Vec::new()));
         
         // Create runtime with DOM extensions
-        let mut runtime = JsRuntime::new(RuntimeOptions::default());
+        let mut runtime = JsRuntime::new(RuntimeOptions {
+            extensions: vec![rift_host::init()],
+            ..Default::default()
+        });
         
+        runtime.op_state().borrow_mut().put(DomBindings {
+            arena: arena.clone(),
+            event_queue: event_queue.clone(),
+        });
+
         // Initialize DOM API
         runtime.execute_script("dom_init", include_str!("dom_api.js"))?;
 
-        Ok(Self {
+        let mut js_runtime = Self {
             runtime,
             arena,
             root_id,
             mutation_listeners,
             event_queue,
             element_counter,
-        })
+        };
+        js_runtime.register_event_bindings()?;
+        Ok(js_runtime)
+    }
+
+    /// Back `addEventListener`/`removeEventListener` with the nodes' `event_listeners`,
+    /// which hold the ids of callbacks kept in the JS-side registry
+    fn register_event_bindings(&mut self) -> Result<(), AnyError> {
+        for (name, add) in [("_addEventListener", true), ("_removeEventListener", false)] {
+            let arena = self.arena.clone();
+            self.register_global_fn(name, move |args| {
+                let (Some(node_id), Some(JsonValue::String(event_type)), Some(callback_id)) =
+                    (args.first().and_then(json_node_id), args.get(1), args.get(2).and_then(JsonValue::as_u64))
+                else {
+                    eprintln!("[JS] {} called with invalid arguments: {:?}", name, args);
+                    return Ok(JsonValue::Bool(false));
+                };
+                let Some(node) = arena.lock().unwrap().get_node(&node_id) else {
+                    return Ok(JsonValue::Bool(false));
+                };
+                let mut node = node.lock().unwrap();
+                let callbacks = node.event_listeners.entry(event_type.clone()).or_default();
+                if add {
+                    callbacks.push(callback_id as u32);
+                } else {
+                    callbacks.retain(|&id| id != callback_id as u32);
+                }
+                Ok(JsonValue::Bool(true))
+            })?;
+        }
+        self.execute_script(
+            "event_bindings",
+            "window._addEventListener = _addEventListener; window._removeEventListener = _removeEventListener;",
+        )
+    }
+
+    /// Run the `requestAnimationFrame` callbacks queued before this frame, passing `timestamp`
+    /// in ms. Callbacks requested while the frame runs wait for the next one. Returns how many ran.
+    pub fn run_animation_frame(&mut self, timestamp: f64) -> Result<usize, AnyError> {
+        let ids = std::mem::take(&mut self.runtime.op_state().borrow_mut().borrow_mut::<AnimationFrames>().pending);
+        if ids.is_empty() {
+            return Ok(0);
+        }
+        let args = deno_core::serde_json::to_string(&(&ids, timestamp))?;
+        self.execute_script("animation_frame", &format!("window._runAnimationFrame(...{});", args))?;
+        Ok(ids.len())
+    }
+
+    /// Call the JS listeners registered on `node_id` for `event_type` with a synthesized event,
+    /// in registration order. A bubbling event then reaches each ancestor's listeners in turn
+    /// until the root or a listener calls `stopPropagation()`. Returns whether any listener was registered.
+    pub fn dispatch_event(&mut self, node_id: &str, event_type: &str, bubbles: bool) -> Result<bool, AnyError> {
+        // Copy the ids out first; listeners may call back into the arena
+        let mut path = Vec::new();
+        {
+            let arena = self.arena.lock().unwrap();
+            for id in event_path(&arena, node_id, bubbles) {
+                let Some(node) = arena.get_node(&id) else { continue };
+                let node = node.lock().unwrap();
+                if let Some(callbacks) = node.event_listeners.get(event_type).filter(|callbacks| !callbacks.is_empty()) {
+                    path.push(JsonValue::from(vec![JsonValue::from(id.clone()), JsonValue::from(callbacks.clone())]));
+                }
+            }
+        }
+        if path.is_empty() {
+            return Ok(false);
+        }
+        let args = deno_core::serde_json::to_string(&(node_id, event_type, bubbles, path))?;
+        self.execute_script("dispatch_event", &format!("window._runEventPath(...{});", args))?;
+        Ok(true)
     }
 
     /// Execute JavaScript code in the runtime
@@ -65,6 +476,17 @@ impl JavaScriptRuntime {
         Ok(())
     }
 
+    /// Execute JavaScript code and return its completion value, such as the value of the
+    /// last expression statement, as JSON. `undefined` comes back as null.
+    pub fn eval_script(&mut self, script_name: &str, code: &str) -> Result<JsonValue, AnyError> {
+        println!("[JS] Evaluating script: {}", script_name);
+        let script_name_static: &'static str = Box::leak(script_name.to_string().into_boxed_str());
+        let result = self.runtime.execute_script(script_name_static, code.to_string())?;
+        let scope = &mut self.runtime.handle_scope();
+        let result = v8::Local::new(scope, result);
+        Ok(serde_v8::from_v8(scope, result)?)
+    }
+
     /// Execute JavaScript code asynchronously
     pub async fn execute_script_async(&mut self, script_name: &str, code: &str) -> Result<(), AnyError> {
         println!("[JS] Executing async script: {}", script_name);
@@ -74,9 +496,46 @@ impl JavaScriptRuntime {
         Ok(())
     }
 
-    /// Run the event loop for async operations
-    pub fn run_event_loop(&mut self) -> Result<(), AnyError> {
+    /// Drive async ops such as `fetch` until none are pending, running the promise
+    /// callbacks that await them
+    pub async fn run_until_idle(&mut self) -> Result<(), AnyError> {
+        self.runtime.run_event_loop(PollEventLoopOptions::default()).await?;
+        Ok(())
+    }
+
+    /// Expose a Rust function to scripts as the global `name`.
+    /// Arguments and the return value are marshaled as JSON; an `Err` is thrown as a JS `Error`.
+    pub fn register_global_fn<F>(&mut self, name: &str, host_fn: F) -> Result<(), AnyError>
+    where
+        F: Fn(Vec<JsonValue>) -> Result<JsonValue, AnyError> + 'static,
+    {
+        self.runtime
+            .op_state()
+            .borrow_mut()
+            .borrow_mut::<HostFunctions>()
+            .0
+            .insert(name.to_string(), Box::new(host_fn));
+
+        let quoted = deno_core::serde_json::to_string(name)?;
+        let binding = format!(
+            "globalThis[{0}] = (...args) => {{ const r = Deno.core.ops.op_host_call({0}, args); if ('error' in r) throw new Error(r.error); return r.value; }};",
+            quoted
+        );
+        self.execute_script("host_fn_binding", &binding)
+    }
+
+    /// Run the event loop for async operations. Returns whether the processed
+    /// mutations asked for a layout recalculation.
+    pub fn run_event_loop(&mut self) -> Result<bool, AnyError> {
         println!("[JS] Running event loop");
+
+        // Fire due timers; a throwing callback doesn't stop the others
+        let due = self.runtime.op_state().borrow_mut().borrow_mut::<TimerQueue>().take_due(Instant::now());
+        for id in due {
+            if let Err(e) = self.execute_script("timer", &format!("window._fireTimer({});", id)) {
+                eprintln!("[JS] Timer {} callback failed: {}", id, e);
+            }
+        }
         
         // Process mutation events
         let events = {
@@ -84,11 +543,12 @@ impl JavaScriptRuntime {
             queue.drain(..).collect::<Vec<_>>()
         };
         
+        let needs_layout = events.iter().any(|event| matches!(event, DomMutationEvent::LayoutRecalculationNeeded));
         for event in events {
             self.process_mutation_event(event);
         }
         
-        Ok(())
+        Ok(needs_layout)
     }
 
     /// Get a reference to the DOM tree
@@ -132,6 +592,7 @@ impl JavaScriptRuntime {
             styles: StyleMap::default(),
             children: Vec::new(),
             event_listeners: HashMap::new(),
+            pseudo_states: Default::default(),
             parent: None,
         };
 
@@ -226,6 +687,31 @@ impl JavaScriptRuntime {
     }
 }
 
+/// Node ids arrive from scripts as either strings or numbers
+fn json_node_id(value: &JsonValue) -> Option<String> {
+    match value {
+        JsonValue::String(id) => Some(id.clone()),
+        JsonValue::Number(id) => Some(id.to_string()),
+        _ => None,
+    }
+}
+
+/// `style_attr` with `property` set to `value`, replacing an earlier declaration of it;
+/// an empty value removes the declaration, as assigning `''` to `element.style` does
+fn with_declaration(style_attr: &str, property: &str, value: &str) -> String {
+    let mut declarations: Vec<String> = style_attr
+        .split(';')
+        .map(str::trim)
+        .filter(|declaration| !declaration.is_empty())
+        .filter(|declaration| declaration.split_once(':').is_none_or(|(name, _)| !name.trim().eq_ignore_ascii_case(property)))
+        .map(str::to_string)
+        .collect();
+    if !value.is_empty() {
+        declarations.push(format!("{}: {}", property, value));
+    }
+    declarations.join("; ")
+}
+
 /// JavaScript script manager for handling page scripts
 pub struct ScriptManager {
     runtime: JavaScriptRuntime,
@@ -262,16 +748,26 @@ impl ScriptManager {
         Ok(())
     }
 
-    /// Execute an external script from URL
-    pub async fn execute_external_script(&mut self, script_url: &str) -> Result<(), AnyError> {
+    /// Execute an external script fetched through `loader`, returning the number of bytes downloaded
+    pub async fn execute_external_script(&mut self, script_url: &str, loader: &dyn ResourceLoader) -> Result<usize, AnyError> {
         println!("[JS] Fetching external script: {}", script_url);
         
         // Fetch the script content
-        let response = reqwest::get(script_url).await?;
-        let script_content = response.text().await?;
+        let bytes = loader.fetch(script_url).await.map_err(|e| AnyError::msg(e.to_string()))?;
+        let script_content = String::from_utf8_lossy(&bytes);
         
         self.execute_script(&script_content, script_url)?;
-        Ok(())
+        Ok(bytes.len())
+    }
+
+    /// Run a script and read back its completion value; see `JavaScriptRuntime::eval_script`
+    pub fn eval_script(&mut self, script_name: &str, code: &str) -> Result<JsonValue, AnyError> {
+        self.runtime.eval_script(script_name, code)
+    }
+
+    /// Wait for the page's pending async work; see `JavaScriptRuntime::run_until_idle`
+    pub async fn run_until_idle(&mut self) -> Result<(), AnyError> {
+        self.runtime.run_until_idle().await
     }
 
     /// Add a DOM mutation handler
@@ -282,21 +778,39 @@ impl ScriptManager {
         self.dom_mutation_handlers.push(Box::new(handler));
     }
 
+    /// Expose a Rust function to page scripts; see `JavaScriptRuntime::register_global_fn`
+    pub fn register_global_fn<F>(&mut self, name: &str, host_fn: F) -> Result<(), AnyError>
+    where
+        F: Fn(Vec<JsonValue>) -> Result<JsonValue, AnyError> + 'static,
+    {
+        self.runtime.register_global_fn(name, host_fn)
+    }
+
+    /// Run one animation frame at `timestamp` ms; see `JavaScriptRuntime::run_animation_frame`
+    pub fn run_animation_frame(&mut self, timestamp: f64) -> Result<usize, AnyError> {
+        self.runtime.run_animation_frame(timestamp)
+    }
+
+    /// Run the page's listeners for `event_type` on `node_id`; see `JavaScriptRuntime::dispatch_event`
+    pub fn dispatch_event(&mut self, node_id: &str, event_type: &str, bubbles: bool) -> Result<bool, AnyError> {
+        self.runtime.dispatch_event(node_id, event_type, bubbles)
+    }
+
     /// Get the runtime for direct access
     pub fn get_runtime(&mut self) -> &mut JavaScriptRuntime {
         &mut self.runtime
     }
 
-    /// Run the JavaScript event loop
-    pub fn run_event_loop(&mut self) -> Result<(), AnyError> {
-        self.runtime.run_event_loop()?;
+    /// Run the JavaScript event loop; true when scripts changed the DOM in a way that needs a new layout
+    pub fn run_event_loop(&mut self) -> Result<bool, AnyError> {
+        let needs_layout = self.runtime.run_event_loop()?;
         
         // Run mutation handlers
         for handler in &self.dom_mutation_handlers {
             handler()?;
         }
         
-        Ok(())
+        Ok(needs_layout)
     }
 }
 
@@ -570,4 +1084,249 @@ mod tests {
         let result = runtime.execute_script("test", "console.log('Hello World');");
         assert!(result.is_ok());
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_register_global_fn_round_trips_through_js() {
+        let arena = Arc::new(Mutex::new(DOMArena::new()));
+        let mut runtime = JavaScriptRuntime::new(arena, String::new()).unwrap();
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let recorded = calls.clone();
+        runtime.register_global_fn("hostEcho", move |args| {
+            let arg = args.into_iter().next().unwrap_or(JsonValue::Null);
+            recorded.lock().unwrap().push(arg.clone());
+            Ok(arg)
+        }).unwrap();
+
+        let result = runtime.execute_script("echo", "if (hostEcho('hi') !== 'hi') throw new Error('bad echo');");
+        assert!(result.is_ok());
+        assert_eq!(*calls.lock().unwrap(), vec![JsonValue::String("hi".to_string())]);
+    }
+
+    #[test]
+    fn test_create_element_adds_node_to_arena() {
+        let arena = Arc::new(Mutex::new(DOMArena::new()));
+        let mut runtime = JavaScriptRuntime::new(arena.clone(), String::new()).unwrap();
+        let created = Arc::new(Mutex::new(Vec::new()));
+        let recorded = created.clone();
+        runtime.register_global_fn("report", move |args| {
+            recorded.lock().unwrap().extend(args);
+            Ok(JsonValue::Null)
+        }).unwrap();
+
+        let script = "const list = document.createElement('ul'); const item = document.createElement('li'); \
+                      item.setAttribute('id', 'first'); item.textContent = 'one'; list.appendChild(item); \
+                      report(list.id, item.id, window._getElementById('first'));";
+        runtime.execute_script("create", script).unwrap();
+
+        let ids: Vec<String> = created.lock().unwrap().iter().filter_map(json_node_id).collect();
+        let [list_id, item_id, found_id] = ids.as_slice() else { panic!("unexpected report: {:?}", ids) };
+        assert_eq!(found_id, item_id);
+        let arena = arena.lock().unwrap();
+        let list = arena.get_node(list_id).unwrap();
+        assert_eq!(list.lock().unwrap().node_type, NodeType::Element("ul".to_string()));
+        assert_eq!(list.lock().unwrap().children, vec![item_id.clone()]);
+        let item = arena.get_node(item_id).unwrap();
+        let item = item.lock().unwrap();
+        assert_eq!(item.parent.as_ref(), Some(list_id));
+        assert_eq!(item.text_content, "one");
+    }
+
+    #[test]
+    fn test_append_child_rejects_an_ancestor_and_reads_tag_name() {
+        let arena = Arc::new(Mutex::new(DOMArena::new()));
+        let mut runtime = JavaScriptRuntime::new(arena.clone(), String::new()).unwrap();
+
+        let script = "const outer = document.createElement('div'); const inner = document.createElement('span'); \
+                      outer.appendChild(inner); \
+                      if (window._appendChild(inner.id, outer.id)) throw new Error('appended an ancestor'); \
+                      if (window._appendChild(outer.id, outer.id)) throw new Error('appended to itself'); \
+                      if (inner.tagName !== 'SPAN') throw new Error('tagName: ' + inner.tagName);";
+        runtime.execute_script("cycle", script).unwrap();
+
+        let arena = arena.lock().unwrap();
+        let outer = arena.nodes.values()
+            .find(|node| node.lock().unwrap().node_type == NodeType::Element("div".to_string()))
+            .unwrap();
+        assert_eq!(outer.lock().unwrap().parent, None);
+    }
+
+    #[test]
+    fn test_get_element_by_id_style_assignment_reaches_node() {
+        let arena = Arc::new(Mutex::new(DOMArena::new()));
+        let mut parser = crate::parser::html::HTMLParser::new(r#"<div id="x" style="width: 10px">hi</div>"#.to_string());
+        let root = parser.parse_into(&mut arena.lock().unwrap());
+        let mut runtime = JavaScriptRuntime::new(arena.clone(), root.id).unwrap();
+
+        let script = "const el = document.getElementById('x'); el.style.color = 'red'; el.style.backgroundColor = 'blue'; \
+                      if (el.style.color !== 'red') throw new Error('color: ' + el.style.color); \
+                      if (document.getElementById('missing') !== null) throw new Error('found missing element');";
+        runtime.execute_script("style", script).unwrap();
+
+        let arena = arena.lock().unwrap();
+        let div = arena.nodes.values().find(|node| node.lock().unwrap().attributes.get("id").is_some_and(|id| id == "x")).unwrap();
+        let div = div.lock().unwrap();
+        assert_eq!(div.styles.color, "red");
+        assert_eq!(div.styles.background_color, "blue");
+        assert_eq!(div.attributes["style"], "width: 10px; color: red; background-color: blue");
+    }
+
+    #[test]
+    fn test_with_declaration_replaces_existing_property() {
+        assert_eq!(with_declaration("color: blue; width: 5px;", "color", "red"), "width: 5px; color: red");
+        assert_eq!(with_declaration("", "color", "red"), "color: red");
+        assert_eq!(with_declaration("color: red; width: 5px", "color", ""), "width: 5px");
+    }
+
+    #[test]
+    fn test_timers_fire_on_event_loop() {
+        let arena = Arc::new(Mutex::new(DOMArena::new()));
+        let mut runtime = JavaScriptRuntime::new(arena, String::new()).unwrap();
+        let script = "globalThis.fired = false; globalThis.ticks = 0; globalThis.cancelled = false; \
+                      setTimeout(() => { fired = true; }, 0); \
+                      const tick = setInterval(() => { if (++ticks === 2) clearInterval(tick); }, 0); \
+                      clearTimeout(setTimeout(() => { cancelled = true; }, 0));";
+        runtime.execute_script("timers", script).unwrap();
+        assert!(runtime.execute_script("before", "if (fired) throw new Error('fired early');").is_ok());
+
+        runtime.run_event_loop().unwrap();
+        assert!(runtime.execute_script("first", "if (!fired || ticks !== 1 || cancelled) throw new Error('first loop');").is_ok());
+        runtime.run_event_loop().unwrap();
+        runtime.run_event_loop().unwrap();
+        assert!(runtime.execute_script("after", "if (ticks !== 2) throw new Error('ticks: ' + ticks);").is_ok());
+    }
+
+    #[test]
+    fn test_infinite_timeout_is_clamped() {
+        let arena = Arc::new(Mutex::new(DOMArena::new()));
+        let mut runtime = JavaScriptRuntime::new(arena, String::new()).unwrap();
+        let script = "globalThis.fired = false; \
+                      setTimeout(() => { fired = true; }, Infinity); \
+                      setInterval(() => { fired = true; }, 1e300);";
+        runtime.execute_script("timers", script).unwrap();
+        runtime.run_event_loop().unwrap();
+        assert!(runtime.execute_script("check", "if (fired) throw new Error('fired early');").is_ok());
+    }
+
+    #[test]
+    fn test_animation_frame_callbacks_run_once_per_frame() {
+        let arena = Arc::new(Mutex::new(DOMArena::new()));
+        let mut scripts = ScriptManager::new(arena, String::new()).unwrap();
+        let script = "globalThis.frames = []; \
+                      requestAnimationFrame((t) => frames.push('a' + t)); \
+                      requestAnimationFrame((t) => frames.push('b' + t)); \
+                      const again = (t) => { frames.push('c' + t); if (t < 32) requestAnimationFrame(again); }; \
+                      requestAnimationFrame(again); \
+                      cancelAnimationFrame(requestAnimationFrame(() => frames.push('cancelled')));";
+        scripts.execute_script(script, "raf").unwrap();
+
+        assert_eq!(scripts.run_animation_frame(16.0).unwrap(), 3);
+        assert_eq!(scripts.run_animation_frame(32.0).unwrap(), 1);
+        assert_eq!(scripts.run_animation_frame(48.0).unwrap(), 0);
+        let check = "if (frames.join() !== 'a16,b16,c16,c32') throw new Error(frames.join());";
+        assert!(scripts.execute_script(check, "check").is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_resolves_with_server_response() {
+        use std::io::{Read, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/data.json", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let read = stream.read(&mut request).unwrap();
+            let body = r#"{"items":[1,2],"name":"rift"}"#;
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body).unwrap();
+            String::from_utf8_lossy(&request[..read]).into_owned()
+        });
+
+        let arena = Arc::new(Mutex::new(DOMArena::new()));
+        let mut runtime = JavaScriptRuntime::new(arena, String::new()).unwrap();
+        let script = format!(
+            "globalThis.result = null; fetch({:?}).then((response) => {{ globalThis.status = response.status; return response.json(); }}).then((data) => {{ result = data; }});",
+            url
+        );
+        runtime.execute_script("fetch", &script).unwrap();
+        runtime.run_until_idle().await.unwrap();
+
+        assert!(server.join().unwrap().starts_with("GET /data.json"));
+        let check = "if (status !== 200 || result.name !== 'rift' || result.items[1] !== 2) throw new Error('bad fetch result');";
+        assert!(runtime.execute_script("check", check).is_ok());
+    }
+
+    #[test]
+    fn test_native_json_round_trips() {
+        let arena = Arc::new(Mutex::new(DOMArena::new()));
+        let mut runtime = JavaScriptRuntime::new(arena, String::new()).unwrap();
+        let source = r#"{"a":[1,"b"],"c":null}"#;
+        let script = format!("JSON.stringify(JSON.parse({:?}))", source);
+        let result = runtime.runtime.execute_script("json", script).unwrap();
+
+        let scope = &mut runtime.runtime.handle_scope();
+        let result = deno_core::v8::Local::new(scope, result);
+        let round_tripped: String = serde_v8::from_v8(scope, result).unwrap();
+        assert_eq!(round_tripped, source);
+    }
+
+    #[test]
+    fn test_eval_script_returns_completion_value() {
+        let arena = Arc::new(Mutex::new(DOMArena::new()));
+        let mut runtime = JavaScriptRuntime::new(arena, String::new()).unwrap();
+        assert_eq!(runtime.eval_script("t", "1 + 2").unwrap(), JsonValue::from(3));
+        assert_eq!(runtime.eval_script("t", "'a' + 'b'").unwrap(), JsonValue::from("ab"));
+        assert_eq!(runtime.eval_script("t", "({ list: [true, null] })").unwrap(), deno_core::serde_json::json!({ "list": [true, null] }));
+        assert_eq!(runtime.eval_script("t", "undefined").unwrap(), JsonValue::Null);
+        assert!(runtime.eval_script("t", "throw new Error('boom')").is_err());
+    }
+
+    #[test]
+    fn test_dispatch_event_runs_registered_listener() {
+        let arena = Arc::new(Mutex::new(DOMArena::new()));
+        let button = DOMNode::create_element("button");
+        let button_id = button.id.clone();
+        arena.lock().unwrap().add_node(button);
+        let mut runtime = JavaScriptRuntime::new(arena.clone(), String::new()).unwrap();
+
+        let script = format!(
+            "globalThis.clicks = 0; const button = {{ id: {:?} }}; patchEventAPI(button); \
+             button.addEventListener('click', (event) => {{ if (event.type === 'click') clicks++; }});",
+            button_id
+        );
+        runtime.execute_script("listen", &script).unwrap();
+        assert_eq!(arena.lock().unwrap().get_node(&button_id).unwrap().lock().unwrap().event_listeners["click"].len(), 1);
+
+        assert!(runtime.dispatch_event(&button_id, "click", false).unwrap());
+        assert!(runtime.dispatch_event(&button_id, "click", false).unwrap());
+        assert!(!runtime.dispatch_event(&button_id, "keydown", false).unwrap());
+        assert!(runtime.execute_script("check", "if (clicks !== 2) throw new Error('clicks: ' + clicks);").is_ok());
+    }
+
+    #[test]
+    fn test_dispatch_event_bubbles_until_stopped() {
+        let arena = Arc::new(Mutex::new(DOMArena::new()));
+        let mut parent = DOMNode::create_element("div");
+        let mut child = DOMNode::create_element("span");
+        child.parent = Some(parent.id.clone());
+        parent.children.push(child.id.clone());
+        let (parent_id, child_id) = (parent.id.clone(), child.id.clone());
+        arena.lock().unwrap().add_node(parent);
+        arena.lock().unwrap().add_node(child);
+        let mut runtime = JavaScriptRuntime::new(arena, String::new()).unwrap();
+
+        let script = format!(
+            "globalThis.seen = []; const parent = {{ id: {:?} }}; const child = {{ id: {:?} }}; \
+             patchEventAPI(parent); patchEventAPI(child); \
+             parent.addEventListener('click', (event) => seen.push(event.target.id + '>' + event.currentTarget.id)); \
+             child.addEventListener('stop', (event) => event.stopPropagation()); \
+             parent.addEventListener('stop', () => seen.push('stopped event reached parent'));",
+            parent_id, child_id
+        );
+        runtime.execute_script("listen", &script).unwrap();
+
+        assert!(runtime.dispatch_event(&child_id, "click", true).unwrap());
+        assert!(!runtime.dispatch_event(&child_id, "click", false).unwrap());
+        assert!(runtime.dispatch_event(&child_id, "stop", true).unwrap());
+        let check = format!("if (seen.length !== 1 || seen[0] !== '{}>{}') throw new Error(String(seen));", child_id, parent_id);
+        assert!(runtime.execute_script("check", &check).is_ok());
+    }
+}
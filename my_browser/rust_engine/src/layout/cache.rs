@@ -0,0 +1,254 @@
+// Incremental layout: subtrees whose content and computed styles hash the same as last pass,
+// and that start from the same flow state, reuse their previous boxes instead of being laid out again
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::dom::node::{DOMArena, DOMNode, LayoutBox, NodeType, StyleMap};
+use crate::layout::float::FloatContext;
+use crate::layout::layout::{CounterScopes, LayoutEngine};
+use crate::parser::css::Stylesheet;
+
+/// Inline flow state a subtree is laid out from (and leaves behind). Only the vertical
+/// offset may differ for a cached subtree to be reused, so floats are kept relative to it.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct FlowState {
+    pub x: f32,
+    pub line_height: f32,
+    pub in_inline_context: bool,
+    pub white_space: String,
+    pub counters: CounterScopes,
+    pub floats: FloatContext,
+    /// Width `%` lengths inside the subtree resolve against
+    pub containing_width: f32,
+    /// Height they resolve against, None when it's indefinite
+    pub containing_height: Option<f32>,
+}
+
+#[derive(Debug)]
+struct CachedSubtree {
+    hash: u64,
+    entry: FlowState,
+    entry_y: f32,
+    boxes: Vec<LayoutBox>,
+    exit: FlowState,
+    exit_y: f32,
+}
+
+/// Counts of subtrees served from the cache and nodes laid out from scratch
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LayoutCacheStats {
+    pub hits: usize,
+    pub recomputed: usize,
+}
+
+/// Per-node layout cache, keyed by node id and validated against a content+style hash
+#[derive(Debug, Default)]
+pub struct LayoutCache {
+    entries: Mutex<HashMap<String, CachedSubtree>>,
+    // Subtree hashes for the pass in progress
+    hashes: Mutex<HashMap<String, u64>>,
+    hits: AtomicUsize,
+    recomputed: AtomicUsize,
+}
+
+impl LayoutCache {
+    pub fn stats(&self) -> LayoutCacheStats {
+        LayoutCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            recomputed: self.recomputed.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    pub(crate) fn begin_pass(&self, hashes: HashMap<String, u64>) {
+        *self.hashes.lock().unwrap() = hashes;
+    }
+
+    pub(crate) fn hash_of(&self, node_id: &str) -> Option<u64> {
+        self.hashes.lock().unwrap().get(node_id).copied()
+    }
+
+    pub(crate) fn record_recompute(&self) {
+        self.recomputed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Append the cached boxes for `node_id`, shifted to `current_y`, and return the state the
+    /// subtree left behind. None when there is no entry or it was computed from different input.
+    pub(crate) fn replay(&self, node_id: &str, hash: u64, entry: &FlowState, current_y: f32, boxes: &mut Vec<LayoutBox>) -> Option<(FlowState, f32)> {
+        let entries = self.entries.lock().unwrap();
+        let cached = entries.get(node_id).filter(|cached| cached.hash == hash && cached.entry == *entry)?;
+        let dy = current_y - cached.entry_y;
+        boxes.extend(cached.boxes.iter().cloned().map(|mut b| {
+            b.y += dy;
+            b
+        }));
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some((cached.exit.clone(), cached.exit_y + dy))
+    }
+
+    pub(crate) fn store(&self, node_id: &str, hash: u64, entry: FlowState, entry_y: f32, boxes: Vec<LayoutBox>, exit: FlowState, exit_y: f32) {
+        let cached = CachedSubtree { hash, entry, entry_y, boxes, exit, exit_y };
+        self.entries.lock().unwrap().insert(node_id.to_string(), cached);
+    }
+}
+
+/// Declared styles by node id. Each entry is keyed by a hash of everything selector matching
+/// reads, so a changed attribute, inline style or ancestor invalidates it on the next lookup.
+#[derive(Debug, Default)]
+pub struct StyleCache {
+    entries: Mutex<HashMap<String, (u64, StyleMap)>>,
+    // Computed styles, under the same key: it covers the ancestors they inherit from
+    computed: Mutex<HashMap<String, (u64, StyleMap)>>,
+    recomputed: AtomicUsize,
+}
+
+impl StyleCache {
+    /// How many times a node's styles were matched against the stylesheet rather than reused
+    pub fn recomputed(&self) -> usize {
+        self.recomputed.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn get(&self, node_id: &str, key: u64) -> Option<StyleMap> {
+        let entries = self.entries.lock().unwrap();
+        entries.get(node_id).filter(|(cached_key, _)| *cached_key == key).map(|(_, styles)| styles.clone())
+    }
+
+    pub(crate) fn store(&self, node_id: &str, key: u64, styles: StyleMap) {
+        self.recomputed.fetch_add(1, Ordering::Relaxed);
+        self.entries.lock().unwrap().insert(node_id.to_string(), (key, styles));
+    }
+
+    pub(crate) fn get_computed(&self, node_id: &str, key: u64) -> Option<StyleMap> {
+        let computed = self.computed.lock().unwrap();
+        computed.get(node_id).filter(|(cached_key, _)| *cached_key == key).map(|(_, styles)| styles.clone())
+    }
+
+    pub(crate) fn store_computed(&self, node_id: &str, key: u64, styles: StyleMap) {
+        self.computed.lock().unwrap().insert(node_id.to_string(), (key, styles));
+    }
+
+    /// Drop the entries of nodes not in `live`: removed nodes, and the generated content runs
+    /// each pass creates afresh
+    pub(crate) fn retain(&self, live: &HashSet<String>) {
+        self.entries.lock().unwrap().retain(|node_id, _| live.contains(node_id));
+        self.computed.lock().unwrap().retain(|node_id, _| live.contains(node_id));
+    }
+
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.entries.lock().unwrap().len() + self.computed.lock().unwrap().len()
+    }
+}
+
+impl LayoutEngine {
+    /// Hash the inputs to a node's declared styles: its and its ancestors' tags, attributes
+    /// (inline style included) and pseudo-states, which selectors and `var()` scopes depend on,
+    /// plus the viewport that media queries test and the stylesheet and `env()` values
+    pub(crate) fn style_key(&self, node: &DOMNode, arena: &DOMArena) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.style_inputs().hash(&mut hasher);
+        self.viewport_width().to_bits().hash(&mut hasher);
+        self.viewport_height().to_bits().hash(&mut hasher);
+        hash_selector_inputs(node, &mut hasher);
+        let mut parent = node.parent.clone();
+        while let Some(ancestor) = parent.and_then(|id| arena.get_node(&id)) {
+            let ancestor = ancestor.lock().unwrap();
+            hash_selector_inputs(&ancestor, &mut hasher);
+            parent = ancestor.parent.clone();
+        }
+        hasher.finish()
+    }
+
+    /// Hash every node under `root` (itself included) over its tag, attributes, text and computed
+    /// styles plus those of its descendants, so any change inside a subtree changes its hash
+    pub(crate) fn subtree_hashes(&self, root: &DOMNode, arena: &DOMArena) -> HashMap<String, u64> {
+        let mut hashes = HashMap::new();
+        self.hash_subtree(root, arena, &mut hashes);
+        hashes
+    }
+
+    fn hash_subtree(&self, node: &DOMNode, arena: &DOMArena, hashes: &mut HashMap<String, u64>) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        node.text_content.hash(&mut hasher);
+        if let NodeType::Element(tag) = &node.node_type {
+            tag.hash(&mut hasher);
+            let mut attributes: Vec<_> = node.attributes.iter().collect();
+            attributes.sort();
+            attributes.hash(&mut hasher);
+            let mut styles = self.get_node_styles(node, arena);
+            // Debug output of a HashMap isn't ordered, so the extra properties are hashed sorted
+            let mut extra: Vec<_> = std::mem::take(&mut styles.extra).into_iter().collect();
+            extra.sort();
+            format!("{:?}", styles).hash(&mut hasher);
+            extra.hash(&mut hasher);
+        }
+        for child_id in &node.children {
+            if let Some(child_node) = arena.get_node(child_id) {
+                let child = child_node.lock().unwrap().clone();
+                self.hash_subtree(&child, arena, hashes).hash(&mut hasher);
+            }
+        }
+        let hash = hasher.finish();
+        hashes.insert(node.id.clone(), hash);
+        hash
+    }
+}
+
+/// Ids of `root` and every node under it
+pub(crate) fn tree_node_ids(root: &DOMNode, arena: &DOMArena) -> HashSet<String> {
+    let mut ids = HashSet::from([root.id.clone()]);
+    let mut pending = root.children.clone();
+    while let Some(id) = pending.pop() {
+        if let Some(node) = arena.get_node(&id) {
+            pending.extend(node.lock().unwrap().children.iter().cloned());
+            ids.insert(id);
+        }
+    }
+    ids
+}
+
+fn hash_selector_inputs(node: &DOMNode, hasher: &mut DefaultHasher) {
+    node.id.hash(hasher);
+    if let NodeType::Element(tag) = &node.node_type {
+        tag.hash(hasher);
+    }
+    let mut attributes: Vec<_> = node.attributes.iter().collect();
+    attributes.sort();
+    attributes.hash(hasher);
+    let mut pseudo_states: Vec<_> = node.pseudo_states.iter().collect();
+    pseudo_states.sort();
+    pseudo_states.hash(hasher);
+}
+
+/// Hash the style inputs every node shares: the stylesheet's rules and root variables, and the
+/// `env()` values. Engines with equal hashes can share a `StyleCache`.
+pub(crate) fn style_inputs_hash(stylesheet: Option<&Stylesheet>, environment: &HashMap<String, String>) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    if let Some(stylesheet) = stylesheet {
+        for rule in &stylesheet.rules {
+            rule.selector.hash(&mut hasher);
+            rule.media.hash(&mut hasher);
+            for declaration in &rule.declarations {
+                declaration.hash(&mut hasher);
+            }
+            sorted(&rule.custom_properties).hash(&mut hasher);
+            sorted(&rule.important).hash(&mut hasher);
+        }
+        sorted(&stylesheet.root_variables).hash(&mut hasher);
+    }
+    sorted(environment).hash(&mut hasher);
+    hasher.finish()
+}
+
+fn sorted<V: Ord>(map: &HashMap<String, V>) -> Vec<(&String, &V)> {
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort();
+    entries
+}
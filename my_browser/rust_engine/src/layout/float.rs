@@ -0,0 +1,130 @@
+// Floats: boxes taken out of the flow and pushed against the left or right edge, with
+// following inline content flowing around the rectangles they occupy
+
+/// Which edge a `float` box is pushed against
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloatSide {
+    Left,
+    Right,
+}
+
+impl FloatSide {
+    /// `left` or `right`; None for `none` and anything else
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "left" | "inline-start" => Some(FloatSide::Left),
+            "right" | "inline-end" => Some(FloatSide::Right),
+            _ => None,
+        }
+    }
+}
+
+/// The margin box a float occupies, in page coordinates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FloatRect {
+    pub side: FloatSide,
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl FloatRect {
+    fn bottom(&self) -> f32 {
+        self.y + self.height
+    }
+
+    /// Whether the float covers any of the band from `y` to `y + height`
+    fn overlaps(&self, y: f32, height: f32) -> bool {
+        self.y < y + height.max(f32::EPSILON) && y < self.bottom()
+    }
+}
+
+/// Floats placed so far in the current block formatting context
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct FloatContext {
+    floats: Vec<FloatRect>,
+}
+
+impl FloatContext {
+    /// Where a line from `y` to `y + height` can start: right of every left float beside it
+    pub fn line_start(&self, y: f32, height: f32) -> f32 {
+        self.floats.iter()
+            .filter(|float| float.side == FloatSide::Left && float.overlaps(y, height))
+            .map(|float| float.x + float.width)
+            .fold(0.0, f32::max)
+    }
+
+    /// Where that line must end: left of every right float beside it, or at `limit`
+    pub fn line_end(&self, y: f32, height: f32, limit: f32) -> f32 {
+        self.floats.iter()
+            .filter(|float| float.side == FloatSide::Right && float.overlaps(y, height))
+            .map(|float| float.x)
+            .fold(limit, f32::min)
+    }
+
+    /// The bottom of the floats `clear` (`left`, `right` or `both`) moves content below
+    pub fn clearance(&self, clear: &str) -> Option<f32> {
+        let sides: &[FloatSide] = match clear.trim().to_ascii_lowercase().as_str() {
+            "left" | "inline-start" => &[FloatSide::Left],
+            "right" | "inline-end" => &[FloatSide::Right],
+            "both" => &[FloatSide::Left, FloatSide::Right],
+            _ => return None,
+        };
+        self.floats.iter()
+            .filter(|float| sides.contains(&float.side))
+            .map(FloatRect::bottom)
+            .reduce(f32::max)
+    }
+
+    /// Place a `width` x `height` float at or below `y`, as high as it fits between the floats
+    /// already there within `limit`, and return its top-left corner
+    pub fn place(&mut self, side: FloatSide, y: f32, width: f32, height: f32, limit: f32) -> (f32, f32) {
+        let mut top = y;
+        loop {
+            let (start, end) = (self.line_start(top, height), self.line_end(top, height, limit));
+            // Too narrow here: try again below the first float that ends in this band
+            let next = self.floats.iter()
+                .filter(|float| float.overlaps(top, height))
+                .map(FloatRect::bottom)
+                .reduce(f32::min);
+            if end - start >= width || next.is_none() {
+                let x = match side {
+                    FloatSide::Left => start,
+                    FloatSide::Right => (end - width).max(start),
+                };
+                self.floats.push(FloatRect { side, x, y: top, width, height });
+                return (x, top);
+            }
+            top = next.unwrap_or(top);
+        }
+    }
+
+    /// The floats with their tops relative to `origin`, so flows that differ only by a
+    /// vertical offset compare equal
+    pub fn relative_to(&self, origin: f32) -> FloatContext {
+        let floats = self.floats.iter().map(|float| FloatRect { y: float.y - origin, ..*float }).collect();
+        FloatContext { floats }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_floats_narrow_lines_and_clear() {
+        let mut floats = FloatContext::default();
+        assert_eq!(floats.place(FloatSide::Left, 0.0, 100.0, 50.0, 800.0), (0.0, 0.0));
+        assert_eq!(floats.place(FloatSide::Right, 0.0, 200.0, 80.0, 800.0), (600.0, 0.0));
+        assert_eq!(floats.line_start(10.0, 20.0), 100.0);
+        assert_eq!(floats.line_end(10.0, 20.0, 800.0), 600.0);
+        assert_eq!(floats.line_start(60.0, 20.0), 0.0, "below the left float lines start at the edge");
+
+        // A float too wide for the gap drops below the float that ends first
+        assert_eq!(floats.place(FloatSide::Left, 0.0, 550.0, 10.0, 800.0), (0.0, 50.0));
+        assert_eq!(floats.clearance("right"), Some(80.0));
+        assert_eq!(floats.clearance("both"), Some(80.0));
+        assert_eq!(floats.clearance("none"), None);
+    }
+}
@@ -1,11 +1,19 @@
-use crate::dom::node::{DOMNode, LayoutBox, NodeType, StyleMap, BoxValues};
-use crate::parser::css::{parse_inline_styles, Stylesheet};
+use crate::dom::node::{split_outside_parens, split_top_level_commas, DOMNode, LayoutBox, NodeType, StyleMap, BoxValues, ZIndex, Inset};
+use crate::parser::css::{cascade_declarations, inline_declarations, split_cascade_declarations, substitute_env, substitute_variables, CssRule, Stylesheet};
 use std::time::Instant;
-use crate::paint::display_list::{DrawCommand, DisplayList};
 use crate::paint::painter::Painter;
+use crate::paint::stacking::{is_positioned, paint_order};
 use crate::compositor::compositor::Compositor;
-use crate::ffi::matches_selector;
+use crate::layout::cache::{style_inputs_hash, tree_node_ids, FlowState, LayoutCache, LayoutCacheStats, StyleCache};
+use crate::layout::float::{FloatContext, FloatSide};
+use crate::style::length::{resolve_length, LengthContext};
+use crate::style::selector::matches_selector;
 use crate::dom::node::DOMArena;
+use std::collections::HashMap;
+use crate::cancellation::CancellationToken;
+use crate::error::RiftError;
+use std::sync::{Arc, Mutex};
+use rayon::prelude::*;
 
 #[derive(Debug, Clone)]
 pub struct LayoutStats {
@@ -38,6 +46,18 @@ pub struct LayoutEngine {
     viewport_height: f32,
     pub stylesheet: Option<Stylesheet>,
     pub layout_stats: LayoutStats,
+    css_enabled: bool,
+    cancellation: Option<CancellationToken>,
+    // Values for `env()`, e.g. `safe-area-inset-top` -> `20px`
+    environment: HashMap<String, String>,
+    // Shared by clones so a host can keep one engine's cache across passes
+    cache: Option<Arc<LayoutCache>>,
+    // Lay out sibling subtrees on the rayon pool in `layout_advanced`
+    parallel: bool,
+    // Declared styles per node, shared by clones; entries are keyed by `style_inputs` among others
+    style_cache: Arc<StyleCache>,
+    // Hash of the stylesheet and `environment`, recomputed whenever either changes
+    style_inputs: u64,
 }
 
 impl LayoutEngine {
@@ -57,14 +77,133 @@ impl LayoutEngine {
             viewport_height,
             stylesheet: None,
             layout_stats: LayoutStats::default(),
+            css_enabled: true,
+            cancellation: None,
+            environment: safe_area_environment([0.0; 4]),
+            cache: None,
+            parallel: true,
+            style_cache: Arc::new(StyleCache::default()),
+            style_inputs: style_inputs_hash(None, &safe_area_environment([0.0; 4])),
         }
     }
 
+    /// Lay out against `stylesheet`. Cached styles and boxes are kept when it's the stylesheet
+    /// already in use, so a long-lived engine can be given each render's sheet.
     pub fn with_stylesheet(mut self, stylesheet: Stylesheet) -> Self {
+        self.set_stylesheet(stylesheet);
+        self
+    }
+
+    /// Like `with_stylesheet`, for an engine kept across renders
+    pub fn set_stylesheet(&mut self, stylesheet: Stylesheet) {
         self.stylesheet = Some(stylesheet);
+        self.update_style_inputs();
+    }
+
+    /// Share `style_cache` with other engines, e.g. one per render, so unchanged nodes keep
+    /// their matched styles between them
+    pub fn with_style_cache(mut self, style_cache: Arc<StyleCache>) -> Self {
+        self.style_cache = style_cache;
+        self
+    }
+
+    fn update_style_inputs(&mut self) {
+        let style_inputs = style_inputs_hash(self.stylesheet.as_ref(), &self.environment);
+        if style_inputs != self.style_inputs {
+            self.style_inputs = style_inputs;
+            if let Some(cache) = &self.cache {
+                cache.clear();
+            }
+        }
+    }
+
+    pub(crate) fn style_inputs(&self) -> u64 {
+        self.style_inputs
+    }
+
+    /// Keep each subtree's boxes between `layout` calls and reuse them while the subtree's
+    /// content, computed styles and starting flow state are unchanged
+    pub fn with_layout_cache(mut self) -> Self {
+        self.cache = Some(Arc::new(LayoutCache::default()));
+        self
+    }
+
+    /// Cache hits and recomputed nodes so far; all zero without `with_layout_cache`
+    pub fn cache_stats(&self) -> LayoutCacheStats {
+        self.cache.as_ref().map(|cache| cache.stats()).unwrap_or_default()
+    }
+
+    /// How many times a node's styles were matched rather than served from the style cache
+    pub fn style_recomputes(&self) -> usize {
+        self.style_cache.recomputed()
+    }
+
+    /// When disabled, stylesheets and inline styles are ignored and every node gets UA defaults
+    pub fn with_css_enabled(mut self, enabled: bool) -> Self {
+        self.set_css_enabled(enabled);
+        self
+    }
+
+    /// Like `with_css_enabled`, for an engine kept across renders
+    pub fn set_css_enabled(&mut self, enabled: bool) {
+        self.css_enabled = enabled;
+    }
+
+    /// Whether `layout_advanced` lays out sibling subtrees in parallel (the default)
+    pub fn with_parallel_layout(mut self, enabled: bool) -> Self {
+        self.parallel = enabled;
+        self
+    }
+
+    /// Poll `token` at each subtree so a host can abort layout partway through
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Safe-area insets in px for `env(safe-area-inset-*)`, e.g. around a phone's notch.
+    /// Until configured they're 0, as on a rectangular screen.
+    pub fn with_safe_area_insets(mut self, top: f32, right: f32, bottom: f32, left: f32) -> Self {
+        self.environment.extend(safe_area_environment([top, right, bottom, left]));
+        self.update_style_inputs();
         self
     }
 
+    /// Resize the viewport. Styles and cached boxes are dropped so percentage and
+    /// viewport-relative lengths and media queries resolve against the new size next layout.
+    pub fn set_viewport(&mut self, width: f32, height: f32) {
+        self.viewport_width = width;
+        self.viewport_height = height;
+        self.style_cache = Arc::new(StyleCache::default());
+        if let Some(cache) = &self.cache {
+            cache.clear();
+        }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancellation.as_ref().is_some_and(|token| token.is_cancelled())
+    }
+
+    pub fn viewport_width(&self) -> f32 {
+        self.viewport_width
+    }
+
+    pub fn viewport_height(&self) -> f32 {
+        self.viewport_height
+    }
+
+    /// Index of the topmost box containing the point, or None on a miss.
+    /// Boxes are tried in reverse painting order, so stacking (`z-index`, positioning) decides
+    /// which box is on top; `pointer-events: none` boxes are transparent to hits.
+    pub fn hit_test(boxes: &[LayoutBox], x: f32, y: f32) -> Option<usize> {
+        paint_order(boxes).into_iter().rev().find(|&index| {
+            let b = &boxes[index];
+            b.pointer_events != "none"
+                && x >= b.x && x < b.x + b.width
+                && y >= b.y && y < b.y + b.height
+        })
+    }
+
     /// Find the <body> node in the DOM tree, or return the given node if not found
     fn find_body_node_id(&self, node: &DOMNode, arena: &DOMArena) -> Option<String> {
         match &node.node_type {
@@ -96,7 +235,7 @@ impl LayoutEngine {
                 return Vec::new();
             }
         };
-        let layout_root = layout_root.lock().unwrap();
+        let layout_root = layout_root.lock().unwrap().clone();
         println!("[LAYOUT] Using {:?} as layout root", layout_root.node_type);
         
         let mut boxes = Vec::new();
@@ -105,20 +244,146 @@ impl LayoutEngine {
         let mut line_height = 0.0;
         let mut in_inline_context = false;
         
-        self.layout_node(&layout_root, arena, &mut boxes, &mut current_x, &mut current_y, &mut line_height, &mut in_inline_context, 0);
+        if let Some(cache) = &self.cache {
+            cache.begin_pass(self.subtree_hashes(&layout_root, arena));
+        }
+        let mut counters = CounterScopes::default();
+        counters.enter();
+        let mut floats = FloatContext::default();
+        self.layout_node(&layout_root, arena, &mut boxes, &mut current_x, &mut current_y, &mut line_height, &mut in_inline_context, "normal", &mut counters, &mut floats, self.viewport_width, Some(self.viewport_height), 0);
+        self.style_cache.retain(&tree_node_ids(dom, arena));
+        assign_parent_indices(&mut boxes, arena);
+        align_line_baselines(&mut boxes);
+        apply_relative_offsets(&mut boxes);
+        apply_absolute_offsets(&mut boxes, (self.viewport_width, self.viewport_height));
         
         println!("[LAYOUT] Basic layout completed: {} boxes created", boxes.len());
         boxes
     }
+
+    /// Like `layout`, but reports `RiftError::Cancelled` instead of a partial layout
+    /// if the cancellation token was set before layout finished
+    pub fn try_layout(&self, dom: &DOMNode, arena: &DOMArena) -> Result<Vec<LayoutBox>, RiftError> {
+        let boxes = self.layout(dom, arena);
+        if self.is_cancelled() {
+            println!("[LAYOUT] Cancelled after {} boxes", boxes.len());
+            return Err(RiftError::Cancelled);
+        }
+        Ok(boxes)
+    }
+
+    /// Bounded layout for very large documents: each subtree is placed from its parent's
+    /// origin, sibling subtrees in parallel unless disabled, within the `MAX_*` limits
+    pub fn layout_advanced(&self, dom: &DOMNode, arena: &DOMArena) -> Vec<LayoutBox> {
+        let mut node_count = 0;
+        let (mut boxes, _) = self.layout_node_advanced(dom, 0.0, 0.0, &mut Vec::new(), 0, &mut node_count, arena);
+        assign_parent_indices(&mut boxes, arena);
+        boxes
+    }
     
-    fn layout_node(&self, node: &DOMNode, arena: &DOMArena, boxes: &mut Vec<LayoutBox>, current_x: &mut f32, current_y: &mut f32, line_height: &mut f32, in_inline_context: &mut bool, depth: usize) {
-        let styles = self.get_node_styles(node);
+    /// Lay out a node and its subtree, replaying cached boxes when the layout cache has a valid entry
+    fn layout_node(&self, node: &DOMNode, arena: &DOMArena, boxes: &mut Vec<LayoutBox>, current_x: &mut f32, current_y: &mut f32, line_height: &mut f32, in_inline_context: &mut bool, parent_white_space: &str, counters: &mut CounterScopes, floats: &mut FloatContext, containing_width: f32, containing_height: Option<f32>, depth: usize) {
+        let cached = self.cache.as_ref().and_then(|cache| Some((cache, cache.hash_of(&node.id)?)));
+        let Some((cache, hash)) = cached else {
+            self.compute_node(node, arena, boxes, current_x, current_y, line_height, in_inline_context, parent_white_space, counters, floats, containing_width, containing_height, depth);
+            return;
+        };
+        let entry = FlowState {
+            x: *current_x,
+            line_height: *line_height,
+            in_inline_context: *in_inline_context,
+            white_space: parent_white_space.to_string(),
+            counters: counters.clone(),
+            floats: floats.relative_to(*current_y),
+            containing_width,
+            containing_height,
+        };
+        if let Some((exit, exit_y)) = cache.replay(&node.id, hash, &entry, *current_y, boxes) {
+            *current_x = exit.x;
+            *current_y = exit_y;
+            *line_height = exit.line_height;
+            *in_inline_context = exit.in_inline_context;
+            *counters = exit.counters;
+            *floats = exit.floats.relative_to(-exit_y);
+            return;
+        }
+        let (start, start_y) = (boxes.len(), *current_y);
+        self.compute_node(node, arena, boxes, current_x, current_y, line_height, in_inline_context, parent_white_space, counters, floats, containing_width, containing_height, depth);
+        if !self.is_cancelled() {
+            let exit = FlowState {
+                x: *current_x,
+                line_height: *line_height,
+                in_inline_context: *in_inline_context,
+                white_space: parent_white_space.to_string(),
+                counters: counters.clone(),
+                floats: floats.relative_to(*current_y),
+                containing_width,
+                containing_height,
+            };
+            cache.store(&node.id, hash, entry, start_y, boxes[start..].to_vec(), exit, *current_y);
+        }
+    }
+
+    fn compute_node(&self, node: &DOMNode, arena: &DOMArena, boxes: &mut Vec<LayoutBox>, current_x: &mut f32, current_y: &mut f32, line_height: &mut f32, in_inline_context: &mut bool, parent_white_space: &str, counters: &mut CounterScopes, floats: &mut FloatContext, containing_width: f32, containing_height: Option<f32>, depth: usize) {
+        if self.is_cancelled() {
+            return;
+        }
+        if let Some(cache) = &self.cache {
+            cache.record_recompute();
+        }
+        let styles = self.get_node_styles(node, arena);
         let display = styles.display.to_lowercase();
+        let is_element = matches!(node.node_type, NodeType::Element(_));
+        // An element's computed white-space already carries the inherited value, so a declared
+        // `normal` still overrides a `pre` parent; text and generated runs take their parent's
+        let white_space = if is_element { styles.white_space.as_str() } else { parent_white_space };
+        // display: none removes the element and its subtree from layout
+        if is_element && display == "none" {
+            return;
+        }
+        if is_element {
+            counters.apply(&styles);
+            counters.enter();
+        }
+        // Absolute/fixed boxes are out of flow: laid out where they'd start, then the flow
+        // continues as if they weren't there. `apply_absolute_offsets` moves them into place.
+        let out_of_flow = is_element && matches!(styles.position.as_str(), "absolute" | "fixed");
+        // `clear` starts the element below the floats on the sides it names
+        if let Some(below) = floats.clearance(&styles.clear).filter(|_| is_element && !out_of_flow) {
+            if *in_inline_context {
+                *current_x = 0.0;
+                *current_y += *line_height;
+                *line_height = 0.0;
+                *in_inline_context = false;
+            }
+            *current_y = current_y.max(below);
+        }
+        let flow = (*current_x, *current_y, *line_height, *in_inline_context);
+        // A float is laid out like a block of its own, with its own floats inside, then moved
+        // against its edge by `place_float`. A float met mid-line goes below that line.
+        let float_side = FloatSide::parse(&styles.float).filter(|_| is_element && !out_of_flow);
+        let (float_start, float_top) = (boxes.len(), if *in_inline_context { *current_y + *line_height } else { *current_y });
+        let outer_floats = float_side.map(|_| std::mem::take(floats));
+        if float_side.is_some() {
+            (*current_x, *current_y, *line_height, *in_inline_context) = (0.0, float_top, 0.0, false);
+        }
         
         match &node.node_type {
+            NodeType::Element(tag_name) if tag_name == "input" => {
+                self.layout_input(node, &styles, boxes, current_x, current_y, line_height, in_inline_context, containing_width);
+            },
+            NodeType::Element(tag_name) if is_embedded_tag(tag_name) || tag_name.eq_ignore_ascii_case("img") => {
+                self.layout_embedded(node, tag_name, &styles, boxes, current_x, current_y, line_height, in_inline_context, containing_width, containing_height);
+            },
+            NodeType::Element(tag_name) if tag_name == "table" || display == "table" => {
+                self.layout_table(node, &styles, arena, boxes, current_x, current_y, line_height, in_inline_context, containing_width);
+            },
+            NodeType::Element(tag_name) if display == "flex" => {
+                self.layout_flex(node, tag_name, &styles, arena, boxes, current_x, current_y, line_height, in_inline_context, counters, containing_width, containing_height, depth);
+            },
             NodeType::Element(tag_name) => {
                 let is_block = display == "block" || tag_name == "div" || tag_name == "p" || tag_name == "h1" || tag_name == "h2" || tag_name == "h3" || tag_name == "h4" || tag_name == "h5" || tag_name == "h6" || tag_name == "section" || tag_name == "article" || tag_name == "header" || tag_name == "footer" || tag_name == "nav" || tag_name == "main" || tag_name == "aside";
-                let is_inline = display == "inline" || tag_name == "span" || tag_name == "a" || tag_name == "strong" || tag_name == "em" || tag_name == "b" || tag_name == "i" || tag_name == "u" || tag_name == "code" || tag_name == "small";
+                let is_inline = display == "inline" || INLINE_TAGS.contains(&tag_name.as_str());
                 
                 if is_block {
                     // Block element: start new line
@@ -129,9 +394,12 @@ impl LayoutEngine {
                         *in_inline_context = false;
                     }
                     
-                    let (width, height) = self.calculate_block_dimensions(&styles, tag_name);
-                    let margin = parse_box_value(&styles.margin);
-                    let padding = parse_box_value(&styles.padding);
+                    let (width, height) = self.calculate_block_dimensions(&styles, tag_name, containing_width, containing_height);
+                    // Children's `%` heights resolve against this height only when it was declared
+                    let child_height = self.resolve_height(&styles.height, &styles, containing_height).map(|_| height);
+                    let context = self.length_context(&styles, containing_width);
+                    let margin = margin_box(&styles, &context);
+                    let padding = padding_box(&styles, &context);
                     
                     // Apply margin
                     *current_x += margin.left;
@@ -143,13 +411,14 @@ impl LayoutEngine {
                         width: width + padding.left + padding.right,
                         height: height + padding.top + padding.bottom,
                         node_type: tag_name.clone(),
-                        text_content: self.extract_text_content(node, arena),
+                        text_content: self.extract_text_content(node, arena, white_space),
                         background_color: styles.background_color.clone(),
+                        background_image: background_image(&styles),
                         color: styles.color.clone(),
-                        font_size: styles.font_size.parse().unwrap_or(16.0),
+                        font_size: font_size_px(&styles),
                         font_family: styles.font_family.clone(),
                         border_color: styles.border_color.clone(),
-                        border_width: parse_box_value(&styles.border_width),
+                        border_width: border_box(&styles, &context),
                         margin: margin.clone(),
                         padding: padding.clone(),
                         font_weight: styles.font_weight.parse().unwrap_or(400.0),
@@ -168,10 +437,30 @@ impl LayoutEngine {
                         word_wrap: styles.word_wrap.clone(),
                         white_space: styles.white_space.clone(),
                         text_overflow: styles.text_overflow.clone(),
-                        color_scheme: styles.color_scheme.clone()
+                        color_scheme: styles.color_scheme.clone(),
+                        border_collapse: styles.border_collapse.clone(),
+                        border_spacing: parse_spacing(&styles.border_spacing),
+                        pointer_events: styles.pointer_events.clone(),
+                        node_id: node.id.clone(),
+                        overflow: styles.overflow.clone(),
+                        will_change: compositing_hint(&styles),
+                        position: styles.position.clone(),
+                        z_index: ZIndex::parse(&styles.z_index),
+                        opacity: styles.opacity.parse().unwrap_or(1.0),
+                        transform: styles.transform.clone(),
+                        transform_origin: styles.transform_origin.clone(),
+                        parent_index: None,
+                        embedded: false,
+                        inset: Inset::from_styles(&styles),
+                        font_style: styles.font_style.clone(),
+                        clip_path: styles.clip_path.clone(),
+                        visibility: styles.visibility.clone(),
+                        border_radius: styles.border_radius.clone(),
+                        box_shadow: styles.box_shadow.clone(),
                     };
                     
                     boxes.push(box_layout);
+                    let first_child_box = boxes.len();
                     
                     // Move to next line
                     *current_x = 0.0;
@@ -179,29 +468,51 @@ impl LayoutEngine {
                     *line_height = 0.0;
                     
                     // Layout children
+                    self.layout_generated_content(node, arena, "before", boxes, current_x, current_y, line_height, in_inline_context, white_space, counters, floats, width, child_height, depth);
                     for child_id in &node.children {
                         if let Some(child_node) = arena.get_node(child_id) {
-                            let child = child_node.lock().unwrap();
-                            self.layout_node(&child, arena, boxes, current_x, current_y, line_height, in_inline_context, depth + 1);
+                            // Copied out so the lock is released: selector matching locks ancestors
+                            let child = child_node.lock().unwrap().clone();
+                            self.layout_node(&child, arena, boxes, current_x, current_y, line_height, in_inline_context, white_space, counters, floats, width, child_height, depth + 1);
+                        }
+                    }
+                    self.layout_generated_content(node, arena, "after", boxes, current_x, current_y, line_height, in_inline_context, white_space, counters, floats, width, child_height, depth);
+                    self.apply_first_line_and_letter(node, arena, boxes, first_child_box);
+                    if let Ok(max_lines) = styles.line_clamp.trim().parse::<usize>() {
+                        self.apply_line_clamp(boxes, first_child_box, max_lines, width, current_y, line_height);
+                    }
+
+                    // Single-line overflow: cut text at the content edge, optionally with an ellipsis
+                    if styles.overflow == "hidden" && white_space == "nowrap" {
+                        let block = &boxes[first_child_box - 1];
+                        let content_right = block.x + padding.left + width;
+                        let ellipsis = styles.text_overflow == "ellipsis";
+                        for b in boxes[first_child_box - 1..].iter_mut() {
+                            truncate_text(b, content_right, ellipsis);
                         }
                     }
                     
+                } else if is_inline && self.has_block_children(node, arena) {
+                    // Block-in-inline, e.g. <a><div>...</div></a>: the blocks break out of the line
+                    self.layout_block_in_inline(node, tag_name, &styles, arena, boxes, current_x, current_y, line_height, in_inline_context, white_space, counters, floats, containing_width, containing_height, depth);
                 } else if is_inline {
                     // Inline element: continue on same line
                     *in_inline_context = true;
                     
-                    let text_content = self.extract_text_content(node, arena);
-                    let font_size = styles.font_size.parse().unwrap_or(16.0);
+                    let text_content = self.extract_text_content(node, arena, white_space);
+                    let font_size = font_size_px(&styles);
                     let estimated_width = text_content.len() as f32 * font_size * 0.6; // Rough estimate
                     let estimated_height = font_size * 1.2;
                     
-                    let margin = parse_box_value(&styles.margin);
-                    let padding = parse_box_value(&styles.padding);
+                    let context = self.length_context(&styles, containing_width);
+                    let margin = margin_box(&styles, &context);
+                    let padding = padding_box(&styles, &context);
+                    *current_x = current_x.max(floats.line_start(*current_y, estimated_height));
                     
                     // Check if we need to wrap to next line
-                    if *current_x + estimated_width + margin.left + margin.right + padding.left + padding.right > self.viewport_width * 0.9 {
-                        *current_x = 0.0;
+                    if *current_x + estimated_width + margin.left + margin.right + padding.left + padding.right > floats.line_end(*current_y, estimated_height, self.line_limit(containing_width)) {
                         *current_y += *line_height;
+                        *current_x = floats.line_start(*current_y, estimated_height);
                         *line_height = 0.0;
                     }
                     
@@ -215,11 +526,12 @@ impl LayoutEngine {
                         node_type: tag_name.clone(),
                         text_content: text_content.clone(),
                         background_color: styles.background_color.clone(),
+                        background_image: background_image(&styles),
                         color: styles.color.clone(),
                         font_size: font_size,
                         font_family: styles.font_family.clone(),
                         border_color: styles.border_color.clone(),
-                        border_width: parse_box_value(&styles.border_width),
+                        border_width: border_box(&styles, &context),
                         margin: margin.clone(),
                         padding: padding.clone(),
                         font_weight: styles.font_weight.parse().unwrap_or(400.0),
@@ -238,7 +550,26 @@ impl LayoutEngine {
                         word_wrap: styles.word_wrap.clone(),
                         white_space: styles.white_space.clone(),
                         text_overflow: styles.text_overflow.clone(),
-                        color_scheme: styles.color_scheme.clone()
+                        color_scheme: styles.color_scheme.clone(),
+                        border_collapse: styles.border_collapse.clone(),
+                        border_spacing: parse_spacing(&styles.border_spacing),
+                        pointer_events: styles.pointer_events.clone(),
+                        node_id: node.id.clone(),
+                        overflow: styles.overflow.clone(),
+                        will_change: compositing_hint(&styles),
+                        position: styles.position.clone(),
+                        z_index: ZIndex::parse(&styles.z_index),
+                        opacity: styles.opacity.parse().unwrap_or(1.0),
+                        transform: styles.transform.clone(),
+                        transform_origin: styles.transform_origin.clone(),
+                        parent_index: None,
+                        embedded: false,
+                        inset: Inset::from_styles(&styles),
+                        font_style: styles.font_style.clone(),
+                        clip_path: styles.clip_path.clone(),
+                        visibility: styles.visibility.clone(),
+                        border_radius: styles.border_radius.clone(),
+                        box_shadow: styles.box_shadow.clone(),
                     };
                     
                     boxes.push(box_layout);
@@ -247,95 +578,756 @@ impl LayoutEngine {
                     *line_height = (*line_height).max(estimated_height + padding.top + padding.bottom);
                     
                     // Layout children
+                    self.layout_generated_content(node, arena, "before", boxes, current_x, current_y, line_height, in_inline_context, white_space, counters, floats, containing_width, containing_height, depth);
                     for child_id in &node.children {
                         if let Some(child_node) = arena.get_node(child_id) {
-                            let child = child_node.lock().unwrap();
-                            self.layout_node(&child, arena, boxes, current_x, current_y, line_height, in_inline_context, depth + 1);
+                            let child = child_node.lock().unwrap().clone();
+                            self.layout_node(&child, arena, boxes, current_x, current_y, line_height, in_inline_context, white_space, counters, floats, containing_width, containing_height, depth + 1);
                         }
                     }
+                    self.layout_generated_content(node, arena, "after", boxes, current_x, current_y, line_height, in_inline_context, white_space, counters, floats, containing_width, containing_height, depth);
                     
                 } else {
                     // Default to block behavior for unknown elements
                     for child_id in &node.children {
                         if let Some(child_node) = arena.get_node(child_id) {
-                            let child = child_node.lock().unwrap();
-                            self.layout_node(&child, arena, boxes, current_x, current_y, line_height, in_inline_context, depth + 1);
+                            let child = child_node.lock().unwrap().clone();
+                            self.layout_node(&child, arena, boxes, current_x, current_y, line_height, in_inline_context, white_space, counters, floats, containing_width, containing_height, depth + 1);
                         }
                     }
                 }
             },
             NodeType::Text => {
                 // Text node: create inline text box
-                let text_content = node.text_content.trim();
-                if !text_content.is_empty() {
-                    let font_size = 16.0; // Default font size
-                    let estimated_width = text_content.len() as f32 * font_size * 0.6;
-                    let estimated_height = font_size * 1.2;
-                    
-                    // Check if we need to wrap to next line
-                    if *current_x + estimated_width > self.viewport_width * 0.9 {
-                        *current_x = 0.0;
-                        *current_y += *line_height;
-                        *line_height = 0.0;
-                        *in_inline_context = false;
+                // Text takes its font and color from the inherited style
+                let font_size = font_size_px(&styles);
+                let estimated_height = line_box_height(&styles.line_height, font_size);
+                // Lines beside a float start and end at its edge instead of the container's
+                let line_start = floats.line_start(*current_y, estimated_height);
+                let mut text_content = apply_white_space(&node.text_content, white_space, *current_x <= line_start);
+                if text_content.ends_with(' ') && !preserves_spaces(white_space) && self.ends_line_box(node, arena) {
+                    text_content.truncate(text_content.trim_end_matches(' ').len());
+                }
+                if !text_content.trim().is_empty() {
+                    *current_x = current_x.max(line_start);
+                    let max_width = floats.line_end(*current_y, estimated_height, self.line_limit(containing_width));
+                    let mut lines = white_space_lines(&text_content, white_space, max_width - line_start, font_size);
+                    // Collapsible text continuing a line fills what's left of it before wrapping
+                    let first_width = lines.first().map_or(0.0, |line| line.chars().count() as f32 * font_size * 0.6);
+                    if white_space == "normal" && *current_x > line_start && *current_x + first_width > max_width {
+                        lines = wrap_text_from(&text_content, *current_x - line_start, max_width - line_start, font_size);
                     }
+                    for (i, text_content) in lines.into_iter().enumerate() {
+                        if i > 0 {
+                            // A preserved newline or a wrap inside this run starts a new line
+                            *current_y += (*line_height).max(estimated_height);
+                            *current_x = floats.line_start(*current_y, estimated_height);
+                            *line_height = 0.0;
+                            *in_inline_context = false;
+                        }
+                        if text_content.trim().is_empty() {
+                            continue;
+                        }
+                        // Only a `pre` run still holds newlines: its box spans one line per row
+                        let row_widths: Vec<f32> = text_content.split('\n').map(|row| row.len() as f32 * font_size * 0.6).collect();
+                        let estimated_width = row_widths.iter().copied().fold(0.0, f32::max);
+                        
+                        // Check if we need to wrap to next line
+                        if *current_x + estimated_width > floats.line_end(*current_y, estimated_height, self.line_limit(containing_width)) {
+                            *current_y += *line_height;
+                            *current_x = floats.line_start(*current_y, estimated_height);
+                            *line_height = 0.0;
+                            *in_inline_context = false;
+                        }
+                        
+                        let box_layout = LayoutBox {
+                            x: *current_x,
+                            y: *current_y,
+                            width: estimated_width,
+                            height: estimated_height * row_widths.len() as f32,
+                            node_type: "text".to_string(),
+                            text_content,
+                            background_color: "transparent".to_string(),
+                            background_image: "none".to_string(),
+                            color: styles.color.clone(),
+                            font_size: font_size,
+                            font_family: styles.font_family.clone(),
+                            border_color: "transparent".to_string(),
+                            border_width: BoxValues::default(),
+                            margin: BoxValues::default(),
+                            padding: BoxValues::default(),
+                            font_weight: styles.font_weight.parse().unwrap_or(400.0),
+                            text_align: styles.text_align.clone(),
+                            flex_direction: "row".to_string(),
+                            flex_wrap: "nowrap".to_string(),
+                            justify_content: "flex-start".to_string(),
+                            align_items: "stretch".to_string(),
+                            flex_grow: 0.0,
+                            flex_shrink: 1.0,
+                            flex_basis: "auto".to_string(),
+                            order: 0,
+                            grid_column: "auto".to_string(),
+                            grid_row: "auto".to_string(),
+                            line_height: 1.2,
+                            word_wrap: "normal".to_string(),
+                            white_space: white_space.to_string(),
+                            text_overflow: "clip".to_string(),
+                            color_scheme: "light".to_string(),
+                            border_collapse: "separate".to_string(),
+                            border_spacing: 0.0,
+                            pointer_events: "auto".to_string(),
+                            node_id: node.id.clone(),
+                            overflow: "visible".to_string(),
+                            will_change: String::new(),
+                            position: "static".to_string(),
+                            z_index: ZIndex::Auto,
+                            opacity: 1.0,
+                            transform: "none".to_string(),
+                            transform_origin: "50% 50%".to_string(),
+                            parent_index: None,
+                            embedded: false,
+                            inset: Inset::default(),
+                            font_style: styles.font_style.clone(),
+                            clip_path: "none".to_string(),
+                            visibility: styles.visibility.clone(),
+                            border_radius: "0".to_string(),
+                            box_shadow: "none".to_string(),
+                        };
                     
-                    let box_layout = LayoutBox {
-                        x: *current_x,
-                        y: *current_y,
-                        width: estimated_width,
-                        height: estimated_height,
-                        node_type: "text".to_string(),
-                        text_content: text_content.to_string(),
-                        background_color: "transparent".to_string(),
-                        color: "#000000".to_string(),
-                        font_size: font_size,
-                        font_family: "Arial".to_string(),
-                        border_color: "transparent".to_string(),
-                        border_width: BoxValues::default(),
-                        margin: BoxValues::default(),
-                        padding: BoxValues::default(),
-                        font_weight: 400.0,
-                        text_align: "left".to_string(),
-                        flex_direction: "row".to_string(),
-                        flex_wrap: "nowrap".to_string(),
-                        justify_content: "flex-start".to_string(),
-                        align_items: "stretch".to_string(),
-                        flex_grow: 0.0,
-                        flex_shrink: 1.0,
-                        flex_basis: "auto".to_string(),
-                        order: 0,
-                        grid_column: "auto".to_string(),
-                        grid_row: "auto".to_string(),
-                        line_height: 1.2,
-                        word_wrap: "normal".to_string(),
-                        white_space: "normal".to_string(),
-                        text_overflow: "clip".to_string(),
-                        color_scheme: "light".to_string()
-                    };
-                    
-                    boxes.push(box_layout);
+                        boxes.push(box_layout);
                     
-                    *current_x += estimated_width;
-                    *line_height = (*line_height).max(estimated_height);
-                    *in_inline_context = true;
+                        *current_x += estimated_width;
+                        if let [_, middle @ .., last] = row_widths.as_slice() {
+                            // The run ends on its last row, which starts back at the line start
+                            *current_y += (*line_height).max(estimated_height) + estimated_height * middle.len() as f32;
+                            *current_x = floats.line_start(*current_y, estimated_height) + last;
+                            *line_height = 0.0;
+                        }
+                        *line_height = (*line_height).max(estimated_height);
+                        *in_inline_context = true;
+                    }
                 }
             },
             _ => {
                 // Other node types: just process children
                 for child_id in &node.children {
                     if let Some(child_node) = arena.get_node(child_id) {
-                        let child = child_node.lock().unwrap();
-                        self.layout_node(&child, arena, boxes, current_x, current_y, line_height, in_inline_context, depth + 1);
+                        let child = child_node.lock().unwrap().clone();
+                        self.layout_node(&child, arena, boxes, current_x, current_y, line_height, in_inline_context, white_space, counters, floats, containing_width, containing_height, depth + 1);
                     }
                 }
             }
         }
+        if out_of_flow {
+            (*current_x, *current_y, *line_height, *in_inline_context) = flow;
+        }
+        if let (Some(side), Some(outer)) = (float_side, outer_floats) {
+            *floats = outer;
+            self.place_float(side, &styles, boxes, float_start, float_top, floats, containing_width);
+            (*current_x, *current_y, *line_height, *in_inline_context) = flow;
+        }
+        if is_element {
+            counters.leave();
+        }
+    }
+
+    /// Move a float's boxes (`boxes[start..]`) against its edge, at or below `top`, and record
+    /// the margin box it occupies. Without a declared width it shrinks to fit its content.
+    fn place_float(&self, side: FloatSide, styles: &StyleMap, boxes: &mut [LayoutBox], start: usize, top: f32, floats: &mut FloatContext, containing_width: f32) {
+        let Some((float_box, content)) = boxes[start..].split_first_mut() else { return };
+        if resolve_length(&styles.width, &self.length_context(styles, containing_width)).is_none() && !content.is_empty() {
+            let content_right = content.iter().map(|b| b.x + b.width).fold(float_box.x, f32::max);
+            float_box.width = content_right - float_box.x + float_box.padding.right;
+        }
+        let margin = float_box.margin.clone();
+        let left = boxes[start..].iter().map(|b| b.x).fold(boxes[start].x - margin.left, f32::min);
+        let right = boxes[start..].iter().map(|b| b.x + b.width).fold(boxes[start].x + boxes[start].width + margin.right, f32::max);
+        let bottom = boxes[start..].iter().map(|b| b.y + b.height).fold(boxes[start].y + boxes[start].height + margin.bottom, f32::max);
+        let (x, y) = floats.place(side, top, right - left, bottom - top, self.viewport_width * 0.9);
+        let (dx, dy) = (x - left, y - top);
+        for b in &mut boxes[start..] {
+            b.x += dx;
+            b.y += dy;
+        }
+    }
+
+    /// Lay out `::before`/`::after` content as an anonymous text run tagged with the pseudo-element
+    fn layout_generated_content(&self, node: &DOMNode, arena: &DOMArena, pseudo: &str, boxes: &mut Vec<LayoutBox>, current_x: &mut f32, current_y: &mut f32, line_height: &mut f32, in_inline_context: &mut bool, white_space: &str, counters: &mut CounterScopes, floats: &mut FloatContext, containing_width: f32, containing_height: Option<f32>, depth: usize) {
+        let declarations = self.pseudo_element_declarations(node, arena, pseudo);
+        let Some(content) = declarations.get("content") else { return };
+        let mut run = DOMNode::new(NodeType::Text);
+        run.text_content = resolve_content(content, node, counters);
+        if run.text_content.is_empty() {
+            return;
+        }
+
+        let start = boxes.len();
+        self.layout_node(&run, arena, boxes, current_x, current_y, line_height, in_inline_context, white_space, counters, floats, containing_width, containing_height, depth + 1);
+        for b in &mut boxes[start..] {
+            b.node_type = format!("::{}", pseudo);
+            b.node_id = node.id.clone();
+            *current_x += restyle_text_box(b, &declarations);
+        }
     }
     
-    fn calculate_block_dimensions(&self, styles: &StyleMap, tag_name: &str) -> (f32, f32) {
-        let width = self.parse_length(&styles.width, self.viewport_width * 0.9);
-        let height = self.parse_length(&styles.height, if tag_name == "p" { 20.0 } else { 100.0 });
+    /// Table layout (`table-layout: auto`): cells are placed on a grid honoring `colspan` and
+    /// `rowspan`, each column is as wide as its widest cell, and rows stack top-to-bottom.
+    /// Cell content is carried as the cell's text rather than laid out as child boxes.
+    fn layout_table(&self, table: &DOMNode, styles: &StyleMap, arena: &DOMArena, boxes: &mut Vec<LayoutBox>, current_x: &mut f32, current_y: &mut f32, line_height: &mut f32, in_inline_context: &mut bool, containing_width: f32) {
+        if *in_inline_context {
+            *current_x = 0.0;
+            *current_y += *line_height;
+            *line_height = 0.0;
+            *in_inline_context = false;
+        }
+
+        let collapse = styles.border_collapse == "collapse";
+        let spacing = if collapse { 0.0 } else { parse_spacing(&styles.border_spacing) };
+        let context = self.length_context(styles, containing_width);
+        let margin = margin_box(styles, &context);
+        let border = border_box(styles, &context);
+        let rows = self.collect_table_rows(table, arena);
+        let cells = self.place_table_cells(&rows, styles, arena);
+        let columns = cells.iter().map(|cell| cell.column + cell.colspan).max().unwrap_or(0).max(1);
+        let spanned = |widths: &[f32], from: usize, span: usize| widths[from..from + span].iter().sum::<f32>() + spacing * (span as f32 - 1.0);
+
+        // Collapsed borders sit on the grid lines, so cells start at the table edge
+        let (inset_x, inset_y) = if collapse { (0.0, 0.0) } else { (border.left + spacing, border.top + spacing) };
+        let edges = if collapse { 0.0 } else { border.left + border.right + 2.0 * spacing };
+        let available = self.viewport_width * 0.9 - edges - spacing * (columns as f32 - 1.0);
+        // Cells resolve `%` against the table's width
+        let table_content = self.viewport_width * 0.9 - edges;
+
+        // Single-column cells size their column first; wider spans then grow the columns they cover
+        let mut widths = vec![0.0f32; columns];
+        for cell in cells.iter().filter(|cell| cell.colspan == 1) {
+            widths[cell.column] = widths[cell.column].max(self.table_cell_width(cell, table_content));
+        }
+        for cell in cells.iter().filter(|cell| cell.colspan > 1) {
+            let extra = self.table_cell_width(cell, table_content) - spanned(&widths, cell.column, cell.colspan);
+            if extra > 0.0 {
+                widths[cell.column..cell.column + cell.colspan].iter_mut().for_each(|width| *width += extra / cell.colspan as f32);
+            }
+        }
+        // An explicit width stretches or squeezes the columns in proportion to their content
+        let content: f32 = widths.iter().sum();
+        let declared = resolve_length(&styles.width, &context).map(|width| width - edges - spacing * (columns as f32 - 1.0));
+        let target = declared.unwrap_or(content).min(available).max(0.0);
+        if content > 0.0 && (declared.is_some() || content > target) {
+            widths.iter_mut().for_each(|width| *width *= target / content);
+        } else if content == 0.0 {
+            widths.iter_mut().for_each(|width| *width = target / columns as f32);
+        }
+        let grid_width = spanned(&widths, 0, columns);
+        let table_width = grid_width + edges;
+
+        // Row heights come from single-row cells; taller row spans grow the last row they cover
+        let mut heights = vec![0.0f32; rows.len()];
+        for cell in cells.iter().filter(|cell| cell.rowspan == 1) {
+            heights[cell.row] = heights[cell.row].max(self.table_cell_height(cell, spanned(&widths, cell.column, cell.colspan), table_content));
+        }
+        for cell in cells.iter().filter(|cell| cell.rowspan > 1) {
+            let extra = self.table_cell_height(cell, spanned(&widths, cell.column, cell.colspan), table_content) - spanned(&heights, cell.row, cell.rowspan);
+            if extra > 0.0 {
+                heights[cell.row + cell.rowspan - 1] += extra;
+            }
+        }
+
+        let table_x = *current_x + margin.left;
+        let table_y = *current_y + margin.top;
+        let table_index = boxes.len();
+        boxes.push(self.styled_box(table, "table", styles, table_x, table_y, table_width, 0.0, String::new(), containing_width));
+
+        let column_x = |column: usize| table_x + inset_x + widths[..column].iter().sum::<f32>() + spacing * column as f32;
+        let row_y = |row: usize| table_y + inset_y + heights[..row].iter().sum::<f32>() + spacing * row as f32;
+        for (index, (row, _)) in rows.iter().enumerate() {
+            let row = row.lock().unwrap().clone();
+            let mut row_styles = self.get_node_styles(&row, arena);
+            row_styles.border_collapse = styles.border_collapse.clone();
+            boxes.push(self.styled_box(&row, "tr", &row_styles, table_x + inset_x, row_y(index), grid_width, heights[index], String::new(), table_width));
+
+            for cell in cells.iter().filter(|cell| cell.row == index) {
+                let tag = match &cell.node.node_type {
+                    NodeType::Element(tag) => tag.clone(),
+                    _ => "td".to_string(),
+                };
+                let width = spanned(&widths, cell.column, cell.colspan);
+                let height = spanned(&heights, cell.row, cell.rowspan);
+                boxes.push(self.styled_box(&cell.node, &tag, &cell.styles, column_x(cell.column), row_y(index), width, height, cell.text.clone(), table_width));
+            }
+        }
+
+        let table_height = row_y(rows.len()) - table_y + if collapse { 0.0 } else { border.bottom };
+        boxes[table_index].height = table_height;
+        boxes[table_index].border_spacing = spacing;
+
+        *current_x = 0.0;
+        *current_y = table_y + table_height + margin.bottom;
+        *line_height = 0.0;
+    }
+
+    /// Give each cell its grid position. Cells fill the first column not already covered by a
+    /// `rowspan` from a row above; spans are clamped so they never run past the last row.
+    fn place_table_cells(&self, rows: &[(Arc<Mutex<DOMNode>>, Vec<Arc<Mutex<DOMNode>>>)], table_styles: &StyleMap, arena: &DOMArena) -> Vec<TableCell> {
+        let mut placed = Vec::new();
+        let mut occupied: Vec<Vec<bool>> = vec![Vec::new(); rows.len()];
+        for (row, (_, cells)) in rows.iter().enumerate() {
+            let mut column = 0;
+            for cell in cells {
+                let node = cell.lock().unwrap().clone();
+                while occupied[row].get(column).copied().unwrap_or(false) {
+                    column += 1;
+                }
+                let colspan = span_attribute(&node, "colspan");
+                let rowspan = span_attribute(&node, "rowspan").min(rows.len() - row);
+                for covered in &mut occupied[row..row + rowspan] {
+                    if covered.len() < column + colspan {
+                        covered.resize(column + colspan, false);
+                    }
+                    covered[column..column + colspan].iter_mut().for_each(|slot| *slot = true);
+                }
+                let mut styles = self.get_node_styles(&node, arena);
+                styles.border_collapse = table_styles.border_collapse.clone();
+                let text = self.extract_text_content(&node, arena, &styles.white_space);
+                placed.push(TableCell { node, styles, text, row, column, colspan, rowspan });
+                column += colspan;
+            }
+        }
+        placed
+    }
+
+    /// A cell's preferred border-box width: its declared width, or else its text on one line
+    fn table_cell_width(&self, cell: &TableCell, table_width: f32) -> f32 {
+        let context = self.length_context(&cell.styles, table_width);
+        let padding = padding_box(&cell.styles, &context);
+        let font_size = font_size_px(&cell.styles);
+        let width = resolve_length(&cell.styles.width, &context)
+            .unwrap_or_else(|| cell.text.chars().count() as f32 * font_size * 0.6);
+        width + padding.left + padding.right
+    }
+
+    /// A cell's border-box height at `width`: its declared height, or else its wrapped text
+    fn table_cell_height(&self, cell: &TableCell, width: f32, table_width: f32) -> f32 {
+        let padding = padding_box(&cell.styles, &self.length_context(&cell.styles, table_width));
+        let font_size = font_size_px(&cell.styles);
+        let lines = wrap_text(&cell.text, (width - padding.left - padding.right).max(font_size), font_size).len().max(1);
+        // Rows size to their cells, so a `%` height has no definite height to resolve against
+        let height = self.resolve_height(&cell.styles.height, &cell.styles, None)
+            .unwrap_or(lines as f32 * font_size * 1.2);
+        height + padding.top + padding.bottom
+    }
+
+    /// Single-line flexbox: items sit along the main axis and share the free space by
+    /// `flex-grow` (or give it up by `flex-shrink`), then `justify-content` and `align-items`
+    /// place them. Each item's children are laid out inside it at the position it ends up in.
+    fn layout_flex(&self, container: &DOMNode, tag_name: &str, styles: &StyleMap, arena: &DOMArena, boxes: &mut Vec<LayoutBox>, current_x: &mut f32, current_y: &mut f32, line_height: &mut f32, in_inline_context: &mut bool, counters: &mut CounterScopes, containing_width: f32, containing_height: Option<f32>, depth: usize) {
+        if *in_inline_context {
+            *current_x = 0.0;
+            *current_y += *line_height;
+            *line_height = 0.0;
+            *in_inline_context = false;
+        }
+
+        let context = self.length_context(styles, containing_width);
+        let margin = margin_box(styles, &context);
+        let padding = padding_box(styles, &context);
+        let row = !styles.flex_direction.starts_with("column");
+        let reverse = styles.flex_direction.ends_with("-reverse");
+        let width = resolve_length(&styles.width, &context).unwrap_or(self.viewport_width * 0.9).min(self.viewport_width * 0.9);
+        let height = self.resolve_height(&styles.height, styles, containing_height);
+        let (content_x, content_y) = (*current_x + margin.left + padding.left, *current_y + margin.top + padding.top);
+
+        let mut items: Vec<FlexItem> = container.children.iter()
+            .filter_map(|id| arena.get_node(id))
+            // Copied out so the lock is released: laying out the item's children locks it again
+            .map(|child| child.lock().unwrap().clone())
+            .filter_map(|child| self.flex_item(child, arena, row, width, height, counters, depth + 1))
+            .collect();
+        items.sort_by_key(|item| item.order);
+        if reverse {
+            items.reverse();
+        }
+
+        // A row's main size is the container width; a column without a height fits its items
+        let outer_main = |item: &FlexItem| item.main + item.margin_main;
+        let main_size = if row { width } else { height.unwrap_or_else(|| items.iter().map(outer_main).sum()) };
+        let free = main_size - items.iter().map(outer_main).sum::<f32>();
+        if free > 0.0 {
+            let total_grow: f32 = items.iter().map(|item| item.grow).sum();
+            if total_grow > 0.0 {
+                for item in &mut items {
+                    item.main += free * item.grow / total_grow;
+                }
+            }
+        } else if free < 0.0 {
+            // Shrink in proportion to shrink factor times size, so small items give up less
+            let total_weight: f32 = items.iter().map(|item| item.shrink * item.main).sum();
+            if total_weight > 0.0 {
+                for item in &mut items {
+                    item.main = (item.main + free * item.shrink * item.main / total_weight).max(0.0);
+                }
+            }
+        }
+
+        let remaining = (main_size - items.iter().map(outer_main).sum::<f32>()).max(0.0);
+        let count = items.len() as f32;
+        // Reversed items are laid out from the end, so flex-start and flex-end swap
+        let justify = match (styles.justify_content.as_str(), reverse) {
+            ("flex-start" | "start", true) => "flex-end",
+            ("flex-end" | "end", true) => "flex-start",
+            (justify, _) => justify,
+        };
+        let (mut position, gap) = match justify {
+            "flex-end" | "end" => (remaining, 0.0),
+            "center" => (remaining / 2.0, 0.0),
+            "space-between" if count > 1.0 => (0.0, remaining / (count - 1.0)),
+            "space-around" if count > 0.0 => (remaining / count / 2.0, remaining / count),
+            "space-evenly" => (remaining / (count + 1.0), remaining / (count + 1.0)),
+            _ => (0.0, 0.0),
+        };
+
+        let cross_size = match (row, height) {
+            (true, Some(height)) => height,
+            (true, None) => items.iter().map(|item| item.cross.unwrap_or(item.content_cross) + item.margin_cross).fold(0.0, f32::max),
+            (false, _) => width,
+        };
+        let container_height = if row { cross_size } else { main_size };
+        boxes.push(self.styled_box(container, tag_name, styles, *current_x + margin.left, *current_y + margin.top, width + padding.left + padding.right, container_height + padding.top + padding.bottom, String::new(), containing_width));
+
+        for item in items {
+            let align = styles.align_items.as_str();
+            let cross = match item.cross {
+                Some(cross) => cross,
+                None if align == "stretch" => cross_size - item.margin_cross,
+                None => item.content_cross,
+            };
+            let cross_offset = match align {
+                "flex-end" | "end" => cross_size - cross - item.margin_cross,
+                "center" => (cross_size - cross - item.margin_cross) / 2.0,
+                _ => 0.0,
+            };
+            let (x, y, w, h) = if row {
+                (content_x + position + item.margin.left, content_y + cross_offset + item.margin.top, item.main, cross)
+            } else {
+                (content_x + cross_offset + item.margin.left, content_y + position + item.margin.top, cross, item.main)
+            };
+            position += item.main + item.margin_main + gap;
+            let item_padding = padding_box(&item.styles, &self.length_context(&item.styles, width));
+            // Growing, shrinking or stretching changed the item's width, so its children wrap again at the final one
+            let content_width = w - item_padding.left - item_padding.right;
+            let children = if item.tag != "text" && content_width != item.children_width {
+                let mut counters = item.counters.clone();
+                self.layout_flex_item_children(&item.node, &item.styles, arena, &mut counters, content_width, item.children_height, depth + 1)
+            } else {
+                item.children
+            };
+            boxes.push(self.styled_box(&item.node, &item.tag, &item.styles, x, y, w, h, item.text, width));
+            for mut child in children {
+                child.x += x + item_padding.left;
+                child.y += y + item_padding.top;
+                boxes.push(child);
+            }
+        }
+
+        *current_x = 0.0;
+        *current_y += margin.top + container_height + padding.top + padding.bottom + margin.bottom;
+        *line_height = 0.0;
+    }
+
+    /// Size a flex container's child along the main axis from its `flex-basis`, falling back
+    /// to its width/height and then its content. Whitespace-only text and `display: none`
+    /// children are not items. `%` resolves against the container's content size.
+    fn flex_item(&self, node: DOMNode, arena: &DOMArena, row: bool, container_width: f32, container_height: Option<f32>, counters: &mut CounterScopes, depth: usize) -> Option<FlexItem> {
+        let styles = self.get_node_styles(&node, arena);
+        let tag = match &node.node_type {
+            NodeType::Element(tag) => tag.clone(),
+            NodeType::Text => "text".to_string(),
+            NodeType::Document => return None,
+        };
+        if styles.display.eq_ignore_ascii_case("none") {
+            return None;
+        }
+        let text = self.extract_text_content(&node, arena, &styles.white_space);
+        if tag == "text" && text.is_empty() {
+            return None;
+        }
+        let font_size = font_size_px(&styles);
+        let context = self.length_context(&styles, container_width);
+        let width = resolve_length(&styles.width, &context);
+        let height = self.resolve_height(&styles.height, &styles, container_height);
+        let children_width = width.unwrap_or(container_width);
+        let children_counters = counters.clone();
+        let children = if tag == "text" { Vec::new() } else { self.layout_flex_item_children(&node, &styles, arena, counters, children_width, height, depth) };
+        // Content size: the text on one line, grown to reach the text and replaced boxes laid out
+        // inside (block boxes span the viewport, so they only count toward the height)
+        let text_width = children.iter()
+            .filter(|b| b.node_type == "text" || b.node_type == "img" || b.embedded)
+            .map(|b| b.x + b.width)
+            .fold(text.chars().count() as f32 * font_size * 0.6, f32::max);
+        let text_height = children.iter().map(|b| b.y + b.height).fold(font_size * 1.2, f32::max);
+        let padding = padding_box(&styles, &context);
+        let margin = margin_box(&styles, &context);
+        let (padding_main, padding_cross) = if row { (padding.left + padding.right, padding.top + padding.bottom) } else { (padding.top + padding.bottom, padding.left + padding.right) };
+        let (main_length, cross_length, main_content, cross_content) = if row { (width, height, text_width, text_height) } else { (height, width, text_height, text_width) };
+        let basis = match styles.flex_basis.as_str() {
+            "auto" | "content" => None,
+            basis => resolve_length(basis, &self.length_context(&styles, if row { container_width } else { container_height.unwrap_or(0.0) })),
+        };
+        Some(FlexItem {
+            main: basis.or(main_length).unwrap_or(main_content) + padding_main,
+            cross: cross_length.map(|cross| cross + padding_cross),
+            content_cross: cross_content + padding_cross,
+            margin_main: if row { margin.left + margin.right } else { margin.top + margin.bottom },
+            margin_cross: if row { margin.top + margin.bottom } else { margin.left + margin.right },
+            grow: styles.flex_grow.parse().unwrap_or(0.0),
+            shrink: styles.flex_shrink.parse().unwrap_or(1.0),
+            order: styles.order.parse().unwrap_or(0),
+            margin,
+            text,
+            tag,
+            styles,
+            node,
+            children,
+            children_width,
+            children_height: height,
+            counters: children_counters,
+        })
+    }
+
+    /// Lay out a flex item's children from the origin of its content box, in a block
+    /// formatting context of their own; `layout_flex` moves them once the item is placed
+    fn layout_flex_item_children(&self, node: &DOMNode, styles: &StyleMap, arena: &DOMArena, counters: &mut CounterScopes, containing_width: f32, containing_height: Option<f32>, depth: usize) -> Vec<LayoutBox> {
+        let mut boxes = Vec::new();
+        let (mut x, mut y, mut line_height, mut in_inline_context) = (0.0, 0.0, 0.0, false);
+        let mut floats = FloatContext::default();
+        counters.apply(styles);
+        counters.enter();
+        self.layout_generated_content(node, arena, "before", &mut boxes, &mut x, &mut y, &mut line_height, &mut in_inline_context, &styles.white_space, counters, &mut floats, containing_width, containing_height, depth);
+        for child_id in &node.children {
+            if let Some(child_node) = arena.get_node(child_id) {
+                let child = child_node.lock().unwrap().clone();
+                self.layout_node(&child, arena, &mut boxes, &mut x, &mut y, &mut line_height, &mut in_inline_context, &styles.white_space, counters, &mut floats, containing_width, containing_height, depth + 1);
+            }
+        }
+        self.layout_generated_content(node, arena, "after", &mut boxes, &mut x, &mut y, &mut line_height, &mut in_inline_context, &styles.white_space, counters, &mut floats, containing_width, containing_height, depth);
+        counters.leave();
+        boxes
+    }
+
+    /// Inputs flow inline and show their value, or a dimmed placeholder when the value is empty.
+    /// Password values are masked and hidden inputs produce no box.
+    fn layout_input(&self, node: &DOMNode, styles: &StyleMap, boxes: &mut Vec<LayoutBox>, current_x: &mut f32, current_y: &mut f32, line_height: &mut f32, in_inline_context: &mut bool, containing_width: f32) {
+        let input_type = node.attributes.get("type").map(|t| t.to_lowercase()).unwrap_or_else(|| "text".to_string());
+        if input_type == "hidden" {
+            return;
+        }
+
+        let value = node.attributes.get("value").cloned().unwrap_or_default();
+        let placeholder = node.attributes.get("placeholder").cloned().unwrap_or_default();
+        let (text, color) = if !value.is_empty() {
+            let text = if input_type == "password" { "•".repeat(value.chars().count()) } else { value };
+            (text, styles.color.clone())
+        } else {
+            (placeholder, PLACEHOLDER_COLOR.to_string())
+        };
+
+        let font_size = font_size_px(styles);
+        let context = self.length_context(styles, containing_width);
+        let margin = margin_box(styles, &context);
+        let padding = padding_box(styles, &context);
+        let width = resolve_length(&styles.width, &context).unwrap_or(150.0) + padding.left + padding.right;
+        let height = font_size * 1.2 + padding.top + padding.bottom;
+
+        if *current_x + width + margin.left + margin.right > self.line_limit(containing_width) {
+            *current_x = 0.0;
+            *current_y += *line_height;
+            *line_height = 0.0;
+        }
+        *in_inline_context = true;
+        *current_x += margin.left;
+
+        let mut input_box = self.styled_box(node, "input", styles, *current_x, *current_y, width, height, text, containing_width);
+        input_box.color = color;
+        boxes.push(input_box);
+
+        *current_x += width + margin.right;
+        *line_height = (*line_height).max(height + margin.top + margin.bottom);
+    }
+
+    /// Reserve an inline placeholder for `<iframe>`, `<object>` or `<embed>`, sized from CSS,
+    /// then the `width`/`height` attributes, then the 300x150 default. Fallback children are not
+    /// laid out; the host draws the embedded content into the box flagged `embedded`.
+    fn layout_embedded(&self, node: &DOMNode, tag_name: &str, styles: &StyleMap, boxes: &mut Vec<LayoutBox>, current_x: &mut f32, current_y: &mut f32, line_height: &mut f32, in_inline_context: &mut bool, containing_width: f32, containing_height: Option<f32>) {
+        let attribute_length = |name: &str, default: f32| {
+            node.attributes.get(name).and_then(|v| v.trim().trim_end_matches("px").parse().ok()).unwrap_or(default)
+        };
+        let context = self.length_context(styles, containing_width);
+        let margin = margin_box(styles, &context);
+        // Images aren't decoded for an intrinsic size, so unsized ones get the replaced-element default like embeds
+        let width = resolve_length(&styles.width, &context).unwrap_or(attribute_length("width", 300.0));
+        let height = self.resolve_height(&styles.height, styles, containing_height).unwrap_or(attribute_length("height", 150.0));
+
+        if *current_x + width + margin.left + margin.right > self.line_limit(containing_width) {
+            *current_x = 0.0;
+            *current_y += *line_height;
+            *line_height = 0.0;
+        }
+        *in_inline_context = true;
+        *current_x += margin.left;
+
+        let mut placeholder = self.styled_box(node, &tag_name.to_lowercase(), styles, *current_x, *current_y, width, height, String::new(), containing_width);
+        placeholder.embedded = is_embedded_tag(tag_name);
+        boxes.push(placeholder);
+
+        *current_x += width + margin.right;
+        *line_height = (*line_height).max(height + margin.top + margin.bottom);
+    }
+
+    /// Collect `<tr>` rows and their `<td>`/`<th>` cells, looking through row groups
+    fn collect_table_rows(&self, table: &DOMNode, arena: &DOMArena) -> Vec<(Arc<Mutex<DOMNode>>, Vec<Arc<Mutex<DOMNode>>>)> {
+        let mut rows = Vec::new();
+        for child_id in &table.children {
+            let Some(child_node) = arena.get_node(child_id) else { continue };
+            let tag = match &child_node.lock().unwrap().node_type {
+                NodeType::Element(tag) => tag.to_lowercase(),
+                _ => continue,
+            };
+            match tag.as_str() {
+                "tr" => {
+                    let cells = self.collect_table_cells(&child_node.lock().unwrap(), arena);
+                    rows.push((child_node, cells));
+                }
+                "thead" | "tbody" | "tfoot" => {
+                    let group = child_node.lock().unwrap();
+                    rows.extend(self.collect_table_rows(&group, arena));
+                }
+                _ => {}
+            }
+        }
+        rows
+    }
+
+    fn collect_table_cells(&self, row: &DOMNode, arena: &DOMArena) -> Vec<Arc<Mutex<DOMNode>>> {
+        row.children.iter()
+            .filter_map(|id| arena.get_node(id))
+            .filter(|cell| matches!(&cell.lock().unwrap().node_type, NodeType::Element(tag) if tag.eq_ignore_ascii_case("td") || tag.eq_ignore_ascii_case("th")))
+            .collect()
+    }
+
+    /// Build a layout box for an element from its computed styles
+    fn styled_box(&self, node: &DOMNode, tag_name: &str, styles: &StyleMap, x: f32, y: f32, width: f32, height: f32, text_content: String, containing_width: f32) -> LayoutBox {
+        let context = self.length_context(styles, containing_width);
+        LayoutBox {
+            x,
+            y,
+            width,
+            height,
+            node_type: tag_name.to_string(),
+            text_content,
+            background_color: styles.background_color.clone(),
+            background_image: background_image(styles),
+            color: styles.color.clone(),
+            font_size: font_size_px(styles),
+            font_family: styles.font_family.clone(),
+            border_color: styles.border_color.clone(),
+            border_width: border_box(styles, &context),
+            margin: margin_box(styles, &context),
+            padding: padding_box(styles, &context),
+            font_weight: styles.font_weight.parse().unwrap_or(400.0),
+            text_align: styles.text_align.clone(),
+            flex_direction: styles.flex_direction.clone(),
+            flex_wrap: styles.flex_wrap.clone(),
+            justify_content: styles.justify_content.clone(),
+            align_items: styles.align_items.clone(),
+            flex_grow: styles.flex_grow.parse().unwrap_or(0.0),
+            flex_shrink: styles.flex_shrink.parse().unwrap_or(1.0),
+            flex_basis: styles.flex_basis.clone(),
+            order: styles.order.parse().unwrap_or(0),
+            grid_column: styles.grid_column.clone(),
+            grid_row: styles.grid_row.clone(),
+            line_height: styles.line_height.parse().unwrap_or(1.2),
+            word_wrap: styles.word_wrap.clone(),
+            white_space: styles.white_space.clone(),
+            text_overflow: styles.text_overflow.clone(),
+            color_scheme: styles.color_scheme.clone(),
+            border_collapse: styles.border_collapse.clone(),
+            border_spacing: parse_spacing(&styles.border_spacing),
+            pointer_events: styles.pointer_events.clone(),
+            node_id: node.id.clone(),
+            overflow: styles.overflow.clone(),
+            will_change: compositing_hint(styles),
+            position: styles.position.clone(),
+            z_index: ZIndex::parse(&styles.z_index),
+            opacity: styles.opacity.parse().unwrap_or(1.0),
+            transform: styles.transform.clone(),
+            transform_origin: styles.transform_origin.clone(),
+            parent_index: None,
+            embedded: false,
+            inset: Inset::from_styles(styles),
+            font_style: styles.font_style.clone(),
+            clip_path: styles.clip_path.clone(),
+            visibility: styles.visibility.clone(),
+            border_radius: styles.border_radius.clone(),
+            box_shadow: styles.box_shadow.clone(),
+        }
+    }
+
+    /// Whether an inline element has block-level element children
+    fn has_block_children(&self, node: &DOMNode, arena: &DOMArena) -> bool {
+        node.children.iter()
+            .filter_map(|id| arena.get_node(id))
+            .any(|child| {
+                let child = child.lock().unwrap();
+                matches!(child.node_type, NodeType::Element(_))
+                    && matches!(self.get_node_styles(&child, arena).display.as_str(), "block" | "flex" | "grid" | "table" | "list-item")
+            })
+    }
+
+    /// Lay out an inline element wrapping block content. The current line is closed, the
+    /// children flow as blocks, and the element's box is grown to cover them so the link
+    /// (or other inline) still spans its content for painting and hit testing.
+    fn layout_block_in_inline(&self, node: &DOMNode, tag_name: &str, styles: &StyleMap, arena: &DOMArena, boxes: &mut Vec<LayoutBox>, current_x: &mut f32, current_y: &mut f32, line_height: &mut f32, in_inline_context: &mut bool, white_space: &str, counters: &mut CounterScopes, floats: &mut FloatContext, containing_width: f32, containing_height: Option<f32>, depth: usize) {
+        if *in_inline_context {
+            *current_x = 0.0;
+            *current_y += *line_height;
+            *line_height = 0.0;
+            *in_inline_context = false;
+        }
+
+        let index = boxes.len();
+        let (x, y) = (*current_x, *current_y);
+        boxes.push(self.styled_box(node, tag_name, styles, x, y, 0.0, 0.0, String::new(), containing_width));
+
+        for child_id in &node.children {
+            if let Some(child_node) = arena.get_node(child_id) {
+                let child = child_node.lock().unwrap().clone();
+                self.layout_node(&child, arena, boxes, current_x, current_y, line_height, in_inline_context, white_space, counters, floats, containing_width, containing_height, depth + 1);
+            }
+        }
+
+        let (right, bottom) = boxes[index + 1..].iter()
+            .fold((x, y), |(right, bottom), b| (right.max(b.x + b.width), bottom.max(b.y + b.height)));
+        boxes[index].width = right - x;
+        boxes[index].height = bottom - y;
+
+        // Content after the element starts on a fresh line
+        if *in_inline_context {
+            *current_x = 0.0;
+            *current_y += *line_height;
+            *line_height = 0.0;
+            *in_inline_context = false;
+        }
+    }
+
+    fn calculate_block_dimensions(&self, styles: &StyleMap, tag_name: &str, containing_width: f32, containing_height: Option<f32>) -> (f32, f32) {
+        let context = self.length_context(styles, containing_width);
+        let width = resolve_length(&styles.width, &context)
+            .map(|width| content_length(styles, width, true, &context))
+            .unwrap_or(self.viewport_width * 0.9);
+        let height = self.resolve_height(&styles.height, styles, containing_height)
+            .map(|height| content_length(styles, height, false, &context))
+            .unwrap_or(if tag_name == "p" { 20.0 } else { 100.0 });
+        let (width, height) = self.clamp_to_min_max(styles, width, height, containing_width, containing_height);
         
         // Apply viewport constraints
         let max_width = self.viewport_width * 0.9;
@@ -386,7 +1378,7 @@ impl LayoutEngine {
         let skip_tags = [
             "script", "style", "meta", "link", "title", "head", 
             "noscript", "template", "svg", "math", "canvas",
-            "iframe", "object", "embed", "applet", "param",
+            "applet", "param",
             "source", "track", "area", "map", "picture", "audio", "video"
         ];
         skip_tags.contains(&tag_name.to_lowercase().as_str())
@@ -455,7 +1447,10 @@ impl LayoutEngine {
         let mut last_queue_size = queue.len();
         let mut last_boxes_count = local_boxes.len();
         
-        println!("[LAYOUT] [ADVANCED] Starting layout with initial queue size: {}", queue.len());
+        // Only the top of the tree logs, so parallel subtrees don't contend for stdout
+        if depth <= 3 {
+            println!("[LAYOUT] [ADVANCED] Starting layout with initial queue size: {}", queue.len());
+        }
         
         while let Some((current_node, node_x, node_y, node_depth)) = queue.pop_front() {
             iterations += 1;
@@ -489,7 +1484,7 @@ impl LayoutEngine {
                 }
             }
             
-            let node_id = format!("{:p}_{}", current_node as *const _, node_depth);
+            let node_id = (current_node.id.clone(), node_depth);
             if processed_nodes.contains(&node_id) {
                 consecutive_no_progress += 1;
                 if consecutive_no_progress > 100 {
@@ -544,7 +1539,7 @@ impl LayoutEngine {
                         continue;
                     }
                     
-                    let styles = self.get_node_styles(current_node);
+                    let styles = self.get_node_styles(current_node, arena);
                     if styles.display == "none" {
                         if self.is_layout_important(tag_name) {
                             println!("[SKIP] Skipping display:none <{}> at depth {}", tag_name, node_depth);
@@ -552,26 +1547,30 @@ impl LayoutEngine {
                         continue;
                     }
                     
-                    let margin = parse_box_value(&styles.margin);
-                    let padding = parse_box_value(&styles.padding);
-                    let border_width = parse_box_value(&styles.border_width);
+                    let context = self.length_context(&styles, self.viewport_width);
+                    let margin = margin_box(&styles, &context);
+                    let padding = padding_box(&styles, &context);
+                    let border_width = border_box(&styles, &context);
                     let border_color = styles.border_color.clone();
                     
                     if self.is_layout_important(tag_name) {
                         println!("[LAYOUT] [ADVANCED] Processing important element: <{}> at depth {}", tag_name, node_depth);
                     }
                     
-                    let (width, height) = self.calculate_dimensions(&styles, tag_name);
+                    let (width, height) = self.calculate_dimensions(&styles, tag_name, self.viewport_width, Some(self.viewport_height));
+                    let text_content = self.extract_text_content(current_node, arena, &styles.white_space);
+                    let has_text = !text_content.is_empty();
                     let box_layout = LayoutBox {
                         x: local_current_x + margin.left,
                         y: local_current_y + margin.top,
                         width,
                         height,
                         node_type: tag_name.clone(),
-                        text_content: self.extract_text_content(current_node, arena),
+                        text_content,
                         background_color: styles.background_color.clone(),
+                        background_image: background_image(&styles),
                         color: styles.color.clone(),
-                        font_size: styles.font_size.parse().unwrap_or(16.0),
+                        font_size: font_size_px(&styles),
                         font_family: styles.font_family.clone(),
                         border_color: border_color.clone(),
                         border_width: border_width.clone(),
@@ -594,40 +1593,34 @@ impl LayoutEngine {
                         white_space: styles.white_space.clone(),
                         text_overflow: styles.text_overflow.clone(),
                         color_scheme: styles.color_scheme.clone(),
+                        border_collapse: styles.border_collapse.clone(),
+                        border_spacing: parse_spacing(&styles.border_spacing),
+                        pointer_events: styles.pointer_events.clone(),
+                        node_id: current_node.id.clone(),
+                        overflow: styles.overflow.clone(),
+                        will_change: compositing_hint(&styles),
+                        position: styles.position.clone(),
+                        z_index: ZIndex::parse(&styles.z_index),
+                        opacity: styles.opacity.parse().unwrap_or(1.0),
+                        transform: styles.transform.clone(),
+                        transform_origin: styles.transform_origin.clone(),
+                        parent_index: None,
+                        embedded: false,
+                        inset: Inset::from_styles(&styles),
+                        font_style: styles.font_style.clone(),
+                        clip_path: styles.clip_path.clone(),
+                        visibility: styles.visibility.clone(),
+                        border_radius: styles.border_radius.clone(),
+                        box_shadow: styles.box_shadow.clone(),
                     };
                     
-                    if self.is_layout_important(tag_name) || !self.extract_text_content(current_node, arena).is_empty() {
+                    if self.is_layout_important(tag_name) || has_text {
                         local_boxes.push(box_layout);
                     }
                     
                     // Advanced child processing with parallel optimization
-                    let child_results: Vec<Vec<LayoutBox>> = current_node.children.iter()
-                        .filter_map(|child_id| {
-                            if let Some(child_node) = arena.get_node(child_id) {
-                                let child = child_node.lock().unwrap();
-                                if self.should_process_node(&child, node_depth + 1) {
-                                    if node_depth + 1 <= 3 {
-                                        match &child.node_type {
-                                            NodeType::Element(tag) => println!("[ENQUEUE] <{}> at depth {} (parallel child)", tag, node_depth + 1),
-                                            NodeType::Text => println!("[ENQUEUE] <text> at depth {} (parallel child)", node_depth + 1),
-                                            NodeType::Document => println!("[ENQUEUE] <document> at depth {} (parallel child)", node_depth + 1),
-                                        }
-                                    }
-                                    let mut local_boxes = Vec::new();
-                                    let mut local_node_count = 0;
-                                    Some(self.layout_node_advanced(&child, local_current_x, local_current_y, &mut local_boxes, node_depth + 1, &mut local_node_count, arena).0)
-                                } else {
-                                    None
-                                }
-                            } else {
-                                None
-                            }
-                        })
-                        .collect();
-                    
-                    for mut child_boxes in child_results {
-                        local_boxes.append(&mut child_boxes);
-                    }
+                    // Sibling subtrees are independent, so they're laid out in parallel
+                    local_boxes.append(&mut self.layout_children_advanced(&current_node.children, local_current_x, local_current_y, node_depth + 1, arena, "parallel child"));
                     
                     local_current_x += width + margin.left + margin.right + border_width.left + border_width.right + padding.left + padding.right;
                     local_max_height = local_max_height.max(height + margin.top + margin.bottom + border_width.top + border_width.bottom + padding.top + padding.bottom);
@@ -635,8 +1628,8 @@ impl LayoutEngine {
                 NodeType::Text => {
                     let text = current_node.text_content.trim();
                     if !text.is_empty() && text.len() > 1 {
-                        let styles = self.get_node_styles(current_node);
-                        let (width, height) = self.calculate_dimensions(&styles, "text");
+                        let styles = self.get_node_styles(current_node, arena);
+                        let (width, height) = self.calculate_dimensions(&styles, "text", self.viewport_width, Some(self.viewport_height));
                         let box_layout = LayoutBox {
                             x: local_current_x,
                             y: local_current_y,
@@ -645,8 +1638,9 @@ impl LayoutEngine {
                             node_type: "text".to_string(),
                             text_content: text.to_string(),
                             background_color: styles.background_color.clone(),
+                            background_image: background_image(&styles),
                             color: styles.color.clone(),
-                            font_size: styles.font_size.parse().unwrap_or(16.0),
+                            font_size: font_size_px(&styles),
                             font_family: styles.font_family.clone(),
                             border_color: "".to_string(),
                             border_width: BoxValues::default(),
@@ -669,6 +1663,25 @@ impl LayoutEngine {
                             white_space: styles.white_space.clone(),
                             text_overflow: styles.text_overflow.clone(),
                             color_scheme: styles.color_scheme.clone(),
+                            border_collapse: styles.border_collapse.clone(),
+                            border_spacing: parse_spacing(&styles.border_spacing),
+                            pointer_events: styles.pointer_events.clone(),
+                            node_id: current_node.id.clone(),
+                            overflow: styles.overflow.clone(),
+                            will_change: compositing_hint(&styles),
+                            position: styles.position.clone(),
+                            z_index: ZIndex::parse(&styles.z_index),
+                            opacity: styles.opacity.parse().unwrap_or(1.0),
+                            transform: styles.transform.clone(),
+                            transform_origin: styles.transform_origin.clone(),
+                            parent_index: None,
+                            embedded: false,
+                            inset: Inset::from_styles(&styles),
+                            font_style: styles.font_style.clone(),
+                            clip_path: styles.clip_path.clone(),
+                            visibility: styles.visibility.clone(),
+                            border_radius: styles.border_radius.clone(),
+                            box_shadow: styles.box_shadow.clone(),
                         };
                         local_boxes.push(box_layout);
                         local_current_x += width;
@@ -677,33 +1690,7 @@ impl LayoutEngine {
                 }
                 NodeType::Document => {
                     println!("[LAYOUT] [ADVANCED] Document node: processing {} children", current_node.children.len());
-                    let child_results: Vec<Vec<LayoutBox>> = current_node.children.iter()
-                        .filter_map(|child_id| {
-                            if let Some(child_node) = arena.get_node(child_id) {
-                                let child = child_node.lock().unwrap();
-                                if self.should_process_node(&child, node_depth + 1) {
-                                    if node_depth + 1 <= 3 {
-                                        match &child.node_type {
-                                            NodeType::Element(tag) => println!("[ENQUEUE] <{}> at depth {} (parallel doc child)", tag, node_depth + 1),
-                                            NodeType::Text => println!("[ENQUEUE] <text> at depth {} (parallel doc child)", node_depth + 1),
-                                            NodeType::Document => println!("[ENQUEUE] <document> at depth {} (parallel doc child)", node_depth + 1),
-                                        }
-                                    }
-                                    let mut local_boxes = Vec::new();
-                                    let mut local_node_count = 0;
-                                    Some(self.layout_node_advanced(&child, local_current_x, local_current_y, &mut local_boxes, node_depth + 1, &mut local_node_count, arena).0)
-                                } else {
-                                    None
-                                }
-                            } else {
-                                None
-                            }
-                        })
-                        .collect();
-                    
-                    for mut child_boxes in child_results {
-                        local_boxes.append(&mut child_boxes);
-                    }
+                    local_boxes.append(&mut self.layout_children_advanced(&current_node.children, local_current_x, local_current_y, node_depth + 1, arena, "parallel doc child"));
                 }
             }
             
@@ -712,66 +1699,318 @@ impl LayoutEngine {
             max_height = max_height.max(local_max_height);
         }
         
-        println!("[LAYOUT] [ADVANCED] Layout completed: {} iterations, {} boxes created, {} nodes processed", 
-            iterations, local_boxes.len(), *node_count);
+        if depth <= 3 {
+            println!("[LAYOUT] [ADVANCED] Layout completed: {} iterations, {} boxes created, {} nodes processed", 
+                iterations, local_boxes.len(), *node_count);
+        }
         
-        (local_boxes.clone(), (current_x, current_y + max_height))
+        (local_boxes, (current_x, current_y + max_height))
     }
 
-    fn extract_text_content(&self, node: &DOMNode, arena: &DOMArena) -> String {
-        let mut text = String::new();
-        match &node.node_type {
-            NodeType::Text => {
-                text.push_str(&node.text_content);
+    /// Lay out each child subtree from `(x, y)` and concatenate their boxes in document order.
+    /// Children are cloned out of the arena before recursing, so no node lock is held while
+    /// another subtree (or this one's style lookups) locks ancestors.
+    fn layout_children_advanced(&self, children: &[String], x: f32, y: f32, depth: usize, arena: &DOMArena, label: &str) -> Vec<LayoutBox> {
+        let layout_child = |child_id: &String| -> Option<Vec<LayoutBox>> {
+            let child = arena.get_node(child_id)?.lock().unwrap().clone();
+            if !self.should_process_node(&child, depth) {
+                return None;
             }
-            NodeType::Element(_) => {
-                for child in &node.children {
-                    if let Some(child_node) = arena.get_node(child) {
-                        let child = child_node.lock().unwrap();
-                        match &child.node_type {
-                            NodeType::Text => {
-                                text.push_str(&child.text_content);
-                                text.push(' ');
-                            }
-                            _ => {}
-                        }
-                    }
+            if depth <= 3 {
+                match &child.node_type {
+                    NodeType::Element(tag) => println!("[ENQUEUE] <{}> at depth {} ({})", tag, depth, label),
+                    NodeType::Text => println!("[ENQUEUE] <text> at depth {} ({})", depth, label),
+                    NodeType::Document => println!("[ENQUEUE] <document> at depth {} ({})", depth, label),
                 }
             }
-            _ => {}
-        }
-        text.trim().to_string()
+            let mut node_count = 0;
+            Some(self.layout_node_advanced(&child, x, y, &mut Vec::new(), depth, &mut node_count, arena).0)
+        };
+        // rayon's indexed collect keeps the children's order
+        let child_results: Vec<Vec<LayoutBox>> = if self.parallel {
+            children.par_iter().filter_map(layout_child).collect()
+        } else {
+            children.iter().filter_map(layout_child).collect()
+        };
+        child_results.concat()
     }
 
-    fn get_node_styles(&self, node: &DOMNode) -> StyleMap {
-        let mut styles = StyleMap::default();
-        
-        // Apply inline styles
-        if let Some(style_attr) = node.attributes.get("style") {
-            let inline_styles = parse_inline_styles(style_attr);
-            styles.merge(&inline_styles);
+    /// Apply `::first-line` to the text on the block's first line box, then `::first-letter`
+    /// to the first character, which is split out into its own box.
+    fn apply_first_line_and_letter(&self, node: &DOMNode, arena: &DOMArena, boxes: &mut Vec<LayoutBox>, start: usize) {
+        let first_line = self.pseudo_element_declarations(node, arena, "first-line");
+        let first_letter = self.pseudo_element_declarations(node, arena, "first-letter");
+        if first_line.is_empty() && first_letter.is_empty() {
+            return;
         }
+        let Some(first_text) = boxes[start..].iter()
+            .position(|b| b.node_type == "text" && !b.text_content.trim().is_empty())
+            .map(|i| start + i) else {
+            return;
+        };
+        let line_y = boxes[first_text].y;
 
-        // Apply external stylesheet if available
-        if let Some(ref stylesheet) = self.stylesheet {
-            self.apply_stylesheet_to_node(node, stylesheet, &mut styles);
+        if !first_line.is_empty() {
+            let mut shift = 0.0;
+            for b in boxes[first_text..].iter_mut().filter(|b| on_line(b, line_y)) {
+                b.x += shift;
+                if b.node_type == "text" {
+                    shift += restyle_text_box(b, &first_line);
+                }
+            }
         }
-        
+
+        if !first_letter.is_empty() {
+            let letter_box = &mut boxes[first_text];
+            let text = letter_box.text_content.trim_start().to_string();
+            let Some(letter) = text.chars().next() else { return };
+
+            // The letter keeps the original box; the rest of the text follows in a new one
+            let mut rest_box = letter_box.clone();
+            let letter_width = letter_box.font_size * 0.6;
+            letter_box.text_content = letter.to_string();
+            letter_box.width = letter_width;
+            let shift = restyle_text_box(letter_box, &first_letter);
+            rest_box.text_content = text[letter.len_utf8()..].to_string();
+            rest_box.x = letter_box.x + letter_box.width;
+            rest_box.width -= letter_width;
+
+            for b in boxes[first_text + 1..].iter_mut().filter(|b| on_line(b, line_y)) {
+                b.x += shift;
+            }
+            if !rest_box.text_content.is_empty() {
+                boxes.insert(first_text + 1, rest_box);
+            }
+        }
+    }
+
+    /// `line-clamp`: break the block's text into lines of `width`, keep the first `max_lines`
+    /// as one box each and end the last kept line with an ellipsis if anything was cut.
+    fn apply_line_clamp(&self, boxes: &mut Vec<LayoutBox>, start: usize, max_lines: usize, width: f32, current_y: &mut f32, line_height: &mut f32) {
+        let text_indices: Vec<usize> = (start..boxes.len()).filter(|&i| boxes[i].node_type == "text").collect();
+        let Some(&first) = text_indices.first() else { return };
+        if max_lines == 0 {
+            return;
+        }
+
+        let text = text_indices.iter().map(|&i| boxes[i].text_content.trim()).collect::<Vec<_>>().join(" ");
+        let template = boxes[first].clone();
+        let char_width = template.font_size * 0.6;
+        let mut lines = wrap_text(&text, width, template.font_size);
+        let clamped = lines.len() > max_lines;
+        lines.truncate(max_lines);
+
+        let line_box_height = template.height;
+        let mut line_boxes: Vec<LayoutBox> = lines.into_iter().enumerate().map(|(row, line)| {
+            let mut line_box = template.clone();
+            line_box.x = if row == 0 { template.x } else { 0.0 };
+            line_box.y = template.y + row as f32 * line_box_height;
+            line_box.width = line.chars().count() as f32 * char_width;
+            line_box.text_content = line;
+            line_box
+        }).collect();
+        if clamped {
+            if let Some(last) = line_boxes.last_mut() {
+                last.text_content.push('…');
+                let right_edge = last.x + width;
+                last.width += char_width;
+                truncate_text(last, right_edge, true);
+            }
+        }
+
+        for &i in text_indices.iter().rev() {
+            boxes.remove(i);
+        }
+        let rows = line_boxes.len();
+        boxes.splice(first..first, line_boxes);
+        *current_y = template.y + (rows - 1) as f32 * line_box_height;
+        *line_height = line_box_height;
+    }
+
+    /// Declarations from rules like `p::first-line` (or the legacy `p:first-line`) that match `node`
+    fn pseudo_element_declarations(&self, node: &DOMNode, arena: &DOMArena, pseudo: &str) -> HashMap<String, String> {
+        let mut declarations = HashMap::new();
+        let Some(stylesheet) = self.stylesheet.as_ref().filter(|_| self.css_enabled) else { return declarations };
+        let matching: Vec<&CssRule> = stylesheet.cascade_order()
+            .filter(|rule| rule.applies_to_viewport(self.viewport_width, self.viewport_height))
+            .filter(|rule| {
+                rule.selector.trim().strip_suffix(pseudo)
+                    .and_then(|s| s.strip_suffix("::").or_else(|| s.strip_suffix(':')))
+                    .is_some_and(|base| matches_selector(node, base, arena))
+            })
+            .collect();
+        declarations.extend(cascade_declarations(&matching).into_iter().map(|(k, v)| (k.clone(), v.clone())));
+        declarations
+    }
+
+    /// All descendant text of `node` in document order, like `textContent`, with
+    /// `white-space` applied as for a block of its own
+    fn extract_text_content(&self, node: &DOMNode, arena: &DOMArena, white_space: &str) -> String {
+        let mut text = String::new();
+        self.collect_text(node, arena, &mut text);
+        normalize_block_text(&text, white_space)
+    }
+
+    /// Whether nothing but whitespace or a block-level sibling follows `node` in its
+    /// parent, so collapsible space at its end falls at a line box boundary
+    fn ends_line_box(&self, node: &DOMNode, arena: &DOMArena) -> bool {
+        let Some(parent) = node.parent.as_ref().and_then(|id| arena.get_node(id)) else { return true };
+        let parent = parent.lock().unwrap();
+        let Some(position) = parent.children.iter().position(|id| *id == node.id) else { return true };
+        let next = parent.children[position + 1..].iter()
+            .filter_map(|id| arena.get_node(id))
+            .find(|sibling| {
+                let sibling = sibling.lock().unwrap();
+                !(sibling.node_type == NodeType::Text && sibling.text_content.trim().is_empty())
+            });
+        drop(parent);
+        let Some(next) = next else { return true };
+        let next = next.lock().unwrap().clone();
+        // `display` isn't inherited, so the sibling's declared value is its computed one
+        matches!(next.node_type, NodeType::Element(_))
+            && matches!(self.declared_styles(&next, arena).display.as_str(), "block" | "flex" | "grid" | "table" | "list-item")
+    }
+
+    /// Append the text under `node` in document order
+    fn collect_text(&self, node: &DOMNode, arena: &DOMArena, text: &mut String) {
+        match &node.node_type {
+            NodeType::Text => text.push_str(&node.text_content),
+            // Embedded fallback content and non-rendered elements contribute no text
+            NodeType::Element(tag) if is_embedded_tag(tag) || matches!(tag.to_lowercase().as_str(), "script" | "style" | "head" | "template") => {}
+            NodeType::Element(_) if self.is_display_none(node, arena) => {}
+            NodeType::Element(_) | NodeType::Document => {
+                for child_id in &node.children {
+                    if let Some(child_node) = arena.get_node(child_id) {
+                        let child = child_node.lock().unwrap().clone();
+                        self.collect_text(&child, arena, text);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The node's computed styles: its declared styles with the inherited properties it didn't
+    /// declare taken from its parent's computed styles. Cached like declared styles, so in a
+    /// top-down pass the parent's are already computed.
+    pub(crate) fn get_node_styles(&self, node: &DOMNode, arena: &DOMArena) -> StyleMap {
+        if !self.css_enabled {
+            return self.declared_styles(node, arena);
+        }
+        let key = self.style_key(node, arena);
+        if let Some(styles) = self.style_cache.get_computed(&node.id, key) {
+            return styles;
+        }
+        let mut styles = self.cached_declared_styles(node, arena, key);
+        let parent_styles = node.parent.as_ref().and_then(|id| arena.get_node(id)).map(|parent| {
+            // A copy, so the parent's own cascade can lock its ancestors
+            let parent = parent.lock().unwrap().clone();
+            self.get_node_styles(&parent, arena)
+        });
+        inherit_from_parent(parent_styles.as_ref(), &mut styles);
+        let parent_font_size = parent_styles.as_ref().map_or(LengthContext::default().root_font_size, font_size_px);
+        self.compute_font_size(&mut styles, parent_font_size);
+        self.style_cache.store_computed(&node.id, key, styles.clone());
         styles
     }
 
-    fn apply_stylesheet_to_node(&self, node: &DOMNode, stylesheet: &Stylesheet, styles: &mut StyleMap) {
+    /// Whether `node` computes to `display: none`. display isn't inherited, so only the node's
+    /// own declarations matter, and without any there's no cascade to compute.
+    fn is_display_none(&self, node: &DOMNode, arena: &DOMArena) -> bool {
+        if !self.css_enabled || (self.stylesheet.is_none() && !node.attributes.contains_key("style")) {
+            return false;
+        }
+        self.declared_styles(node, arena).display.eq_ignore_ascii_case("none")
+    }
+
+    /// The node's own cascaded styles (inline, then the stylesheet), before inheritance.
+    /// Served from the style cache while the node and its ancestors are unchanged.
+    fn declared_styles(&self, node: &DOMNode, arena: &DOMArena) -> StyleMap {
+        if !self.css_enabled {
+            return user_agent_styles(node);
+        }
+        self.cached_declared_styles(node, arena, self.style_key(node, arena))
+    }
+
+    fn cached_declared_styles(&self, node: &DOMNode, arena: &DOMArena, key: u64) -> StyleMap {
+        if let Some(styles) = self.style_cache.get(&node.id, key) {
+            return styles;
+        }
+        let styles = self.match_declared_styles(node, arena);
+        self.style_cache.store(&node.id, key, styles.clone());
+        styles
+    }
+
+    fn match_declared_styles(&self, node: &DOMNode, arena: &DOMArena) -> StyleMap {
+        let mut styles = user_agent_styles(node);
+        // Inherited properties stay `inherit` until declared, so one declared at its initial
+        // value isn't mistaken for one the parent should supply
+        for property in INHERITED_PROPERTIES {
+            styles.set_property(property, "inherit");
+        }
+
+        let mut inline = Vec::new();
+        if let Some(style_attr) = node.attributes.get("style") {
+            inline = inline_declarations(style_attr);
+        }
+
+        // The stylesheet cascade applies the inline declarations in their place among its rules
+        if let Some(ref stylesheet) = self.stylesheet {
+            self.apply_stylesheet_to_node(node, arena, stylesheet, &inline, &mut styles);
+        } else {
+            let (important, normal): (Vec<_>, Vec<_>) = inline.iter().partition(|(_, _, important)| *important);
+            for (property, value, _) in normal.into_iter().chain(important) {
+                match substitute_env(value, &self.environment) {
+                    Some(value) => self.apply_css_property(&mut styles, property, &value),
+                    None => self.apply_invalid_at_computed_value_time(&mut styles, property),
+                }
+            }
+        }
+        styles
+    }
+
+    /// Cascade the matching rules over `styles` in CSS precedence order: normal rule declarations,
+    /// then the node's `inline` declarations, then `!important` rules, then `!important` inline ones
+    fn apply_stylesheet_to_node(&self, node: &DOMNode, arena: &DOMArena, stylesheet: &Stylesheet, inline: &[(String, String, bool)], styles: &mut StyleMap) {
         if let NodeType::Element(_tag_name) = &node.node_type {
-            for rule in &stylesheet.rules {
-                if matches_selector(node, &rule.selector) {
-                    for (property, value) in &rule.declarations {
-                        self.apply_css_property(styles, property, value);
-                    }
+            // Only computed once a declaration actually uses var()
+            let mut variables = None;
+            let matching: Vec<&CssRule> = stylesheet.cascade_order()
+                .filter(|rule| rule.applies_to_viewport(self.viewport_width, self.viewport_height))
+                .filter(|rule| matches_selector(node, &rule.selector, arena))
+                .collect();
+            let (normal, important) = split_cascade_declarations(&matching);
+            let inline_with = |important: bool| inline.iter().filter(move |(_, _, flag)| *flag == important).map(|(property, value, _)| (property, value));
+            let ordered = normal.into_iter()
+                .chain(inline_with(false))
+                .chain(important)
+                .chain(inline_with(true));
+            for (property, value) in ordered {
+                if !value.contains("var(") && !value.contains("env(") {
+                    self.apply_css_property(styles, property, value);
+                    continue;
+                }
+                let variables = variables.get_or_insert_with(|| stylesheet.variables_for(node, arena));
+                let resolved = substitute_variables(value, variables)
+                    .and_then(|value| substitute_env(&value, &self.environment));
+                match resolved {
+                    Some(value) => self.apply_css_property(styles, property, &value),
+                    None => self.apply_invalid_at_computed_value_time(styles, property),
                 }
             }
         }
     }
 
+    /// A declaration whose `var()` or `env()` can't be resolved still wins the cascade, but
+    /// computes as if unset: inherited properties take the parent's value, others their initial one
+    fn apply_invalid_at_computed_value_time(&self, styles: &mut StyleMap, property: &str) {
+        let property = property.to_lowercase();
+        if INHERITED_PROPERTIES.contains(&property.as_str()) {
+            self.apply_css_property(styles, &property, "inherit");
+        } else if let Some(initial) = StyleMap::default().get_property(&property) {
+            self.apply_css_property(styles, &property, initial);
+        }
+    }
+
     fn apply_css_property(&self, styles: &mut StyleMap, property: &str, value: &str) {
         // This is a simplified version - the full implementation is in css_parser.rs
         match property.to_lowercase().as_str() {
@@ -782,19 +2021,48 @@ impl LayoutEngine {
             "color" => styles.color = value.to_string(),
             "font-size" => styles.font_size = value.to_string(),
             "font-family" => styles.font_family = value.to_string(),
-            "border-width" => styles.border_width = value.to_string(),
+            "border-width" => styles.set_border_width(value),
             "border-color" => styles.border_color = value.to_string(),
-            "padding" => styles.padding = value.to_string(),
-            "margin" => styles.margin = value.to_string(),
+            "padding" => styles.set_padding(value),
+            "margin" => styles.set_margin(value),
             "font-weight" => styles.font_weight = value.to_string(),
+            "font-style" => styles.font_style = value.to_string(),
             "text-align" => styles.text_align = value.to_string(),
-            _ => {}
+            "white-space" => styles.white_space = value.to_string(),
+            "text-overflow" => styles.text_overflow = value.to_string(),
+            "line-clamp" | "-webkit-line-clamp" => styles.line_clamp = value.to_string(),
+            "content" => styles.content = value.to_string(),
+            "counter-reset" => styles.counter_reset = value.to_string(),
+            "counter-increment" => styles.counter_increment = value.to_string(),
+            "border-collapse" => styles.border_collapse = value.to_string(),
+            "border-spacing" => styles.border_spacing = value.to_string(),
+            "pointer-events" => styles.pointer_events = value.to_string(),
+            "overflow" => styles.overflow = value.to_string(),
+            "will-change" => styles.will_change = value.to_string(),
+            "animation" => styles.animation = value.to_string(),
+            "position" => styles.position = value.to_string(),
+            "top" => styles.top = value.to_string(),
+            "right" => styles.right = value.to_string(),
+            "bottom" => styles.bottom = value.to_string(),
+            "left" => styles.left = value.to_string(),
+            "inset" => styles.set_inset(value),
+            "z-index" => styles.z_index = value.to_string(),
+            "opacity" => styles.opacity = value.to_string(),
+            "transform" => styles.transform = value.to_string(),
+            // Everything else, e.g. the margin/padding/border longhands
+            _ => styles.set_property(property, value),
         }
     }
 
-    fn calculate_dimensions(&self, styles: &StyleMap, tag_name: &str) -> (f32, f32) {
-        let width = self.parse_length(&styles.width, if tag_name == "text" { 100.0 } else { 200.0 });
-        let height = self.parse_length(&styles.height, if tag_name == "text" { 20.0 } else { 100.0 });
+    fn calculate_dimensions(&self, styles: &StyleMap, tag_name: &str, containing_width: f32, containing_height: Option<f32>) -> (f32, f32) {
+        let context = self.length_context(styles, containing_width);
+        let width = resolve_length(&styles.width, &context)
+            .map(|width| content_length(styles, width, true, &context))
+            .unwrap_or(if tag_name == "text" { 100.0 } else { 200.0 });
+        let height = self.resolve_height(&styles.height, styles, containing_height)
+            .map(|height| content_length(styles, height, false, &context))
+            .unwrap_or(if tag_name == "text" { 20.0 } else { 100.0 });
+        let (width, height) = self.clamp_to_min_max(styles, width, height, containing_width, containing_height);
         
         // Apply viewport constraints
         let max_width = self.viewport_width * 0.9;
@@ -803,46 +2071,1427 @@ impl LayoutEngine {
         (width.min(max_width), height.min(max_height))
     }
 
-    fn parse_length(&self, value: &str, default: f32) -> f32 {
-        if value.is_empty() {
-            return default;
+    /// Clamp a content size to `min-`/`max-width` and `min-`/`max-height`; `none`, `auto` and
+    /// other non-lengths leave that bound open. As in CSS, a minimum beats a smaller maximum.
+    fn clamp_to_min_max(&self, styles: &StyleMap, width: f32, height: f32, containing_width: f32, containing_height: Option<f32>) -> (f32, f32) {
+        let edges = self.length_context(styles, containing_width);
+        let bound = |length: Option<f32>, horizontal: bool| length.map(|length| content_length(styles, length, horizontal, &edges));
+        let clamp = |size: f32, min: Option<f32>, max: Option<f32>| size.min(max.unwrap_or(f32::INFINITY)).max(min.unwrap_or(0.0));
+        (
+            clamp(width, bound(resolve_length(&styles.min_width, &edges), true), bound(resolve_length(&styles.max_width, &edges), true)),
+            clamp(
+                height,
+                bound(self.resolve_height(&styles.min_height, styles, containing_height), false),
+                bound(self.resolve_height(&styles.max_height, styles, containing_height), false),
+            ),
+        )
+    }
+
+    /// Where lines break: the containing block's edge, and never past the page's
+    fn line_limit(&self, containing_width: f32) -> f32 {
+        containing_width.min(self.viewport_width * 0.9)
+    }
+
+    /// Resolve a height, `min-height` or `max-height`: `%` against the containing block's
+    /// height, or None, as for `auto`, when that height is indefinite
+    fn resolve_height(&self, value: &str, styles: &StyleMap, containing_height: Option<f32>) -> Option<f32> {
+        match containing_height {
+            Some(height) => resolve_length(value, &self.length_context(styles, height)),
+            None if value.contains('%') => None,
+            None => resolve_length(value, &self.length_context(styles, 0.0)),
         }
-        
-        if value.ends_with("px") {
-            value[..value.len() - 2].parse().unwrap_or(default)
-        } else if value.ends_with("%") {
-            let percent = value[..value.len() - 1].parse().unwrap_or(0.0);
-            if value.contains("width") {
-                self.viewport_width * percent / 100.0
-            } else {
-                self.viewport_height * percent / 100.0
+    }
+
+    /// Context for resolving an element's lengths: `%` against `percent_base` and `em` against
+    /// its computed font size. Widths, margins and padding pass the containing block's width;
+    /// heights go through `resolve_height`.
+    fn length_context(&self, styles: &StyleMap, percent_base: f32) -> LengthContext {
+        LengthContext {
+            percent_base,
+            font_size: font_size_px(styles),
+            viewport_width: self.viewport_width,
+            viewport_height: self.viewport_height,
+            ..LengthContext::default()
+        }
+    }
+
+    /// Compute `font-size` to px. `em` and `%` are relative to the parent's computed size, and
+    /// values that don't resolve, such as the size keywords, keep the parent's.
+    fn compute_font_size(&self, styles: &mut StyleMap, parent_font_size: f32) {
+        let context = LengthContext {
+            percent_base: parent_font_size,
+            font_size: parent_font_size,
+            viewport_width: self.viewport_width,
+            viewport_height: self.viewport_height,
+            ..LengthContext::default()
+        };
+        let font_size = resolve_length(&styles.font_size, &context).unwrap_or(parent_font_size);
+        styles.font_size = format!("{}px", font_size);
+    }
+}
+
+/// A flex container's child as `layout_flex` sizes it. Sizes include padding;
+/// `cross` is set when the item has a definite cross size.
+struct FlexItem {
+    node: DOMNode,
+    tag: String,
+    styles: StyleMap,
+    text: String,
+    main: f32,
+    cross: Option<f32>,
+    content_cross: f32,
+    margin: BoxValues,
+    margin_main: f32,
+    margin_cross: f32,
+    grow: f32,
+    shrink: f32,
+    order: i32,
+    /// The item's subtree, laid out relative to its content box
+    children: Vec<LayoutBox>,
+    /// Content width and height `children` were laid out at, and the counters they started from
+    children_width: f32,
+    children_height: Option<f32>,
+    counters: CounterScopes,
+}
+
+/// Link each box to the box of its nearest DOM ancestor, so paint can rebuild the box tree.
+/// A node split into several boxes (e.g. `::first-letter`) parents through its first box.
+fn assign_parent_indices(boxes: &mut [LayoutBox], arena: &DOMArena) {
+    let mut first_box = HashMap::new();
+    for (index, b) in boxes.iter().enumerate() {
+        first_box.entry(b.node_id.clone()).or_insert(index);
+    }
+    for (index, b) in boxes.iter_mut().enumerate() {
+        // Generated content belongs to its element's box
+        if b.node_type.starts_with("::") {
+            b.parent_index = first_box.get(&b.node_id).copied().filter(|&parent| parent < index);
+            continue;
+        }
+        let mut parent_id = arena.get_node(&b.node_id).and_then(|node| node.lock().unwrap().parent.clone());
+        while let Some(id) = parent_id {
+            if let Some(&parent) = first_box.get(&id).filter(|&&parent| parent < index) {
+                b.parent_index = Some(parent);
+                break;
+            }
+            parent_id = arena.get_node(&id).and_then(|node| node.lock().unwrap().parent.clone());
+        }
+    }
+}
+
+/// CSS counters in scope during layout. Each element opens a frame for its children; a
+/// `counter-reset` lands in the frame the element itself sits in, so it also covers its
+/// following siblings, and a reset nested deeper hides the outer counter until that frame closes.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct CounterScopes {
+    frames: Vec<HashMap<String, i32>>,
+}
+
+impl CounterScopes {
+    fn enter(&mut self) {
+        self.frames.push(HashMap::new());
+    }
+
+    fn leave(&mut self) {
+        self.frames.pop();
+    }
+
+    /// Apply an element's `counter-reset` then `counter-increment`
+    fn apply(&mut self, styles: &StyleMap) {
+        for (name, value) in parse_counter_list(&styles.counter_reset, 0) {
+            if let Some(frame) = self.frames.last_mut() {
+                frame.insert(name, value);
+            }
+        }
+        for (name, by) in parse_counter_list(&styles.counter_increment, 1) {
+            match self.frames.iter_mut().rev().find_map(|frame| frame.get_mut(&name)) {
+                Some(value) => *value += by,
+                // Incrementing a counter that isn't in scope instantiates it at 0 first
+                None => if let Some(frame) = self.frames.last_mut() {
+                    frame.insert(name, by);
+                },
+            }
+        }
+    }
+
+    /// Values of every `name` counter in scope, outermost first
+    fn values(&self, name: &str) -> Vec<i32> {
+        self.frames.iter().filter_map(|frame| frame.get(name).copied()).collect()
+    }
+}
+
+/// Parse `name [integer]` pairs such as `section 2 item`; `none` yields nothing
+fn parse_counter_list(value: &str, default: i32) -> Vec<(String, i32)> {
+    let mut counters: Vec<(String, i32)> = Vec::new();
+    for token in value.split_whitespace() {
+        match (token.parse::<i32>(), counters.last_mut()) {
+            (Ok(number), Some(last)) => last.1 = number,
+            (Ok(_), None) => {}
+            (Err(_), _) if token == "none" => {}
+            (Err(_), _) => counters.push((token.to_string(), default)),
+        }
+    }
+    counters
+}
+
+/// Resolve a `content` value: quoted strings, `counter(name)`, `counters(name, "sep")`
+/// and `attr(name)`, concatenated. `none`/`normal` produce nothing.
+fn resolve_content(value: &str, node: &DOMNode, counters: &CounterScopes) -> String {
+    let mut text = String::new();
+    let mut rest = value.trim();
+    while !rest.is_empty() {
+        if let Some(quote) = rest.chars().next().filter(|c| *c == '"' || *c == '\'') {
+            let body = &rest[1..];
+            let end = body.find(quote).unwrap_or(body.len());
+            text.push_str(&body[..end]);
+            rest = body.get(end + 1..).unwrap_or("");
+        } else if let Some((function, after)) = rest.split_once('(') {
+            let (arguments, after) = after.split_once(')').unwrap_or((after, ""));
+            let arguments: Vec<&str> = arguments.split(',').map(|a| a.trim().trim_matches(|c| c == '"' || c == '\'')).collect();
+            let name = arguments[0];
+            match function.trim() {
+                "counter" => text.push_str(&counters.values(name).last().copied().unwrap_or(0).to_string()),
+                "counters" => {
+                    let values: Vec<String> = counters.values(name).iter().map(i32::to_string).collect();
+                    text.push_str(&if values.is_empty() { "0".to_string() } else { values.join(arguments.get(1).copied().unwrap_or("")) });
+                }
+                "attr" => text.push_str(node.attributes.get(name).map(String::as_str).unwrap_or("")),
+                _ => {}
             }
+            rest = after;
         } else {
-            value.parse().unwrap_or(default)
+            // Keywords such as `none` or `normal`
+            rest = rest.split_once(char::is_whitespace).map(|(_, after)| after).unwrap_or("");
         }
+        rest = rest.trim_start();
     }
+    text
 }
 
-fn parse_box_value(value: &str) -> BoxValues {
-    let parts: Vec<&str> = value.split_whitespace().collect();
-    match parts.len() {
-        1 => {
-            let val = parts[0].parse().unwrap_or(0.0);
-            BoxValues { top: val, right: val, bottom: val, left: val }
+/// Inline-level boxes share a line's top y during layout; move each onto the line's baseline.
+/// Text sits on the baseline by its font ascent (0.8em plus half the 1.2 line-height leading),
+/// replaced elements (images, embeds) by the bottom of their margin box. A line that grows
+/// because of the shift pushes everything laid out after it down.
+fn align_line_baselines(boxes: &mut [LayoutBox]) {
+    let mut start = 0;
+    while start < boxes.len() {
+        if !is_line_fragment(&boxes[start]) {
+            start += 1;
+            continue;
         }
-        2 => {
-            let top_bottom = parts[0].parse().unwrap_or(0.0);
-            let left_right = parts[1].parse().unwrap_or(0.0);
-            BoxValues { top: top_bottom, right: left_right, bottom: top_bottom, left: left_right }
+        let top = boxes[start].y;
+        let end = (start..boxes.len())
+            .find(|&i| !is_line_fragment(&boxes[i]) || (boxes[i].y - top).abs() > 0.01)
+            .unwrap_or(boxes.len());
+
+        let metrics: Vec<(f32, f32)> = boxes[start..end].iter().map(baseline_metrics).collect();
+        let ascent = metrics.iter().map(|m| m.0).fold(0.0, f32::max);
+        let descent = metrics.iter().map(|m| m.1).fold(0.0, f32::max);
+        let old_height = boxes[start..end].iter().map(line_extent).fold(0.0, f32::max);
+        for (b, (box_ascent, _)) in boxes[start..end].iter_mut().zip(&metrics) {
+            b.y = top + ascent - box_ascent + if is_replaced_box(b) { b.margin.top } else { 0.0 };
         }
-        4 => {
-            BoxValues {
-                top: parts[0].parse().unwrap_or(0.0),
-                right: parts[1].parse().unwrap_or(0.0),
-                bottom: parts[2].parse().unwrap_or(0.0),
-                left: parts[3].parse().unwrap_or(0.0),
+
+        let growth = ascent + descent - old_height;
+        if growth > 0.01 {
+            for b in boxes[end..].iter_mut().filter(|b| b.y >= top + old_height - 0.01) {
+                b.y += growth;
             }
         }
-        _ => BoxValues::default(),
+        start = end;
+    }
+}
+
+/// Boxes placed on inline lines: text runs, inline elements and replaced elements
+fn is_line_fragment(b: &LayoutBox) -> bool {
+    !matches!(b.position.as_str(), "absolute" | "fixed")
+        && (b.node_type == "text" || b.node_type.starts_with("::") || is_replaced_box(b) || INLINE_TAGS.contains(&b.node_type.as_str()))
+}
+
+fn is_replaced_box(b: &LayoutBox) -> bool {
+    b.embedded || b.node_type == "img"
+}
+
+/// Distance from the box's top (margin top for replaced boxes) to the baseline, and below it
+fn baseline_metrics(b: &LayoutBox) -> (f32, f32) {
+    if is_replaced_box(b) {
+        return (b.margin.top + b.height, b.margin.bottom);
+    }
+    let ascent = b.padding.top + b.border_width.top + b.font_size * 0.9;
+    (ascent, (b.height - ascent).max(0.0))
+}
+
+/// Height the box took from the line during layout
+fn line_extent(b: &LayoutBox) -> f32 {
+    if is_replaced_box(b) { b.height + b.margin.top + b.margin.bottom } else { b.height }
+}
+
+/// Move `position: absolute`/`fixed` boxes, with their descendants, to their offsets from the
+/// containing block: the nearest positioned ancestor's box, else the viewport. A box with both
+/// opposite offsets set stretches between them.
+fn apply_absolute_offsets(boxes: &mut [LayoutBox], viewport: (f32, f32)) {
+    for index in 0..boxes.len() {
+        if !matches!(boxes[index].position.as_str(), "absolute" | "fixed") {
+            continue;
+        }
+        let mut container = boxes[index].parent_index.filter(|_| boxes[index].position == "absolute");
+        while let Some(parent) = container {
+            if is_positioned(&boxes[parent]) {
+                break;
+            }
+            container = boxes[parent].parent_index;
+        }
+        let (cx, cy, cw, ch) = container
+            .map(|parent| (boxes[parent].x, boxes[parent].y, boxes[parent].width, boxes[parent].height))
+            .unwrap_or((0.0, 0.0, viewport.0, viewport.1));
+
+        let b = &mut boxes[index];
+        let (old_x, old_y) = (b.x, b.y);
+        match (b.inset.left, b.inset.right) {
+            (Some(left), Some(right)) => {
+                b.x = cx + left;
+                b.width = (cw - left - right).max(0.0);
+            }
+            (Some(left), None) => b.x = cx + left,
+            (None, Some(right)) => b.x = cx + cw - right - b.width,
+            (None, None) => {}
+        }
+        match (b.inset.top, b.inset.bottom) {
+            (Some(top), Some(bottom)) => {
+                b.y = cy + top;
+                b.height = (ch - top - bottom).max(0.0);
+            }
+            (Some(top), None) => b.y = cy + top,
+            (None, Some(bottom)) => b.y = cy + ch - bottom - b.height,
+            (None, None) => {}
+        }
+
+        let (dx, dy) = (b.x - old_x, b.y - old_y);
+        shift_descendants(boxes, index, dx, dy);
+    }
+}
+
+/// Shift `position: relative` boxes, with their descendants, from their place in the flow by
+/// `top`/`left` (or `bottom`/`right` when those are unset). The flow around them is unchanged.
+fn apply_relative_offsets(boxes: &mut [LayoutBox]) {
+    for index in 0..boxes.len() {
+        if boxes[index].position != "relative" {
+            continue;
+        }
+        let inset = &boxes[index].inset;
+        let dx = inset.left.or(inset.right.map(|right| -right)).unwrap_or(0.0);
+        let dy = inset.top.or(inset.bottom.map(|bottom| -bottom)).unwrap_or(0.0);
+        if dx == 0.0 && dy == 0.0 {
+            continue;
+        }
+        boxes[index].x += dx;
+        boxes[index].y += dy;
+        shift_descendants(boxes, index, dx, dy);
+    }
+}
+
+/// Move every box whose `parent_index` chain reaches `index`; descendants follow their ancestor
+fn shift_descendants(boxes: &mut [LayoutBox], index: usize, dx: f32, dy: f32) {
+    for inner in index + 1..boxes.len() {
+        let mut ancestor = boxes[inner].parent_index;
+        while let Some(parent) = ancestor.filter(|&parent| parent > index) {
+            ancestor = boxes[parent].parent_index;
+        }
+        if ancestor == Some(index) {
+            boxes[inner].x += dx;
+            boxes[inner].y += dy;
+        }
+    }
+}
+
+/// The `will-change` hint for the compositor; running animations count as changing
+fn compositing_hint(styles: &StyleMap) -> String {
+    let declared = styles.will_change.trim();
+    if (declared.is_empty() || declared == "auto") && !styles.animation.is_empty() && styles.animation != "none" {
+        return "animation".to_string();
+    }
+    declared.to_string()
+}
+
+/// Apply `white-space` to a run of text. `pre` and `pre-wrap` keep it as written; `pre-line`
+/// keeps newlines but collapses the spaces around them. Otherwise runs of spaces, tabs and
+/// newlines collapse to a single space, and leading whitespace is dropped at a line start.
+fn apply_white_space(text: &str, white_space: &str, at_line_start: bool) -> String {
+    match white_space {
+        _ if preserves_spaces(white_space) => text.to_string(),
+        "pre-line" => {
+            let lines: Vec<&str> = text.split('\n').collect();
+            let last = lines.len() - 1;
+            lines.iter().enumerate().map(|(i, line)| {
+                let collapsed = collapse_spaces(line, at_line_start || i > 0);
+                if i < last { collapsed.trim_end().to_string() } else { collapsed }
+            }).collect::<Vec<_>>().join("\n")
+        }
+        _ => collapse_spaces(text, at_line_start),
+    }
+}
+
+/// Apply `white-space` to text that fills a block of its own: collapsible space at both
+/// edges is dropped, while `pre` and `pre-wrap` keep the text exactly as written
+fn normalize_block_text(text: &str, white_space: &str) -> String {
+    if preserves_spaces(white_space) {
+        return text.to_string();
+    }
+    apply_white_space(text, white_space, true).trim_matches(' ').to_string()
+}
+
+fn preserves_spaces(white_space: &str) -> bool {
+    matches!(white_space, "pre" | "pre-wrap" | "break-spaces")
+}
+
+fn collapse_spaces(text: &str, at_line_start: bool) -> String {
+    let mut collapsed = String::with_capacity(text.len());
+    let mut pending_space = false;
+    for c in text.chars() {
+        if c.is_whitespace() {
+            pending_space = true;
+            continue;
+        }
+        if pending_space && !(at_line_start && collapsed.is_empty()) {
+            collapsed.push(' ');
+        }
+        pending_space = false;
+        collapsed.push(c);
+    }
+    if pending_space && !collapsed.is_empty() {
+        collapsed.push(' ');
+    }
+    collapsed
+}
+
+/// Split a run of text into the lines `white-space` produces: `pre-wrap` and `pre-line` break
+/// at preserved newlines, and every mode except `pre` and `nowrap` wraps to fit `max_width`.
+/// A `pre` run stays whole, newlines included, and is laid out as one box over several lines.
+fn white_space_lines(text: &str, white_space: &str, max_width: f32, font_size: f32) -> Vec<String> {
+    if white_space == "pre" {
+        return vec![text.to_string()];
+    }
+    let preserves_newlines = matches!(white_space, "pre-wrap" | "pre-line" | "break-spaces");
+    let wraps = !matches!(white_space, "pre" | "nowrap");
+    let max_chars = ((max_width / (font_size * 0.6)).floor() as usize).max(1);
+    let segments: Vec<&str> = if preserves_newlines { text.split('\n').collect() } else { vec![text] };
+    segments.into_iter().flat_map(|segment| {
+        if !wraps || segment.chars().count() <= max_chars {
+            vec![segment.to_string()]
+        } else if matches!(white_space, "pre-wrap" | "break-spaces") {
+            break_lines_keeping_spaces(segment, max_chars)
+        } else {
+            wrap_text(segment, max_width, font_size)
+        }
+    }).collect()
+}
+
+/// Wrap text that starts `offset` into a line of `max_width`: the first line takes the words
+/// that fit in the rest of that line, the others wrap at the full width. When not even the
+/// first word fits, all lines wrap at the full width and the caller starts a new line.
+fn wrap_text_from(text: &str, offset: f32, max_width: f32, font_size: f32) -> Vec<String> {
+    let remaining_chars = ((max_width - offset).max(0.0) / (font_size * 0.6)).floor() as usize;
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let (mut taken, mut used) = (0, 0);
+    while let Some(word) = words.get(taken) {
+        let needed = word.chars().count() + usize::from(taken > 0);
+        if used + needed > remaining_chars {
+            break;
+        }
+        used += needed;
+        taken += 1;
+    }
+    if taken == 0 {
+        return wrap_text(text, max_width, font_size);
+    }
+    let mut lines = vec![words[..taken].join(" ")];
+    lines.extend(wrap_text(&words[taken..].join(" "), max_width, font_size));
+    lines
+}
+
+/// Height of one line of text: a unitless `line-height` multiplies the font size, a length
+/// (`px`, `em`, `%` of the font size) is used as is, and `normal` is 1.2 times the font size
+fn line_box_height(line_height: &str, font_size: f32) -> f32 {
+    match line_height.trim().parse::<f32>() {
+        Ok(multiplier) => font_size * multiplier,
+        Err(_) => resolve_length(line_height, &LengthContext { percent_base: font_size, font_size, ..LengthContext::default() })
+            .unwrap_or(font_size * 1.2),
+    }
+}
+
+/// Like `wrap_text`, but spaces are kept as written and hang at the end of a wrapped line
+fn break_lines_keeping_spaces(text: &str, max_chars: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_inclusive(' ') {
+        if !line.trim().is_empty() && line.trim_end().chars().count() + word.trim_end().chars().count() > max_chars {
+            lines.push(std::mem::take(&mut line));
+        }
+        line.push_str(word);
+    }
+    lines.push(line);
+    lines
+}
+
+/// Whether the box sits on the line at `line_y`, allowing for rounding in the offsets
+fn on_line(b: &LayoutBox, line_y: f32) -> bool {
+    (b.y - line_y).abs() < 0.01
+}
+
+/// Apply pseudo-element declarations to a text box, rescaling it for a new font size.
+/// Returns how much wider the box became so the rest of the line can shift over.
+fn restyle_text_box(b: &mut LayoutBox, declarations: &HashMap<String, String>) -> f32 {
+    let old_width = b.width;
+    for (property, value) in declarations {
+        match property.as_str() {
+            "font-size" => {
+                let font_size = parse_px(value);
+                if font_size > 0.0 {
+                    // A zero-size box has no extent to scale from
+                    if b.font_size > 0.0 {
+                        b.width *= font_size / b.font_size;
+                        b.height *= font_size / b.font_size;
+                    }
+                    b.font_size = font_size;
+                }
+            }
+            "font-weight" => {
+                b.font_weight = match value.trim() {
+                    "bold" | "bolder" => 700.0,
+                    "normal" => 400.0,
+                    "lighter" => 300.0,
+                    other => other.parse().unwrap_or(b.font_weight),
+                }
+            }
+            "color" => b.color = value.clone(),
+            "background-color" | "background" => b.background_color = value.clone(),
+            "font-family" => b.font_family = value.clone(),
+            "font-style" => b.font_style = value.trim().to_string(),
+            _ => {}
+        }
+    }
+    b.width - old_width
+}
+
+/// Greedily break text at whitespace into lines no wider than `available_width`, measuring
+/// each character as 0.6 of `font_size`. A word longer than a whole line is split across lines.
+pub fn wrap_text(text: &str, available_width: f32, font_size: f32) -> Vec<String> {
+    let max_chars = ((available_width / (font_size * 0.6)).floor() as usize).max(1);
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let mut word: Vec<char> = word.chars().collect();
+        let line_len = line.chars().count();
+        if line_len > 0 && line_len + 1 + word.len() <= max_chars {
+            line.push(' ');
+            line.extend(word.iter());
+            continue;
+        }
+        if line_len > 0 {
+            lines.push(std::mem::take(&mut line));
+        }
+        while word.len() > max_chars {
+            lines.push(word.drain(..max_chars).collect());
+        }
+        line = word.into_iter().collect();
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// Cut a box's text so it ends at `right_edge`, replacing the last visible character with
+/// an ellipsis when requested. Text that already fits is left alone.
+fn truncate_text(b: &mut LayoutBox, right_edge: f32, ellipsis: bool) {
+    let char_width = b.font_size * 0.6;
+    let available = (right_edge - b.x - b.padding.left).max(0.0);
+    let max_chars = (available / char_width).floor() as usize;
+    if char_width <= 0.0 || b.text_content.chars().count() <= max_chars {
+        return;
+    }
+
+    let mut text: String = if ellipsis {
+        b.text_content.chars().take(max_chars.saturating_sub(1)).collect()
+    } else {
+        b.text_content.chars().take(max_chars).collect()
+    };
+    if ellipsis && max_chars > 0 {
+        text.push('…');
+    }
+    b.text_content = text;
+    b.width = b.width.min(right_edge - b.x).max(0.0);
+}
+
+/// Embedded-content elements that get a placeholder box
+fn is_embedded_tag(tag_name: &str) -> bool {
+    matches!(tag_name.to_lowercase().as_str(), "iframe" | "object" | "embed")
+}
+
+/// Properties an element takes from its parent unless it sets them itself; undeclared ones
+/// are left at `inherit` by the cascade
+const INHERITED_PROPERTIES: [&str; 9] = ["color", "font-family", "font-size", "font-style", "font-weight", "line-height", "text-align", "visibility", "white-space"];
+
+/// Elements laid out inline by default
+const INLINE_TAGS: [&str; 9] = ["span", "a", "strong", "em", "b", "i", "u", "code", "small"];
+
+/// Styles before any author CSS: phrasing elements are inline
+fn user_agent_styles(node: &DOMNode) -> StyleMap {
+    let mut styles = StyleMap::default();
+    if let NodeType::Element(tag) = &node.node_type {
+        if INLINE_TAGS.contains(&tag.to_lowercase().as_str()) {
+            styles.display = "inline".to_string();
+        }
+    }
+    styles
+}
+
+/// `env()` values for the given top, right, bottom and left safe-area insets in px
+fn safe_area_environment(insets: [f32; 4]) -> HashMap<String, String> {
+    ["top", "right", "bottom", "left"].into_iter().zip(insets)
+        .map(|(side, inset)| (format!("safe-area-inset-{}", side), format!("{}px", inset)))
+        .collect()
+}
+
+/// Text color used for input placeholders
+const PLACEHOLDER_COLOR: &str = "#757575";
+
+/// Content width (`horizontal`) or height for a specified `width`/`height`: under
+/// `box-sizing: border-box` the specified length includes padding and border
+fn content_length(styles: &StyleMap, length: f32, horizontal: bool, context: &LengthContext) -> f32 {
+    if !styles.box_sizing.trim().eq_ignore_ascii_case("border-box") {
+        return length;
+    }
+    let (padding, border) = (padding_box(styles, context), border_box(styles, context));
+    let edges = if horizontal {
+        padding.left + padding.right + border.left + border.right
+    } else {
+        padding.top + padding.bottom + border.top + border.bottom
+    };
+    (length - edges).max(0.0)
+}
+
+/// Margins from the `margin-*` longhands, which the `margin` shorthand expands into
+fn margin_box(styles: &StyleMap, context: &LengthContext) -> BoxValues {
+    box_sides([&styles.margin_top, &styles.margin_right, &styles.margin_bottom, &styles.margin_left], context)
+}
+
+fn padding_box(styles: &StyleMap, context: &LengthContext) -> BoxValues {
+    box_sides([&styles.padding_top, &styles.padding_right, &styles.padding_bottom, &styles.padding_left], context)
+}
+
+fn border_box(styles: &StyleMap, context: &LengthContext) -> BoxValues {
+    box_sides([&styles.border_top_width, &styles.border_right_width, &styles.border_bottom_width, &styles.border_left_width], context)
+}
+
+/// Resolve the four sides; `%` is against the containing block's width on every side, as in CSS
+fn box_sides([top, right, bottom, left]: [&String; 4], context: &LengthContext) -> BoxValues {
+    let side = |value: &String| resolve_length(value, context).unwrap_or(0.0);
+    BoxValues { top: side(top), right: side(right), bottom: side(bottom), left: side(left) }
+}
+
+/// Resolve the inherited properties still `inherit`, which `match_declared_styles` leaves every
+/// undeclared one at, from the parent's computed styles, or their initial values at the root
+fn inherit_from_parent(parent_styles: Option<&StyleMap>, styles: &mut StyleMap) {
+    let initial = StyleMap::default();
+    let parent_styles = parent_styles.unwrap_or(&initial);
+    for property in INHERITED_PROPERTIES {
+        if styles.get_property(property) == Some("inherit") {
+            let inherited = parent_styles.get_property(property).unwrap_or("").to_string();
+            styles.set_property(property, &inherited);
+        }
+    }
+}
+
+/// An element's computed font size; `get_node_styles` has already resolved it to px
+fn font_size_px(styles: &StyleMap) -> f32 {
+    resolve_length(&styles.font_size, &LengthContext::default()).unwrap_or(LengthContext::default().root_font_size)
+}
+
+/// `background-image`, falling back to the image in the first layer of the `background`
+/// shorthand, which may sit among colors, positions and repeat keywords
+fn background_image(styles: &StyleMap) -> String {
+    if !styles.background_image.is_empty() && styles.background_image != "none" {
+        return styles.background_image.clone();
+    }
+    split_outside_parens(split_top_level_commas(&styles.background)[0])
+        .into_iter()
+        .find(|token| token.contains("gradient(") || token.starts_with("url("))
+        .map_or_else(|| styles.background_image.clone(), str::to_string)
+}
+
+/// A `<td>`/`<th>` placed on the table grid by `place_table_cells`
+struct TableCell {
+    node: DOMNode,
+    styles: StyleMap,
+    text: String,
+    row: usize,
+    column: usize,
+    colspan: usize,
+    rowspan: usize,
+}
+
+/// `colspan` or `rowspan`: at least 1, and capped like browsers cap them
+fn span_attribute(node: &DOMNode, name: &str) -> usize {
+    node.attributes.get(name).and_then(|value| value.trim().parse::<usize>().ok()).unwrap_or(1).clamp(1, 1000)
+}
+
+/// Parse a single length such as `4px` or `4`, treating anything else as 0
+fn parse_px(value: &str) -> f32 {
+    value.trim().trim_end_matches("px").parse().unwrap_or(0.0)
+}
+
+/// Parse `border-spacing`; only the horizontal value is used for now
+fn parse_spacing(value: &str) -> f32 {
+    value.split_whitespace().next().map(parse_px).unwrap_or(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::html::HTMLParser;
+    use crate::parser::css::parse_css;
+    use crate::paint::display_list::DrawCommand;
+
+    fn rect(x: f32, y: f32, width: f32, height: f32) -> LayoutBox {
+        let mut b = LayoutBox::new();
+        b.x = x;
+        b.y = y;
+        b.width = width;
+        b.height = height;
+        b
+    }
+
+    fn layout_html(html: &str) -> Vec<LayoutBox> {
+        let mut parser = HTMLParser::new(html.to_string()).with_reuse();
+        let dom = parser.parse();
+        LayoutEngine::new(800.0, 600.0).layout(&dom, parser.arena().unwrap())
+    }
+
+    #[test]
+    fn test_input_renders_value_or_placeholder() {
+        let boxes = layout_html(r#"<body><input value="hi"><input placeholder="name"><input type="password" value="abc"><input type="hidden" value="x"></body>"#);
+        let inputs: Vec<&LayoutBox> = boxes.iter().filter(|b| b.node_type == "input").collect();
+
+        assert_eq!(inputs.len(), 3, "hidden input must not produce a box");
+        assert_eq!(inputs[0].text_content, "hi");
+        assert_eq!(inputs[1].text_content, "name");
+        assert_eq!(inputs[1].color, PLACEHOLDER_COLOR);
+        assert_eq!(inputs[2].text_content, "•••");
+        assert!(inputs[1].x > inputs[0].x);
+    }
+
+    #[test]
+    fn test_whitespace_collapses_in_normal_flow() {
+        let text_of = |html: &str| {
+            layout_html(html).into_iter().find(|b| b.node_type == "text").map(|b| b.text_content).unwrap()
+        };
+
+        assert_eq!(text_of("<body><p>a   b\n\tc</p></body>"), "a b c");
+        assert_eq!(text_of("<body><p style=\"white-space:pre\">a   b\n\tc</p></body>"), "a   b\n\tc");
+    }
+
+    #[test]
+    fn test_white_space_modes_break_lines() {
+        let lines_of = |white_space: &str, text: &str| -> Vec<(String, f32)> {
+            let boxes = layout_html(&format!("<body><p style=\"white-space:{}\">{}</p></body>", white_space, text));
+            boxes.into_iter().filter(|b| b.node_type == "text").map(|b| (b.text_content, b.y)).collect()
+        };
+        let long = "word  ".repeat(30);
+
+        let pre_wrap = lines_of("pre-wrap", &long);
+        assert!(pre_wrap.len() > 1, "long pre-wrap text wraps");
+        assert!(pre_wrap[0].0.contains("word  word"), "pre-wrap keeps double spaces");
+        assert!(pre_wrap[1].1 > pre_wrap[0].1);
+
+        let pre_line = lines_of("pre-line", "a   b\nc");
+        assert_eq!(pre_line.iter().map(|(t, _)| t.as_str()).collect::<Vec<_>>(), ["a b", "c"]);
+        assert!(pre_line[1].1 > pre_line[0].1, "pre-line breaks on an explicit newline");
+
+        let normal = lines_of("normal", "a   b\nc");
+        assert_eq!(normal.iter().map(|(t, _)| t.as_str()).collect::<Vec<_>>(), ["a b c"]);
+        assert!(lines_of("pre", &long).len() == 1, "pre never wraps");
+    }
+
+    #[test]
+    fn test_indented_source_collapses_unless_preserved() {
+        let source = "\n    Hello   there\n    <b>bold</b>   world\n  ";
+        let layout = |white_space: &str| {
+            layout_html(&format!("<body><div style=\"white-space:{}\">{}</div><p>next</p></body>", white_space, source))
+        };
+        let texts = |boxes: &[LayoutBox]| boxes.iter().filter(|b| b.node_type == "text").map(|b| b.text_content.clone()).collect::<Vec<_>>();
+
+        let normal = layout("normal");
+        assert_eq!(normal.iter().find(|b| b.node_type == "div").unwrap().text_content, "Hello there bold world");
+        assert_eq!(texts(&normal), ["Hello there ", "bold", " world", "next"]);
+
+        let pre = layout("pre");
+        assert_eq!(pre.iter().find(|b| b.node_type == "div").unwrap().text_content, source.replace("<b>", "").replace("</b>", ""));
+        // Each run keeps its newlines in one box; `bold` continues the run's last row
+        assert_eq!(texts(&pre), ["\n    Hello   there\n    ", "bold", "   world\n  ", "next"]);
+        let text_box = |content: &str| pre.iter().find(|b| b.node_type == "text" && b.text_content.contains(content)).unwrap().clone();
+        let (hello, bold) = (text_box("Hello"), text_box("bold"));
+        let line = text_box("next").height;
+        assert!((hello.height - 3.0 * line).abs() < 0.01, "the first run spans three rows");
+        assert!((bold.y - (hello.y + 2.0 * line)).abs() < 0.01);
+
+        // A declared `normal` collapses again inside a `pre` parent
+        let reset = layout_html("<body><div style=\"white-space:pre\"><p style=\"white-space:normal\">a   b</p><p>c   d</p></div></body>");
+        assert_eq!(texts(&reset), ["a b", "c   d"]);
+    }
+
+    #[test]
+    fn test_restyling_a_zero_size_text_box_keeps_it_finite() {
+        let mut b = rect(0.0, 0.0, 0.0, 0.0);
+        b.font_size = 0.0;
+        let shift = restyle_text_box(&mut b, &HashMap::from([("font-size".to_string(), "20px".to_string())]));
+        assert_eq!((shift, b.width, b.height, b.font_size), (0.0, 0.0, 0.0, 20.0));
+    }
+
+    #[test]
+    fn test_first_letter_and_first_line() {
+        let styled = |css: &str, body: &str| {
+            let mut parser = HTMLParser::new(format!("<body>{}</body>", body)).with_reuse();
+            let dom = parser.parse();
+            let engine = LayoutEngine::new(800.0, 600.0).with_stylesheet(parse_css(css));
+            let boxes = engine.layout(&dom, parser.arena().unwrap());
+            boxes.into_iter().filter(|b| b.node_type == "text").collect::<Vec<_>>()
+        };
+
+        let text = styled("p::first-letter{font-size:32px}", "<p>Hello</p>");
+        assert_eq!(text.len(), 2);
+        assert_eq!((text[0].text_content.as_str(), text[0].font_size), ("H", 32.0));
+        assert_eq!((text[1].text_content.as_str(), text[1].font_size), ("ello", 16.0));
+        assert_eq!(text[1].x, text[0].x + text[0].width);
+
+        let body = format!("<p>{}<em></em>{}</p>", "a".repeat(60), "b".repeat(60));
+        let text = styled("p::first-line{font-weight:bold}", &body);
+        assert_eq!(text.len(), 2);
+        assert!(text[1].y > text[0].y, "second run should wrap onto the next line");
+        assert_eq!(text[0].font_weight, 700.0);
+        assert_eq!(text[1].font_weight, 400.0);
+    }
+
+    #[test]
+    fn test_text_overflow_ellipsis_and_clip() {
+        let label = "A very long label that cannot fit";
+        let text_of = |text_overflow: &str| {
+            let html = format!(r#"<body><div style="width:96px;overflow:hidden;white-space:nowrap;text-overflow:{}">{}</div></body>"#, text_overflow, label);
+            let boxes = layout_html(&html);
+            let text = boxes.iter().find(|b| b.node_type == "text").unwrap();
+            assert!(text.x + text.width <= 96.0);
+            text.text_content.clone()
+        };
+
+        // 96px at 16px * 0.6 per character fits 10 characters
+        assert_eq!(text_of("ellipsis"), "A very lo…");
+        assert_eq!(text_of("clip"), "A very lon");
+    }
+
+    #[test]
+    fn test_line_clamp_keeps_two_lines() {
+        let boxes = layout_html(r#"<body><div style="width:96px;line-clamp:2">one two three four five six</div></body>"#);
+        let lines: Vec<&LayoutBox> = boxes.iter().filter(|b| b.node_type == "text").collect();
+
+        // 96px at 16px * 0.6 per character fits 10 characters per line
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].text_content, "one two");
+        assert_eq!(lines[1].text_content, "three fou…");
+        assert!(lines[1].y > lines[0].y);
+        assert!(lines[1].x + lines[1].width <= 96.0);
+    }
+
+    #[test]
+    fn test_anchor_wrapping_block_covers_it() {
+        let boxes = layout_html(r#"<body><span>before</span><a href="/post"><div>x</div></a></body>"#);
+        let span = boxes.iter().find(|b| b.node_type == "span").unwrap();
+        let link = boxes.iter().find(|b| b.node_type == "a").unwrap();
+        let div = boxes.iter().find(|b| b.node_type == "div").unwrap();
+
+        // The div starts a new line at full block width instead of flowing inline
+        assert_eq!(div.x, 0.0);
+        assert!(div.y >= span.y + span.height);
+        assert_eq!(div.width, 720.0);
+        assert!(link.x <= div.x && link.y <= div.y);
+        assert!(link.x + link.width >= div.x + div.width && link.y + link.height >= div.y + div.height);
+    }
+
+    #[test]
+    fn test_inline_image_sits_on_text_baseline() {
+        let boxes = layout_html(r#"<body><span>Logo</span><img src="logo.png" width="40" height="50"> here</body>"#);
+        let image = boxes.iter().find(|b| b.node_type == "img").unwrap();
+        let text = boxes.iter().find(|b| b.node_type == "text" && b.text_content.contains("here")).unwrap();
+
+        // Text baseline: 0.8em ascent plus half the leading below the text box top
+        let baseline = text.y + text.font_size * 0.9;
+        assert!((image.y + image.height - baseline).abs() < 0.01);
+        assert!(image.y + image.height > text.y, "the image bottom is below the text top, not level with it");
+        assert!(image.y < text.y && text.y > 0.0, "the taller image pushes the text down to its bottom edge");
+        assert!(!image.embedded);
+
+        let default_sized = layout_html(r#"<body><img src="logo.png"></body>"#);
+        let default_sized = default_sized.iter().find(|b| b.node_type == "img").unwrap();
+        assert_eq!((default_sized.width, default_sized.height), (300.0, 150.0), "an unsized image gets the replaced-element default");
+    }
+
+    #[test]
+    fn test_id_rule_beats_later_type_rule() {
+        let mut parser = HTMLParser::new(r#"<body><div id="hero">Hi</div><div>Other</div></body>"#.to_string()).with_reuse();
+        let dom = parser.parse();
+        let css = "#hero { color: blue } div { color: red; width: 200px } div { width: 300px }";
+        let boxes = LayoutEngine::new(800.0, 600.0).with_stylesheet(parse_css(css)).layout(&dom, parser.arena().unwrap());
+        let divs: Vec<&LayoutBox> = boxes.iter().filter(|b| b.node_type == "div").collect();
+
+        assert_eq!(divs[0].color, "blue", "higher specificity wins despite coming first");
+        assert_eq!(divs[1].color, "red");
+        assert_eq!(divs[0].width, 300.0, "source order breaks specificity ties");
+    }
+
+    #[test]
+    fn test_layout_cache_recomputes_only_changed_subtree() {
+        let mut parser = HTMLParser::new(r#"<body><section id="a"><p>First</p></section><section id="b"><p>Second</p></section></body>"#.to_string()).with_reuse();
+        let dom = parser.parse();
+        let engine = LayoutEngine::new(800.0, 600.0).with_layout_cache();
+        engine.layout(&dom, parser.arena().unwrap());
+        let before = engine.cache_stats();
+
+        let arena = parser.arena_mut().unwrap();
+        let second = arena.nodes.values().find(|node| node.lock().unwrap().text_content == "Second").unwrap().clone();
+        second.lock().unwrap().text_content = "Second, now longer".to_string();
+        let boxes = engine.layout(&dom, parser.arena().unwrap());
+        let after = engine.cache_stats();
+
+        assert_eq!(after.hits - before.hits, 1, "section#a is served from the cache");
+        assert_eq!(after.recomputed - before.recomputed, 4, "only body, section#b, its p and the text are laid out");
+        let fresh = LayoutEngine::new(800.0, 600.0).layout(&dom, parser.arena().unwrap());
+        let geometry = |boxes: &[LayoutBox]| boxes.iter().map(|b| (b.x, b.y, b.width, b.height, b.text_content.clone())).collect::<Vec<_>>();
+        assert_eq!(geometry(&boxes), geometry(&fresh));
+    }
+
+    #[test]
+    fn test_style_cache_reuses_matches_until_attributes_change() {
+        let mut parser = HTMLParser::new(r#"<body><section><p id="a">First</p><p id="b">Second</p></section></body>"#.to_string()).with_reuse();
+        let dom = parser.parse();
+        let css = parse_css("p { color: red } .highlight { color: blue }");
+        let engine = LayoutEngine::new(800.0, 600.0).with_stylesheet(css);
+        engine.layout(&dom, parser.arena().unwrap());
+        let first_pass = engine.style_recomputes();
+        engine.layout(&dom, parser.arena().unwrap());
+        assert_eq!(engine.style_recomputes(), first_pass, "an unchanged tree is served from the cache");
+
+        let arena = parser.arena_mut().unwrap();
+        let second = arena.nodes.values().find(|node| node.lock().unwrap().attributes.get("id").is_some_and(|id| id == "b")).unwrap().clone();
+        second.lock().unwrap().attributes.insert("class".to_string(), "highlight".to_string());
+        let boxes = engine.layout(&dom, parser.arena().unwrap());
+
+        assert_eq!(engine.style_recomputes() - first_pass, 2, "only p#b and its text are matched again");
+        let colors: Vec<&str> = boxes.iter().filter(|b| b.node_type == "p").map(|b| b.color.as_str()).collect();
+        assert_eq!(colors, ["red", "blue"]);
+    }
+
+    #[test]
+    fn test_style_cache_survives_clones_given_the_same_stylesheet() {
+        let mut parser = HTMLParser::new(r#"<body><p id="a">First</p><p id="b">Second</p></body>"#.to_string()).with_reuse();
+        let dom = parser.parse();
+        let engine = LayoutEngine::new(800.0, 600.0);
+        // Each render gives a clone of the long-lived engine that render's stylesheet
+        engine.clone().with_stylesheet(parse_css("p { color: red }")).layout(&dom, parser.arena().unwrap());
+        let first_pass = engine.style_recomputes();
+        engine.clone().with_stylesheet(parse_css("p { color: red }")).layout(&dom, parser.arena().unwrap());
+        assert_eq!(engine.style_recomputes(), first_pass, "the same stylesheet reuses the shared cache");
+
+        let boxes = engine.clone().with_stylesheet(parse_css("p { color: blue }")).layout(&dom, parser.arena().unwrap());
+        assert_eq!(engine.style_recomputes() - first_pass, first_pass, "a changed stylesheet matches every node again");
+        assert!(boxes.iter().filter(|b| b.node_type == "p").all(|b| b.color == "blue"));
+    }
+
+    #[test]
+    fn test_style_cache_drops_nodes_gone_from_the_tree() {
+        let mut parser = HTMLParser::new(r#"<body><section><p id="a">First</p><p id="b">Second</p></section></body>"#.to_string()).with_reuse();
+        let dom = parser.parse();
+        let css = parse_css("p::before { content: \"> \" }");
+        let engine = LayoutEngine::new(800.0, 600.0).with_stylesheet(css);
+        engine.layout(&dom, parser.arena().unwrap());
+        let entries = engine.style_cache.len();
+        engine.layout(&dom, parser.arena().unwrap());
+        assert_eq!(engine.style_cache.len(), entries, "generated content runs aren't kept between passes");
+
+        let arena = parser.arena_mut().unwrap();
+        let second = arena.nodes.values().find(|node| node.lock().unwrap().attributes.get("id").is_some_and(|id| id == "b")).unwrap().clone();
+        let (second_id, section_id) = {
+            let second = second.lock().unwrap();
+            (second.id.clone(), second.parent.clone().unwrap())
+        };
+        arena.get_node(&section_id).unwrap().lock().unwrap().children.retain(|id| *id != second_id);
+        engine.layout(&dom, parser.arena().unwrap());
+
+        assert!(engine.style_cache.len() < entries, "p#b and its text are dropped");
+    }
+
+    #[test]
+    fn test_table_cells_align_on_grid() {
+        let boxes = layout_html("<body><table><tr><td>a</td><td>b</td></tr><tr><td>a much wider cell</td><td>c</td></tr></table></body>");
+        let cells: Vec<&LayoutBox> = boxes.iter().filter(|b| b.node_type == "td").collect();
+        assert_eq!(cells.len(), 4);
+        assert_eq!(cells[0].x, cells[2].x);
+        assert_eq!(cells[1].x, cells[3].x);
+        assert_eq!(cells[0].y, cells[1].y);
+        assert_eq!(cells[2].y, cells[3].y);
+        assert!(cells[2].y > cells[0].y);
+        assert_eq!(cells[0].width, cells[2].width, "a column is as wide as its widest cell");
+        assert!(cells[0].width > cells[1].width);
+
+        let spans = layout_html(r#"<body><table><tr><td colspan="2">header</td><td rowspan="2">side</td></tr><tr><td>x</td><td>y</td></tr></table></body>"#);
+        let cell = |text: &str| spans.iter().find(|b| b.node_type == "td" && b.text_content == text).unwrap();
+        assert_eq!(cell("header").x, cell("x").x);
+        assert_eq!(cell("header").x + cell("header").width, cell("y").x + cell("y").width);
+        assert_eq!(cell("side").x, cell("y").x + cell("y").width);
+        assert!((cell("side").y + cell("side").height - (cell("y").y + cell("y").height)).abs() < 0.01, "rowspan covers both rows");
+    }
+
+    #[test]
+    fn test_text_flows_beside_left_float_and_clear_drops_below() {
+        let boxes = layout_html(r#"<body><div id="f" style="float:left;width:100px;height:100px"></div><p>beside the float</p><div id="c" style="clear:both;height:20px">below</div></body>"#);
+        let float = boxes.iter().find(|b| b.node_type == "div" && b.width == 100.0).unwrap();
+        let text = boxes.iter().find(|b| b.node_type == "text" && b.text_content == "beside the float").unwrap();
+        assert!(text.y < float.y + float.height, "the text sits beside the float: {} vs {}", text.y, float.y);
+        assert!(text.x >= 100.0, "text starts past the float at x={}", text.x);
+
+        let cleared = boxes.iter().find(|b| b.node_type == "div" && b.text_content == "below").unwrap();
+        assert!(cleared.y >= float.y + float.height, "clear:both drops below the float: {} vs {}", cleared.y, float.y + float.height);
+
+        let right = layout_html(r#"<body><img style="float:right;width:50px;height:50px"><span>x</span></body>"#);
+        let image = right.iter().find(|b| b.node_type == "img").unwrap();
+        assert_eq!(image.x + image.width, 800.0 * 0.9);
+    }
+
+    #[test]
+    fn test_set_viewport_resolves_relative_widths_against_new_size() {
+        let mut parser = HTMLParser::new(r#"<body><div id="half" style="width:50%">a</div><p style="width:25vw">b</p></body>"#.to_string()).with_reuse();
+        let dom = parser.parse();
+        let mut engine = LayoutEngine::new(800.0, 600.0).with_layout_cache();
+        let widths = |engine: &LayoutEngine| {
+            let boxes = engine.layout(&dom, parser.arena().unwrap());
+            let width_of = |tag: &str| boxes.iter().find(|b| b.node_type == tag).unwrap().width;
+            (width_of("div"), width_of("p"))
+        };
+        let wide = widths(&engine);
+        engine.set_viewport(400.0, 600.0);
+        let narrow = widths(&engine);
+
+        assert_eq!(engine.viewport_width(), 400.0);
+        assert_eq!(narrow.1, 100.0);
+        assert_eq!(wide.1, 200.0);
+        // 50% of the body, whose auto width is 90% of the viewport
+        assert_eq!(wide.0, 360.0);
+        assert_eq!(narrow.0, 180.0);
+    }
+
+    #[test]
+    fn test_lengths_resolve_against_containing_block_and_own_font_size() {
+        let boxes = layout_html(r#"<body><section style="width:400px; font-size:20px"><article style="width:50%; margin-left:10%; font-size:1.5em; padding-left:1em">a</article></section></body>"#);
+        let article = boxes.iter().find(|b| b.node_type == "article").unwrap();
+
+        assert_eq!(article.font_size, 30.0);
+        assert_eq!(article.padding.left, 30.0);
+        assert_eq!(article.margin.left, 40.0);
+        assert_eq!(article.width, 200.0 + 30.0);
+    }
+
+    #[test]
+    fn test_percent_heights_resolve_against_containing_block_height() {
+        let boxes = layout_html(r#"<body><section style="height:200px"><p style="height:50%">a</p></section><p style="height:50%; min-height:10%">b</p></body>"#);
+        let heights: Vec<f32> = boxes.iter().filter(|b| b.node_type == "p").map(|b| b.height).collect();
+
+        // The second p's containing block is the auto-height body, so its `%` heights act as `auto`
+        assert_eq!(heights, vec![100.0, 20.0]);
+    }
+
+    #[test]
+    fn test_inline_style_beats_normal_rules_but_not_important_ones() {
+        let html = r#"<body><div style="color:red; width: 50px">a</div><p style="color:red">b</p></body>"#;
+        let mut parser = HTMLParser::new(html.to_string()).with_reuse();
+        let dom = parser.parse();
+        let css = parse_css("div { color: blue; width: 300px } #none, p { color: green !important }");
+        let boxes = LayoutEngine::new(800.0, 600.0).with_stylesheet(css).layout(&dom, parser.arena().unwrap());
+
+        let div = boxes.iter().find(|b| b.node_type == "div").unwrap();
+        assert_eq!((div.color.as_str(), div.width), ("red", 50.0));
+        assert_eq!(boxes.iter().find(|b| b.node_type == "p").unwrap().color, "green");
+    }
+
+    #[test]
+    fn test_color_and_font_inherit_down_the_tree() {
+        let mut parser = HTMLParser::new(r#"<body><div><p>Intro <span>nested</span></p></div></body>"#.to_string()).with_reuse();
+        let dom = parser.parse();
+        let css = parse_css("body { color: red; font-family: Georgia } p { font-size: 20 }");
+        let boxes = LayoutEngine::new(800.0, 600.0).with_stylesheet(css).layout(&dom, parser.arena().unwrap());
+        let span = boxes.iter().find(|b| b.node_type == "span").unwrap();
+        let text = boxes.iter().find(|b| b.node_type == "text" && b.text_content.contains("Intro")).unwrap();
+
+        assert_eq!(span.color, "red");
+        assert_eq!(span.font_family, "Georgia");
+        assert_eq!(span.font_size, 20.0);
+        assert_eq!((text.color.as_str(), text.font_size), ("red", 20.0));
+    }
+
+    #[test]
+    fn test_declared_initial_value_is_not_inherited_over() {
+        let mut parser = HTMLParser::new(r#"<body><p>a <span>b</span></p></body>"#.to_string()).with_reuse();
+        let dom = parser.parse();
+        let css = parse_css("p { color: red; font-size: 20 } span { color: black; font-size: 16 }");
+        let boxes = LayoutEngine::new(800.0, 600.0).with_stylesheet(css).layout(&dom, parser.arena().unwrap());
+        let span = boxes.iter().find(|b| b.node_type == "span").unwrap();
+
+        assert_eq!((span.color.as_str(), span.font_size), ("black", 16.0));
+    }
+
+    #[test]
+    fn test_important_beats_higher_specificity() {
+        let mut parser = HTMLParser::new(r#"<body><div id="hero">Hi</div></body>"#.to_string()).with_reuse();
+        let dom = parser.parse();
+        let css = parse_css("div { color: red !important } #hero { color: blue }");
+        assert!(css.rules.iter().any(|rule| rule.is_important("color") && rule.declarations["color"] == "red"));
+        let boxes = LayoutEngine::new(800.0, 600.0).with_stylesheet(css).layout(&dom, parser.arena().unwrap());
+        let hero = boxes.iter().find(|b| b.node_type == "div").unwrap();
+
+        assert_eq!(hero.color, "red", "an important type rule beats a normal id rule");
+    }
+
+    #[test]
+    fn test_env_safe_area_insets() {
+        let mut parser = HTMLParser::new(r#"<body><header>Title</header></body>"#.to_string()).with_reuse();
+        let dom = parser.parse();
+        let css = parse_css("header { padding-top: env(safe-area-inset-top, 4px) }");
+        let header_padding = |engine: LayoutEngine| {
+            let boxes = engine.with_stylesheet(css.clone()).layout(&dom, parser.arena().unwrap());
+            boxes.iter().find(|b| b.node_type == "header").unwrap().padding.top
+        };
+
+        assert_eq!(header_padding(LayoutEngine::new(800.0, 600.0).with_safe_area_insets(20.0, 0.0, 0.0, 0.0)), 20.0);
+        assert_eq!(header_padding(LayoutEngine::new(800.0, 600.0)), 0.0, "unconfigured insets are 0");
+
+        // Only an unknown name falls back; without a fallback the declaration computes to the initial value
+        let padding_for = |css: &str, style: &str| {
+            let mut parser = HTMLParser::new(format!(r#"<body><header style="{}">Title</header></body>"#, style)).with_reuse();
+            let dom = parser.parse();
+            let boxes = LayoutEngine::new(800.0, 600.0).with_stylesheet(parse_css(css)).layout(&dom, parser.arena().unwrap());
+            boxes.iter().find(|b| b.node_type == "header").unwrap().padding.top
+        };
+        assert_eq!(padding_for("header { padding-top: env(no-such-inset, 4px) }", ""), 4.0);
+        assert_eq!(padding_for("header { padding-top: 8px } body header { padding-top: env(no-such-inset) }", ""), 0.0);
+        assert_eq!(padding_for("header { padding-top: 8px }", "padding-top: env(no-such-inset)"), 0.0);
+    }
+
+    #[test]
+    fn test_iframe_reserves_placeholder_box() {
+        let boxes = layout_html(r#"<body><iframe src="/embed" width="300" height="150"><p>fallback</p></iframe><p>after</p></body>"#);
+        let frame = boxes.iter().find(|b| b.node_type == "iframe").unwrap();
+        let after = boxes.iter().find(|b| b.node_type == "p").unwrap();
+
+        assert!(frame.embedded);
+        assert_eq!((frame.width, frame.height), (300.0, 150.0));
+        assert!(after.y >= frame.y + frame.height, "following content flows below the iframe");
+        assert!(!boxes.iter().any(|b| b.text_content.contains("fallback")));
+    }
+
+    #[test]
+    fn test_inset_positions_absolute_box() {
+        let boxes = layout_html(r#"<body>
+            <div id="outer" style="position:relative;width:200px;height:100px"><div id="fill" style="position:absolute;inset:0"></div></div>
+            <div id="inner" style="position:relative;width:200px;height:100px"><div id="pad" style="position:absolute;inset:10px 20px"></div></div>
+        </body>"#);
+        let rect = |b: &LayoutBox| (b.x, b.y, b.width, b.height);
+        let divs: Vec<&LayoutBox> = boxes.iter().filter(|b| b.node_type == "div").collect();
+        let (outer, fill, inner, pad) = (divs[0], divs[1], divs[2], divs[3]);
+
+        assert_eq!(rect(fill), rect(outer));
+        assert_eq!(pad.inset, Inset { top: Some(10.0), right: Some(20.0), bottom: Some(10.0), left: Some(20.0) });
+        assert_eq!(rect(pad), (inner.x + 20.0, inner.y + 10.0, 160.0, 80.0));
+    }
+
+    #[test]
+    fn test_italic_inherits_into_text_commands() {
+        let boxes = layout_html(r#"<body><div style="font-style:italic">slanted<p>also slanted</p><p style="--font-style-hint:none">still slanted</p><p style="font-style:normal">upright</p></div></body>"#);
+        let italic_text: Vec<(String, bool)> = Painter::from_layout_boxes(&boxes).into_iter()
+            .filter_map(|command| match command {
+                DrawCommand::Text { content, italic, .. } => Some((content, italic)),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(italic_text, vec![
+            ("slanted".to_string(), true),
+            ("also slanted".to_string(), true),
+            ("still slanted".to_string(), true),
+            ("upright".to_string(), false),
+        ]);
+    }
+
+    #[test]
+    fn test_media_query_applies_below_breakpoint() {
+        let css = ".sidebar { color: blue } @media (max-width: 600px) { .sidebar { display: none } }";
+        let mut parser = HTMLParser::new(r#"<body><div class="sidebar">Links</div></body>"#.to_string()).with_reuse();
+        parser.parse();
+        let arena = parser.arena().unwrap();
+        let sidebar = arena.nodes.values()
+            .map(|node| node.lock().unwrap().clone())
+            .find(|node| node.attributes.get("class").map(String::as_str) == Some("sidebar"))
+            .unwrap();
+
+        let narrow = LayoutEngine::new(480.0, 800.0).with_stylesheet(parse_css(css));
+        let wide = LayoutEngine::new(1024.0, 800.0).with_stylesheet(parse_css(css));
+        assert_eq!(narrow.get_node_styles(&sidebar, arena).display, "none");
+        assert_eq!(wide.get_node_styles(&sidebar, arena).display, "block");
+        assert_eq!(wide.get_node_styles(&sidebar, arena).color, "blue");
+    }
+
+    #[test]
+    fn test_counters_number_generated_content() {
+        let mut parser = HTMLParser::new(r#"<body>
+            <h2>Intro</h2><h2>Usage</h2>
+            <ol><li>a</li><li>b<ol><li>c</li><li>d</li></ol></li><li>e</li></ol>
+        </body>"#.to_string()).with_reuse();
+        let dom = parser.parse();
+        let css = r#"h2 { counter-increment: h2 } h2::before { content: counter(h2) ". " }
+            ol { counter-reset: item } li { counter-increment: item } li::before { content: counters(item, ".") }"#;
+        let boxes = LayoutEngine::new(800.0, 600.0).with_stylesheet(parse_css(css)).layout(&dom, parser.arena().unwrap());
+        let generated: Vec<&str> = boxes.iter().filter(|b| b.node_type == "::before").map(|b| b.text_content.trim()).collect();
+
+        // The nested list restarts at 1 and resumes the outer count afterwards
+        assert_eq!(generated, vec!["1.", "2.", "1", "2", "2.1", "2.2", "3"]);
+        let first = boxes.iter().position(|b| b.node_type == "::before").unwrap();
+        assert_eq!(boxes[boxes[first].parent_index.unwrap()].node_type, "h2");
+    }
+
+    #[test]
+    fn test_cancelled_layout_stops_early() {
+        let html = format!("<body>{}</body>", "<div><p>row</p></div>".repeat(1000));
+        let mut parser = HTMLParser::new(html).with_reuse();
+        let dom = parser.parse();
+        let arena = parser.arena().unwrap();
+
+        let full = LayoutEngine::new(800.0, 600.0).layout(&dom, arena);
+        let token = CancellationToken::new();
+        let engine = LayoutEngine::new(800.0, 600.0).with_cancellation(token.clone());
+        token.cancel();
+
+        let partial = engine.layout(&dom, arena);
+        assert!(partial.len() < full.len(), "layout should stop before visiting every node");
+        assert_eq!(engine.try_layout(&dom, arena).err(), Some(RiftError::Cancelled));
+    }
+
+    #[test]
+    fn test_hit_test_skips_pointer_events_none() {
+        let mut overlay = rect(0.0, 0.0, 200.0, 200.0);
+        overlay.pointer_events = "none".to_string();
+        let boxes = vec![rect(0.0, 0.0, 400.0, 400.0), rect(50.0, 50.0, 100.0, 100.0), overlay];
+
+        assert_eq!(LayoutEngine::hit_test(&boxes, 60.0, 60.0), Some(1));
+        assert_eq!(LayoutEngine::hit_test(&boxes, 10.0, 10.0), Some(0));
+        assert_eq!(LayoutEngine::hit_test(&boxes, 500.0, 500.0), None);
+
+        // A z-indexed box wins over a later sibling it overlaps
+        let mut raised = rect(0.0, 0.0, 100.0, 100.0);
+        raised.position = "relative".to_string();
+        raised.z_index = ZIndex::parse("5");
+        let mut sibling = rect(50.0, 50.0, 100.0, 100.0);
+        (raised.parent_index, sibling.parent_index) = (Some(0), Some(0));
+        let boxes = vec![rect(0.0, 0.0, 400.0, 400.0), raised, sibling];
+        assert_eq!(LayoutEngine::hit_test(&boxes, 60.0, 60.0), Some(1));
+        assert_eq!(LayoutEngine::hit_test(&boxes, 120.0, 120.0), Some(2));
+    }
+
+    #[test]
+    fn advanced_layout_visits_every_node_of_a_deep_tree() {
+        let html = "<body>".to_string() + &"<div>level text".repeat(40) + &"</div>".repeat(40) + "</body>";
+        let mut parser = HTMLParser::new(html).with_reuse();
+        let dom = parser.parse();
+        let arena = parser.arena().unwrap();
+        let texts: Vec<String> = arena.nodes.values()
+            .map(|node| node.lock().unwrap().clone())
+            .filter(|node| node.node_type == NodeType::Text)
+            .map(|node| node.id)
+            .collect();
+        assert_eq!(texts.len(), 40);
+
+        let boxes = LayoutEngine::new(800.0, 600.0).layout_advanced(&dom, arena);
+        for id in &texts {
+            assert!(boxes.iter().any(|b| b.node_type == "text" && &b.node_id == id), "text node {} was skipped", id);
+        }
+        assert_eq!(boxes.iter().filter(|b| b.node_type == "div").count(), 40);
+    }
+
+    #[test]
+    fn parallel_advanced_layout_matches_sequential() {
+        // 500 sections of 50 divs with text: a little over 50k nodes
+        let html = "<body>".to_string() + &format!("<section>{}</section>", "<div>ab</div>".repeat(50)).repeat(500) + "</body>";
+        let mut parser = HTMLParser::new(html).with_reuse();
+        let dom = parser.parse();
+        let arena = parser.arena().unwrap();
+        assert!(arena.nodes.len() > 50_000);
+
+        // Best of two runs, so the first doesn't pay for warming up the thread pool alone
+        let run = |parallel: bool| {
+            let engine = LayoutEngine::new(800.0, 600.0).with_css_enabled(false).with_parallel_layout(parallel);
+            let timed = || {
+                let start = Instant::now();
+                let boxes = engine.layout_advanced(&dom, arena);
+                (boxes, start.elapsed())
+            };
+            let (boxes, first) = timed();
+            (boxes, first.min(timed().1))
+        };
+        let (sequential, sequential_time) = run(false);
+        let (parallel, parallel_time) = run(true);
+        let summary = |boxes: &[LayoutBox]| boxes.iter().map(|b| (b.node_id.clone(), b.x, b.y, b.width, b.height, b.parent_index)).collect::<Vec<_>>();
+        assert_eq!(sequential.len(), 1 + 500 * 101);
+        assert_eq!(summary(&parallel), summary(&sequential));
+        if std::thread::available_parallelism().map_or(1, |n| n.get()) >= 4 {
+            assert!(parallel_time < sequential_time, "no speedup: {:?} vs {:?}", parallel_time, sequential_time);
+        }
+    }
+
+    #[test]
+    fn test_inline_tags_stay_inline_without_css() {
+        let mut parser = HTMLParser::new("<body><p>Hello <b>world</b></p></body>".to_string()).with_reuse();
+        let dom = parser.parse();
+        let boxes = LayoutEngine::new(800.0, 600.0).with_css_enabled(false).layout(&dom, parser.arena().unwrap());
+        let texts = boxes.iter().filter(|b| b.node_type == "text").map(|b| b.text_content.as_str()).collect::<Vec<_>>();
+        // The space before <b> doesn't end a line box, so it survives
+        assert_eq!(texts, ["Hello ", "world"]);
+    }
+
+    #[test]
+    fn block_text_includes_nested_inline_text() {
+        let boxes = layout_html("<body><p>Hello <b>world</b></p><div>a\n   <i>b  <u>c</u></i></div></body>");
+        let text_of = |tag: &str| boxes.iter().find(|b| b.node_type == tag).unwrap().text_content.clone();
+        assert_eq!(text_of("p"), "Hello world");
+        assert_eq!(text_of("div"), "a b c");
+    }
+
+    #[test]
+    fn test_flex_row_justify_and_grow() {
+        let boxes = layout_html(r#"<body>
+            <div style="display:flex;width:600px;justify-content:space-between"><span style="width:100px">a</span><span style="width:100px">b</span><span style="width:100px">c</span></div>
+            <section style="display:flex;width:600px"><p style="width:100px;flex-grow:1">d</p><p style="width:100px;flex-grow:3">e</p></section>
+        </body>"#);
+        let spans: Vec<&LayoutBox> = boxes.iter().filter(|b| b.node_type == "span").collect();
+        let row = boxes.iter().find(|b| b.node_type == "div").unwrap();
+        assert_eq!(spans.iter().map(|b| b.x - row.x).collect::<Vec<_>>(), vec![0.0, 250.0, 500.0]);
+        assert!(spans.iter().all(|b| b.y == spans[0].y && b.width == 100.0));
+
+        let grown: Vec<&LayoutBox> = boxes.iter().filter(|b| b.node_type == "p").collect();
+        let section = boxes.iter().find(|b| b.node_type == "section").unwrap();
+        assert_eq!(grown.iter().map(|b| (b.x - section.x, b.width)).collect::<Vec<_>>(), vec![(0.0, 200.0), (200.0, 400.0)]);
+        assert!(section.y >= row.y + row.height, "containers stack like blocks");
+    }
+
+    #[test]
+    fn test_flex_item_lays_out_nested_children() {
+        let boxes = layout_html(r#"<body><main style="display:flex;width:600px">
+            <section style="width:100px">x</section>
+            <article style="padding:5px"><img width="40" height="30"><div>inner</div></article>
+        </main></body>"#);
+        let find = |tag: &str| boxes.iter().find(|b| b.node_type == tag).unwrap();
+        let (item, img, inner) = (find("article"), find("img"), find("div"));
+        assert_eq!((item.x, item.y), (find("section").x + 100.0, find("section").y));
+        assert_eq!((img.x, img.y, img.width, img.height), (item.x + 5.0, item.y + 5.0, 40.0, 30.0));
+        assert_eq!(inner.x, item.x + 5.0);
+        assert!(inner.y >= img.y + img.height, "the block goes below the image");
+        let text = boxes.iter().find(|b| b.node_type == "text" && b.text_content == "inner").unwrap();
+        assert!(text.y >= inner.y && text.y + text.height <= item.y + item.height, "the item grows to fit its children");
+    }
+
+    #[test]
+    fn test_flex_item_children_wrap_at_the_items_final_width() {
+        let words = "word ".repeat(40);
+        let boxes = layout_html(&format!(r#"<body><main style="display:flex;width:300px">
+            <article>{}</article><aside style="width:100px;flex-shrink:0">side</aside>
+        </main></body>"#, words));
+        let item = boxes.iter().find(|b| b.node_type == "article").unwrap();
+        let lines: Vec<&LayoutBox> = boxes.iter().filter(|b| b.node_type == "text" && b.text_content.contains("word")).collect();
+
+        assert_eq!(item.width, 200.0, "the item shrank to make room for the aside");
+        assert!(lines.len() > 1);
+        assert!(lines.iter().all(|line| line.x + line.width <= item.x + item.width), "text wraps inside the shrunk item");
+    }
+
+    #[test]
+    fn test_absolute_child_ignores_siblings_and_relative_shifts() {
+        let page = |positioned: &str, offsets: &str| layout_html(&format!(r#"<body>
+            <div style="position:relative;width:300px;height:200px"><p>first</p><p>second</p>{}<h2>after</h2></div>
+            <h3 style="position:relative;{}">moved</h3>
+            <h4>unmoved</h4>
+        </body>"#, positioned, offsets));
+        let boxes = page(r#"<section style="position:absolute;top:10px;left:20px;width:50px;height:50px"></section>"#, "top:5px;left:7px");
+        let find = |boxes: &[LayoutBox], tag: &str| boxes.iter().find(|b| b.node_type == tag).unwrap().clone();
+        let (container, positioned) = (find(&boxes, "div"), find(&boxes, "section"));
+        assert_eq!((positioned.x, positioned.y), (container.x + 20.0, container.y + 10.0));
+
+        let without = page("", "");
+        for tag in ["h2", "h4"] {
+            assert_eq!(find(&boxes, tag).y, find(&without, tag).y, "<{}> flows as if the absolute box weren't there", tag);
+        }
+        let (moved, unmoved) = (find(&boxes, "h3"), find(&without, "h3"));
+        assert_eq!((moved.x, moved.y), (unmoved.x + 7.0, unmoved.y + 5.0));
+    }
+
+    #[test]
+    fn test_border_box_includes_padding_in_width() {
+        let boxes = layout_html(r#"<body>
+            <div style="width:100px;height:50px;padding:10px">content</div>
+            <div style="width:100px;height:50px;padding:10px;box-sizing:border-box">border</div>
+        </body>"#);
+        let sizes: Vec<(f32, f32)> = boxes.iter().filter(|b| b.node_type == "div").map(|b| (b.width, b.height)).collect();
+        assert_eq!(sizes, vec![(120.0, 70.0), (100.0, 50.0)]);
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_min_and_max_clamp_dimensions() {
+        let boxes = layout_html(r#"<body>
+            <div style="width:500px;max-width:200px;height:40px">wide</div>
+            <div style="width:100px;height:10px;min-height:50px">short</div>
+            <div style="width:100px;height:10px;min-height:auto;max-width:none">open</div>
+        </body>"#);
+        let sizes: Vec<(f32, f32)> = boxes.iter().filter(|b| b.node_type == "div").map(|b| (b.width, b.height)).collect();
+        assert_eq!(sizes, vec![(200.0, 40.0), (100.0, 50.0), (100.0, 10.0)]);
+    }
+
+    #[test]
+    fn test_text_wraps_at_word_boundaries() {
+        assert_eq!(wrap_text("the quick brown fox jumps over the lazy dog", 96.0, 16.0), ["the quick", "brown fox", "jumps over", "the lazy", "dog"]);
+
+        // 200px viewport: lines are at most 180px, 18 characters at 16px
+        let text_boxes = |body: &str| -> Vec<LayoutBox> {
+            let mut parser = HTMLParser::new(format!("<body>{}</body>", body)).with_reuse();
+            let dom = parser.parse();
+            let boxes = LayoutEngine::new(200.0, 600.0).layout(&dom, parser.arena().unwrap());
+            boxes.into_iter().filter(|b| b.node_type == "text").collect()
+        };
+        let wrapped = text_boxes("<p>the quick brown fox jumps over the lazy dog</p>");
+        assert_eq!(wrapped.iter().map(|b| b.text_content.as_str()).collect::<Vec<_>>(), ["the quick brown", "fox jumps over the", "lazy dog"]);
+        assert!(wrapped.windows(2).all(|pair| (pair[1].y - pair[0].y - 19.2).abs() < 0.01 && pair[1].x == 0.0));
+        let tall = text_boxes("<p style=\"line-height:2\">the quick brown fox jumps</p>");
+        assert!((tall[1].y - tall[0].y - 32.0).abs() < 0.01, "line-height: 2 spaces lines 32px apart");
+
+        let continued = text_boxes("<p><b>Hi</b> the quick brown fox jumps</p>");
+        assert_eq!((continued[1].text_content.as_str(), continued[1].y), ("the quick", continued[0].y), "fills the rest of the line first");
+        assert_eq!(continued[2].text_content, "brown fox jumps");
+
+        assert_eq!(text_boxes("<p style=\"white-space:nowrap\">the quick brown fox jumps over the lazy dog</p>").len(), 1);
+    }
+
+    #[test]
+    fn test_display_none_and_visibility_hidden() {
+        let boxes = layout_html(r#"<body><div style="display:none;height:40px"><p>gone</p><span>too</span></div><section>after</section></body>"#);
+        assert!(boxes.iter().all(|b| !["div", "p", "span"].contains(&b.node_type.as_str()) && !b.text_content.contains("gone")));
+
+        let visible = layout_html(r#"<body><div style="height:40px;background-color:red">shown</div><section>after</section></body>"#);
+        let hidden = layout_html(r#"<body><div style="height:40px;background-color:red;visibility:hidden">shown</div><section>after</section></body>"#);
+        let section_y = |boxes: &[LayoutBox]| boxes.iter().find(|b| b.node_type == "section").unwrap().y;
+        assert_eq!(section_y(&hidden), section_y(&visible), "hidden box keeps its space");
+        let painted = |boxes: &[LayoutBox]| Painter::from_layout_boxes(boxes).len();
+        assert!(painted(&hidden) < painted(&visible));
+        assert!(Painter::from_layout_boxes(&hidden).iter().all(|command| !matches!(command, DrawCommand::Text { content, .. } if content == "shown")));
+    }
+}
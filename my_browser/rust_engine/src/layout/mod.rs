@@ -1 +1,5 @@
-pub mod layout; 
\ No newline at end of file
+pub mod layout;
+pub mod snapshot;
+pub mod scroll;
+pub mod cache;
+pub mod float;
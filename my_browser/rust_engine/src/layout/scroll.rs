@@ -0,0 +1,124 @@
+// scrollIntoView-style offset computation
+// Finds an element's nearest scroll container and the scroll position that brings it into view
+
+use crate::dom::node::{DOMArena, LayoutBox};
+use crate::layout::layout::LayoutEngine;
+
+/// Where the target should end up inside its scroll container
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScrollAlign {
+    Start,
+    Center,
+    End,
+}
+
+impl ScrollAlign {
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "center" => ScrollAlign::Center,
+            "end" => ScrollAlign::End,
+            _ => ScrollAlign::Start,
+        }
+    }
+}
+
+impl LayoutEngine {
+    /// Scroll offset `(x, y)` for the target's nearest scroll container (or the viewport) that
+    /// aligns the target per `align`, clamped to the container's scroll range.
+    /// Returns None when the target has no layout box.
+    pub fn scroll_into_view_offset(&self, boxes: &[LayoutBox], arena: &DOMArena, target_node_id: &str, align: ScrollAlign) -> Option<(f32, f32)> {
+        let target = boxes.iter().find(|b| b.node_id == target_node_id)?;
+
+        // Walk up the parent chain looking for an element that scrolls
+        let mut container: Option<&LayoutBox> = None;
+        let mut current = arena.get_node(target_node_id)?.lock().unwrap().parent.clone();
+        while let Some(ancestor_id) = current {
+            if let Some(b) = boxes.iter().find(|b| b.node_id == ancestor_id) {
+                if is_scroll_container(b) {
+                    container = Some(b);
+                    break;
+                }
+            }
+            current = arena.get_node(&ancestor_id).and_then(|node| node.lock().unwrap().parent.clone());
+        }
+
+        let (left, top, width, height) = match container {
+            Some(c) => (c.x, c.y, c.width, c.height),
+            None => (0.0, 0.0, self.viewport_width(), self.viewport_height()),
+        };
+
+        // Scrollable extent is everything laid out inside the container
+        let contents: Vec<&LayoutBox> = match container {
+            Some(c) => boxes.iter().filter(|b| is_descendant(arena, &b.node_id, &c.node_id)).collect(),
+            None => boxes.iter().collect(),
+        };
+        let content_right = contents.iter().map(|b| b.x + b.width).fold(left + width, f32::max);
+        let content_bottom = contents.iter().map(|b| b.y + b.height).fold(top + height, f32::max);
+        let max_x = content_right - (left + width);
+        let max_y = content_bottom - (top + height);
+
+        let offset = |start: f32, size: f32, view_start: f32, view_size: f32| match align {
+            ScrollAlign::Start => start - view_start,
+            ScrollAlign::Center => start + size / 2.0 - (view_start + view_size / 2.0),
+            ScrollAlign::End => start + size - (view_start + view_size),
+        };
+        let x = offset(target.x, target.width, left, width).clamp(0.0, max_x);
+        let y = offset(target.y, target.height, top, height).clamp(0.0, max_y);
+        Some((x, y))
+    }
+}
+
+fn is_scroll_container(b: &LayoutBox) -> bool {
+    matches!(b.overflow.as_str(), "auto" | "scroll" | "hidden")
+}
+
+fn is_descendant(arena: &DOMArena, node_id: &str, ancestor_id: &str) -> bool {
+    let mut current = arena.get_node(node_id).and_then(|node| node.lock().unwrap().parent.clone());
+    while let Some(id) = current {
+        if id == ancestor_id {
+            return true;
+        }
+        current = arena.get_node(&id).and_then(|node| node.lock().unwrap().parent.clone());
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dom::node::DOMNode;
+
+    fn laid_out(node: &DOMNode, y: f32, height: f32) -> LayoutBox {
+        let mut b = LayoutBox::new();
+        b.node_id = node.id.clone();
+        b.y = y;
+        b.width = 200.0;
+        b.height = height;
+        b
+    }
+
+    #[test]
+    fn test_scroll_offset_clamped_to_scroll_range() {
+        let mut arena = DOMArena::new();
+        let mut container = DOMNode::create_element("div");
+        let mut filler = DOMNode::create_element("p");
+        let mut target = DOMNode::create_element("p");
+        filler.parent = Some(container.id.clone());
+        target.parent = Some(container.id.clone());
+        container.children = vec![filler.id.clone(), target.id.clone()];
+
+        let mut container_box = laid_out(&container, 0.0, 100.0);
+        container_box.overflow = "auto".to_string();
+        let boxes = vec![container_box, laid_out(&filler, 0.0, 300.0), laid_out(&target, 300.0, 50.0)];
+        let target_id = target.id.clone();
+        for node in [container, filler, target] {
+            arena.add_node(node);
+        }
+
+        let engine = LayoutEngine::new(800.0, 600.0);
+        // Aligning to the top would need 300px but the content only scrolls 250px
+        assert_eq!(engine.scroll_into_view_offset(&boxes, &arena, &target_id, ScrollAlign::Start), Some((0.0, 250.0)));
+        assert_eq!(engine.scroll_into_view_offset(&boxes, &arena, &target_id, ScrollAlign::End), Some((0.0, 250.0)));
+        assert_eq!(engine.scroll_into_view_offset(&boxes, &arena, "missing", ScrollAlign::Start), None);
+    }
+}
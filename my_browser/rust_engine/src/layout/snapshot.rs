@@ -0,0 +1,175 @@
+// Layout snapshots for regression testing
+// Serializes computed layout boxes to JSON and diffs a layout against a saved golden file
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::dom::node::LayoutBox;
+
+/// Default tolerance (in px) used when comparing float properties of two layouts
+pub const DEFAULT_LAYOUT_TOLERANCE: f32 = 0.01;
+
+/// A single difference between two layouts, keyed by box index
+#[derive(Debug, Clone, PartialEq)]
+pub enum LayoutChange {
+    /// Box exists in the new layout but not in the snapshot
+    Added { index: usize, node_type: String },
+    /// Box exists in the snapshot but not in the new layout
+    Removed { index: usize, node_type: String },
+    /// Numeric property differs by more than the tolerance
+    Geometry { index: usize, property: &'static str, old: f32, new: f32 },
+    /// String property differs
+    Property { index: usize, property: &'static str, old: String, new: String },
+}
+
+impl fmt::Display for LayoutChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LayoutChange::Added { index, node_type } => write!(f, "box {} added ({})", index, node_type),
+            LayoutChange::Removed { index, node_type } => write!(f, "box {} removed ({})", index, node_type),
+            LayoutChange::Geometry { index, property, old, new } => {
+                write!(f, "box {} {}: {} -> {}", index, property, old, new)
+            }
+            LayoutChange::Property { index, property, old, new } => {
+                write!(f, "box {} {}: '{}' -> '{}'", index, property, old, new)
+            }
+        }
+    }
+}
+
+/// Serialize layout boxes to pretty-printed JSON
+pub fn layout_to_json(boxes: &[LayoutBox]) -> Result<String, serde_json::Error> {
+    serde_json::to_string_pretty(boxes)
+}
+
+/// Deserialize layout boxes from JSON produced by `layout_to_json`
+pub fn layout_from_json(json: &str) -> Result<Vec<LayoutBox>, serde_json::Error> {
+    serde_json::from_str(json)
+}
+
+/// Compare two layouts box-by-box, treating float differences within `tolerance` as equal
+pub fn diff_layouts(old: &[LayoutBox], new: &[LayoutBox], tolerance: f32) -> Vec<LayoutChange> {
+    let mut changes = Vec::new();
+
+    for (index, (a, b)) in old.iter().zip(new.iter()).enumerate() {
+        let floats = [
+            ("x", a.x, b.x),
+            ("y", a.y, b.y),
+            ("width", a.width, b.width),
+            ("height", a.height, b.height),
+            ("font_size", a.font_size, b.font_size),
+            ("font_weight", a.font_weight, b.font_weight),
+            ("line_height", a.line_height, b.line_height),
+            ("margin_top", a.margin.top, b.margin.top),
+            ("margin_right", a.margin.right, b.margin.right),
+            ("margin_bottom", a.margin.bottom, b.margin.bottom),
+            ("margin_left", a.margin.left, b.margin.left),
+            ("padding_top", a.padding.top, b.padding.top),
+            ("padding_right", a.padding.right, b.padding.right),
+            ("padding_bottom", a.padding.bottom, b.padding.bottom),
+            ("padding_left", a.padding.left, b.padding.left),
+            ("border_width_top", a.border_width.top, b.border_width.top),
+            ("border_width_right", a.border_width.right, b.border_width.right),
+            ("border_width_bottom", a.border_width.bottom, b.border_width.bottom),
+            ("border_width_left", a.border_width.left, b.border_width.left),
+        ];
+        for (property, old_value, new_value) in floats {
+            if (old_value - new_value).abs() > tolerance {
+                changes.push(LayoutChange::Geometry { index, property, old: old_value, new: new_value });
+            }
+        }
+
+        let strings = [
+            ("node_type", &a.node_type, &b.node_type),
+            ("text_content", &a.text_content, &b.text_content),
+            ("background_color", &a.background_color, &b.background_color),
+            ("color", &a.color, &b.color),
+            ("font_family", &a.font_family, &b.font_family),
+            ("border_color", &a.border_color, &b.border_color),
+            ("text_align", &a.text_align, &b.text_align),
+        ];
+        for (property, old_value, new_value) in strings {
+            if old_value != new_value {
+                changes.push(LayoutChange::Property {
+                    index,
+                    property,
+                    old: old_value.clone(),
+                    new: new_value.clone(),
+                });
+            }
+        }
+    }
+
+    for (index, b) in new.iter().enumerate().skip(old.len()) {
+        changes.push(LayoutChange::Added { index, node_type: b.node_type.clone() });
+    }
+    for (index, a) in old.iter().enumerate().skip(new.len()) {
+        changes.push(LayoutChange::Removed { index, node_type: a.node_type.clone() });
+    }
+
+    changes
+}
+
+/// Write layout boxes to `path` as a JSON golden snapshot
+pub fn save_layout_snapshot(boxes: &[LayoutBox], path: impl AsRef<Path>) -> Result<(), Box<dyn std::error::Error>> {
+    fs::write(path, layout_to_json(boxes)?)?;
+    Ok(())
+}
+
+/// Compare layout boxes against the snapshot at `path` using the default tolerance
+pub fn compare_layout_snapshot(boxes: &[LayoutBox], path: impl AsRef<Path>) -> Result<Vec<LayoutChange>, Box<dyn std::error::Error>> {
+    compare_layout_snapshot_with_tolerance(boxes, path, DEFAULT_LAYOUT_TOLERANCE)
+}
+
+/// Compare layout boxes against the snapshot at `path` with an explicit float tolerance
+pub fn compare_layout_snapshot_with_tolerance(
+    boxes: &[LayoutBox],
+    path: impl AsRef<Path>,
+    tolerance: f32,
+) -> Result<Vec<LayoutChange>, Box<dyn std::error::Error>> {
+    let saved = layout_from_json(&fs::read_to_string(path)?)?;
+    Ok(diff_layouts(&saved, boxes, tolerance))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_layout() -> Vec<LayoutBox> {
+        let mut body = LayoutBox::new();
+        body.node_type = "body".to_string();
+        body.width = 800.0;
+        body.height = 120.0;
+        let mut heading = LayoutBox::new();
+        heading.node_type = "h1".to_string();
+        heading.text_content = "Title".to_string();
+        heading.y = 10.0;
+        heading.width = 800.0;
+        heading.height = 32.0;
+        vec![body, heading]
+    }
+
+    #[test]
+    fn test_snapshot_roundtrip_and_diff() {
+        let path = std::env::temp_dir().join(format!("rift_layout_snapshot_{}.json", std::process::id()));
+        let boxes = sample_layout();
+        save_layout_snapshot(&boxes, &path).unwrap();
+
+        assert!(compare_layout_snapshot(&boxes, &path).unwrap().is_empty());
+
+        let mut perturbed = sample_layout();
+        perturbed[1].y = 12.0;
+        perturbed[1].height = 32.001; // within tolerance
+        perturbed[1].color = "red".to_string();
+        perturbed.push(LayoutBox::new());
+
+        let changes = compare_layout_snapshot(&perturbed, &path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(changes, vec![
+            LayoutChange::Geometry { index: 1, property: "y", old: 10.0, new: 12.0 },
+            LayoutChange::Property { index: 1, property: "color", old: "black".to_string(), new: "red".to_string() },
+            LayoutChange::Added { index: 2, node_type: String::new() },
+        ]);
+    }
+}
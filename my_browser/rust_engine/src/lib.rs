@@ -10,26 +10,62 @@ pub mod paint;
 pub mod compositor;
 pub mod ffi;
 pub mod javascript;
+pub mod error;
+pub mod cancellation;
+pub mod metrics;
+pub mod loader;
 
 // Re-export commonly used types for convenience
 pub use dom::node::{DOMNode, LayoutBox, FFILayoutBox, NodeType, StyleMap, BoxValues};
-pub use parser::html::{HTMLParser, StreamingHTMLParser};
+pub use dom::links::{extract_links, rel_nofollow_links, links_with_rel, Link};
+pub use parser::html::{HTMLParser, StreamingHTMLParser, ParserCheckpoint};
 pub use parser::css::{parse_css, Stylesheet};
 pub use layout::layout::LayoutEngine;
+pub use layout::snapshot::{save_layout_snapshot, compare_layout_snapshot, LayoutChange};
 pub use paint::painter::Painter;
 pub use compositor::compositor::Compositor;
 pub use javascript::{JavaScriptRuntime, ScriptManager};
+pub use error::RiftError;
+pub use cancellation::CancellationToken;
+pub use metrics::{Metrics, MetricsSnapshot};
+pub use style::color::Color;
+pub use loader::{ResourceLoader, HttpLoader, LoadResult};
 
 // Re-export FFI types and functions
 pub use ffi::{LayoutBoxArray, DrawCommand, DrawCommandArray, FFIPerformanceTracker};
 pub use ffi::functions::*;
 
+use std::collections::HashMap;
+use bytes::Bytes;
+use indexmap::IndexMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use dom::node::DOMArena;
+
+/// How long `render_html_with_js` waits for scripts' fetches and other async work before
+/// laying out without them
+const SCRIPT_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Parsed stylesheets kept before the oldest is evicted
+const STYLESHEET_CACHE_CAPACITY: usize = 32;
+
 // Main entry point for the Velox browser rendering engine
 pub struct VeloxEngine {
     pub layout_engine: LayoutEngine,
     pub painter: Painter,
     pub compositor: Compositor,
     pub script_manager: Option<ScriptManager>,
+    /// When false, `render_html` skips all CSS and lays out with UA defaults only
+    pub css_enabled: bool,
+    metrics: Arc<Metrics>,
+    // Source of every document, stylesheet, script and image the engine fetches
+    loader: Arc<dyn ResourceLoader>,
+    // Parsed stylesheets keyed by their source text, oldest first
+    stylesheet_cache: Mutex<IndexMap<String, Stylesheet>>,
+    // (root node id, `id` attribute) -> node id, validated against the arena on each hit
+    id_index: Mutex<HashMap<(String, String), String>>,
+    // Bytes of the last `load_url` document's images, keyed by resolved URL
+    images: Mutex<HashMap<String, Bytes>>,
 }
 
 impl VeloxEngine {
@@ -39,6 +75,12 @@ impl VeloxEngine {
             painter: Painter::new(),
             compositor: Compositor::new(),
             script_manager: None,
+            css_enabled: true,
+            metrics: Arc::new(Metrics::new()),
+            loader: Arc::new(HttpLoader::new()),
+            stylesheet_cache: Mutex::new(IndexMap::new()),
+            id_index: Mutex::new(HashMap::new()),
+            images: Mutex::new(HashMap::new()),
         }
     }
 
@@ -47,9 +89,49 @@ impl VeloxEngine {
         self
     }
 
+    /// Safe-area insets in px exposed to stylesheets as `env(safe-area-inset-*)`
+    pub fn with_safe_area_insets(mut self, top: f32, right: f32, bottom: f32, left: f32) -> Self {
+        self.layout_engine = self.layout_engine.with_safe_area_insets(top, right, bottom, left);
+        self
+    }
+
+    /// Resize the viewport for subsequent renders, e.g. after the host window changes size
+    pub fn set_viewport(&mut self, width: f32, height: f32) {
+        self.layout_engine.set_viewport(width, height);
+    }
+
+    /// Enable or disable CSS; a plain structural layout is faster for text extraction
+    pub fn with_css_enabled(mut self, enabled: bool) -> Self {
+        self.css_enabled = enabled;
+        self
+    }
+
+    /// Fetch documents, stylesheets, scripts and images through `loader` instead of the network
+    pub fn with_loader(mut self, loader: Arc<dyn ResourceLoader>) -> Self {
+        self.loader = loader;
+        self
+    }
+
+    /// Fetch `url` through the engine's loader
+    pub async fn fetch_resource(&self, url: &str) -> LoadResult {
+        self.fetch_cancellable(url, &CancellationToken::new()).await
+    }
+
+    /// Like `fetch_resource`, but gives up with `RiftError::Cancelled` once `token` is set
+    async fn fetch_cancellable(&self, url: &str, token: &CancellationToken) -> LoadResult {
+        let bytes = token.run(self.loader.fetch(url)).await??;
+        self.metrics.record_fetch(bytes.len() as u64);
+        Ok(bytes)
+    }
+
     /// Initialize JavaScript runtime with DOM tree
     pub fn init_javascript(&mut self, dom: &DOMNode) -> Result<(), Box<dyn std::error::Error>> {
-        let mut script_manager = ScriptManager::new(ffi::GLOBAL_DOM_ARENA.clone(), dom.id.clone())?;
+        self.bind_scripts(ffi::GLOBAL_DOM_ARENA.clone(), dom)
+    }
+
+    // Replace the script runtime with one bound to `dom` in `arena`
+    fn bind_scripts(&mut self, arena: Arc<Mutex<DOMArena>>, dom: &DOMNode) -> Result<(), Box<dyn std::error::Error>> {
+        let mut script_manager = ScriptManager::new(arena, dom.id.clone())?;
         script_manager.initialize()?;
         self.script_manager = Some(script_manager);
         Ok(())
@@ -65,48 +147,139 @@ impl VeloxEngine {
 
     /// Execute external JavaScript from URL
     pub async fn execute_external_script(&mut self, script_url: &str) -> Result<(), Box<dyn std::error::Error>> {
-        if let Some(script_manager) = &mut self.script_manager {
-            script_manager.execute_external_script(script_url).await?;
+        self.execute_external_script_cancellable(script_url, &CancellationToken::new()).await
+    }
+
+    async fn execute_external_script_cancellable(&mut self, script_url: &str, token: &CancellationToken) -> Result<(), Box<dyn std::error::Error>> {
+        if self.script_manager.is_none() {
+            return Ok(());
         }
-        Ok(())
+        let bytes = self.fetch_cancellable(script_url, token).await.map_err(|e| e as Box<dyn std::error::Error>)?;
+        self.execute_script(&String::from_utf8_lossy(&bytes), script_url)
     }
 
-    /// Run JavaScript event loop
-    pub fn run_js_event_loop(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        if let Some(script_manager) = &mut self.script_manager {
-            script_manager.run_event_loop()?;
+    /// Run JavaScript event loop; true when script mutations need a new layout
+    pub fn run_js_event_loop(&mut self) -> Result<bool, Box<dyn std::error::Error>> {
+        match &mut self.script_manager {
+            Some(script_manager) => Ok(script_manager.run_event_loop()?),
+            None => Ok(false),
         }
-        Ok(())
     }
 
-    pub fn render_html(&self, html: &str) -> Vec<LayoutBox> {
-        // Parse HTML
-        let mut parser = HTMLParser::new(html.to_string());
+    pub fn render_html(&mut self, html: &str) -> Vec<LayoutBox> {
+        // Parse HTML, keeping the parser's arena so layout can resolve the parsed nodes
+        let mut parser = HTMLParser::new(html.to_string()).with_reuse();
         let dom = parser.parse();
-        let stylesheet = parser.get_stylesheet();
+        let stylesheet = self.css_enabled.then(|| parser.get_stylesheet());
+        let Some(arena) = parser.arena_mut() else {
+            return Vec::new();
+        };
+        self.style_and_layout(&dom, arena, stylesheet)
+    }
+
+    /// Render HTML against an external stylesheet, parsing each distinct stylesheet only once
+    pub fn render_html_with_css(&mut self, html: &str, css: &str) -> Vec<LayoutBox> {
+        let mut parser = HTMLParser::new(html.to_string()).with_reuse();
+        let dom = parser.parse();
+        let stylesheet = self.css_enabled.then(|| self.stylesheet(css));
+        let Some(arena) = parser.arena_mut() else {
+            return Vec::new();
+        };
+        self.style_and_layout(&dom, arena, stylesheet)
+    }
+
+    /// Lay out `dom`, styled by `stylesheet`; None, when CSS is disabled, lays it out with UA
+    /// defaults only. Callers skip extracting and parsing the CSS in that case.
+    fn style_and_layout(&mut self, dom: &DOMNode, arena: &mut DOMArena, stylesheet: Option<Stylesheet>) -> Vec<LayoutBox> {
+        self.metrics.record_layout_pass();
+        self.layout_engine.set_css_enabled(stylesheet.is_some());
+        let Some(stylesheet) = stylesheet else {
+            return self.layout_engine.layout(dom, arena);
+        };
 
         // Apply styles
         let mut styled_dom = dom.clone();
-        {
-            let mut arena = ffi::GLOBAL_DOM_ARENA.lock().unwrap();
-            ffi::apply_stylesheet_to_dom(&mut styled_dom, &stylesheet, &mut *arena);
-        }
+        ffi::apply_stylesheet_to_dom(&mut styled_dom, &stylesheet, arena);
+
         // Layout
-        let layout_engine = self.layout_engine.clone().with_stylesheet(stylesheet);
-        layout_engine.layout(&styled_dom, &ffi::GLOBAL_DOM_ARENA.lock().unwrap())
+        self.layout_engine.set_stylesheet(stylesheet);
+        self.layout_engine.layout(&styled_dom, arena)
+    }
+
+    /// Parse `css`, reusing the cached result for a stylesheet seen before
+    pub fn stylesheet(&self, css: &str) -> Stylesheet {
+        let mut cache = self.stylesheet_cache.lock().unwrap();
+        if let Some(stylesheet) = cache.get(css) {
+            self.metrics.record_cache_hit();
+            return stylesheet.clone();
+        }
+        self.metrics.record_cache_miss();
+        let stylesheet = parse_css(css);
+        if cache.len() >= STYLESHEET_CACHE_CAPACITY {
+            cache.shift_remove_index(0);
+        }
+        cache.insert(css.to_string(), stylesheet.clone());
+        stylesheet
+    }
+
+    /// Node id of the element under `root` whose `id` attribute is `id`. Results are indexed;
+    /// an entry is only trusted while the node still exists, still carries that id and is
+    /// still under `root`.
+    pub fn find_element_by_id(&self, root: &DOMNode, arena: &DOMArena, id: &str) -> Option<String> {
+        let key = (root.id.clone(), id.to_string());
+        let mut index = self.id_index.lock().unwrap();
+        if let Some(node_id) = index.get(&key) {
+            let still_valid = arena.get_node(node_id)
+                .is_some_and(|node| node.lock().unwrap().attributes.get("id").map(String::as_str) == Some(id))
+                && is_inclusive_descendant(arena, node_id, &root.id);
+            if still_valid {
+                self.metrics.record_cache_hit();
+                return Some(node_id.clone());
+            }
+        }
+        self.metrics.record_cache_miss();
+        let found = root.find_element_by_id_ref(id, arena)?;
+        index.insert(key, found.clone());
+        Some(found)
+    }
+
+    /// Counters for cache hits, fetches and layout passes
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+
+    /// Like `render_html`, but stops early with `RiftError::Cancelled` once `token` is set
+    pub fn render_html_cancellable(&self, html: &str, token: &CancellationToken) -> Result<Vec<LayoutBox>, RiftError> {
+        let mut parser = HTMLParser::new(html.to_string()).with_reuse().with_cancellation(token.clone());
+        let dom = parser.parse();
+        token.check()?;
+
+        let stylesheet = self.css_enabled.then(|| parser.get_stylesheet());
+        let Some(arena) = parser.arena_mut() else {
+            return Ok(Vec::new());
+        };
+        let mut layout_engine = self.layout_engine.clone()
+            .with_css_enabled(self.css_enabled)
+            .with_cancellation(token.clone());
+        let mut styled_dom = dom.clone();
+        if let Some(stylesheet) = stylesheet {
+            ffi::apply_stylesheet_to_dom(&mut styled_dom, &stylesheet, arena);
+            token.check()?;
+            layout_engine = layout_engine.with_stylesheet(stylesheet);
+        }
+        layout_engine.try_layout(&styled_dom, arena)
     }
 
     /// Render HTML with JavaScript execution
     pub async fn render_html_with_js(&mut self, html: &str) -> Result<Vec<LayoutBox>, Box<dyn std::error::Error>> {
         // Parse HTML
+        // Parse into the shared arena that the JavaScript runtime and FFI DOM functions use
         let mut parser = HTMLParser::new(html.to_string());
-        let dom = parser.parse();
+        let dom = ffi::parse_document(&mut parser, &mut ffi::get_global_arena());
         let stylesheet = parser.get_stylesheet();
 
-        // Initialize JavaScript runtime if not already done
-        if self.script_manager.is_none() {
-            self.init_javascript(&dom)?;
-        }
+        // The parse replaced the previous document, so scripts get a runtime bound to this one
+        self.init_javascript(&dom)?;
 
         // Execute inline scripts
         for (i, script_content) in parser.get_extracted_scripts().iter().enumerate() {
@@ -116,32 +289,149 @@ impl VeloxEngine {
 
         // Execute external scripts
         for script_url in parser.get_script_src_urls() {
-            self.execute_external_script(script_url).await?;
+            if let Err(e) = self.execute_external_script(script_url).await {
+                eprintln!("[JS] Failed to run external script {}: {}", script_url, e);
+            }
+        }
+
+        // Let fetches and other async work the scripts started finish before layout. A hung
+        // request or a failing promise job shouldn't stop the page from rendering.
+        if let Some(script_manager) = &mut self.script_manager {
+            match tokio::time::timeout(SCRIPT_IDLE_TIMEOUT, script_manager.run_until_idle()).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => eprintln!("[JS] Async script work failed: {}", e),
+                Err(_) => eprintln!("[JS] Async script work still pending after {:?}, laying out without it", SCRIPT_IDLE_TIMEOUT),
+            }
         }
 
         // Apply styles
         let mut styled_dom = dom.clone();
         {
-            let mut arena = ffi::GLOBAL_DOM_ARENA.lock().unwrap();
+            let mut arena = ffi::get_global_arena();
             ffi::apply_stylesheet_to_dom(&mut styled_dom, &stylesheet, &mut *arena);
         }
         // Layout
-        let layout_engine = self.layout_engine.clone().with_stylesheet(stylesheet);
-        let layout_boxes = layout_engine.layout(&styled_dom, &ffi::GLOBAL_DOM_ARENA.lock().unwrap());
+        self.layout_engine.set_stylesheet(stylesheet);
+        let mut layout_boxes = self.layout_engine.layout(&styled_dom, &ffi::get_global_arena());
 
-        // Run JavaScript event loop for any pending operations
-        self.run_js_event_loop()?;
+        // Run JavaScript event loop for any pending operations, laying out again if they mutated the DOM
+        if self.run_js_event_loop()? {
+            println!("[JS] DOM mutated by scripts, recalculating layout");
+            layout_boxes = self.layout_engine.layout(&styled_dom, &ffi::get_global_arena());
+        }
 
         Ok(layout_boxes)
     }
 
-    pub fn render_url(&self, url: &str) -> Result<Vec<LayoutBox>, Box<dyn std::error::Error>> {
-        // This would use the async streaming parser in a real implementation
-        // For now, return an error indicating this needs to be implemented
-        Err("URL rendering not yet implemented".into())
+    /// Fetch and render the document at `url`. Linked stylesheets are fetched through the
+    /// loader and cascade after the document's `<style>` blocks; external scripts are fetched
+    /// and run against the document only once JavaScript has been initialized, and one that
+    /// fails to load or throws is skipped. Images are fetched too and kept for `image` until
+    /// the next load.
+    pub async fn load_url(&mut self, url: &str) -> Result<Vec<LayoutBox>, Box<dyn std::error::Error>> {
+        self.load_url_cancellable(url, &CancellationToken::new()).await
+    }
+
+    /// Like `load_url`, but fails with `RiftError::Cancelled` once `token` is set, including
+    /// while the document or one of its subresources is still being fetched
+    pub async fn load_url_cancellable(&mut self, url: &str, token: &CancellationToken) -> Result<Vec<LayoutBox>, Box<dyn std::error::Error>> {
+        let html = self.fetch_cancellable(url, token).await.map_err(|e| e as Box<dyn std::error::Error>)?;
+        let mut parser = HTMLParser::new(String::from_utf8_lossy(&html).into_owned());
+        let mut arena = DOMArena::new();
+        let dom = parser.parse_into(&mut arena);
+        let subresources = loader::collect_subresources(&dom, &arena);
+        let document = Arc::new(Mutex::new(arena));
+
+        let mut css = parser.extracted_css.join("\n");
+        if self.css_enabled {
+            for href in &subresources.stylesheets {
+                let sheet_url = loader::resolve_url(url, href);
+                let fetched = self.fetch_cancellable(&sheet_url, token).await;
+                token.check()?;
+                match fetched {
+                    Ok(bytes) => {
+                        css.push('\n');
+                        css.push_str(&String::from_utf8_lossy(&bytes));
+                    }
+                    Err(e) => eprintln!("[LOADER] Failed to load stylesheet {}: {}", sheet_url, e),
+                }
+            }
+        }
+        if self.script_manager.is_some() {
+            self.bind_scripts(document.clone(), &dom)?;
+        }
+        for src in &subresources.scripts {
+            let script_url = loader::resolve_url(url, src);
+            let result = self.execute_external_script_cancellable(&script_url, token).await;
+            token.check()?;
+            if let Err(e) = result {
+                eprintln!("[JS] Failed to run external script {}: {}", script_url, e);
+            }
+        }
+
+        let mut images = HashMap::new();
+        for src in &subresources.images {
+            let image_url = loader::resolve_url(url, src);
+            let fetched = self.fetch_cancellable(&image_url, token).await;
+            token.check()?;
+            match fetched {
+                Ok(bytes) => {
+                    images.insert(image_url, bytes);
+                }
+                Err(e) => eprintln!("[LOADER] Failed to load image {}: {}", image_url, e),
+            }
+        }
+        *self.images.lock().unwrap() = images;
+
+        let stylesheet = self.css_enabled.then(|| self.stylesheet(&css));
+        let mut arena = document.lock().unwrap();
+        Ok(self.style_and_layout(&dom, &mut arena, stylesheet))
+    }
+
+    /// Bytes of an image the last `load_url` fetched, by its resolved URL
+    pub fn image(&self, url: &str) -> Option<Bytes> {
+        self.images.lock().unwrap().get(url).cloned()
+    }
+
+    /// Blocking fetch of `url` through the loader, rendered without its subresources; async
+    /// callers that want linked stylesheets, scripts and images use `load_url`
+    pub fn render_url(&mut self, url: &str) -> Result<Vec<LayoutBox>, Box<dyn std::error::Error>> {
+        self.render_url_cancellable(url, &CancellationToken::new())
+    }
+
+    /// Like `render_url`, but fails with `RiftError::Cancelled` once `token` is set, including
+    /// while the fetch is still waiting on the server
+    pub fn render_url_cancellable(&mut self, url: &str, token: &CancellationToken) -> Result<Vec<LayoutBox>, Box<dyn std::error::Error>> {
+        let loader = self.loader.clone();
+        let owned_url = url.to_string();
+        let fetch_token = token.clone();
+        let fetch = move || -> LoadResult {
+            let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+            runtime.block_on(fetch_token.run(loader.fetch(&owned_url)))?
+        };
+        let html = match tokio::runtime::Handle::try_current() {
+            // Blocking on a runtime's own thread panics, so the fetch gets a thread of its own
+            Ok(_) => std::thread::spawn(fetch).join().map_err(|_| "fetch thread panicked")?,
+            Err(_) => fetch(),
+        }.map_err(|e| e as Box<dyn std::error::Error>)?;
+        self.metrics.record_fetch(html.len() as u64);
+        token.check()?;
+        Ok(self.render_html(&String::from_utf8_lossy(&html)))
     }
 }
 
+/// Whether `node_id` is `ancestor_id` or lies under it by parent links
+fn is_inclusive_descendant(arena: &DOMArena, node_id: &str, ancestor_id: &str) -> bool {
+    let mut current = Some(node_id.to_string());
+    while let Some(id) = current {
+        if id == ancestor_id {
+            return true;
+        }
+        current = arena.get_node(&id).and_then(|node| node.lock().unwrap().parent.clone());
+    }
+    false
+}
+
 // Default implementation for common use cases
 impl Default for VeloxEngine {
     fn default() -> Self {
@@ -156,7 +446,7 @@ pub fn create_velox_engine(width: f32, height: f32) -> VeloxEngine {
 
 // Convenience function for quick HTML rendering
 pub fn render_html_quick(html: &str) -> Vec<LayoutBox> {
-    let engine = VeloxEngine::default();
+    let mut engine = VeloxEngine::default();
     engine.render_html(html)
 }
 
@@ -222,4 +512,223 @@ pub use ffi::{
     dom_clone_node,
     dom_remove_node,
     dom_contains_node,
-}; 
\ No newline at end of file
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_html_with_css_disabled() {
+        let html = r#"<body><div style="width:300px;background-color:#ff0000">Hi</div><p>Text</p></body>"#;
+        let styled = VeloxEngine::new(800.0, 600.0).render_html(html);
+        let plain = VeloxEngine::new(800.0, 600.0).with_css_enabled(false).render_html(html);
+
+        let styled_div = styled.iter().find(|b| b.node_type == "div").unwrap();
+        assert_eq!(styled_div.width, 300.0);
+        assert_eq!(styled_div.background_color, "#ff0000");
+
+        let plain_div = plain.iter().find(|b| b.node_type == "div").unwrap();
+        assert_ne!(plain_div.width, 300.0);
+        assert_eq!(plain_div.background_color, StyleMap::default().background_color);
+
+        let structure = |boxes: &[LayoutBox]| boxes.iter().map(|b| b.node_type.clone()).collect::<Vec<_>>();
+        assert_eq!(structure(&plain), structure(&styled));
+
+        // With CSS off the stylesheet isn't even parsed
+        let mut engine = VeloxEngine::new(800.0, 600.0).with_css_enabled(false);
+        let boxes = engine.render_html_with_css(html, "div { width: 100px }");
+        assert_ne!(boxes.iter().find(|b| b.node_type == "div").unwrap().width, 100.0);
+        assert_eq!(engine.metrics().snapshot().cache_misses, 0);
+    }
+
+    #[test]
+    fn test_metrics_count_cache_hits_and_layout_passes() {
+        let mut engine = VeloxEngine::new(800.0, 600.0);
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let sink = reports.clone();
+        engine.metrics().set_callback(2, move |snapshot| sink.lock().unwrap().push(*snapshot));
+
+        let css = "div { color: #ff0000; }";
+        engine.render_html_with_css("<body><div>one</div></body>", css);
+        let boxes = engine.render_html_with_css("<body><div>two</div></body>", css);
+        assert_eq!(boxes.iter().find(|b| b.node_type == "div").unwrap().color, "#ff0000");
+
+        let mut parser = HTMLParser::new(r#"<body><p id="intro">Hi</p></body>"#.to_string()).with_reuse();
+        let dom = parser.parse();
+        let arena = parser.arena().unwrap();
+        let first = engine.find_element_by_id(&dom, arena, "intro");
+        assert!(first.is_some());
+        assert_eq!(engine.find_element_by_id(&dom, arena, "intro"), first);
+        assert_eq!(engine.find_element_by_id(&dom, arena, "intro"), first);
+
+        // One stylesheet miss then a hit, one id miss then two hits
+        let snapshot = engine.metrics().snapshot();
+        assert_eq!((snapshot.cache_hits, snapshot.cache_misses, snapshot.layout_passes), (3, 2, 2));
+        assert_eq!(snapshot.fetches, 0);
+        assert_eq!(reports.lock().unwrap().len(), 1);
+        assert_eq!(reports.lock().unwrap()[0].layout_passes, 2);
+    }
+
+    #[test]
+    fn test_find_element_by_id_stays_under_root() {
+        let engine = VeloxEngine::new(800.0, 600.0);
+        let mut parser = HTMLParser::new(r#"<body><div id="a"><p id="target">Hi</p></div><div id="b"></div></body>"#.to_string()).with_reuse();
+        let dom = parser.parse();
+        let arena = parser.arena().unwrap();
+        let target = engine.find_element_by_id(&dom, arena, "target");
+        assert!(target.is_some());
+
+        // A cached hit from the whole document mustn't leak into a subtree that lacks it
+        let other = dom.find_element_by_id_ref("b", arena).unwrap();
+        let other = arena.get_node(&other).unwrap().lock().unwrap().clone();
+        assert_eq!(engine.find_element_by_id(&other, arena, "target"), None);
+        let subtree = dom.find_element_by_id_ref("a", arena).unwrap();
+        let subtree = arena.get_node(&subtree).unwrap().lock().unwrap().clone();
+        assert_eq!(engine.find_element_by_id(&subtree, arena, "target"), target);
+    }
+
+    #[test]
+    fn test_stylesheet_cache_is_bounded() {
+        let engine = VeloxEngine::new(800.0, 600.0);
+        for i in 0..STYLESHEET_CACHE_CAPACITY + 4 {
+            engine.stylesheet(&format!("p {{ width: {}px; }}", i));
+        }
+        assert_eq!(engine.stylesheet_cache.lock().unwrap().len(), STYLESHEET_CACHE_CAPACITY);
+        // The oldest entries went first
+        assert!(!engine.stylesheet_cache.lock().unwrap().contains_key("p { width: 0px; }"));
+    }
+
+    #[tokio::test]
+    async fn test_render_html_with_js_relayouts_after_timer_mutation() {
+        let html = r#"<body><p id="relayout-kept">kept</p><p id="relayout-hidden">hidden</p>
+            <script>setTimeout(() => { document.getElementById('relayout-hidden').style.display = 'none'; }, 0);</script></body>"#;
+        let _parse = ffi::TOP_LEVEL_PARSE.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let mut engine = VeloxEngine::new(800.0, 600.0);
+        let boxes = engine.render_html_with_js(html).await.unwrap();
+
+        let texts: Vec<&str> = boxes.iter().map(|b| b.text_content.trim()).filter(|text| !text.is_empty()).collect();
+        assert!(texts.contains(&"kept"));
+        assert!(!texts.contains(&"hidden"), "{:?}", texts);
+    }
+
+    struct MockLoader {
+        files: HashMap<String, &'static str>,
+        requested: Mutex<Vec<String>>,
+    }
+
+    impl ResourceLoader for MockLoader {
+        fn fetch<'a>(&'a self, url: &'a str) -> futures::future::BoxFuture<'a, LoadResult> {
+            Box::pin(async move {
+                self.requested.lock().unwrap().push(url.to_string());
+                let body = self.files.get(url).ok_or_else(|| format!("no such file: {}", url))?;
+                Ok(bytes::Bytes::from_static(body.as_bytes()))
+            })
+        }
+    }
+
+    #[test]
+    fn test_load_url_fetches_through_custom_loader() {
+        let files = HashMap::from([
+            ("app://site/docs/index.html".to_string(), r#"<html><head>
+                <link rel="stylesheet" href="theme.css"><script src="/app.js"></script>
+            </head><body><div>Hi</div><img src="logo.png"></body></html>"#),
+            ("app://site/docs/theme.css".to_string(), "div { color: #00ff00; }"),
+            ("app://site/docs/logo.png".to_string(), "PNG"),
+            ("app://site/app.js".to_string(), "document.title = 'x';"),
+        ]);
+        let loader = Arc::new(MockLoader { files, requested: Mutex::new(Vec::new()) });
+        let mut engine = VeloxEngine::new(800.0, 600.0).with_loader(loader.clone());
+        engine.init_javascript(&DOMNode::new(NodeType::Document)).unwrap();
+
+        let boxes = futures::executor::block_on(engine.load_url("app://site/docs/index.html")).unwrap();
+        assert_eq!(boxes.iter().find(|b| b.node_type == "div").unwrap().color, "#00ff00");
+        assert_eq!(*loader.requested.lock().unwrap(), vec![
+            "app://site/docs/index.html", "app://site/docs/theme.css", "app://site/app.js", "app://site/docs/logo.png",
+        ]);
+        assert_eq!(engine.metrics().snapshot().fetches, 4);
+        assert_eq!(engine.image("app://site/docs/logo.png").as_deref(), Some(&b"PNG"[..]));
+        assert!(futures::executor::block_on(engine.load_url("app://site/missing.html")).is_err());
+
+        let boxes = engine.render_url("app://site/docs/index.html").unwrap();
+        assert!(boxes.iter().any(|b| b.node_type == "div"));
+        assert!(engine.render_url("app://site/missing.html").is_err());
+    }
+
+    #[test]
+    fn test_load_url_skips_scripts_that_fail_to_load() {
+        let files = HashMap::from([
+            ("app://site/index.html".to_string(), r#"<body><script src="gone.js"></script><div>Still here</div></body>"#),
+        ]);
+        let loader = Arc::new(MockLoader { files, requested: Mutex::new(Vec::new()) });
+        let mut engine = VeloxEngine::new(800.0, 600.0).with_loader(loader.clone());
+        engine.init_javascript(&DOMNode::new(NodeType::Document)).unwrap();
+
+        let boxes = futures::executor::block_on(engine.load_url("app://site/index.html")).unwrap();
+        assert!(boxes.iter().any(|b| b.text_content.contains("Still here")));
+        assert_eq!(*loader.requested.lock().unwrap(), vec!["app://site/index.html", "app://site/gone.js"]);
+    }
+
+    #[tokio::test]
+    async fn test_render_url_inside_a_runtime() {
+        let files = HashMap::from([("app://site/index.html".to_string(), "<body><div>Hi</div></body>")]);
+        let loader = Arc::new(MockLoader { files, requested: Mutex::new(Vec::new()) });
+        let mut engine = VeloxEngine::new(800.0, 600.0).with_loader(loader);
+
+        let boxes = engine.render_url("app://site/index.html").unwrap();
+        assert!(boxes.iter().any(|b| b.node_type == "div"));
+        assert_eq!(engine.metrics().snapshot().fetches, 1);
+    }
+
+    /// Serves `page` at app://site/index.html and never answers anything else, like a stalled server
+    struct StallingLoader {
+        page: &'static str,
+    }
+
+    impl ResourceLoader for StallingLoader {
+        fn fetch<'a>(&'a self, url: &'a str) -> futures::future::BoxFuture<'a, LoadResult> {
+            if url == "app://site/index.html" {
+                return Box::pin(async move { Ok(bytes::Bytes::from_static(self.page.as_bytes())) });
+            }
+            Box::pin(futures::future::pending())
+        }
+    }
+
+    /// Cancel `token` from another thread once the fetch under test has had time to start
+    fn cancel_soon(token: &CancellationToken) -> std::thread::JoinHandle<()> {
+        let token = token.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            token.cancel();
+        })
+    }
+
+    fn is_cancelled(error: &(dyn std::error::Error + 'static)) -> bool {
+        error.downcast_ref::<RiftError>() == Some(&RiftError::Cancelled)
+    }
+
+    #[test]
+    fn test_cancel_stops_a_pending_document_fetch() {
+        let mut engine = VeloxEngine::new(800.0, 600.0).with_loader(Arc::new(StallingLoader { page: "" }));
+        let token = CancellationToken::new();
+        let canceller = cancel_soon(&token);
+
+        let result = engine.render_url_cancellable("app://site/stalled.html", &token);
+        canceller.join().unwrap();
+        assert!(is_cancelled(&*result.unwrap_err()));
+    }
+
+    #[test]
+    fn test_cancel_stops_a_pending_script_fetch() {
+        let page = r#"<html><head><script src="/stalled.js"></script></head><body><div>Hi</div></body></html>"#;
+        let mut engine = VeloxEngine::new(800.0, 600.0).with_loader(Arc::new(StallingLoader { page }));
+        engine.init_javascript(&DOMNode::new(NodeType::Document)).unwrap();
+        let token = CancellationToken::new();
+        let canceller = cancel_soon(&token);
+
+        let result = futures::executor::block_on(engine.load_url_cancellable("app://site/index.html", &token));
+        canceller.join().unwrap();
+        assert!(is_cancelled(&*result.unwrap_err()));
+        assert_eq!(engine.metrics().snapshot().fetches, 1, "only the page itself arrived");
+    }
+}
@@ -0,0 +1,113 @@
+// Resource loading: every document, stylesheet, script and image the engine fetches goes
+// through a `ResourceLoader`, so embedders can serve bundles, caches or sandboxed I/O
+
+use std::error::Error;
+
+use bytes::Bytes;
+use futures::future::BoxFuture;
+
+use crate::dom::node::{DOMArena, DOMNode, NodeType};
+
+pub type LoadResult = Result<Bytes, Box<dyn Error + Send + Sync>>;
+
+/// Fetches the bytes behind a URL. Implementations return a boxed future so the engine can
+/// hold any loader as `Arc<dyn ResourceLoader>`:
+///
+/// ```ignore
+/// fn fetch<'a>(&'a self, url: &'a str) -> BoxFuture<'a, LoadResult> {
+///     Box::pin(async move { Ok(self.files.get(url).cloned().ok_or("not found")?) })
+/// }
+/// ```
+pub trait ResourceLoader: Send + Sync {
+    fn fetch<'a>(&'a self, url: &'a str) -> BoxFuture<'a, LoadResult>;
+}
+
+/// The default loader, fetching over HTTP(S) with reqwest
+#[derive(Debug, Default, Clone)]
+pub struct HttpLoader {
+    client: reqwest::Client,
+}
+
+impl HttpLoader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ResourceLoader for HttpLoader {
+    fn fetch<'a>(&'a self, url: &'a str) -> BoxFuture<'a, LoadResult> {
+        Box::pin(async move {
+            println!("[LOADER] Fetching: {}", url);
+            let response = self.client.get(url).send().await?.error_for_status()?;
+            Ok(response.bytes().await?)
+        })
+    }
+}
+
+/// External resources a document references, as written in the markup
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Subresources {
+    /// `<link rel="stylesheet" href>`
+    pub stylesheets: Vec<String>,
+    /// `<script src>`
+    pub scripts: Vec<String>,
+    /// `<img src>`
+    pub images: Vec<String>,
+}
+
+/// Collect the stylesheets, scripts and images under `root` in document order
+pub fn collect_subresources(root: &DOMNode, arena: &DOMArena) -> Subresources {
+    let mut found = Subresources::default();
+    collect(root, arena, &mut found);
+    found
+}
+
+fn collect(node: &DOMNode, arena: &DOMArena, found: &mut Subresources) {
+    if let NodeType::Element(tag) = &node.node_type {
+        let attribute = |name: &str| node.attributes.get(name).map(|v| v.trim()).filter(|v| !v.is_empty());
+        match tag.to_lowercase().as_str() {
+            "link" => {
+                let is_stylesheet = attribute("rel")
+                    .is_some_and(|rel| rel.split_whitespace().any(|r| r.eq_ignore_ascii_case("stylesheet")));
+                if let (true, Some(href)) = (is_stylesheet, attribute("href")) {
+                    found.stylesheets.push(href.to_string());
+                }
+            }
+            "script" => found.scripts.extend(attribute("src").map(str::to_string)),
+            "img" => found.images.extend(attribute("src").map(str::to_string)),
+            _ => {}
+        }
+    }
+    for child_id in &node.children {
+        if let Some(child) = arena.get_node(child_id) {
+            collect(&child.lock().unwrap(), arena, found);
+        }
+    }
+}
+
+/// Resolve `href` against the document URL `base`. Handles absolute, scheme-relative,
+/// root-relative and path-relative references; no `..` normalisation.
+pub fn resolve_url(base: &str, href: &str) -> String {
+    if href.contains("://") || href.starts_with("data:") {
+        return href.to_string();
+    }
+    let Some((scheme, rest)) = base.split_once("://") else {
+        return href.to_string();
+    };
+    if let Some(authority_and_path) = href.strip_prefix("//") {
+        return format!("{}://{}", scheme, authority_and_path);
+    }
+    let origin_len = scheme.len() + 3 + rest.find('/').unwrap_or(rest.len());
+    if href.starts_with('/') {
+        return format!("{}{}", &base[..origin_len], href);
+    }
+    // Drop the query/fragment and the last path segment of the base
+    let path_end = base.find(['?', '#']).unwrap_or(base.len());
+    let directory_end = base[..path_end].rfind('/').filter(|&i| i >= origin_len).map_or(origin_len, |i| i + 1);
+    let directory = &base[..directory_end];
+    if directory.ends_with('/') {
+        format!("{}{}", directory, href)
+    } else {
+        format!("{}/{}", directory, href)
+    }
+}
@@ -0,0 +1,91 @@
+// Engine metrics: cheap atomic counters hosts can read to tune caching and fetching
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+
+/// Callback fed a snapshot every `interval` layout passes
+type MetricsCallback = Arc<dyn Fn(&MetricsSnapshot) + Send + Sync>;
+
+/// Counters shared by the engine's caches, fetchers and layout passes
+#[derive(Default)]
+pub struct Metrics {
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    fetches: AtomicU64,
+    bytes_downloaded: AtomicU64,
+    layout_passes: AtomicU64,
+    callback: Mutex<Option<(u64, MetricsCallback)>>,
+}
+
+/// Point-in-time copy of the counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct MetricsSnapshot {
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    pub fetches: u64,
+    pub bytes_downloaded: u64,
+    pub layout_passes: u64,
+}
+
+impl MetricsSnapshot {
+    /// Fraction of cache lookups that hit, or 0 before any lookup
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.cache_hits + self.cache_misses;
+        if lookups == 0 { 0.0 } else { self.cache_hits as f64 / lookups as f64 }
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_cache_hit(&self) {
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_cache_miss(&self) {
+        self.cache_misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_fetch(&self, bytes: u64) {
+        self.fetches.fetch_add(1, Ordering::Relaxed);
+        self.bytes_downloaded.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Count a layout pass and fire the periodic callback when one is due
+    pub fn record_layout_pass(&self) {
+        let passes = self.layout_passes.fetch_add(1, Ordering::Relaxed) + 1;
+        // Called outside the lock so the callback may itself read or replace it
+        let due = self.callback.lock().unwrap().as_ref()
+            .filter(|(interval, _)| passes.is_multiple_of(*interval))
+            .map(|(_, callback)| callback.clone());
+        if let Some(callback) = due {
+            callback(&self.snapshot());
+        }
+    }
+
+    /// Call `callback` with a snapshot every `interval` layout passes (an interval of 0 is treated as 1)
+    pub fn set_callback<F>(&self, interval: u64, callback: F)
+    where
+        F: Fn(&MetricsSnapshot) + Send + Sync + 'static,
+    {
+        *self.callback.lock().unwrap() = Some((interval.max(1), Arc::new(callback)));
+    }
+
+    pub fn clear_callback(&self) {
+        *self.callback.lock().unwrap() = None;
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            cache_hits: self.cache_hits.load(Ordering::Relaxed),
+            cache_misses: self.cache_misses.load(Ordering::Relaxed),
+            fetches: self.fetches.load(Ordering::Relaxed),
+            bytes_downloaded: self.bytes_downloaded.load(Ordering::Relaxed),
+            layout_passes: self.layout_passes.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,79 @@
+// clip-path: resolve `inset()` and `circle()` against a box into clip geometry for the display list
+
+use crate::dom::node::{box_sides, LayoutBox};
+
+/// A clip region in page coordinates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClipShape {
+    Rect { x: f32, y: f32, w: f32, h: f32 },
+    Circle { cx: f32, cy: f32, r: f32 },
+}
+
+impl ClipShape {
+    pub fn contains(&self, px: f32, py: f32) -> bool {
+        match *self {
+            ClipShape::Rect { x, y, w, h } => px >= x && px < x + w && py >= y && py < y + h,
+            ClipShape::Circle { cx, cy, r } => (px - cx).powi(2) + (py - cy).powi(2) <= r * r,
+        }
+    }
+}
+
+/// The clip for a box's `clip-path`, or None for `none` and unsupported shapes.
+/// Supports `inset(<1-4 lengths> [round ...])` and `circle([<radius>] [at <x> <y>])`,
+/// with px or % lengths; the `round` radii are ignored.
+pub fn clip_shape(b: &LayoutBox) -> Option<ClipShape> {
+    let value = b.clip_path.trim();
+    let (function, args) = value.strip_suffix(')')?.split_once('(')?;
+    match function.trim() {
+        "inset" => {
+            let offsets = args.split(" round ").next().unwrap_or("");
+            let [top, right, bottom, left] = box_sides(offsets)?;
+            let top = length(&top, b.height)?;
+            let right = length(&right, b.width)?;
+            let bottom = length(&bottom, b.height)?;
+            let left = length(&left, b.width)?;
+            Some(ClipShape::Rect {
+                x: b.x + left,
+                y: b.y + top,
+                w: (b.width - left - right).max(0.0),
+                h: (b.height - top - bottom).max(0.0),
+            })
+        }
+        "circle" => {
+            let tokens: Vec<&str> = args.split_whitespace().collect();
+            let at = tokens.iter().position(|&t| t == "at").unwrap_or(tokens.len());
+            let (cx, cy) = match tokens.get(at + 1..).unwrap_or(&[]) {
+                [] => (b.width / 2.0, b.height / 2.0),
+                [x] => (position_offset(x, b.width)?, b.height / 2.0),
+                [x, y] => (position_offset(x, b.width)?, position_offset(y, b.height)?),
+                _ => return None,
+            };
+            let r = match tokens[..at].join(" ").as_str() {
+                "" | "closest-side" => cx.min(b.width - cx).min(cy).min(b.height - cy),
+                "farthest-side" => cx.max(b.width - cx).max(cy).max(b.height - cy),
+                // Percentages resolve against the box diagonal over sqrt(2)
+                radius => length(radius, (b.width.powi(2) + b.height.powi(2)).sqrt() / std::f32::consts::SQRT_2)?,
+            };
+            Some(ClipShape::Circle { cx: b.x + cx, cy: b.y + cy, r: r.max(0.0) })
+        }
+        _ => None,
+    }
+}
+
+pub(crate) fn length(value: &str, basis: f32) -> Option<f32> {
+    let value = value.trim();
+    if let Some(percent) = value.strip_suffix('%') {
+        return percent.trim().parse::<f32>().ok().map(|p| basis * p / 100.0);
+    }
+    value.strip_suffix("px").unwrap_or(value).trim().parse().ok()
+}
+
+/// A `circle()` position component: a length, a percentage or a keyword
+pub(crate) fn position_offset(value: &str, extent: f32) -> Option<f32> {
+    match value {
+        "left" | "top" => Some(0.0),
+        "center" => Some(extent / 2.0),
+        "right" | "bottom" => Some(extent),
+        value => length(value, extent),
+    }
+}
@@ -0,0 +1,75 @@
+// border-radius and box-shadow: resolve a box's decoration styles into paint geometry
+
+use crate::dom::node::{split_outside_parens, split_top_level_commas, LayoutBox};
+
+/// An outer `box-shadow`, with lengths in px
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoxShadow {
+    pub offset_x: f32,
+    pub offset_y: f32,
+    pub blur: f32,
+    pub spread: f32,
+    pub color: String,
+}
+
+/// The box's corner radius in px, or 0 for square corners. Corners share one radius: with
+/// several values the first is used. Percentages resolve against the shorter side, and the
+/// radius is capped at half of it.
+pub fn corner_radius(b: &LayoutBox) -> f32 {
+    let shorter = b.width.min(b.height);
+    let first = b.border_radius.split(['/', ' ']).find(|value| !value.is_empty()).unwrap_or("0");
+    let radius = match first.strip_suffix('%') {
+        Some(percent) => percent.parse::<f32>().map(|p| shorter * p / 100.0).unwrap_or(0.0),
+        None => first.strip_suffix("px").unwrap_or(first).parse().unwrap_or(0.0),
+    };
+    radius.clamp(0.0, shorter.max(0.0) / 2.0)
+}
+
+/// The first shadow in `box-shadow`: `<offset-x> <offset-y> [<blur> [<spread>]]` with an
+/// optional `<color>` before or after the lengths. None for `none`, `inset` shadows and
+/// anything unparseable. Without a color the shadow takes the box's text color, like
+/// `currentcolor`.
+pub fn box_shadow(b: &LayoutBox) -> Option<BoxShadow> {
+    let first = split_top_level_commas(&b.box_shadow)[0];
+    let mut lengths = Vec::new();
+    let mut color = None;
+    // Set once a color follows the lengths; the lengths can't continue after it
+    let mut lengths_done = false;
+    for token in split_outside_parens(first) {
+        if token == "inset" || token == "none" {
+            return None;
+        }
+        match parse_length(token) {
+            Some(length) if !lengths_done => lengths.push(length),
+            Some(_) => return None,
+            None if color.is_none() => {
+                lengths_done = !lengths.is_empty();
+                color = Some(token.to_string());
+            }
+            None => return None,
+        }
+    }
+    let (offset_x, offset_y, blur, spread) = match lengths.as_slice() {
+        [x, y] => (*x, *y, 0.0, 0.0),
+        [x, y, blur] => (*x, *y, *blur, 0.0),
+        [x, y, blur, spread] => (*x, *y, *blur, *spread),
+        _ => return None,
+    };
+    Some(BoxShadow {
+        offset_x,
+        offset_y,
+        blur: blur.max(0.0),
+        spread,
+        color: color.unwrap_or_else(|| b.color.clone()),
+    })
+}
+
+/// A px length; unitless 0 is allowed, as in CSS
+fn parse_length(token: &str) -> Option<f32> {
+    match token.strip_suffix("px") {
+        Some(number) => number.parse().ok(),
+        None if token == "0" => Some(0.0),
+        None => None,
+    }
+}
+
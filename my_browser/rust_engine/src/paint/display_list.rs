@@ -1,8 +1,22 @@
+use crate::paint::clip::ClipShape;
+
 #[derive(Debug, Clone)]
 pub enum DrawCommand {
     Rect { x: f32, y: f32, w: f32, h: f32, color: u32 },
-    Text { x: f32, y: f32, content: String, font: String, size: f32, color: u32 },
+    // `italic` is set for both `font-style: italic` and `oblique`
+    Text { x: f32, y: f32, content: String, font: String, size: f32, color: u32, italic: bool },
     Image { x: f32, y: f32, src: String },
+    // Compositor layer boundaries: commands between a BeginLayer and its EndLayer
+    // are rasterized on their own layer
+    BeginLayer { id: u32, x: f32, y: f32, w: f32, h: f32 },
+    EndLayer { id: u32 },
+    // `clip-path`: commands up to the matching PopClip only draw inside the shape
+    PushClip { shape: ClipShape },
+    PopClip,
+    // `opacity` group: commands up to the matching EndOpacity are drawn offscreen and blended
+    // at `alpha`. Groups nest, so nested opacities multiply.
+    BeginOpacity { alpha: f32 },
+    EndOpacity,
 }
 
 pub type DisplayList = Vec<DrawCommand>; 
\ No newline at end of file
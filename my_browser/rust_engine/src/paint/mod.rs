@@ -1,2 +1,6 @@
 pub mod painter;
-pub mod display_list; 
\ No newline at end of file
+pub mod display_list;
+pub mod stacking;
+pub mod clip;
+pub mod decoration;
+pub mod transform;
@@ -1,5 +1,8 @@
 use crate::dom::node::LayoutBox;
+use crate::paint::clip::clip_shape;
 use crate::paint::display_list::{DrawCommand, DisplayList};
+use crate::paint::stacking::paint_order;
+use crate::style::color::Color;
 
 pub struct Painter;
 
@@ -14,53 +17,357 @@ impl Painter {
     }
 
     pub fn from_layout_boxes(layout_boxes: &[LayoutBox]) -> DisplayList {
+        Self::paint_groups(layout_boxes, false)
+    }
+
+    /// Paint boxes in stacking order, bracketing each `clip-path` box's subtree with
+    /// PushClip/PopClip and, with `opacity_groups`, each translucent box's subtree with
+    /// BeginOpacity/EndOpacity
+    pub(crate) fn paint_groups(layout_boxes: &[LayoutBox], opacity_groups: bool) -> DisplayList {
         let mut display_list = Vec::new();
-        for b in layout_boxes {
-            // Draw background rect if not transparent
-            if b.background_color != "transparent" && !b.background_color.is_empty() {
-                display_list.push(DrawCommand::Rect {
-                    x: b.x,
-                    y: b.y,
-                    w: b.width,
-                    h: b.height,
-                    color: parse_color(&b.background_color),
-                });
+        let borders = Self::border_segments(layout_boxes);
+        let text_descendants = Self::text_descendants(layout_boxes);
+        // Open brackets as (owning box, closing command), innermost last
+        let mut open: Vec<(usize, DrawCommand)> = Vec::new();
+        for index in paint_order(layout_boxes) {
+            while let Some((owner, _)) = open.last() {
+                if is_ancestor_or_self(layout_boxes, *owner, index) {
+                    break;
+                }
+                display_list.extend(open.pop().map(|(_, close)| close));
             }
-            // Draw text if present
-            if !b.text_content.is_empty() {
-                display_list.push(DrawCommand::Text {
-                    x: b.x,
-                    y: b.y,
-                    content: b.text_content.clone(),
-                    font: b.font_family.clone(),
-                    size: b.font_size,
-                    color: parse_color(&b.color),
-                });
+            let b = &layout_boxes[index];
+            // The group encloses the clip: opacity applies to the clipped result
+            if opacity_groups && b.opacity < 1.0 {
+                display_list.push(DrawCommand::BeginOpacity { alpha: b.opacity.max(0.0) });
+                open.push((index, DrawCommand::EndOpacity));
             }
-            // TODO: Add border, image, etc.
+            if let Some(shape) = clip_shape(b) {
+                display_list.push(DrawCommand::PushClip { shape });
+                open.push((index, DrawCommand::PopClip));
+            }
+            Self::paint_box(layout_boxes, index, &borders, &text_descendants, &mut display_list);
         }
+        display_list.extend(open.into_iter().rev().map(|(_, close)| close));
         display_list
     }
+
+    /// Emit the draw commands for a single box; `borders` comes from `border_segments` and
+    /// `text_descendants` from `text_descendants`
+    pub fn paint_box(layout_boxes: &[LayoutBox], index: usize, borders: &[BorderSegment], text_descendants: &[bool], display_list: &mut DisplayList) {
+        let b = &layout_boxes[index];
+        // `visibility: hidden` keeps the box's space but draws nothing
+        if b.visibility == "hidden" {
+            return;
+        }
+        // Draw background rect if not transparent
+        if b.background_color != "transparent" && !b.background_color.is_empty() {
+            display_list.push(DrawCommand::Rect {
+                x: b.x,
+                y: b.y,
+                w: b.width,
+                h: b.height,
+                color: parse_color(&b.background_color),
+            });
+        }
+        // Draw text if present; an element's text is drawn by its text boxes when it has any
+        if !b.text_content.is_empty() && (b.node_type == "text" || !text_descendants[index]) {
+            display_list.push(DrawCommand::Text {
+                x: b.x,
+                y: b.y,
+                content: b.text_content.clone(),
+                font: b.font_family.clone(),
+                size: b.font_size,
+                color: parse_color(&b.color),
+                italic: is_slanted(&b.font_style),
+            });
+        }
+        // Borders are painted after the background of the last box that owns them
+        for segment in borders.iter().filter(|segment| segment.owner == index) {
+            display_list.push(DrawCommand::Rect {
+                x: segment.x,
+                y: segment.y,
+                w: segment.width,
+                h: segment.height,
+                color: parse_color(&segment.color),
+            });
+        }
+        // TODO: Add image, etc.
+    }
+
+    /// For each box, whether a `text` box laid out for one of its descendants follows it.
+    /// Children come after their parents, so one pass from the end hands it up the tree.
+    pub fn text_descendants(layout_boxes: &[LayoutBox]) -> Vec<bool> {
+        let mut has_text = vec![false; layout_boxes.len()];
+        for index in (0..layout_boxes.len()).rev() {
+            if let Some(parent) = layout_boxes[index].parent_index.filter(|&parent| parent < index) {
+                has_text[parent] |= has_text[index] || layout_boxes[index].node_type == "text";
+            }
+        }
+        has_text
+    }
+
+    /// Compute the border edges for every box. Tables with `border-collapse: collapse`
+    /// share a single border between adjacent cells, resolved to the wider one;
+    /// otherwise each box draws its own four edges.
+    pub fn border_segments(layout_boxes: &[LayoutBox]) -> Vec<BorderSegment> {
+        let mut segments = Vec::new();
+        let mut index = 0;
+        while index < layout_boxes.len() {
+            let b = &layout_boxes[index];
+            if b.node_type == "table" {
+                // Rows and cells directly follow their table in tree order
+                let end = index + 1 + layout_boxes[index + 1..].iter()
+                    .take_while(|part| is_table_part(&part.node_type))
+                    .count();
+                if b.border_collapse == "collapse" {
+                    segments.extend(collapsed_table_borders(layout_boxes, index, end));
+                } else {
+                    // Separate model: rows draw no borders of their own
+                    for (part, cell) in layout_boxes[index..end].iter().enumerate() {
+                        if cell.node_type != "tr" {
+                            segments.extend(box_border_edges(cell, index + part));
+                        }
+                    }
+                }
+                index = end;
+            } else {
+                segments.extend(box_border_edges(b, index));
+                index += 1;
+            }
+        }
+        segments
+    }
+}
+
+/// A solid border edge, painted as a filled rect after the background of box `owner`
+#[derive(Debug, Clone, PartialEq)]
+pub struct BorderSegment {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    pub color: String,
+    pub owner: usize,
+}
+
+/// Whether a computed `font-style` draws slanted glyphs (`italic`, `oblique` or `oblique <angle>`)
+pub fn is_slanted(font_style: &str) -> bool {
+    let font_style = font_style.trim();
+    font_style == "italic" || font_style.starts_with("oblique")
+}
+
+/// Whether `ancestor` is `index` or one of its ancestors by `parent_index`
+pub(crate) fn is_ancestor_or_self(layout_boxes: &[LayoutBox], ancestor: usize, index: usize) -> bool {
+    let mut current = Some(index);
+    while let Some(i) = current {
+        if i == ancestor {
+            return true;
+        }
+        current = layout_boxes[i].parent_index.filter(|&parent| parent < i);
+    }
+    false
+}
+
+fn is_table_part(node_type: &str) -> bool {
+    matches!(node_type, "tr" | "td" | "th")
+}
+
+fn has_visible_border(b: &LayoutBox) -> bool {
+    !b.border_color.is_empty() && b.border_color != "transparent"
+}
+
+fn box_border_edges(b: &LayoutBox, owner: usize) -> Vec<BorderSegment> {
+    let mut edges = Vec::new();
+    if !has_visible_border(b) {
+        return edges;
+    }
+    let bw = &b.border_width;
+    let sides = [
+        (bw.top, b.x, b.y, b.width, bw.top),
+        (bw.right, b.x + b.width - bw.right, b.y, bw.right, b.height),
+        (bw.bottom, b.x, b.y + b.height - bw.bottom, b.width, bw.bottom),
+        (bw.left, b.x, b.y, bw.left, b.height),
+    ];
+    for (width, x, y, w, h) in sides {
+        if width > 0.0 {
+            edges.push(BorderSegment { x, y, width: w, height: h, color: b.border_color.clone(), owner });
+        }
+    }
+    edges
+}
+
+/// Resolve collapsed borders for the table at `table` whose rows and cells occupy `table + 1..end`.
+/// Every cell edge becomes one grid-line segment; rows and the table contribute to the edges
+/// that lie on their own outline, and conflicting widths resolve to the largest.
+fn collapsed_table_borders(layout_boxes: &[LayoutBox], table: usize, end: usize) -> Vec<BorderSegment> {
+    const EPSILON: f32 = 0.01;
+    let near = |a: f32, b: f32| (a - b).abs() < EPSILON;
+
+    // (vertical, line, start, end) -> (width, color, owner)
+    let mut edges: Vec<((bool, f32, f32, f32), (f32, String, usize))> = Vec::new();
+    let mut add_candidate = |key: (bool, f32, f32, f32), width: f32, b: &LayoutBox, owner: usize| {
+        if let Some((_, best)) = edges.iter_mut().find(|(k, _)| k.0 == key.0 && near(k.1, key.1) && near(k.2, key.2) && near(k.3, key.3)) {
+            if width > best.0 {
+                best.0 = width;
+                best.1 = b.border_color.clone();
+            }
+            best.2 = best.2.max(owner);
+        } else {
+            edges.push((key, (width, b.border_color.clone(), owner)));
+        }
+    };
+
+    let outlines: Vec<usize> = std::iter::once(table)
+        .chain((table + 1..end).filter(|&i| layout_boxes[i].node_type == "tr"))
+        .collect();
+
+    for cell_index in (table + 1..end).filter(|&i| layout_boxes[i].node_type != "tr") {
+        let c = &layout_boxes[cell_index];
+        let (left, top, right, bottom) = (c.x, c.y, c.x + c.width, c.y + c.height);
+        let cell_edges = [
+            ((false, top, left, right), c.border_width.top),
+            ((false, bottom, left, right), c.border_width.bottom),
+            ((true, left, top, bottom), c.border_width.left),
+            ((true, right, top, bottom), c.border_width.right),
+        ];
+        for (key, width) in cell_edges {
+            let width = if has_visible_border(c) { width } else { 0.0 };
+            add_candidate(key, width, c, cell_index);
+            // Rows and the table only contribute where their outline coincides with this edge
+            for &outline in &outlines {
+                let o = &layout_boxes[outline];
+                if !has_visible_border(o) {
+                    continue;
+                }
+                let (vertical, line, _, _) = key;
+                let width = if vertical && near(line, o.x) {
+                    o.border_width.left
+                } else if vertical && near(line, o.x + o.width) {
+                    o.border_width.right
+                } else if !vertical && near(line, o.y) {
+                    o.border_width.top
+                } else if !vertical && near(line, o.y + o.height) {
+                    o.border_width.bottom
+                } else {
+                    continue;
+                };
+                add_candidate(key, width, o, cell_index);
+            }
+        }
+    }
+
+    edges.into_iter()
+        .filter(|(_, (width, _, _))| *width > 0.0)
+        .map(|((vertical, line, start, end), (width, color, owner))| {
+            // Collapsed borders are centred on the grid line
+            if vertical {
+                BorderSegment { x: line - width / 2.0, y: start, width, height: end - start, color, owner }
+            } else {
+                BorderSegment { x: start, y: line - width / 2.0, width: end - start, height: width, color, owner }
+            }
+        })
+        .collect()
 }
 
+/// Packed ARGB for the display list; unparseable colors paint black
 fn parse_color(s: &str) -> u32 {
-    // Very basic: expects #RRGGBB or #AARRGGBB
-    if s.starts_with('#') {
-        let hex = &s[1..];
-        if hex.len() == 6 {
-            // #RRGGBB
-            let r = u8::from_str_radix(&hex[0..2], 16).unwrap_or(0);
-            let g = u8::from_str_radix(&hex[2..4], 16).unwrap_or(0);
-            let b = u8::from_str_radix(&hex[4..6], 16).unwrap_or(0);
-            return (0xFF << 24) | ((r as u32) << 16) | ((g as u32) << 8) | (b as u32);
-        } else if hex.len() == 8 {
-            // #AARRGGBB
-            let a = u8::from_str_radix(&hex[0..2], 16).unwrap_or(0xFF);
-            let r = u8::from_str_radix(&hex[2..4], 16).unwrap_or(0);
-            let g = u8::from_str_radix(&hex[4..6], 16).unwrap_or(0);
-            let b = u8::from_str_radix(&hex[6..8], 16).unwrap_or(0);
-            return ((a as u32) << 24) | ((r as u32) << 16) | ((g as u32) << 8) | (b as u32);
+    Color::parse_or(s, Color::BLACK).to_argb()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dom::node::{DOMArena, DOMNode};
+    use crate::layout::layout::LayoutEngine;
+    use crate::paint::clip::ClipShape;
+    use crate::parser::html::HTMLParser;
+
+    fn table_boxes(table_style: &str, cell_styles: [&str; 2]) -> Vec<LayoutBox> {
+        let mut arena = DOMArena::new();
+        let mut table = DOMNode::create_element("table");
+        table.set_attribute("style".to_string(), table_style.to_string());
+        let mut row = DOMNode::create_element("tr");
+        for style in cell_styles {
+            let mut cell = DOMNode::create_element("td");
+            cell.set_attribute("style".to_string(), style.to_string());
+            let cell_id = cell.id.clone();
+            arena.add_node(cell);
+            row.add_child(cell_id, &mut arena);
         }
+        let row_id = row.id.clone();
+        arena.add_node(row);
+        table.add_child(row_id, &mut arena);
+        arena.add_node(table.clone());
+        LayoutEngine::new(800.0, 600.0).layout(&table, &arena)
     }
-    0xFF000000 // Default to opaque black
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_text_descendants_reach_every_ancestor() {
+        let boxed = |node_type: &str, parent_index: Option<usize>| {
+            let mut b = LayoutBox::new();
+            b.node_type = node_type.to_string();
+            b.parent_index = parent_index;
+            b
+        };
+        let boxes = vec![boxed("div", None), boxed("span", Some(0)), boxed("text", Some(1)), boxed("p", Some(0)), boxed("img", Some(3))];
+
+        assert_eq!(Painter::text_descendants(&boxes), vec![true, true, false, false, false]);
+    }
+
+    #[test]
+    fn test_collapsed_and_separate_table_borders() {
+        let boxes = table_boxes(
+            "border-collapse: collapse; border-width: 1px; width: 200px",
+            ["border-width: 1px", "border-width: 3px"],
+        );
+        let segments = Painter::border_segments(&boxes);
+        // One shared border on the line between the cells, resolved to the wider 3px
+        let shared: Vec<_> = segments.iter().filter(|s| s.height > s.width && (s.x + s.width / 2.0 - 100.0).abs() < 0.01).collect();
+        assert_eq!(shared.len(), 1);
+        assert_eq!(shared[0].width, 3.0);
+        // Left, shared and right verticals plus a top and bottom edge per cell
+        assert_eq!(segments.len(), 7);
+
+        let boxes = table_boxes(
+            "border-spacing: 4px; border-width: 1px; width: 210px",
+            ["border-width: 1px", "border-width: 1px"],
+        );
+        let cells: Vec<_> = boxes.iter().filter(|b| b.node_type == "td").collect();
+        assert_eq!(cells[1].x - (cells[0].x + cells[0].width), 4.0);
+        assert_eq!(cells[0].x - boxes[0].x, 5.0);
+        let segments = Painter::border_segments(&boxes);
+        // Four edges for the table and four for each cell
+        assert_eq!(segments.len(), 12);
+        assert_eq!(segments.iter().filter(|s| s.owner == 0).count(), 4);
+    }
+
+    #[test]
+    fn test_clip_path_wraps_subtree_in_clip() {
+        let display_list = |clip_path: &str| {
+            let html = format!(r#"<body><div style="width: 100px; height: 100px; clip-path: {}"><p>Inside</p></div><p>After</p></body>"#, clip_path);
+            let mut parser = HTMLParser::new(html).with_reuse();
+            let dom = parser.parse();
+            let boxes = LayoutEngine::new(800.0, 600.0).layout(&dom, parser.arena().unwrap());
+            let div = boxes.iter().find(|b| b.node_type == "div").unwrap().clone();
+            (div, Painter::from_layout_boxes(&boxes))
+        };
+
+        let (div, commands) = display_list("inset(10px)");
+        let push = commands.iter().position(|c| matches!(c, DrawCommand::PushClip { .. })).unwrap();
+        let pop = commands.iter().position(|c| matches!(c, DrawCommand::PopClip)).unwrap();
+        let DrawCommand::PushClip { shape } = commands[push] else { unreachable!() };
+        assert_eq!(shape, ClipShape::Rect { x: div.x + 10.0, y: div.y + 10.0, w: div.width - 20.0, h: div.height - 20.0 });
+        assert!(!shape.contains(div.x + 5.0, div.y + 5.0));
+        let clipped_text = |range: std::ops::Range<usize>| commands[range].iter()
+            .filter_map(|c| match c { DrawCommand::Text { content, .. } => Some(content.clone()), _ => None })
+            .collect::<Vec<_>>();
+        assert!(clipped_text(push..pop).iter().any(|t| t.contains("Inside")), "the div's content is inside the clip");
+        assert!(clipped_text(pop..commands.len()).iter().any(|t| t.contains("After")), "the sibling is painted unclipped");
+
+        let (div, commands) = display_list("circle(50%)");
+        let shapes: Vec<ClipShape> = commands.iter().filter_map(|c| match c { DrawCommand::PushClip { shape } => Some(*shape), _ => None }).collect();
+        let radius = (div.width.powi(2) + div.height.powi(2)).sqrt() / std::f32::consts::SQRT_2 / 2.0;
+        assert_eq!(shapes, [ClipShape::Circle { cx: div.x + div.width / 2.0, cy: div.y + div.height / 2.0, r: radius }]);
+    }
+}
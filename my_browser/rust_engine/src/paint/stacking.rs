@@ -0,0 +1,173 @@
+// Stacking contexts and CSS painting order
+// Rebuilds the box tree from `parent_index` and orders boxes as CSS 2.1 Appendix E describes (simplified)
+
+use crate::dom::node::{LayoutBox, ZIndex};
+
+/// Whether the box is positioned, i.e. `position` is anything but `static`
+pub fn is_positioned(b: &LayoutBox) -> bool {
+    matches!(b.position.as_str(), "relative" | "absolute" | "fixed" | "sticky")
+}
+
+/// Whether the box starts its own stacking context: a positioned box with an integer
+/// `z-index`, fixed/sticky boxes, `opacity` below 1, or any transform
+pub fn creates_stacking_context(b: &LayoutBox) -> bool {
+    (is_positioned(b) && b.z_index != ZIndex::Auto)
+        || matches!(b.position.as_str(), "fixed" | "sticky")
+        || b.opacity < 1.0
+        || !(b.transform.is_empty() || b.transform == "none")
+}
+
+/// Box indices in painting order. Within each stacking context: the context's own box,
+/// negative z-index contexts, in-flow non-positioned content in tree order, positioned
+/// `z-index: auto`/0 content in tree order, then positive z-index contexts.
+/// Boxes without a `parent_index` are painted as root contexts in index order.
+pub fn paint_order(boxes: &[LayoutBox]) -> Vec<usize> {
+    let mut children = vec![Vec::new(); boxes.len()];
+    let mut roots = Vec::new();
+    for (index, b) in boxes.iter().enumerate() {
+        match b.parent_index.filter(|&parent| parent < index) {
+            Some(parent) => children[parent].push(index),
+            None => roots.push(index),
+        }
+    }
+
+    // Each root box (normally <body>) anchors the root stacking context
+    let mut order = Vec::with_capacity(boxes.len());
+    for root in roots {
+        paint_context(boxes, &children, root, &mut order);
+    }
+    order
+}
+
+/// Paint one stacking context and everything stacked inside it
+fn paint_context(boxes: &[LayoutBox], children: &[Vec<usize>], index: usize, order: &mut Vec<usize>) {
+    order.push(index);
+    paint_layers(boxes, children, &children[index], order);
+}
+
+/// An entry of the positioned layer: a `z-index: 0` context, painted with everything inside
+/// it, or a positioned `z-index: auto` box followed by its in-flow content
+enum Positioned {
+    Context(usize),
+    Auto(Vec<usize>),
+}
+
+fn paint_layers(boxes: &[LayoutBox], children: &[Vec<usize>], kids: &[usize], order: &mut Vec<usize>) {
+    let mut negative = Vec::new();
+    let mut in_flow = Vec::new();
+    let mut positioned = Vec::new();
+    let mut positive = Vec::new();
+    collect_layers(boxes, children, kids, &mut negative, &mut in_flow, &mut positioned, &mut positive);
+
+    // Stable sorts keep tree order among equal z-index values
+    negative.sort_by_key(|&(z, _)| z);
+    positive.sort_by_key(|&(z, _)| z);
+
+    for (_, index) in negative {
+        paint_context(boxes, children, index, order);
+    }
+    order.extend(in_flow);
+    for entry in positioned {
+        match entry {
+            Positioned::Context(index) => paint_context(boxes, children, index, order),
+            Positioned::Auto(content) => order.extend(content),
+        }
+    }
+    for (_, index) in positive {
+        paint_context(boxes, children, index, order);
+    }
+}
+
+/// Sort descendants into the layers of the current context. In-flow boxes are walked into and
+/// nested contexts are painted atomically at their layer. A positioned `z-index: auto` box
+/// takes its in-flow content with it, but the positioned boxes and contexts inside it still
+/// stack in the current context.
+fn collect_layers(
+    boxes: &[LayoutBox],
+    children: &[Vec<usize>],
+    kids: &[usize],
+    negative: &mut Vec<(i32, usize)>,
+    in_flow: &mut Vec<usize>,
+    positioned: &mut Vec<Positioned>,
+    positive: &mut Vec<(i32, usize)>,
+) {
+    for &index in kids {
+        let b = &boxes[index];
+        if creates_stacking_context(b) {
+            match b.z_index.level() {
+                z if z < 0 => negative.push((z, index)),
+                0 => positioned.push(Positioned::Context(index)),
+                z => positive.push((z, index)),
+            }
+        } else if is_positioned(b) {
+            // Reserve the box's slot first so positioned descendants follow it in tree order
+            let slot = positioned.len();
+            positioned.push(Positioned::Auto(Vec::new()));
+            let mut content = vec![index];
+            collect_layers(boxes, children, &children[index], negative, &mut content, positioned, positive);
+            positioned[slot] = Positioned::Auto(content);
+        } else {
+            in_flow.push(index);
+            collect_layers(boxes, children, &children[index], negative, in_flow, positioned, positive);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn styled_box(parent: Option<usize>, position: &str, z_index: &str) -> LayoutBox {
+        let mut b = LayoutBox::new();
+        b.parent_index = parent;
+        b.position = position.to_string();
+        b.z_index = ZIndex::parse(z_index);
+        b
+    }
+
+    #[test]
+    fn test_z_index_auto_keeps_tree_order() {
+        let boxes = vec![
+            styled_box(None, "static", "auto"),
+            styled_box(Some(0), "relative", "auto"),
+            styled_box(Some(0), "static", "auto"),
+            styled_box(Some(0), "absolute", "auto"),
+            styled_box(Some(2), "static", "auto"),
+        ];
+        assert!(!boxes.iter().any(creates_stacking_context));
+        // In-flow content first, then positioned auto boxes in tree order
+        assert_eq!(paint_order(&boxes), vec![0, 2, 4, 1, 3]);
+    }
+
+    #[test]
+    fn test_explicit_z_index_forms_stacking_contexts() {
+        let boxes = vec![
+            styled_box(None, "static", "auto"),
+            styled_box(Some(0), "relative", "2"),
+            styled_box(Some(0), "relative", "-1"),
+            styled_box(Some(0), "static", "5"), // z-index on a static box is ignored
+            styled_box(Some(0), "relative", "1"),
+            styled_box(Some(4), "absolute", "100"), // confined to its parent's context
+        ];
+        assert_eq!(ZIndex::parse("auto"), ZIndex::Auto);
+        assert!(creates_stacking_context(&boxes[1]));
+        assert!(!creates_stacking_context(&boxes[3]));
+
+        assert_eq!(paint_order(&boxes), vec![0, 2, 3, 4, 5, 1]);
+    }
+
+    #[test]
+    fn test_z_index_inside_auto_box_stacks_in_parent_context() {
+        let boxes = vec![
+            styled_box(None, "static", "auto"),
+            styled_box(Some(0), "relative", "auto"),
+            styled_box(Some(1), "static", "auto"),
+            styled_box(Some(1), "absolute", "10"),
+            styled_box(Some(0), "relative", "5"),
+            styled_box(Some(1), "absolute", "-1"),
+        ];
+        // The z-index 10 child outranks its parent's z-index 5 sibling, and the -1 child
+        // drops below the parent's in-flow content
+        assert_eq!(paint_order(&boxes), vec![0, 5, 1, 2, 4, 3]);
+    }
+}
@@ -0,0 +1,145 @@
+// CSS transforms: resolve `transform` and `transform-origin` into a 2D affine matrix in page space
+
+use crate::dom::node::LayoutBox;
+use crate::paint::clip::{length, position_offset};
+
+/// A 2D affine matrix, as in CSS `matrix(a, b, c, d, e, f)`:
+/// `x' = a*x + c*y + e`, `y' = b*x + d*y + f`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub a: f32,
+    pub b: f32,
+    pub c: f32,
+    pub d: f32,
+    pub e: f32,
+    pub f: f32,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform::IDENTITY
+    }
+}
+
+impl Transform {
+    pub const IDENTITY: Transform = Transform { a: 1.0, b: 0.0, c: 0.0, d: 1.0, e: 0.0, f: 0.0 };
+
+    pub fn translate(x: f32, y: f32) -> Self {
+        Transform { e: x, f: y, ..Transform::IDENTITY }
+    }
+
+    pub fn scale(sx: f32, sy: f32) -> Self {
+        Transform { a: sx, d: sy, ..Transform::IDENTITY }
+    }
+
+    pub fn rotate(radians: f32) -> Self {
+        let (sin, cos) = radians.sin_cos();
+        Transform { a: cos, b: sin, c: -sin, d: cos, e: 0.0, f: 0.0 }
+    }
+
+    /// `self` after `other`: the result applies `other` first
+    pub fn then(&self, other: &Transform) -> Transform {
+        Transform {
+            a: self.a * other.a + self.c * other.b,
+            b: self.b * other.a + self.d * other.b,
+            c: self.a * other.c + self.c * other.d,
+            d: self.b * other.c + self.d * other.d,
+            e: self.a * other.e + self.c * other.f + self.e,
+            f: self.b * other.e + self.d * other.f + self.f,
+        }
+    }
+
+    pub fn apply(&self, x: f32, y: f32) -> (f32, f32) {
+        (self.a * x + self.c * y + self.e, self.b * x + self.d * y + self.f)
+    }
+}
+
+/// The box's own `transform` in page coordinates, applied around its `transform-origin`.
+/// Functions compose left to right as in CSS. Supports `translate()`/`translateX()`/
+/// `translateY()`, `scale()`/`scaleX()`/`scaleY()` and `rotate()`; the identity for `none`
+/// or anything unparseable.
+pub fn box_transform(b: &LayoutBox) -> Transform {
+    let Some(functions) = parse_functions(&b.transform, b) else { return Transform::IDENTITY };
+    if functions == Transform::IDENTITY {
+        return functions;
+    }
+    let (ox, oy) = origin(b);
+    Transform::translate(b.x + ox, b.y + oy)
+        .then(&functions)
+        .then(&Transform::translate(-(b.x + ox), -(b.y + oy)))
+}
+
+/// Each box's transform combined with its ancestors', following `parent_index`
+pub fn accumulated_transforms(boxes: &[LayoutBox]) -> Vec<Transform> {
+    let mut transforms: Vec<Transform> = Vec::with_capacity(boxes.len());
+    for b in boxes {
+        let own = box_transform(b);
+        // Parents always come before their children
+        let inherited = b.parent_index.and_then(|parent| transforms.get(parent)).copied().unwrap_or_default();
+        transforms.push(inherited.then(&own));
+    }
+    transforms
+}
+
+fn parse_functions(value: &str, b: &LayoutBox) -> Option<Transform> {
+    let mut matrix = Transform::IDENTITY;
+    let mut rest = value.trim();
+    if rest.is_empty() || rest == "none" {
+        return Some(matrix);
+    }
+    while !rest.is_empty() {
+        let (name, after) = rest.split_once('(')?;
+        let (args, after) = after.split_once(')')?;
+        let args: Vec<&str> = args.split(',').flat_map(str::split_whitespace).collect();
+        let function = match (name.trim().to_ascii_lowercase().as_str(), args.as_slice()) {
+            ("translate", [x]) => Transform::translate(length(x, b.width)?, 0.0),
+            ("translate", [x, y]) => Transform::translate(length(x, b.width)?, length(y, b.height)?),
+            ("translatex", [x]) => Transform::translate(length(x, b.width)?, 0.0),
+            ("translatey", [y]) => Transform::translate(0.0, length(y, b.height)?),
+            ("scale", [s]) => Transform::scale(scale_factor(s)?, scale_factor(s)?),
+            ("scale", [sx, sy]) => Transform::scale(scale_factor(sx)?, scale_factor(sy)?),
+            ("scalex", [sx]) => Transform::scale(scale_factor(sx)?, 1.0),
+            ("scaley", [sy]) => Transform::scale(1.0, scale_factor(sy)?),
+            ("rotate", [angle]) => Transform::rotate(parse_angle(angle)?),
+            _ => return None,
+        };
+        matrix = matrix.then(&function);
+        rest = after.trim_start();
+    }
+    Some(matrix)
+}
+
+/// `transform-origin` as an offset from the box's top-left; defaults to the center
+fn origin(b: &LayoutBox) -> (f32, f32) {
+    let parts: Vec<&str> = b.transform_origin.split_whitespace().collect();
+    let (x, y) = match parts.as_slice() {
+        // A lone vertical keyword sets y and centers x
+        [single] if matches!(*single, "top" | "bottom") => ("center", *single),
+        [single] => (*single, "center"),
+        [x, y, ..] => (*x, *y),
+        [] => ("center", "center"),
+    };
+    (
+        position_offset(x, b.width).unwrap_or(b.width / 2.0),
+        position_offset(y, b.height).unwrap_or(b.height / 2.0),
+    )
+}
+
+/// A scale factor: a number or a percentage
+fn scale_factor(value: &str) -> Option<f32> {
+    match value.strip_suffix('%') {
+        Some(percent) => percent.parse::<f32>().ok().map(|p| p / 100.0),
+        None => value.parse().ok(),
+    }
+}
+
+/// An angle in radians from `deg`, `rad`, `grad` or `turn`; unitless 0 is allowed
+fn parse_angle(value: &str) -> Option<f32> {
+    let units = [("deg", std::f32::consts::PI / 180.0), ("grad", std::f32::consts::PI / 200.0), ("rad", 1.0), ("turn", std::f32::consts::TAU)];
+    for (unit, factor) in units {
+        if let Some(number) = value.strip_suffix(unit) {
+            return number.parse::<f32>().ok().map(|n| n * factor);
+        }
+    }
+    (value == "0").then_some(0.0)
+}
@@ -0,0 +1,79 @@
+// HTML character references: named (`&amp;`), decimal (`&#65;`) and hex (`&#x41;`)
+
+/// Named references the decoder knows, without the `&` and `;`
+const NAMED_ENTITIES: &[(&str, char)] = &[
+    ("amp", '&'), ("lt", '<'), ("gt", '>'), ("quot", '"'), ("apos", '\''),
+    ("nbsp", '\u{a0}'), ("copy", '©'), ("reg", '®'), ("trade", '™'),
+    ("hellip", '…'), ("mdash", '—'), ("ndash", '–'), ("bull", '•'), ("middot", '·'),
+    ("lsquo", '‘'), ("rsquo", '’'), ("ldquo", '“'), ("rdquo", '”'),
+    ("laquo", '«'), ("raquo", '»'), ("deg", '°'), ("plusmn", '±'), ("times", '×'),
+    ("divide", '÷'), ("frac12", '½'), ("frac14", '¼'), ("frac34", '¾'),
+    ("euro", '€'), ("pound", '£'), ("yen", '¥'), ("cent", '¢'), ("sect", '§'), ("para", '¶'),
+    ("iexcl", '¡'), ("iquest", '¿'), ("shy", '\u{ad}'),
+    ("larr", '←'), ("rarr", '→'), ("uarr", '↑'), ("darr", '↓'), ("hearts", '♥'),
+];
+
+/// Replace character references with the characters they stand for. A reference must end
+/// in `;`; unknown names, invalid code points and unterminated sequences are left as written.
+pub fn decode_entities(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        decoded.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        // Longest named reference is short; don't scan far for the `;`
+        let reference = rest[1..].find(';').filter(|&end| end <= 32).map(|end| &rest[1..end + 1]);
+        match reference.and_then(decode_reference) {
+            Some(c) => {
+                decoded.push(c);
+                rest = &rest[reference.unwrap().len() + 2..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Decode the part between `&` and `;`
+fn decode_reference(reference: &str) -> Option<char> {
+    if let Some(number) = reference.strip_prefix('#') {
+        let code = match number.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => number.parse().ok()?,
+        };
+        return char::from_u32(code).filter(|&c| c != '\0');
+    }
+    NAMED_ENTITIES.iter().find(|(name, _)| *name == reference).map(|&(_, c)| c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::html::HTMLParser;
+
+    #[test]
+    fn test_entities_decode_in_text_and_attributes() {
+        assert_eq!(decode_entities("Tom &amp; Jerry"), "Tom & Jerry");
+        assert_eq!(decode_entities("&#65;&#x41;&#X42;"), "AAB");
+        assert_eq!(decode_entities("&lt;p&gt; &quot;hi&quot;&nbsp;&copy;"), "<p> \"hi\"\u{a0}©");
+        assert_eq!(decode_entities("AT&T &bogus; &#xZZ; &amp"), "AT&T &bogus; &#xZZ; &amp");
+
+        let mut parser = HTMLParser::new(r#"<body><p title="Tom &amp; Jerry">It&#39;s &#x41;</p></body>"#.to_string()).with_reuse();
+        parser.parse();
+        let arena = parser.arena().unwrap();
+        let find = |predicate: &dyn Fn(&crate::dom::node::DOMNode) -> bool| {
+            arena.nodes.values().map(|node| node.lock().unwrap().clone()).find(|node| predicate(node)).unwrap()
+        };
+        let paragraph = find(&|node| node.attributes.contains_key("title"));
+        assert_eq!(paragraph.attributes["title"], "Tom & Jerry");
+        let text = find(&|node| node.parent.as_deref() == Some(paragraph.id.as_str()));
+        assert_eq!(text.text_content, "It's A");
+    }
+}
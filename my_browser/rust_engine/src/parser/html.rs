@@ -3,6 +3,9 @@ use crate::parser::css::{parse_css, Stylesheet};
 use std::collections::HashMap;
 use std::time::Instant;
 use crate::dom::node::DOMArena;
+use crate::parser::entities::decode_entities;
+use crate::cancellation::CancellationToken;
+use encoding_rs::{Encoding, UTF_8};
 
 #[derive(Debug, Clone)]
 pub struct Token {
@@ -40,11 +43,12 @@ pub enum ParserState {
 }
 
 // Enhanced streaming HTML parser with better JavaScript and CSS handling
+#[derive(Clone)]
 pub struct StreamingHTMLParser {
     buffer: String,
     state: ParserState,
     tokens: Vec<Token>,
-    partial_token: Option<String>,
+    scanned: usize, // Bytes at the start of `buffer` already searched for the current state's terminator
     inside_script_or_style: bool,
     script_or_style_tag: String,
     external_stylesheets: Vec<String>,
@@ -54,6 +58,9 @@ pub struct StreamingHTMLParser {
     current_position: usize,
     script_src_urls: Vec<String>, // External script URLs
     style_href_urls: Vec<String>, // External style URLs
+    encoding: &'static Encoding, // How `feed_chunk` decodes bytes
+    encoding_explicit: bool, // Set by the caller (e.g. Content-Type), so `<meta charset>` doesn't override it
+    pending_bytes: Vec<u8>, // Incomplete UTF-8 sequence held back until the next chunk
 }
 
 impl StreamingHTMLParser {
@@ -63,7 +70,7 @@ impl StreamingHTMLParser {
             buffer: String::new(),
             state: ParserState::Initial,
             tokens: Vec::new(),
-            partial_token: None,
+            scanned: 0,
             inside_script_or_style: false,
             script_or_style_tag: String::new(),
             external_stylesheets: Vec::new(),
@@ -73,9 +80,43 @@ impl StreamingHTMLParser {
             current_position: 0,
             script_src_urls: Vec::new(),
             style_href_urls: Vec::new(),
+            encoding: UTF_8,
+            encoding_explicit: false,
+            pending_bytes: Vec::new(),
         }
     }
 
+    /// Clear all parsing state so the parser can be reused, keeping allocated capacity
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+        self.state = ParserState::Initial;
+        self.tokens.clear();
+        self.scanned = 0;
+        self.inside_script_or_style = false;
+        self.script_or_style_tag.clear();
+        self.external_stylesheets.clear();
+        self.extracted_css.clear();
+        self.extracted_scripts.clear();
+        self.parsing_stats = ParsingStats::default();
+        self.current_position = 0;
+        self.script_src_urls.clear();
+        self.style_href_urls.clear();
+        self.encoding = UTF_8;
+        self.encoding_explicit = false;
+        self.pending_bytes.clear();
+    }
+
+    /// Capture the tokenizer state (pending buffer, state, offsets and extracted resources)
+    /// so the parse of a shared document prefix can be resumed with `restore`
+    pub fn checkpoint(&self) -> ParserCheckpoint {
+        ParserCheckpoint { parser: self.clone() }
+    }
+
+    /// Rewind to a checkpoint; the checkpoint itself is left intact for reuse
+    pub fn restore(&mut self, checkpoint: &ParserCheckpoint) {
+        self.clone_from(&checkpoint.parser);
+    }
+
     /// Process a new chunk of HTML data with enhanced parsing
     pub fn process_chunk(&mut self, chunk: &str) -> Vec<Token> {
         println!("[STREAMING] Processing chunk of {} characters", chunk.len());
@@ -87,63 +128,39 @@ impl StreamingHTMLParser {
         new_tokens
     }
 
-    /// Enhanced buffer processing with better state management
+    /// Enhanced buffer processing with better state management. Consumed input is drained from
+    /// `buffer`; an unfinished construct (a tag, comment, script or trailing text) stays buffered
+    /// until a later chunk completes it, and `scanned` remembers how much of it was already
+    /// searched so each byte is only scanned once however the input is chunked.
     fn process_buffer_enhanced(&mut self) -> Vec<Token> {
         let mut new_tokens = Vec::new();
         let mut processed_pos = 0;
-        let mut iteration_count = 0;
-        let max_iterations = self.buffer.len() * 2; // Safety limit to prevent infinite loops
         
-        while processed_pos < self.buffer.len() && iteration_count < max_iterations {
-            iteration_count += 1;
-            let start_pos = processed_pos;
-            let mut made_progress = false;
-            
+        while processed_pos < self.buffer.len() {
             match self.state {
-                ParserState::Initial => {
-                    if let Some(lt_pos) = self.buffer[processed_pos..].find('<') {
-                        if lt_pos > 0 {
-                            let text = self.buffer[processed_pos..processed_pos + lt_pos].to_string();
-                            if !text.trim().is_empty() {
-                                let token = Token {
-                                    token_type: TokenType::Text,
-                                    value: text,
-                                    attributes: HashMap::new(),
-                                    position: self.current_position + processed_pos,
-                                };
-                                new_tokens.push(token);
-                                self.parsing_stats.tokens_created += 1;
-                            }
-                        }
-                        processed_pos += lt_pos;
-                        made_progress = true;
-                        if processed_pos + 1 < self.buffer.len() {
-                            let next_char = self.buffer.chars().nth(processed_pos + 1);
-                            match next_char {
-                                Some('/') => self.state = ParserState::InCloseTag,
-                                Some('!') => {
-                                    if self.buffer[processed_pos..].starts_with("<!--") {
-                                        self.state = ParserState::InComment;
-                                    } else if self.buffer[processed_pos..].to_lowercase().starts_with("<!doctype") {
-                                        self.state = ParserState::InDoctype;
-                                    } else if self.buffer[processed_pos..].starts_with("<![CDATA[") {
-                                        self.state = ParserState::InCDATA;
-                                    } else {
-                                        self.state = ParserState::InTag;
-                                    }
-                                }
-                                Some('?') => self.state = ParserState::InProcessingInstruction,
-                                Some(_) => self.state = ParserState::InTag,
-                                None => {
-                                    eprintln!("[HTML PARSER] Unexpected end of buffer after '<' at position {}", processed_pos);
-                                    self.state = ParserState::InTag;
-                                }
-                            }
-                        } else {
-                            self.state = ParserState::InTag;
+                ParserState::Initial | ParserState::InText => {
+                    // A `<` opens markup only before a letter, `/`, `!` or `?`; any other is literal text
+                    let mut search = processed_pos;
+                    let markup = loop {
+                        let Some(offset) = self.find_from(search, "<") else { break None };
+                        let lt = search + offset;
+                        match self.buffer.as_bytes().get(lt + 1) {
+                            Some(&next) if !next.is_ascii_alphabetic() && !matches!(next, b'/' | b'!' | b'?') => search = lt + 1,
+                            _ => break Some(lt),
                         }
-                    } else {
-                        let text = self.buffer[processed_pos..].to_string();
+                    };
+                    let Some(lt_pos) = markup.map(|lt| lt - processed_pos) else {
+                        // The text may continue in the next chunk
+                        self.scanned = self.buffer.len() - processed_pos;
+                        break;
+                    };
+                    if self.buffer.len() == processed_pos + lt_pos + 1 {
+                        // Wait for the next chunk to see whether the `<` starts a tag
+                        self.scanned = lt_pos;
+                        break;
+                    }
+                    if lt_pos > 0 {
+                        let text = self.buffer[processed_pos..processed_pos + lt_pos].to_string();
                         if !text.trim().is_empty() {
                             let token = Token {
                                 token_type: TokenType::Text,
@@ -154,13 +171,32 @@ impl StreamingHTMLParser {
                             new_tokens.push(token);
                             self.parsing_stats.tokens_created += 1;
                         }
-                        processed_pos = self.buffer.len();
-                        made_progress = true;
-                        break;
+                    }
+                    processed_pos += lt_pos;
+                    let rest = &self.buffer.as_bytes()[processed_pos..];
+                    match rest.get(1) {
+                        None => break,
+                        Some(b'/') => self.state = ParserState::InCloseTag,
+                        Some(b'!') => {
+                            if rest.starts_with(b"<!--") {
+                                self.state = ParserState::InComment;
+                            } else if rest.len() >= 9 && rest[..9].eq_ignore_ascii_case(b"<!doctype") {
+                                self.state = ParserState::InDoctype;
+                            } else if rest.starts_with(b"<![CDATA[") {
+                                self.state = ParserState::InCDATA;
+                            } else if rest.len() < 9 && !rest.contains(&b'>') {
+                                // Too short to tell a comment, doctype or CDATA section apart yet
+                                break;
+                            } else {
+                                self.state = ParserState::InTag;
+                            }
+                        }
+                        Some(b'?') => self.state = ParserState::InProcessingInstruction,
+                        Some(_) => self.state = ParserState::InTag,
                     }
                 }
                 ParserState::InTag => {
-                    if let Some(gt_pos) = self.buffer[processed_pos..].find('>') {
+                    if let Some(gt_pos) = self.find_from(processed_pos, ">") {
                         let tag_content = self.buffer[processed_pos..processed_pos + gt_pos + 1].to_string();
                         let token = self.parse_tag_enhanced(&tag_content);
                         if let Some(token) = token {
@@ -195,14 +231,16 @@ impl StreamingHTMLParser {
                             }
                         }
                         processed_pos += gt_pos + 1;
-                        self.state = ParserState::Initial;
-                        made_progress = true;
+                        if !matches!(self.state, ParserState::InScript | ParserState::InStyle) {
+                            self.state = ParserState::Initial;
+                        }
                     } else {
-                        self.partial_token = Some(self.buffer[processed_pos..].to_string());
+                        self.scanned = self.buffer.len() - processed_pos;
+                        break;
                     }
                 }
                 ParserState::InCloseTag => {
-                    if let Some(gt_pos) = self.buffer[processed_pos..].find('>') {
+                    if let Some(gt_pos) = self.find_from(processed_pos, ">") {
                         let tag_content = self.buffer[processed_pos..processed_pos + gt_pos + 1].to_string();
                         let token = self.parse_close_tag(&tag_content);
                         if let Some(token) = token {
@@ -211,14 +249,14 @@ impl StreamingHTMLParser {
                         }
                         processed_pos += gt_pos + 1;
                         self.state = ParserState::Initial;
-                        made_progress = true;
                     } else {
-                        self.partial_token = Some(self.buffer[processed_pos..].to_string());
+                        self.scanned = self.buffer.len() - processed_pos;
+                        break;
                     }
                 }
                 ParserState::InScript => {
                     let close_tag = "</script>";
-                    if let Some(close_pos) = self.buffer[processed_pos..].find(close_tag) {
+                    if let Some(close_pos) = self.find_from(processed_pos, close_tag) {
                         let script_content = self.buffer[processed_pos..processed_pos + close_pos].to_string();
                         if !script_content.trim().is_empty() {
                             let token = Token {
@@ -234,15 +272,15 @@ impl StreamingHTMLParser {
                         processed_pos += close_pos;
                         self.inside_script_or_style = false;
                         self.script_or_style_tag.clear();
-                        self.state = ParserState::InTag;
-                        made_progress = true;
+                        self.state = ParserState::InCloseTag;
                     } else {
-                        self.partial_token = Some(self.buffer[processed_pos..].to_string());
+                        self.scanned = self.buffer.len() - processed_pos;
+                        break;
                     }
                 }
                 ParserState::InStyle => {
                     let close_tag = "</style>";
-                    if let Some(close_pos) = self.buffer[processed_pos..].find(close_tag) {
+                    if let Some(close_pos) = self.find_from(processed_pos, close_tag) {
                         let style_content = self.buffer[processed_pos..processed_pos + close_pos].to_string();
                         if !style_content.trim().is_empty() {
                             let token = Token {
@@ -258,15 +296,15 @@ impl StreamingHTMLParser {
                         processed_pos += close_pos;
                         self.inside_script_or_style = false;
                         self.script_or_style_tag.clear();
-                        self.state = ParserState::InTag;
-                        made_progress = true;
+                        self.state = ParserState::InCloseTag;
                     } else {
-                        self.partial_token = Some(self.buffer[processed_pos..].to_string());
+                        self.scanned = self.buffer.len() - processed_pos;
+                        break;
                     }
                 }
                 ParserState::InComment => {
                     let close_tag = "-->";
-                    if let Some(close_pos) = self.buffer[processed_pos..].find(close_tag) {
+                    if let Some(close_pos) = self.find_from(processed_pos, close_tag) {
                         let comment_content = self.buffer[processed_pos..processed_pos + close_pos].to_string();
                         let token = Token {
                             token_type: TokenType::Comment,
@@ -278,14 +316,14 @@ impl StreamingHTMLParser {
                         self.parsing_stats.tokens_created += 1;
                         processed_pos += close_pos + close_tag.len();
                         self.state = ParserState::Initial;
-                        made_progress = true;
                     } else {
-                        self.partial_token = Some(self.buffer[processed_pos..].to_string());
+                        self.scanned = self.buffer.len() - processed_pos;
+                        break;
                     }
                 }
                 ParserState::InDoctype => {
                     let close_tag = ">";
-                    if let Some(close_pos) = self.buffer[processed_pos..].find(close_tag) {
+                    if let Some(close_pos) = self.find_from(processed_pos, close_tag) {
                         let doctype_content = self.buffer[processed_pos..processed_pos + close_pos + 1].to_string();
                         let token = Token {
                             token_type: TokenType::Doctype,
@@ -297,14 +335,14 @@ impl StreamingHTMLParser {
                         self.parsing_stats.tokens_created += 1;
                         processed_pos += close_pos + 1;
                         self.state = ParserState::Initial;
-                        made_progress = true;
                     } else {
-                        self.partial_token = Some(self.buffer[processed_pos..].to_string());
+                        self.scanned = self.buffer.len() - processed_pos;
+                        break;
                     }
                 }
                 ParserState::InCDATA => {
                     let close_tag = "]]>";
-                    if let Some(close_pos) = self.buffer[processed_pos..].find(close_tag) {
+                    if let Some(close_pos) = self.find_from(processed_pos, close_tag) {
                         let cdata_content = self.buffer[processed_pos..processed_pos + close_pos].to_string();
                         let token = Token {
                             token_type: TokenType::Text,
@@ -316,14 +354,14 @@ impl StreamingHTMLParser {
                         self.parsing_stats.tokens_created += 1;
                         processed_pos += close_pos + close_tag.len();
                         self.state = ParserState::Initial;
-                        made_progress = true;
                     } else {
-                        self.partial_token = Some(self.buffer[processed_pos..].to_string());
+                        self.scanned = self.buffer.len() - processed_pos;
+                        break;
                     }
                 }
                 ParserState::InProcessingInstruction => {
                     let close_tag = "?>";
-                    if let Some(close_pos) = self.buffer[processed_pos..].find(close_tag) {
+                    if let Some(close_pos) = self.find_from(processed_pos, close_tag) {
                         let pi_content = self.buffer[processed_pos..processed_pos + close_pos + close_tag.len()].to_string();
                         let token = Token {
                             token_type: TokenType::Comment,
@@ -335,80 +373,32 @@ impl StreamingHTMLParser {
                         self.parsing_stats.tokens_created += 1;
                         processed_pos += close_pos + close_tag.len();
                         self.state = ParserState::Initial;
-                        made_progress = true;
                     } else {
-                        self.partial_token = Some(self.buffer[processed_pos..].to_string());
-                    }
-                }
-                ParserState::InText => {
-                    if let Some(lt_pos) = self.buffer[processed_pos..].find('<') {
-                        let text = self.buffer[processed_pos..processed_pos + lt_pos].to_string();
-                        if !text.trim().is_empty() {
-                            let token = Token {
-                                token_type: TokenType::Text,
-                                value: text,
-                                attributes: HashMap::new(),
-                                position: self.current_position + processed_pos,
-                            };
-                            new_tokens.push(token);
-                            self.parsing_stats.tokens_created += 1;
-                        }
-                        processed_pos += lt_pos;
-                        self.state = ParserState::InTag;
-                        made_progress = true;
-                    } else {
-                        let text = self.buffer[processed_pos..].to_string();
-                        if !text.trim().is_empty() {
-                            let token = Token {
-                                token_type: TokenType::Text,
-                                value: text,
-                                attributes: HashMap::new(),
-                                position: self.current_position + processed_pos,
-                            };
-                            new_tokens.push(token);
-                            self.parsing_stats.tokens_created += 1;
-                        }
-                        processed_pos = self.buffer.len();
-                        made_progress = true;
+                        self.scanned = self.buffer.len() - processed_pos;
                         break;
                     }
                 }
             }
-            // Fallback: if no progress was made, treat next char as text and advance
-            if !made_progress {
-                if processed_pos < self.buffer.len() {
-                    let fallback_char = self.buffer[processed_pos..].chars().next().unwrap();
-                    let token = Token {
-                        token_type: TokenType::Text,
-                        value: fallback_char.to_string(),
-                        attributes: HashMap::new(),
-                        position: self.current_position + processed_pos,
-                    };
-                    new_tokens.push(token);
-                    self.parsing_stats.tokens_created += 1;
-                    processed_pos += fallback_char.len_utf8();
-                    self.state = ParserState::Initial;
-                }
-            }
-            // Safety check: ensure we're making progress
-            if processed_pos == start_pos {
-                eprintln!("[HTML PARSER] Warning: No progress made at position {}, advancing by 1", processed_pos);
-                processed_pos += 1;
-                self.state = ParserState::Initial;
-            }
-        }
-        if iteration_count >= max_iterations {
-            eprintln!("[HTML PARSER] Warning: Maximum iterations reached ({}) at position {}", max_iterations, processed_pos);
-            self.buffer.clear();
-            self.state = ParserState::Initial;
         }
         self.current_position += processed_pos;
         if processed_pos > 0 {
-            self.buffer = self.buffer[processed_pos..].to_string();
+            // Drain in place so the buffer keeps its capacity
+            self.buffer.drain(..processed_pos.min(self.buffer.len()));
         }
         new_tokens
     }
 
+    /// Offset of `terminator` in `buffer[start..]`. Resuming at the start of the buffer skips what
+    /// an earlier call already searched, less room for a terminator split across chunks.
+    fn find_from(&mut self, start: usize, terminator: &str) -> Option<usize> {
+        let scanned = std::mem::take(&mut self.scanned);
+        let mut skip = if start == 0 { scanned.saturating_sub(terminator.len() - 1) } else { 0 };
+        while !self.buffer.is_char_boundary(start + skip) {
+            skip -= 1;
+        }
+        self.buffer[start + skip..].find(terminator).map(|offset| skip + offset)
+    }
+
     /// Enhanced tag parsing with better attribute handling
     fn parse_tag_enhanced(&mut self, tag_content: &str) -> Option<Token> {
         let trimmed = tag_content.trim();
@@ -457,65 +447,44 @@ impl StreamingHTMLParser {
         })
     }
 
-    /// Enhanced attribute parsing with better quote handling
+    /// Enhanced attribute parsing with better quote handling.
+    /// Values may be double-quoted, single-quoted or unquoted (`width=100`); valueless boolean
+    /// attributes (`disabled`) get an empty value. When a name repeats, the first one wins.
     fn parse_attributes_enhanced(&self, attr_string: &str) -> HashMap<String, String> {
         let mut attributes = HashMap::new();
-        let mut current_attr = String::new();
-        let mut current_value = String::new();
-        let mut in_quotes = false;
-        let mut quote_char = '\0';
-        let mut parsing_value = false;
+        let mut chars = attr_string.chars().peekable();
         
-        for ch in attr_string.chars() {
-            match ch {
-                '"' | '\'' => {
-                    if !in_quotes {
-                        in_quotes = true;
-                        quote_char = ch;
-                        parsing_value = true;
-                    } else if ch == quote_char {
-                        in_quotes = false;
-                        // Store the attribute
-                        if !current_attr.is_empty() {
-                            attributes.insert(current_attr.trim().to_lowercase(), current_value.trim().to_string());
-                            current_attr.clear();
-                            current_value.clear();
-                        }
-                        parsing_value = false;
-                    } else {
-                        if parsing_value {
-                            current_value.push(ch);
-                        }
-                    }
-                }
-                '=' if !in_quotes => {
-                    parsing_value = true;
+        loop {
+            while chars.next_if(|c| c.is_whitespace() || *c == '/').is_some() {}
+            let mut name = String::new();
+            while let Some(ch) = chars.next_if(|c| !c.is_whitespace() && *c != '=' && *c != '/') {
+                name.push(ch);
+            }
+            if name.is_empty() {
+                // Nothing but a stray `=` (or the end) left
+                if chars.next().is_none() {
+                    break;
                 }
-                ' ' | '\t' | '\n' | '\r' => {
-                    if !in_quotes {
-                        if !current_attr.is_empty() && !current_value.is_empty() {
-                            attributes.insert(current_attr.trim().to_lowercase(), current_value.trim().to_string());
-                            current_attr.clear();
-                            current_value.clear();
-                        }
-                        parsing_value = false;
-                    } else if parsing_value {
-                        current_value.push(ch);
+                continue;
+            }
+            
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            let mut value = String::new();
+            if chars.next_if_eq(&'=').is_some() {
+                while chars.next_if(|c| c.is_whitespace()).is_some() {}
+                match chars.next_if(|c| *c == '"' || *c == '\'') {
+                    Some(quote) => {
+                        value.extend(chars.by_ref().take_while(|&c| c != quote));
+                        value = value.trim().to_string();
                     }
-                }
-                _ => {
-                    if parsing_value {
-                        current_value.push(ch);
-                    } else {
-                        current_attr.push(ch);
+                    None => {
+                        while let Some(ch) = chars.next_if(|c| !c.is_whitespace()) {
+                            value.push(ch);
+                        }
                     }
                 }
             }
-        }
-        
-        // Handle last attribute
-        if !current_attr.is_empty() {
-            attributes.insert(current_attr.trim().to_lowercase(), current_value.trim().to_string());
+            attributes.entry(name.to_lowercase()).or_insert_with(|| decode_entities(&value));
         }
         
         attributes
@@ -534,11 +503,79 @@ impl StreamingHTMLParser {
         &self.style_href_urls
     }
 
-    /// Feed a chunk of bytes to the parser (alias for process_chunk)
-    pub fn feed_chunk(&mut self, chunk: &[u8]) {
-        if let Ok(chunk_str) = String::from_utf8(chunk.to_vec()) {
-            self.process_chunk(&chunk_str);
+    /// Decode bytes with `set_encoding`'s encoding (UTF-8 by default) and process them.
+    /// A multibyte UTF-8 sequence split across chunks is held back until it is complete;
+    /// legacy encodings are decoded chunk by chunk. Without an explicit encoding, a
+    /// `<meta charset>` in the document switches the encoding for the chunks after it.
+    pub fn feed_chunk(&mut self, chunk: &[u8]) -> Vec<Token> {
+        self.pending_bytes.extend_from_slice(chunk);
+        let complete = if self.encoding == UTF_8 {
+            self.pending_bytes.len() - incomplete_utf8_tail(&self.pending_bytes)
+        } else {
+            self.pending_bytes.len()
+        };
+        let bytes: Vec<u8> = self.pending_bytes.drain(..complete).collect();
+        let (text, _) = self.encoding.decode_without_bom_handling(&bytes);
+        let tokens = self.process_chunk(&text);
+        if !self.encoding_explicit {
+            if let Some(encoding) = tokens.iter().find_map(meta_charset).and_then(|label| Encoding::for_label(label.as_bytes())) {
+                self.encoding = encoding;
+            }
+        }
+        tokens
+    }
+
+    /// Signal the end of the document: process bytes `feed_chunk` is still holding back (a
+    /// truncated sequence decodes to replacement characters) and emit trailing text that was
+    /// waiting for more input. An unterminated tag, comment or script is dropped.
+    pub fn finish(&mut self) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        if !self.pending_bytes.is_empty() {
+            let bytes = std::mem::take(&mut self.pending_bytes);
+            let (text, _) = self.encoding.decode_without_bom_handling(&bytes);
+            tokens = self.process_chunk(&text);
         }
+        if !self.buffer.is_empty() {
+            if matches!(self.state, ParserState::Initial | ParserState::InText) {
+                if !self.buffer.trim().is_empty() {
+                    tokens.push(Token {
+                        token_type: TokenType::Text,
+                        value: self.buffer.clone(),
+                        attributes: HashMap::new(),
+                        position: self.current_position,
+                    });
+                    self.parsing_stats.tokens_created += 1;
+                }
+            } else {
+                eprintln!("[HTML PARSER] Warning: unterminated {:?} at end of document", self.state);
+            }
+            self.current_position += self.buffer.len();
+            self.buffer.clear();
+            self.scanned = 0;
+        }
+        tokens
+    }
+
+    /// Decode subsequent chunks with the encoding named by `label` (`"iso-8859-1"`,
+    /// `"windows-1252"`, `"utf-8"`, ...). Returns false, keeping the current encoding,
+    /// for an unknown label.
+    pub fn set_encoding(&mut self, label: &str) -> bool {
+        match Encoding::for_label(label.trim().as_bytes()) {
+            Some(encoding) => {
+                self.encoding = encoding;
+                self.encoding_explicit = true;
+                true
+            }
+            None => {
+                eprintln!("[STREAMING] Unknown encoding label: {}", label);
+                false
+            }
+        }
+    }
+
+    /// Name of the encoding `feed_chunk` currently decodes with
+    pub fn encoding(&self) -> &'static str {
+        self.encoding.name()
     }
 
     /// Get all tokens processed so far
@@ -558,7 +595,7 @@ impl StreamingHTMLParser {
 
     /// Check if parsing is complete (no partial tokens)
     pub fn is_complete(&self) -> bool {
-        self.partial_token.is_none() && self.buffer.is_empty()
+        self.buffer.is_empty()
     }
 
     /// Get all extracted CSS blocks
@@ -567,12 +604,48 @@ impl StreamingHTMLParser {
     }
 }
 
+/// Saved `StreamingHTMLParser` state from `checkpoint`
+#[derive(Clone)]
+pub struct ParserCheckpoint {
+    parser: StreamingHTMLParser,
+}
+
+impl ParserCheckpoint {
+    /// Document offset of the next character the parser will consume
+    pub fn position(&self) -> usize {
+        self.parser.current_position
+    }
+}
+
+/// Feed `input` to `streaming` in `HTMLParser::CHUNK_SIZE` pieces, stopping between pieces
+/// once `cancellation` is set
+fn tokenize_chunks(streaming: &mut StreamingHTMLParser, input: &str, cancellation: Option<&CancellationToken>) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    while start < input.len() {
+        if cancellation.is_some_and(CancellationToken::is_cancelled) {
+            return tokens;
+        }
+        let mut end = (start + HTMLParser::CHUNK_SIZE).min(input.len());
+        while !input.is_char_boundary(end) {
+            end += 1;
+        }
+        tokens.extend(streaming.process_chunk(&input[start..end]));
+        start = end;
+    }
+    tokens.extend(streaming.finish());
+    tokens
+}
+
 pub struct HTMLParser {
     input: String,
     position: usize,
     pub extracted_css: Vec<String>, // Store extracted CSS for later processing
     pub external_stylesheets: Vec<String>, // Store external CSS hrefs
     pub parsing_stats: ParsingStats,
+    tokenizer: Option<StreamingHTMLParser>, // Pooled tokenizer reused across parses
+    arena: Option<DOMArena>, // Pooled arena reused across parses
+    cancellation: Option<CancellationToken>, // Polled between chunks; a cancelled parse keeps what it has
 }
 
 #[derive(Debug, Clone)]
@@ -619,10 +692,72 @@ impl HTMLParser {
                 total_chars,
                 ..Default::default()
             },
+            tokenizer: None,
+            arena: None,
+            cancellation: None,
         }
     }
 
+    /// Stop tokenizing and building the DOM once `token` is cancelled. The parse then returns
+    /// the document built so far; callers check the token to tell it apart from a full parse.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancellation.as_ref().is_some_and(|token| token.is_cancelled())
+    }
+
+    /// Reuse one tokenizer and one arena for every parse instead of allocating them per call.
+    /// The arena is cleared at the start of each parse, so nodes from the previous parse go away.
+    pub fn with_reuse(mut self) -> Self {
+        self.tokenizer = Some(StreamingHTMLParser::new());
+        self.arena = Some(DOMArena::new());
+        self
+    }
+
+    /// Replace the input for the next `parse`, keeping any pooled tokenizer and arena
+    pub fn set_input(&mut self, input: String) {
+        self.parsing_stats = ParsingStats {
+            total_chars: input.len(),
+            ..Default::default()
+        };
+        self.input = input;
+        self.position = 0;
+        self.extracted_css.clear();
+        self.external_stylesheets.clear();
+    }
+
+    /// The pooled arena holding the most recently parsed DOM, if reuse is enabled
+    pub fn arena(&self) -> Option<&DOMArena> {
+        self.arena.as_ref()
+    }
+
+    /// Mutable access to the pooled arena, e.g. to apply a stylesheet to the parsed DOM
+    pub fn arena_mut(&mut self) -> Option<&mut DOMArena> {
+        self.arena.as_mut()
+    }
+
     pub fn parse(&mut self) -> DOMNode {
+        // Reuse the pooled arena when enabled
+        let mut arena = match self.arena.take() {
+            Some(mut pooled) => {
+                pooled.clear();
+                pooled
+            }
+            None => DOMArena::new(),
+        };
+        let root_node = self.parse_into(&mut arena);
+        if self.tokenizer.is_some() {
+            self.arena = Some(arena);
+        }
+        root_node
+    }
+
+    /// Parse into a caller-owned arena, such as the one the FFI DOM functions share.
+    /// Nodes already in the arena are left alone.
+    pub fn parse_into(&mut self, arena: &mut DOMArena) -> DOMNode {
         let start_time = Instant::now();
         println!("Rust: HTML Parser initialized for {} characters", self.input.len());
         
@@ -646,19 +781,20 @@ impl HTMLParser {
             return DOMNode::new(NodeType::Document);
         }
         
-        // Build DOM with enhanced parsing using a shared arena
-        let mut arena = DOMArena::new();
+        // Build DOM with enhanced parsing
         let mut root = DOMNode::new(NodeType::Document);
         let root_id = root.id.clone();
-        arena.add_node(root);
-        self.build_dom_enhanced(&tokens, &mut arena.get_node(&root_id).unwrap().lock().unwrap(), &mut arena);
+        arena.add_node(root.clone());
+        // Build against a copy of the root: the builder locks the arena's root node itself
+        // when appending top-level children, so holding its lock here would deadlock
+        self.build_dom_enhanced(&tokens, &mut root, arena);
         
         // Extract CSS from style tags and inline styles
         self.extract_css_enhanced(&tokens);
         
         self.parsing_stats.parsing_time_ms = start_time.elapsed().as_millis() as u64;
         let root_node = arena.get_node(&root_id).unwrap().lock().unwrap().clone();
-        self.parsing_stats.dom_nodes_created = self.count_nodes(&root_node, &arena);
+        self.parsing_stats.dom_nodes_created = self.count_nodes(&root_node, arena);
         
         println!("Rust: DOM built with {} nodes in {}ms", 
             self.parsing_stats.dom_nodes_created, self.parsing_stats.parsing_time_ms);
@@ -670,17 +806,32 @@ impl HTMLParser {
 
     /// Tokenize using the streaming parser for compatibility
     pub fn tokenize_streaming(&mut self) -> Vec<Token> {
-        let mut streaming = StreamingHTMLParser::new();
-        streaming.process_chunk(&self.input)
+        match self.tokenizer.as_mut() {
+            Some(streaming) => {
+                streaming.reset();
+                tokenize_chunks(streaming, &self.input, self.cancellation.as_ref())
+            }
+            None => tokenize_chunks(&mut StreamingHTMLParser::new(), &self.input, self.cancellation.as_ref()),
+        }
     }
 
     /// Build DOM using the enhanced builder for compatibility
     pub fn build_dom_enhanced(&mut self, tokens: &[Token], root: &mut DOMNode, arena: &mut DOMArena) {
         let mut stack: Vec<String> = vec![root.id.clone()];
+        // Tag names of the open elements, parallel to `stack` (the root has none)
+        let mut open_tags: Vec<String> = vec![String::new()];
         
-        for token in tokens {
+        for (index, token) in tokens.iter().enumerate() {
+            if index % Self::PROGRESS_INTERVAL == 0 && self.is_cancelled() {
+                break;
+            }
             match token.token_type {
                 TokenType::OpenTag => {
+                    // Optional end tags: e.g. `<li>` ends an open `<li>`, a block ends an open `<p>`
+                    while stack.len() > 1 && closes_implicitly(&token.value, open_tags.last().unwrap()) {
+                        stack.pop();
+                        open_tags.pop();
+                    }
                     let mut node = DOMNode::new(NodeType::Element(token.value.clone()));
                     
                     // Copy attributes
@@ -688,6 +839,7 @@ impl HTMLParser {
                         node.attributes.insert(key.clone(), value.clone());
                     }
                     
+                    node.parent = stack.last().cloned();
                     let node_id = node.id.clone();
                     arena.add_node(node);
                     
@@ -702,18 +854,24 @@ impl HTMLParser {
                     // Push to stack if not self-closing
                     if !self.is_self_closing_tag(&token.value) {
                         stack.push(node_id);
+                        open_tags.push(token.value.clone());
                     }
                 }
                 TokenType::CloseTag => {
-                    if stack.len() > 1 {
-                        stack.pop();
+                    // Close the nearest open element with this name and anything left open inside it;
+                    // a stray end tag with nothing to close is ignored
+                    let name = token.value.trim();
+                    if let Some(open) = open_tags.iter().rposition(|tag| tag == name).filter(|&i| i > 0) {
+                        stack.truncate(open);
+                        open_tags.truncate(open);
                     }
                 }
                 TokenType::Text => {
                     if !token.value.trim().is_empty() {
                         let mut text_node = DOMNode::new(NodeType::Text);
-                        text_node.text_content = token.value.clone();
+                        text_node.text_content = decode_entities(&token.value);
                         
+                        text_node.parent = stack.last().cloned();
                         let text_node_id = text_node.id.clone();
                         arena.add_node(text_node);
                         
@@ -733,6 +891,7 @@ impl HTMLParser {
                     ));
                     content_node.text_content = token.value.clone();
                     
+                    content_node.parent = stack.last().cloned();
                     let content_node_id = content_node.id.clone();
                     arena.add_node(content_node);
                     
@@ -753,7 +912,7 @@ impl HTMLParser {
 
     /// Check if tag is self-closing
     fn is_self_closing_tag(&self, tag_name: &str) -> bool {
-        matches!(tag_name, "img" | "br" | "hr" | "input" | "meta" | "link" | "area" | "base" | "col" | "embed" | "source" | "track" | "wbr")
+        is_void_element(tag_name)
     }
 
     /// Calculate maximum depth of DOM tree
@@ -827,4 +986,271 @@ impl HTMLParser {
         
         println!("[CSS] Extraction complete for {} style tags", self.parsing_stats.css_blocks_extracted);
     }
-} 
\ No newline at end of file
+} 
+/// Number of bytes at the end of `bytes` that start a UTF-8 sequence without finishing it
+fn incomplete_utf8_tail(bytes: &[u8]) -> usize {
+    for back in 1..=bytes.len().min(3) {
+        let byte = bytes[bytes.len() - back];
+        if byte & 0xC0 == 0x80 {
+            continue; // Continuation byte, keep looking for the lead byte
+        }
+        let width = match byte {
+            0xF0.. => 4,
+            0xE0.. => 3,
+            0xC0.. => 2,
+            _ => 1,
+        };
+        return if width > back { back } else { 0 };
+    }
+    0
+}
+
+/// The charset in a Content-Type value such as `text/html; charset=ISO-8859-1`
+pub fn charset_from_content_type(content_type: &str) -> Option<&str> {
+    content_type.split(';')
+        .filter_map(|param| param.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+        .map(|(_, value)| value.trim().trim_matches(|c| c == '"' || c == '\''))
+}
+
+/// The charset declared by a `<meta charset>` or `<meta http-equiv="Content-Type">` tag
+fn meta_charset(token: &Token) -> Option<&str> {
+    if token.token_type != TokenType::OpenTag || token.value != "meta" {
+        return None;
+    }
+    token.attributes.get("charset").map(String::as_str)
+        .or_else(|| token.attributes.get("content").and_then(|content| charset_from_content_type(content)))
+}
+
+/// Void elements never have children or an end tag
+pub fn is_void_element(tag_name: &str) -> bool {
+    matches!(tag_name, "img" | "br" | "hr" | "input" | "meta" | "link" | "area" | "base" | "col" | "embed" | "source" | "track" | "wbr")
+}
+
+/// Whether an `opening` start tag implicitly ends the currently open `open` element,
+/// per the HTML optional end tag rules
+fn closes_implicitly(opening: &str, open: &str) -> bool {
+    match open {
+        "p" => matches!(opening,
+            "address" | "article" | "aside" | "blockquote" | "details" | "div" | "dl" | "fieldset"
+            | "figcaption" | "figure" | "footer" | "form" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6"
+            | "header" | "hgroup" | "hr" | "main" | "menu" | "nav" | "ol" | "p" | "pre" | "section"
+            | "table" | "ul" | "li" | "dd" | "dt"),
+        "li" => opening == "li",
+        "dt" | "dd" => matches!(opening, "dt" | "dd"),
+        "td" | "th" => matches!(opening, "td" | "th" | "tr" | "tbody" | "thead" | "tfoot"),
+        "tr" => matches!(opening, "tr" | "tbody" | "thead" | "tfoot"),
+        "thead" | "tbody" | "tfoot" => matches!(opening, "tbody" | "thead" | "tfoot"),
+        "option" => matches!(opening, "option" | "optgroup"),
+        "optgroup" => opening == "optgroup",
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Counts allocations made by the current thread so parallel tests don't interfere. It replaces
+    // the whole test binary's allocator, so it's only built with `--features count-allocations`.
+    #[cfg(feature = "count-allocations")]
+    mod counting {
+        use std::alloc::{GlobalAlloc, Layout, System};
+        use std::cell::Cell;
+
+        struct CountingAllocator;
+
+        thread_local! {
+            static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+        }
+
+        unsafe impl GlobalAlloc for CountingAllocator {
+            unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+                let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+                System.alloc(layout)
+            }
+
+            unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+                System.dealloc(ptr, layout)
+            }
+        }
+
+        #[global_allocator]
+        static GLOBAL: CountingAllocator = CountingAllocator;
+
+        pub fn allocations() -> usize {
+            ALLOCATIONS.with(|count| count.get())
+        }
+    }
+
+    // Tag positions record the start of their chunk, so only kinds, values and attributes are compared
+    fn token_summary(tokens: &[Token]) -> Vec<(TokenType, String, Vec<(String, String)>)> {
+        tokens.iter().map(|t| {
+            let mut attributes: Vec<(String, String)> = t.attributes.clone().into_iter().collect();
+            attributes.sort();
+            (t.token_type.clone(), t.value.clone(), attributes)
+        }).collect()
+    }
+
+    #[test]
+    fn test_checkpoint_resumes_shared_prefix() {
+        let prefix = "<html><head><title>Shop</title><style>p { color: red; }</style></head><body>";
+        let suffixes = ["<p class=\"a\">Cart</p></body></html>", "<div><img src=\"x.png\">Search</div></body></html>"];
+
+        let mut parser = StreamingHTMLParser::new();
+        let prefix_tokens = parser.process_chunk(prefix);
+        let checkpoint = parser.checkpoint();
+        assert_eq!(checkpoint.position(), prefix.len());
+
+        for suffix in suffixes {
+            parser.restore(&checkpoint);
+            let mut resumed = prefix_tokens.clone();
+            resumed.extend(parser.process_chunk(suffix));
+
+            let mut fresh = StreamingHTMLParser::new();
+            let full = fresh.process_chunk(&format!("{}{}", prefix, suffix));
+            assert_eq!(token_summary(&resumed), token_summary(&full));
+            assert_eq!(parser.get_extracted_css(), fresh.get_extracted_css());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "count-allocations")]
+    fn test_reused_parser_allocates_less() {
+        use counting::allocations;
+
+        let html = "<html><body>".to_string()
+            + &"<div class=\"row\"><p>Hello <b>world</b></p><img src=\"a.png\"></div>".repeat(50)
+            + "</body></html>";
+        const PARSES: usize = 20;
+
+        let before = allocations();
+        for _ in 0..PARSES {
+            let mut parser = HTMLParser::new(html.clone());
+            parser.parse();
+        }
+        let per_call = allocations() - before;
+
+        let mut parser = HTMLParser::new(String::new()).with_reuse();
+        let before = allocations();
+        for _ in 0..PARSES {
+            parser.set_input(html.clone());
+            parser.parse();
+        }
+        let reused = allocations() - before;
+
+        assert!(reused < per_call);
+        assert!(parser.arena().unwrap().nodes.len() > 100);
+    }
+
+    #[test]
+    fn test_optional_end_tags_close_implicitly() {
+        let mut parser = HTMLParser::new("<body><ul><li>a<li>b</ul><p>x<p>y<div>z</div></body>".to_string()).with_reuse();
+        parser.parse();
+        let arena = parser.arena().unwrap();
+        let nodes: Vec<_> = arena.nodes.values().map(|node| node.lock().unwrap().clone()).collect();
+        let element = |tag: &str| nodes.iter()
+            .filter(|node| matches!(&node.node_type, NodeType::Element(t) if t == tag))
+            .collect::<Vec<_>>();
+        let list = element("ul")[0];
+        let items = element("li");
+        assert_eq!(items.len(), 2);
+        assert!(items.iter().all(|li| li.parent.as_deref() == Some(list.id.as_str())));
+        let paragraphs = element("p");
+        assert_eq!(paragraphs.len(), 2);
+        assert_eq!(paragraphs[0].parent, paragraphs[1].parent);
+        assert_eq!(element("div")[0].parent, paragraphs[0].parent);
+        assert_eq!(list.parent, paragraphs[0].parent);
+    }
+
+    #[test]
+    fn test_boolean_and_unquoted_attributes() {
+        let mut parser = StreamingHTMLParser::new();
+        let tokens = parser.process_chunk(r#"<input disabled type=text value="a b" Type="other" checked>"#);
+        let input = tokens.iter().find(|t| t.token_type == TokenType::OpenTag && t.value == "input").unwrap();
+        assert_eq!(input.attributes.len(), 4);
+        assert_eq!(input.attributes["disabled"], "");
+        assert_eq!(input.attributes["type"], "text");
+        assert_eq!(input.attributes["value"], "a b");
+        assert_eq!(input.attributes["checked"], "");
+
+        let tokens = parser.process_chunk("<td width=100 class = 'wide'><br/>");
+        assert_eq!(tokens[0].attributes["width"], "100");
+        assert_eq!(tokens[0].attributes["class"], "wide");
+    }
+
+    #[test]
+    fn test_feed_chunk_joins_split_utf8_and_honors_charset() {
+        let mut parser = StreamingHTMLParser::new();
+        let mut tokens = parser.feed_chunk(b"<p>\xC3");
+        tokens.extend(parser.feed_chunk(b"\xA9</p>"));
+        let texts: Vec<_> = tokens.iter().filter(|t| t.token_type == TokenType::Text).collect();
+        assert_eq!(texts.len(), 1);
+        assert_eq!(texts[0].value, "\u{e9}");
+
+        // ISO-8859-1 byte 0xE9 is also "\u{e9}", once the meta tag has been seen
+        let mut parser = StreamingHTMLParser::new();
+        parser.feed_chunk(b"<head><meta charset=\"iso-8859-1\"></head>");
+        assert_eq!(parser.encoding(), "windows-1252");
+        let tokens = parser.feed_chunk(b"<p>caf\xE9</p>");
+        assert!(tokens.iter().any(|t| t.token_type == TokenType::Text && t.value == "caf\u{e9}"));
+
+        let mut parser = StreamingHTMLParser::new();
+        assert!(parser.set_encoding(charset_from_content_type("text/html; charset=UTF-8").unwrap()));
+        parser.feed_chunk(b"<meta charset=\"iso-8859-1\">");
+        assert_eq!(parser.encoding(), "UTF-8");
+        assert!(!parser.set_encoding("bogus"));
+    }
+
+    #[test]
+    fn test_cancelled_parse_stops_at_chunk_boundary() {
+        let html = "<p>row</p>".repeat(2 * HTMLParser::CHUNK_SIZE / 10);
+        let full = HTMLParser::new(html.clone()).parse();
+        assert!(!full.children.is_empty());
+
+        let token = CancellationToken::new();
+        token.cancel();
+        let cancelled = HTMLParser::new(html).with_cancellation(token).parse();
+        assert!(cancelled.children.is_empty(), "no chunk is tokenized once the token is set");
+    }
+
+    #[test]
+    fn test_lone_less_than_is_text() {
+        let mut parser = StreamingHTMLParser::new();
+        let mut tokens = parser.process_chunk("<p>1 < 2 and 3 > 2</p><script>if (a<b && c <d) { go(); }</script><p>x <");
+        tokens.extend(parser.finish());
+
+        let texts: Vec<&str> = tokens.iter().filter(|t| t.token_type == TokenType::Text).map(|t| t.value.as_str()).collect();
+        assert_eq!(texts, ["1 < 2 and 3 > 2", "x <"]);
+        let scripts: Vec<&str> = tokens.iter().filter(|t| t.token_type == TokenType::ScriptContent).map(|t| t.value.as_str()).collect();
+        assert_eq!(scripts, ["if (a<b && c <d) { go(); }"]);
+        assert_eq!(tokens.iter().filter(|t| t.token_type == TokenType::OpenTag).count(), 3);
+    }
+
+    #[test]
+    fn test_small_chunks_tokenize_like_whole_document() {
+        let section = "<div class=\"card\" data-x='1 > 0'><!-- note --><p>Caf\u{e9} &amp; cr\u{e8}me</p>\
+            <script>if (a < b) { run(\"</p>\"); }</script><style>p { color: red; }</style><br/>text</div>\n";
+        let document = "<!DOCTYPE html><html><body>".to_string() + &section.repeat(1_000_000 / section.len()) + "</body></html>";
+
+        let start = Instant::now();
+        let mut chunked = StreamingHTMLParser::new();
+        let mut tokens = Vec::new();
+        for chunk in document.as_bytes().chunks(1024) {
+            tokens.extend(chunked.feed_chunk(chunk));
+        }
+        tokens.extend(chunked.finish());
+        let elapsed = start.elapsed();
+
+        let mut whole = StreamingHTMLParser::new();
+        let mut expected = whole.process_chunk(&document);
+        expected.extend(whole.finish());
+        assert_eq!(token_summary(&tokens), token_summary(&expected));
+        assert_eq!(chunked.get_extracted_css(), whole.get_extracted_css());
+        assert!(tokens.iter().any(|t| t.token_type == TokenType::ScriptContent && t.value.contains("</p>")));
+        assert!(chunked.is_complete());
+        // Unoptimized builds get some headroom
+        let budget = if cfg!(debug_assertions) { 5.0 } else { 1.0 };
+        assert!(elapsed.as_secs_f32() < budget);
+    }
+}
@@ -1,3 +1,4 @@
 pub mod html;
 pub mod css;
-pub mod javascript; 
\ No newline at end of file
+pub mod javascript;
+pub mod entities;
@@ -0,0 +1,210 @@
+// CSS color parsing into a normalized RGBA value
+// Accepts hex (#rgb, #rgba, #rrggbb, #rrggbbaa), rgb()/rgba(), hsl()/hsla() and named colors
+
+/// An sRGB color with 8-bit channels and straight (non-premultiplied) alpha
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Color {
+    pub const BLACK: Color = Color::rgb(0, 0, 0);
+    pub const TRANSPARENT: Color = Color { r: 0, g: 0, b: 0, a: 0 };
+
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Color { r, g, b, a: 255 }
+    }
+
+    /// Parse a CSS color value; None for anything unrecognised (including `currentcolor`,
+    /// which depends on the element)
+    pub fn parse(value: &str) -> Option<Color> {
+        let value = value.trim().to_ascii_lowercase();
+        if let Some(hex) = value.strip_prefix('#') {
+            return parse_hex(hex);
+        }
+        if let Some((function, arguments)) = value.strip_suffix(')').and_then(|v| v.split_once('(')) {
+            return match function.trim() {
+                "rgb" | "rgba" => parse_rgb(arguments),
+                "hsl" | "hsla" => parse_hsl(arguments),
+                _ => None,
+            };
+        }
+        if value == "transparent" {
+            return Some(Color::TRANSPARENT);
+        }
+        NAMED_COLORS.iter()
+            .find(|(name, _)| *name == value)
+            .map(|&(_, rgb)| Color::rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))
+    }
+
+    /// `parse`, falling back to `fallback` for unparseable values
+    pub fn parse_or(value: &str, fallback: Color) -> Color {
+        Color::parse(value).unwrap_or(fallback)
+    }
+
+    /// Normalized `#rrggbbaa`, in CSS channel order
+    pub fn to_hex(&self) -> String {
+        format!("#{:02x}{:02x}{:02x}{:02x}", self.r, self.g, self.b, self.a)
+    }
+
+    /// `#aarrggbb`, the hex form of `to_argb` handed to the host through draw commands
+    pub fn to_argb_hex(&self) -> String {
+        format!("#{:08x}", self.to_argb())
+    }
+
+    /// Packed `0xAARRGGBB`, as used by the display list
+    pub fn to_argb(&self) -> u32 {
+        u32::from_be_bytes([self.a, self.r, self.g, self.b])
+    }
+}
+
+fn parse_hex(hex: &str) -> Option<Color> {
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let digit = |i: usize| u8::from_str_radix(&hex[i..i + 1], 16).ok().map(|d| d * 17);
+    let pair = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    match hex.len() {
+        3 => Some(Color::rgb(digit(0)?, digit(1)?, digit(2)?)),
+        4 => Some(Color { r: digit(0)?, g: digit(1)?, b: digit(2)?, a: digit(3)? }),
+        6 => Some(Color::rgb(pair(0)?, pair(2)?, pair(4)?)),
+        8 => Some(Color { r: pair(0)?, g: pair(2)?, b: pair(4)?, a: pair(6)? }),
+        _ => None,
+    }
+}
+
+/// Split function arguments in either the legacy `a, b, c, alpha` or the modern
+/// `a b c / alpha` syntax
+fn split_arguments(arguments: &str) -> Option<(Vec<&str>, Option<&str>)> {
+    if arguments.contains(',') {
+        let mut parts: Vec<&str> = arguments.split(',').map(str::trim).collect();
+        let alpha = if parts.len() == 4 { parts.pop() } else { None };
+        return (parts.len() == 3).then_some((parts, alpha));
+    }
+    let (channels, alpha) = match arguments.split_once('/') {
+        Some((channels, alpha)) => (channels, Some(alpha.trim())),
+        None => (arguments, None),
+    };
+    let parts: Vec<&str> = channels.split_whitespace().collect();
+    (parts.len() == 3).then_some((parts, alpha))
+}
+
+/// A number or percentage scaled so 100% == `full`
+fn parse_scaled(value: &str, full: f32) -> Option<f32> {
+    match value.strip_suffix('%') {
+        Some(percent) => percent.trim().parse::<f32>().ok().map(|p| p / 100.0 * full),
+        None => value.parse().ok(),
+    }
+}
+
+fn parse_alpha(alpha: Option<&str>) -> Option<u8> {
+    let alpha = alpha.map(|a| parse_scaled(a, 1.0)).unwrap_or(Some(1.0))?;
+    Some((alpha.clamp(0.0, 1.0) * 255.0).round() as u8)
+}
+
+fn to_channel(value: f32) -> u8 {
+    value.clamp(0.0, 255.0).round() as u8
+}
+
+fn parse_rgb(arguments: &str) -> Option<Color> {
+    let (channels, alpha) = split_arguments(arguments)?;
+    Some(Color {
+        r: to_channel(parse_scaled(channels[0], 255.0)?),
+        g: to_channel(parse_scaled(channels[1], 255.0)?),
+        b: to_channel(parse_scaled(channels[2], 255.0)?),
+        a: parse_alpha(alpha)?,
+    })
+}
+
+fn parse_hsl(arguments: &str) -> Option<Color> {
+    let (channels, alpha) = split_arguments(arguments)?;
+    let hue = channels[0].trim_end_matches("deg").parse::<f32>().ok()?.rem_euclid(360.0) / 360.0;
+    let saturation = (parse_scaled(channels[1], 1.0)?).clamp(0.0, 1.0);
+    let lightness = (parse_scaled(channels[2], 1.0)?).clamp(0.0, 1.0);
+
+    // CSS Color 4, "HSL to RGB"
+    let q = if lightness < 0.5 { lightness * (1.0 + saturation) } else { lightness + saturation - lightness * saturation };
+    let p = 2.0 * lightness - q;
+    let hue_to_rgb = |t: f32| {
+        let t = t.rem_euclid(1.0);
+        let v = if t < 1.0 / 6.0 {
+            p + (q - p) * 6.0 * t
+        } else if t < 0.5 {
+            q
+        } else if t < 2.0 / 3.0 {
+            p + (q - p) * (2.0 / 3.0 - t) * 6.0
+        } else {
+            p
+        };
+        to_channel(v * 255.0)
+    };
+    Some(Color {
+        r: hue_to_rgb(hue + 1.0 / 3.0),
+        g: hue_to_rgb(hue),
+        b: hue_to_rgb(hue - 1.0 / 3.0),
+        a: parse_alpha(alpha)?,
+    })
+}
+
+/// CSS named colors (CSS Color 4), as 0xRRGGBB
+const NAMED_COLORS: [(&str, u32); 148] = [
+    ("aliceblue", 0xf0f8ff), ("antiquewhite", 0xfaebd7), ("aqua", 0x00ffff), ("aquamarine", 0x7fffd4),
+    ("azure", 0xf0ffff), ("beige", 0xf5f5dc), ("bisque", 0xffe4c4), ("black", 0x000000),
+    ("blanchedalmond", 0xffebcd), ("blue", 0x0000ff), ("blueviolet", 0x8a2be2), ("brown", 0xa52a2a),
+    ("burlywood", 0xdeb887), ("cadetblue", 0x5f9ea0), ("chartreuse", 0x7fff00), ("chocolate", 0xd2691e),
+    ("coral", 0xff7f50), ("cornflowerblue", 0x6495ed), ("cornsilk", 0xfff8dc), ("crimson", 0xdc143c),
+    ("cyan", 0x00ffff), ("darkblue", 0x00008b), ("darkcyan", 0x008b8b), ("darkgoldenrod", 0xb8860b),
+    ("darkgray", 0xa9a9a9), ("darkgreen", 0x006400), ("darkgrey", 0xa9a9a9), ("darkkhaki", 0xbdb76b),
+    ("darkmagenta", 0x8b008b), ("darkolivegreen", 0x556b2f), ("darkorange", 0xff8c00), ("darkorchid", 0x9932cc),
+    ("darkred", 0x8b0000), ("darksalmon", 0xe9967a), ("darkseagreen", 0x8fbc8f), ("darkslateblue", 0x483d8b),
+    ("darkslategray", 0x2f4f4f), ("darkslategrey", 0x2f4f4f), ("darkturquoise", 0x00ced1), ("darkviolet", 0x9400d3),
+    ("deeppink", 0xff1493), ("deepskyblue", 0x00bfff), ("dimgray", 0x696969), ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1e90ff), ("firebrick", 0xb22222), ("floralwhite", 0xfffaf0), ("forestgreen", 0x228b22),
+    ("fuchsia", 0xff00ff), ("gainsboro", 0xdcdcdc), ("ghostwhite", 0xf8f8ff), ("gold", 0xffd700),
+    ("goldenrod", 0xdaa520), ("gray", 0x808080), ("green", 0x008000), ("greenyellow", 0xadff2f),
+    ("grey", 0x808080), ("honeydew", 0xf0fff0), ("hotpink", 0xff69b4), ("indianred", 0xcd5c5c),
+    ("indigo", 0x4b0082), ("ivory", 0xfffff0), ("khaki", 0xf0e68c), ("lavender", 0xe6e6fa),
+    ("lavenderblush", 0xfff0f5), ("lawngreen", 0x7cfc00), ("lemonchiffon", 0xfffacd), ("lightblue", 0xadd8e6),
+    ("lightcoral", 0xf08080), ("lightcyan", 0xe0ffff), ("lightgoldenrodyellow", 0xfafad2), ("lightgray", 0xd3d3d3),
+    ("lightgreen", 0x90ee90), ("lightgrey", 0xd3d3d3), ("lightpink", 0xffb6c1), ("lightsalmon", 0xffa07a),
+    ("lightseagreen", 0x20b2aa), ("lightskyblue", 0x87cefa), ("lightslategray", 0x778899), ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xb0c4de), ("lightyellow", 0xffffe0), ("lime", 0x00ff00), ("limegreen", 0x32cd32),
+    ("linen", 0xfaf0e6), ("magenta", 0xff00ff), ("maroon", 0x800000), ("mediumaquamarine", 0x66cdaa),
+    ("mediumblue", 0x0000cd), ("mediumorchid", 0xba55d3), ("mediumpurple", 0x9370db), ("mediumseagreen", 0x3cb371),
+    ("mediumslateblue", 0x7b68ee), ("mediumspringgreen", 0x00fa9a), ("mediumturquoise", 0x48d1cc), ("mediumvioletred", 0xc71585),
+    ("midnightblue", 0x191970), ("mintcream", 0xf5fffa), ("mistyrose", 0xffe4e1), ("moccasin", 0xffe4b5),
+    ("navajowhite", 0xffdead), ("navy", 0x000080), ("oldlace", 0xfdf5e6), ("olive", 0x808000),
+    ("olivedrab", 0x6b8e23), ("orange", 0xffa500), ("orangered", 0xff4500), ("orchid", 0xda70d6),
+    ("palegoldenrod", 0xeee8aa), ("palegreen", 0x98fb98), ("paleturquoise", 0xafeeee), ("palevioletred", 0xdb7093),
+    ("papayawhip", 0xffefd5), ("peachpuff", 0xffdab9), ("peru", 0xcd853f), ("pink", 0xffc0cb),
+    ("plum", 0xdda0dd), ("powderblue", 0xb0e0e6), ("purple", 0x800080), ("rebeccapurple", 0x663399),
+    ("red", 0xff0000), ("rosybrown", 0xbc8f8f), ("royalblue", 0x4169e1), ("saddlebrown", 0x8b4513),
+    ("salmon", 0xfa8072), ("sandybrown", 0xf4a460), ("seagreen", 0x2e8b57), ("seashell", 0xfff5ee),
+    ("sienna", 0xa0522d), ("silver", 0xc0c0c0), ("skyblue", 0x87ceeb), ("slateblue", 0x6a5acd),
+    ("slategray", 0x708090), ("slategrey", 0x708090), ("snow", 0xfffafa), ("springgreen", 0x00ff7f),
+    ("steelblue", 0x4682b4), ("tan", 0xd2b48c), ("teal", 0x008080), ("thistle", 0xd8bfd8),
+    ("tomato", 0xff6347), ("turquoise", 0x40e0d0), ("violet", 0xee82ee), ("wheat", 0xf5deb3),
+    ("white", 0xffffff), ("whitesmoke", 0xf5f5f5), ("yellow", 0xffff00), ("yellowgreen", 0x9acd32),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_color_forms_normalize_identically() {
+        let forms = ["red", "#f00", "#FF0000", "rgb(255,0,0)", "rgba(255, 0, 0, 1)", "rgb(100% 0% 0%)", "hsl(0, 100%, 50%)", "#ff0000ff"];
+        for form in forms {
+            assert_eq!(Color::parse(form).map(|c| c.to_hex()).as_deref(), Some("#ff0000ff"), "{}", form);
+        }
+        assert_eq!(Color::parse("#ff000080"), Some(Color { r: 255, g: 0, b: 0, a: 128 }));
+        assert_eq!(Color::parse("rgba(0, 0, 255, 0.5)").unwrap().to_argb(), 0x800000ff);
+        assert_eq!(Color::parse("hsl(120deg 100% 25%)"), Some(Color::rgb(0, 128, 0)));
+        assert_eq!(Color::parse("transparent"), Some(Color::TRANSPARENT));
+        assert_eq!(Color::parse("currentcolor"), None);
+        assert_eq!(Color::parse("#12"), None);
+    }
+}
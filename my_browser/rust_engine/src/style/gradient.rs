@@ -0,0 +1,132 @@
+// CSS gradient parsing
+// Accepts `linear-gradient([<angle> | to <side-or-corner>,] <color> [<position>], ...)`
+
+use crate::dom::node::{split_outside_parens, split_top_level_commas};
+use crate::style::color::Color;
+
+/// A linear gradient. `angle` is in degrees, clockwise from "to top" as in CSS;
+/// each stop's position runs from 0 at the start of the gradient line to 1 at its end.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gradient {
+    pub angle: f32,
+    pub stops: Vec<(Color, f32)>,
+}
+
+impl Gradient {
+    /// Parse a `linear-gradient()`; None for other images and malformed gradients.
+    /// Stops without a position are spread evenly between their neighbours.
+    pub fn parse(value: &str) -> Option<Gradient> {
+        let value = value.trim();
+        let (function, arguments) = value.strip_suffix(')')?.split_once('(')?;
+        if !function.trim().eq_ignore_ascii_case("linear-gradient") {
+            return None;
+        }
+        let mut parts = split_top_level_commas(arguments);
+        let angle = match parts.first().and_then(|first| parse_direction(first)) {
+            Some(angle) => {
+                parts.remove(0);
+                angle
+            }
+            // Without a direction the gradient runs top to bottom
+            None => 180.0,
+        };
+
+        let mut stops: Vec<(Color, Option<f32>)> = Vec::new();
+        for part in parts {
+            let tokens = split_outside_parens(part);
+            let (color, positions) = tokens.split_first()?;
+            let color = Color::parse(color)?;
+            match positions {
+                [] => stops.push((color, None)),
+                // A stop with two positions is two stops of the same color
+                [start, rest @ ..] if rest.len() <= 1 => {
+                    stops.push((color, Some(parse_position(start)?)));
+                    if let Some(end) = rest.first() {
+                        stops.push((color, Some(parse_position(end)?)));
+                    }
+                }
+                _ => return None,
+            }
+        }
+        if stops.len() < 2 {
+            return None;
+        }
+        Some(Gradient { angle, stops: resolve_positions(stops) })
+    }
+}
+
+/// Fill in missing positions: the ends default to 0 and 1, runs in between are spread
+/// evenly, and a position before an earlier one is raised to it
+fn resolve_positions(stops: Vec<(Color, Option<f32>)>) -> Vec<(Color, f32)> {
+    let last = stops.len() - 1;
+    let mut positions: Vec<Option<f32>> = stops.iter().map(|(_, position)| *position).collect();
+    positions[0].get_or_insert(0.0);
+    positions[last].get_or_insert(1.0);
+    let mut previous = 0;
+    for index in 1..=last {
+        if let Some(position) = positions[index] {
+            let start = positions[previous].unwrap_or(0.0);
+            let gap = (index - previous) as f32;
+            for (step, missing) in (previous + 1..index).enumerate() {
+                positions[missing] = Some(start + (position - start) * (step + 1) as f32 / gap);
+            }
+            previous = index;
+        }
+    }
+    let mut highest = 0.0f32;
+    stops.into_iter().zip(positions).map(|((color, _), position)| {
+        highest = highest.max(position.unwrap_or(0.0));
+        (color, highest)
+    }).collect()
+}
+
+/// An angle (`deg`, `turn`, `rad`, `grad`) or `to <side-or-corner>`, in degrees
+fn parse_direction(value: &str) -> Option<f32> {
+    let value = value.trim().to_ascii_lowercase();
+    if let Some(sides) = value.strip_prefix("to ") {
+        let mut sides: Vec<&str> = sides.split_whitespace().collect();
+        sides.sort_unstable();
+        // Corners point at the corner of a square box
+        return match sides.as_slice() {
+            ["top"] => Some(0.0),
+            ["right"] => Some(90.0),
+            ["bottom"] => Some(180.0),
+            ["left"] => Some(270.0),
+            ["right", "top"] => Some(45.0),
+            ["bottom", "right"] => Some(135.0),
+            ["bottom", "left"] => Some(225.0),
+            ["left", "top"] => Some(315.0),
+            _ => None,
+        };
+    }
+    let units = [("deg", 1.0), ("grad", 0.9), ("rad", 180.0 / std::f32::consts::PI), ("turn", 360.0)];
+    units.iter().find_map(|(unit, factor)| {
+        value.strip_suffix(unit)?.parse::<f32>().ok().map(|n| n * factor)
+    })
+}
+
+/// A stop position as a fraction of the gradient line; only percentages are supported
+fn parse_position(value: &str) -> Option<f32> {
+    value.strip_suffix('%')?.parse::<f32>().ok().map(|percent| percent / 100.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_linear_gradient() {
+        let gradient = Gradient::parse("linear-gradient(90deg, red 0%, blue 100%)").unwrap();
+        assert_eq!(gradient, Gradient { angle: 90.0, stops: vec![(Color::rgb(255, 0, 0), 0.0), (Color::rgb(0, 0, 255), 1.0)] });
+
+        let spread = Gradient::parse("linear-gradient(to left, rgb(0, 0, 0), #fff, red 80%, blue)").unwrap();
+        assert_eq!(spread.angle, 270.0);
+        let positions: Vec<f32> = spread.stops.iter().map(|&(_, position)| position).collect();
+        assert_eq!(positions, [0.0, 0.4, 0.8, 1.0]);
+        assert_eq!(Gradient::parse("linear-gradient(red, blue)").unwrap().angle, 180.0);
+
+        assert_eq!(Gradient::parse("radial-gradient(red, blue)"), None);
+        assert_eq!(Gradient::parse("linear-gradient(red)"), None);
+        assert_eq!(Gradient::parse("url(a.png)"), None);
+    }
+}
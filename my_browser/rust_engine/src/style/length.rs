@@ -0,0 +1,210 @@
+// CSS length resolution to px
+// Handles px, unitless numbers, %, em, rem, vw, vh, vmin, vmax and calc() against an explicit context
+
+/// What relative lengths resolve against
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LengthContext {
+    /// The containing block dimension `%` refers to (its width for widths, height for heights)
+    pub percent_base: f32,
+    /// The element's own font size, for `em`
+    pub font_size: f32,
+    /// The root element's font size, for `rem`
+    pub root_font_size: f32,
+    pub viewport_width: f32,
+    pub viewport_height: f32,
+}
+
+impl Default for LengthContext {
+    fn default() -> Self {
+        Self {
+            percent_base: 0.0,
+            font_size: 16.0,
+            root_font_size: 16.0,
+            viewport_width: 0.0,
+            viewport_height: 0.0,
+        }
+    }
+}
+
+impl LengthContext {
+    pub fn with_percent_base(mut self, percent_base: f32) -> Self {
+        self.percent_base = percent_base;
+        self
+    }
+}
+
+/// Resolve a length to px; None for empty, `auto` or unparseable values
+pub fn resolve_length(value: &str, context: &LengthContext) -> Option<f32> {
+    let value = value.trim().to_ascii_lowercase();
+    if let Some(expression) = value.strip_prefix("calc(").and_then(|v| v.strip_suffix(')')) {
+        return evaluate_calc(expression, context);
+    }
+    let split = value.find(|c: char| !(c.is_ascii_digit() || matches!(c, '.' | '-' | '+' | 'e')))
+        .unwrap_or(value.len());
+    // `e` only belongs to the number when it is an exponent, not the start of `em`
+    let split = if value[..split].ends_with('e') { split - 1 } else { split };
+    let number: f32 = value[..split].parse().ok()?;
+    let px = match &value[split..] {
+        "" | "px" => number,
+        "%" => context.percent_base * number / 100.0,
+        "em" => context.font_size * number,
+        "rem" => context.root_font_size * number,
+        "vw" => context.viewport_width * number / 100.0,
+        "vh" => context.viewport_height * number / 100.0,
+        "vmin" => context.viewport_width.min(context.viewport_height) * number / 100.0,
+        "vmax" => context.viewport_width.max(context.viewport_height) * number / 100.0,
+        _ => return None,
+    };
+    Some(px)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CalcToken {
+    // Lengths are resolved to px as they are read; numbers are unitless
+    Number(f32),
+    Length(f32),
+    Operator(char),
+    Open,
+    Close,
+}
+
+/// Evaluate the inside of `calc()`: `+ - * /` with the usual precedence, unary minus and
+/// nested parentheses (or nested `calc()`). None for malformed input, division by zero, a
+/// length multiplied by a length, or a division by anything but a number.
+pub fn evaluate_calc(expression: &str, context: &LengthContext) -> Option<f32> {
+    let tokens = tokenize_calc(expression, context)?;
+    let mut pos = 0;
+    let value = calc_sum(&tokens, &mut pos)?.value;
+    (pos == tokens.len() && value.is_finite()).then_some(value)
+}
+
+/// A calc() operand or partial result: px when `is_length`, otherwise a plain number
+#[derive(Debug, Clone, Copy)]
+struct CalcValue {
+    value: f32,
+    is_length: bool,
+}
+
+fn tokenize_calc(expression: &str, context: &LengthContext) -> Option<Vec<CalcToken>> {
+    let mut tokens = Vec::new();
+    let mut chars = expression.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => { chars.next(); }
+            '(' => { chars.next(); tokens.push(CalcToken::Open); }
+            ')' => { chars.next(); tokens.push(CalcToken::Close); }
+            '+' | '-' | '*' | '/' => { chars.next(); tokens.push(CalcToken::Operator(c)); }
+            c if c.is_ascii_digit() || c == '.' => {
+                let mut end = start;
+                while let Some(&(i, c)) = chars.peek() {
+                    if !(c.is_ascii_alphanumeric() || c == '.' || c == '%') {
+                        break;
+                    }
+                    end = i + c.len_utf8();
+                    chars.next();
+                }
+                let operand = &expression[start..end];
+                tokens.push(match operand.parse() {
+                    Ok(number) => CalcToken::Number(number),
+                    Err(_) => CalcToken::Length(resolve_length(operand, context)?),
+                });
+            }
+            // `calc(` nested inside calc() is just a parenthesis
+            'c' if expression[start..].starts_with("calc(") => {
+                for _ in 0.."calc(".len() {
+                    chars.next();
+                }
+                tokens.push(CalcToken::Open);
+            }
+            _ => return None,
+        }
+    }
+    Some(tokens)
+}
+
+fn calc_sum(tokens: &[CalcToken], pos: &mut usize) -> Option<CalcValue> {
+    let mut lhs = calc_product(tokens, pos)?;
+    while let Some(&CalcToken::Operator(op @ ('+' | '-'))) = tokens.get(*pos) {
+        *pos += 1;
+        let rhs = calc_product(tokens, pos)?;
+        lhs = CalcValue {
+            value: if op == '+' { lhs.value + rhs.value } else { lhs.value - rhs.value },
+            is_length: lhs.is_length || rhs.is_length,
+        };
+    }
+    Some(lhs)
+}
+
+/// `*` needs a number on at least one side, and `/` a nonzero number on the right
+fn calc_product(tokens: &[CalcToken], pos: &mut usize) -> Option<CalcValue> {
+    let mut lhs = calc_factor(tokens, pos)?;
+    while let Some(&CalcToken::Operator(op @ ('*' | '/'))) = tokens.get(*pos) {
+        *pos += 1;
+        let rhs = calc_factor(tokens, pos)?;
+        lhs = match op {
+            '*' if lhs.is_length && rhs.is_length => return None,
+            '*' => CalcValue { value: lhs.value * rhs.value, is_length: lhs.is_length || rhs.is_length },
+            _ if rhs.is_length || rhs.value == 0.0 => return None,
+            _ => CalcValue { value: lhs.value / rhs.value, is_length: lhs.is_length },
+        };
+    }
+    Some(lhs)
+}
+
+fn calc_factor(tokens: &[CalcToken], pos: &mut usize) -> Option<CalcValue> {
+    let token = *tokens.get(*pos)?;
+    *pos += 1;
+    match token {
+        CalcToken::Number(value) => Some(CalcValue { value, is_length: false }),
+        CalcToken::Length(value) => Some(CalcValue { value, is_length: true }),
+        CalcToken::Operator('-') => calc_factor(tokens, pos).map(|operand| CalcValue { value: -operand.value, ..operand }),
+        CalcToken::Operator('+') => calc_factor(tokens, pos),
+        CalcToken::Open => {
+            let value = calc_sum(tokens, pos)?;
+            (tokens.get(*pos) == Some(&CalcToken::Close)).then(|| *pos += 1)?;
+            Some(value)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relative_units_resolve_against_context() {
+        let context = LengthContext {
+            percent_base: 600.0,
+            font_size: 20.0,
+            root_font_size: 16.0,
+            viewport_width: 1000.0,
+            viewport_height: 800.0,
+        };
+        assert_eq!(resolve_length("50%", &context), Some(300.0));
+        assert_eq!(resolve_length("2em", &context), Some(40.0));
+        assert_eq!(resolve_length("1.5rem", &context), Some(24.0));
+        assert_eq!(resolve_length("10vw", &context), Some(100.0));
+        assert_eq!(resolve_length("10vh", &context), Some(80.0));
+        assert_eq!(resolve_length("12px", &context), Some(12.0));
+        assert_eq!(resolve_length("7", &context), Some(7.0));
+        assert_eq!(resolve_length("50%", &context.with_percent_base(200.0)), Some(100.0));
+        assert_eq!(resolve_length("auto", &context), None);
+    }
+
+    #[test]
+    fn test_calc_evaluates_with_precedence() {
+        let context = LengthContext { percent_base: 400.0, font_size: 10.0, ..LengthContext::default() };
+        assert_eq!(resolve_length("calc(100% - 20px)", &context), Some(380.0));
+        assert_eq!(resolve_length("calc(50px + 2em)", &context), Some(70.0));
+        assert_eq!(resolve_length("calc((100% - 40px) / 2)", &context), Some(180.0));
+        assert_eq!(resolve_length("calc(10px + 2 * 5px)", &context), Some(20.0));
+        assert_eq!(resolve_length("calc(100% - 20px", &context), None);
+        assert_eq!(resolve_length("calc(10px / 0)", &context), None);
+        assert_eq!(resolve_length("calc(10px +)", &context), None);
+        assert_eq!(resolve_length("calc(2em * 3)", &context), Some(60.0));
+        assert_eq!(resolve_length("calc(10px * 2px)", &context), None);
+        assert_eq!(resolve_length("calc(100% / 10px)", &context), None);
+        assert_eq!(resolve_length("calc((10px + 5px) / (1 + 2))", &context), Some(5.0));
+    }
+}
@@ -1,2 +1,7 @@
 // Style matching and application modules
-// TODO: Move style-related logic from other modules here 
\ No newline at end of file
+// TODO: Move style-related logic from other modules here
+
+pub mod color;
+pub mod gradient;
+pub mod length;
+pub mod selector;
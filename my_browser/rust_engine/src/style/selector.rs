@@ -0,0 +1,145 @@
+// CSS selector matching against DOM nodes, shared by the cascade and the DOM query APIs
+
+use crate::dom::node::{split_top_level_commas, DOMArena, DOMNode, NodeType};
+
+/// Match a selector against an element. Descendant (`a b`) and child (`a > b`) combinators
+/// are resolved by walking `node.parent` through the arena, so the ancestors must not be locked.
+pub fn matches_selector(node: &DOMNode, selector: &str, arena: &DOMArena) -> bool {
+    let selector = selector.trim();
+    // Fast path: a single compound selector
+    if !selector.contains(|c: char| c.is_whitespace() || c == '>') {
+        return matches_simple_selector(node, selector);
+    }
+
+    // Compounds left to right; combinators[i] sits between compounds[i] and compounds[i + 1]
+    let mut compounds = Vec::new();
+    let mut combinators = Vec::new();
+    let mut pending = ' ';
+    for part in split_combinators(selector) {
+        if part == ">" {
+            pending = '>';
+            continue;
+        }
+        if !compounds.is_empty() {
+            combinators.push(pending);
+        }
+        compounds.push(part);
+        pending = ' ';
+    }
+    let Some((subject, ancestors)) = compounds.split_last() else { return false };
+    matches_simple_selector(node, subject) && matches_ancestors(ancestors, &combinators, node.parent.clone(), arena)
+}
+
+/// Match `compounds` (all but the subject) against the ancestor chain starting at `parent`
+fn matches_ancestors(compounds: &[&str], combinators: &[char], parent: Option<String>, arena: &DOMArena) -> bool {
+    let Some((last, rest)) = compounds.split_last() else { return true };
+    let child_only = combinators[compounds.len() - 1] == '>';
+    let mut current = parent;
+    while let Some(id) = current {
+        let Some(ancestor) = arena.get_node(&id) else { return false };
+        let (matched, next) = {
+            let ancestor = ancestor.lock().unwrap();
+            (matches_simple_selector(&ancestor, last), ancestor.parent.clone())
+        };
+        if matched && matches_ancestors(rest, combinators, next.clone(), arena) {
+            return true;
+        }
+        if child_only {
+            return false;
+        }
+        current = next;
+    }
+    false
+}
+
+/// Split a selector list (`.a, #b > p`) at its top-level commas, dropping empty entries
+pub fn split_selector_list(selectors: &str) -> Vec<&str> {
+    let mut parts = split_top_level_commas(selectors);
+    parts.retain(|part| !part.is_empty());
+    parts
+}
+
+/// Split a complex selector into compounds and `>` tokens, leaving `[...]` contents intact
+fn split_combinators(selector: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = None;
+    let mut in_brackets = false;
+    for (i, c) in selector.char_indices() {
+        match c {
+            '[' => in_brackets = true,
+            ']' => in_brackets = false,
+            _ if in_brackets => {}
+            '>' => {
+                parts.extend(start.take().map(|s| &selector[s..i]));
+                parts.push(">");
+                continue;
+            }
+            c if c.is_whitespace() => {
+                parts.extend(start.take().map(|s| &selector[s..i]));
+                continue;
+            }
+            _ => {}
+        }
+        start.get_or_insert(i);
+    }
+    parts.extend(start.map(|s| &selector[s..]));
+    parts
+}
+
+/// Match a compound selector: an optional tag followed by any `.class`, `#id`, `[attr]` and
+/// `:state` parts. A state such as `:hover` matches while it is in `node.pseudo_states`.
+fn matches_simple_selector(node: &DOMNode, selector: &str) -> bool {
+    let NodeType::Element(tag_name) = &node.node_type else { return false };
+    let tag_end = selector.find(['.', '#', '[', ':']).unwrap_or(selector.len());
+    let tag = &selector[..tag_end];
+    if !tag.is_empty() && tag != "*" && tag != tag_name {
+        return false;
+    }
+    let mut rest = &selector[tag_end..];
+    if rest.is_empty() {
+        return !tag.is_empty();
+    }
+    while !rest.is_empty() {
+        if let Some(attribute) = rest.strip_prefix('[') {
+            let Some(end) = attribute.find(']') else { return false };
+            if !matches_attribute(node, &attribute[..end]) {
+                return false;
+            }
+            rest = &attribute[end + 1..];
+            continue;
+        }
+        let end = rest[1..].find(['.', '#', '[', ':']).map_or(rest.len(), |i| i + 1);
+        let (part, next) = rest.split_at(end);
+        let matched = match part.split_at(1) {
+            (".", class_name) => node.attributes.get("class")
+                .is_some_and(|classes| classes.split_whitespace().any(|c| c == class_name)),
+            ("#", id_name) => node.attributes.get("id").is_some_and(|id| id == id_name),
+            (":", state) => node.pseudo_states.contains(state),
+            _ => false,
+        };
+        if !matched {
+            return false;
+        }
+        rest = next;
+    }
+    true
+}
+
+/// Match the inside of an attribute selector: `attr`, `attr=v`, `attr^=v`, `attr$=v` or `attr*=v`
+fn matches_attribute(node: &DOMNode, condition: &str) -> bool {
+    let Some(eq) = condition.find('=') else {
+        return node.attributes.contains_key(condition.trim());
+    };
+    let (name, operator) = match condition[..eq].trim_end() {
+        name if name.ends_with(['^', '$', '*']) => (&name[..name.len() - 1], &name[name.len() - 1..]),
+        name => (name, ""),
+    };
+    let expected = condition[eq + 1..].trim().trim_matches(|c| c == '"' || c == '\'');
+    let Some(actual) = node.attributes.get(name.trim()) else { return false };
+    match operator {
+        "^" => !expected.is_empty() && actual.starts_with(expected),
+        "$" => !expected.is_empty() && actual.ends_with(expected),
+        "*" => !expected.is_empty() && actual.contains(expected),
+        _ => actual == expected,
+    }
+}